@@ -0,0 +1,594 @@
+//! Cluster mode: several beacons behind a load balancer sharing one Redis pub/sub bus.
+//!
+//! Peer senders, user connections and server subscriptions stay local to each node. Anything routed
+//! by peer_id, user_id or signing_pubkey is delivered locally and also published as a
+//! [`ClusterEvent`]; every other node delivers it to its own matching connections. Voice room
+//! membership and swarm announcements are replicated so rosters and peer lists are cluster-wide;
+//! remote members are stored under a `remote:{node}/{conn}` ConnId and dropped when their node
+//! reports the connection closed or stops sending heartbeats.
+//!
+//! Enabled with BEACON_CLUSTER=1 (feature "redis-backend", SIGNALING_REDIS_URL set). Tests and
+//! single-process setups can run nodes on a [`LocalBus`] instead.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::state::swarm::SwarmPeer;
+use crate::state::AppState;
+use crate::{ConnId, PeerId, ServerId, SignalingMessage, SigningPubkey};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// A node silent for this long is considered gone and its replicated members are dropped.
+const NODE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Events waiting to be published; beyond this they are dropped (and logged).
+const OUTBOX_CAPACITY: usize = 4096;
+
+#[derive(Clone, Debug)]
+pub struct ClusterSettings {
+    pub node_id: String,
+    /// Redis channels are `{prefix}:{peer|user|server|state}`.
+    pub channel_prefix: String,
+}
+
+impl ClusterSettings {
+    /// None unless BEACON_CLUSTER is set.
+    pub fn from_env() -> Option<Self> {
+        let enabled = env::var("BEACON_CLUSTER")
+            .map(|v| matches!(v.trim(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+        let node_id = env::var("BEACON_CLUSTER_NODE_ID")
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let channel_prefix = env::var("BEACON_CLUSTER_CHANNEL_PREFIX")
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| "cordia:cluster".to_string());
+        Some(Self { node_id, channel_prefix })
+    }
+}
+
+/// Something another node has to deliver or mirror. `json` is an already serialized SignalingMessage.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ClusterEvent {
+    /// For whichever node holds this peer_id.
+    Peer { peer_id: PeerId, json: String },
+    /// For all connections of these users.
+    Users { user_ids: Vec<String>, json: String },
+    /// For all connections subscribed to a server.
+    Server {
+        signing_pubkey: SigningPubkey,
+        json: String,
+        coalesce_key: Option<String>,
+    },
+    /// Ephemeral chat traffic for one chat; `activity` is the ChatActivity ping for connections
+    /// that use chat subscriptions but do not follow the chat.
+    Chat {
+        signing_pubkey: SigningPubkey,
+        chat_id: String,
+        json: String,
+        coalesce_key: Option<String>,
+        activity: Option<String>,
+    },
+    /// For all connections with user_id in their friend list.
+    FriendSubscribers {
+        user_id: String,
+        json: String,
+        coalesce_key: Option<String>,
+    },
+    /// For the members of a voice chat connected to the receiving node.
+    VoiceRoom {
+        server_id: ServerId,
+        chat_id: String,
+        json: String,
+        exclude_peer: Option<PeerId>,
+    },
+    VoiceJoined {
+        server_id: ServerId,
+        chat_id: String,
+        signing_pubkey: SigningPubkey,
+        peer_id: PeerId,
+        user_id: String,
+        conn_id: ConnId,
+    },
+    VoiceLeft {
+        server_id: ServerId,
+        chat_id: String,
+        peer_id: PeerId,
+    },
+    SwarmUpsert {
+        signing_pubkey: SigningPubkey,
+        sha256: String,
+        peer: SwarmPeer,
+    },
+    SwarmRemove {
+        signing_pubkey: SigningPubkey,
+        sha256: String,
+        conn_id: ConnId,
+    },
+    /// A connection on the sending node is gone; drop what was mirrored for it.
+    ConnClosed { conn_id: ConnId },
+    Heartbeat,
+    /// Sent by a node that just (re)joined the bus: others re-send their voice and swarm members.
+    SyncRequest,
+}
+
+impl ClusterEvent {
+    /// Redis channel suffix. All events go through one connection, so order is kept across channels.
+    fn channel(&self) -> &'static str {
+        match self {
+            ClusterEvent::Peer { .. } => "peer",
+            ClusterEvent::Users { .. } => "user",
+            ClusterEvent::Server { .. } | ClusterEvent::Chat { .. } | ClusterEvent::FriendSubscribers { .. } => {
+                "server"
+            }
+            _ => "state",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Envelope {
+    pub node: String,
+    #[serde(flatten)]
+    pub event: ClusterEvent,
+}
+
+/// In-process bus standing in for Redis pub/sub (tests, single-host experiments).
+#[derive(Clone)]
+pub struct LocalBus(broadcast::Sender<String>);
+
+impl LocalBus {
+    pub fn new() -> Self {
+        Self(broadcast::channel(OUTBOX_CAPACITY).0)
+    }
+}
+
+impl Default for LocalBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub enum Transport {
+    Local(LocalBus),
+    #[cfg(feature = "redis-backend")]
+    Redis {
+        client: redis::Client,
+        channel_prefix: String,
+    },
+}
+
+struct RemoteNode {
+    last_seen: Instant,
+    /// Remote ConnIds with mirrored voice or swarm entries.
+    conns: HashSet<ConnId>,
+}
+
+pub struct Cluster {
+    pub node_id: String,
+    outbox: mpsc::Sender<Envelope>,
+    nodes: Mutex<HashMap<String, RemoteNode>>,
+}
+
+impl Cluster {
+    pub fn publish(&self, event: ClusterEvent) {
+        let envelope = Envelope {
+            node: self.node_id.clone(),
+            event,
+        };
+        if self.outbox.try_send(envelope).is_err() {
+            warn!("Cluster outbox full or closed; dropping event");
+        }
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.lock().map(|n| n.len()).unwrap_or(0) + 1
+    }
+
+    fn touch(&self, node: &str, conn: Option<&ConnId>, now: Instant) {
+        let mut nodes = self.nodes.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = nodes.entry(node.to_string()).or_insert_with(|| {
+            info!("Cluster node {} joined", node);
+            RemoteNode {
+                last_seen: now,
+                conns: HashSet::new(),
+            }
+        });
+        entry.last_seen = now;
+        if let Some(conn) = conn {
+            entry.conns.insert(conn.clone());
+        }
+    }
+
+    fn forget_conn(&self, node: &str, conn: &ConnId) {
+        let mut nodes = self.nodes.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = nodes.get_mut(node) {
+            entry.conns.remove(conn);
+        }
+    }
+
+    /// Remove nodes not heard from within NODE_TIMEOUT; returns their mirrored ConnIds.
+    fn take_stale(&self, now: Instant) -> Vec<(String, Vec<ConnId>)> {
+        let mut nodes = self.nodes.lock().unwrap_or_else(|e| e.into_inner());
+        let stale: Vec<String> = nodes
+            .iter()
+            .filter(|(_, n)| now.duration_since(n.last_seen) > NODE_TIMEOUT)
+            .map(|(id, _)| id.clone())
+            .collect();
+        stale
+            .into_iter()
+            .filter_map(|id| nodes.remove(&id).map(|n| (id, n.conns.into_iter().collect())))
+            .collect()
+    }
+}
+
+/// ConnId under which a remote node's connection is mirrored locally.
+pub fn remote_conn_id(node: &str, conn_id: &ConnId) -> ConnId {
+    format!("remote:{}/{}", node, conn_id)
+}
+
+/// Join the cluster: set `state.cluster` and spawn the publisher, subscriber and heartbeat tasks.
+pub fn start(state: Arc<AppState>, transport: Transport, node_id: String) {
+    let (outbox, outbox_rx) = mpsc::channel(OUTBOX_CAPACITY);
+    let cluster = Arc::new(Cluster {
+        node_id: node_id.clone(),
+        outbox,
+        nodes: Mutex::new(HashMap::new()),
+    });
+    if state.cluster.set(cluster.clone()).is_err() {
+        warn!("Cluster already started; ignoring");
+        return;
+    }
+
+    match transport {
+        Transport::Local(bus) => {
+            let rx = bus.0.subscribe();
+            tokio::spawn(local_publisher(bus, outbox_rx));
+            tokio::spawn(local_subscriber(state.clone(), rx));
+        }
+        #[cfg(feature = "redis-backend")]
+        Transport::Redis { client, channel_prefix } => {
+            tokio::spawn(redis_publisher(client.clone(), channel_prefix.clone(), outbox_rx));
+            tokio::spawn(redis_subscriber(state.clone(), client, channel_prefix));
+        }
+    }
+
+    cluster.publish(ClusterEvent::SyncRequest);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            interval.tick().await;
+            cluster.publish(ClusterEvent::Heartbeat);
+            for (node, conns) in cluster.take_stale(Instant::now()) {
+                warn!("Cluster node {} timed out; dropping {} mirrored connections", node, conns.len());
+                for conn_id in conns {
+                    drop_remote_conn(&state, &conn_id, true).await;
+                }
+            }
+        }
+    });
+    info!("Cluster mode enabled (node {}).", node_id);
+}
+
+async fn local_publisher(bus: LocalBus, mut outbox: mpsc::Receiver<Envelope>) {
+    while let Some(envelope) = outbox.recv().await {
+        if let Ok(payload) = serde_json::to_string(&envelope) {
+            let _ = bus.0.send(payload);
+        }
+    }
+}
+
+async fn local_subscriber(state: Arc<AppState>, mut rx: broadcast::Receiver<String>) {
+    loop {
+        match rx.recv().await {
+            Ok(payload) => receive(&state, &payload).await,
+            Err(broadcast::error::RecvError::Lagged(n)) => warn!("Cluster bus lagged; {} events lost", n),
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(feature = "redis-backend")]
+async fn redis_publisher(client: redis::Client, prefix: String, mut outbox: mpsc::Receiver<Envelope>) {
+    let mut conn = None;
+    while let Some(envelope) = outbox.recv().await {
+        let Ok(payload) = serde_json::to_string(&envelope) else {
+            continue;
+        };
+        let channel = format!("{}:{}", prefix, envelope.event.channel());
+        if conn.is_none() {
+            match client.get_multiplexed_tokio_connection().await {
+                Ok(c) => conn = Some(c),
+                Err(e) => {
+                    warn!("Cluster publish: Redis connect failed; dropping event: {}", e);
+                    continue;
+                }
+            }
+        }
+        let Some(c) = conn.as_mut() else {
+            continue;
+        };
+        let res: redis::RedisResult<()> = redis::cmd("PUBLISH").arg(&channel).arg(payload).query_async(c).await;
+        if let Err(e) = res {
+            warn!("Cluster publish to {} failed: {}", channel, e);
+            conn = None;
+        }
+    }
+}
+
+#[cfg(feature = "redis-backend")]
+async fn redis_subscriber(state: Arc<AppState>, client: redis::Client, prefix: String) {
+    use futures_util::StreamExt;
+
+    let pattern = format!("{}:*", prefix);
+    let mut backoff = Duration::from_secs(1);
+    let mut first = true;
+    loop {
+        let pubsub = match client.get_async_pubsub().await {
+            Ok(mut pubsub) => match pubsub.psubscribe(&pattern).await {
+                Ok(()) => Some(pubsub),
+                Err(e) => {
+                    warn!("Cluster subscribe failed: {}", e);
+                    None
+                }
+            },
+            Err(e) => {
+                warn!("Cluster subscriber: Redis connect failed: {}", e);
+                None
+            }
+        };
+        if let Some(pubsub) = pubsub {
+            backoff = Duration::from_secs(1);
+            if !first {
+                // Events published while we were away are lost; ask the others to re-send state.
+                state.publish(ClusterEvent::SyncRequest);
+            }
+            first = false;
+            let mut messages = pubsub.into_on_message();
+            while let Some(msg) = messages.next().await {
+                match msg.get_payload::<String>() {
+                    Ok(payload) => receive(&state, &payload).await,
+                    Err(e) => warn!("Cluster message with bad payload: {}", e),
+                }
+            }
+            warn!("Cluster subscription lost; reconnecting");
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(Duration::from_secs(30));
+    }
+}
+
+async fn receive(state: &AppState, payload: &str) {
+    let envelope: Envelope = match serde_json::from_str(payload) {
+        Ok(e) => e,
+        Err(e) => {
+            warn!("Ignoring malformed cluster event: {}", e);
+            return;
+        }
+    };
+    let Some(cluster) = state.cluster.get() else {
+        return;
+    };
+    if envelope.node == cluster.node_id {
+        return;
+    }
+    apply(state, cluster, envelope).await;
+}
+
+/// Deliver or mirror an event from another node on this one.
+async fn apply(state: &AppState, cluster: &Cluster, envelope: Envelope) {
+    use tokio_tungstenite::tungstenite::Message;
+
+    let node = envelope.node;
+    let now = Instant::now();
+    match envelope.event {
+        ClusterEvent::Peer { peer_id, json } => {
+            cluster.touch(&node, None, now);
+            if let Some(sender) = state.signaling.read().await.peer_senders.get(&peer_id) {
+                let _ = sender.send(Message::Text(json));
+            }
+        }
+        ClusterEvent::Users { user_ids, json } => {
+            cluster.touch(&node, None, now);
+            let friends = state.friends.read().await;
+            for user_id in &user_ids {
+                friends.send_to_user(user_id, &json);
+            }
+        }
+        ClusterEvent::Server { signing_pubkey, json, coalesce_key } => {
+            cluster.touch(&node, None, now);
+            state
+                .signaling
+                .read()
+                .await
+                .send_to_server_subscribers(&signing_pubkey, &json, coalesce_key.as_deref(), None);
+        }
+        ClusterEvent::Chat { signing_pubkey, chat_id, json, coalesce_key, activity } => {
+            cluster.touch(&node, None, now);
+            state.signaling.read().await.send_to_chat_subscribers(
+                &signing_pubkey,
+                &chat_id,
+                &json,
+                coalesce_key.as_deref(),
+                activity.as_deref(),
+                None,
+            );
+        }
+        ClusterEvent::FriendSubscribers { user_id, json, coalesce_key } => {
+            cluster.touch(&node, None, now);
+            state
+                .signaling
+                .read()
+                .await
+                .send_to_friend_subscribers(&user_id, &json, coalesce_key.as_deref());
+        }
+        ClusterEvent::VoiceRoom { server_id, chat_id, json, exclude_peer } => {
+            cluster.touch(&node, None, now);
+            state
+                .send_to_voice_room_local(&server_id, &chat_id, &json, exclude_peer.as_ref())
+                .await;
+        }
+        ClusterEvent::VoiceJoined { server_id, chat_id, signing_pubkey, peer_id, user_id, conn_id } => {
+            let conn_id = remote_conn_id(&node, &conn_id);
+            cluster.touch(&node, Some(&conn_id), now);
+            let mut voice = state.voice.write().await;
+            voice.server_signing_pubkeys.insert(server_id.clone(), signing_pubkey);
+            voice.register_voice_peer(peer_id, user_id, server_id, chat_id, conn_id);
+        }
+        ClusterEvent::VoiceLeft { server_id, chat_id, peer_id } => {
+            cluster.touch(&node, None, now);
+            state.voice.write().await.unregister_voice_peer(&peer_id, &server_id, &chat_id);
+        }
+        ClusterEvent::SwarmUpsert { signing_pubkey, sha256, mut peer } => {
+            peer.conn_id = remote_conn_id(&node, &peer.conn_id);
+            cluster.touch(&node, Some(&peer.conn_id), now);
+            state.swarm.write().await.upsert(signing_pubkey, sha256, peer);
+        }
+        ClusterEvent::SwarmRemove { signing_pubkey, sha256, conn_id } => {
+            cluster.touch(&node, None, now);
+            let conn_id = remote_conn_id(&node, &conn_id);
+            state.swarm.write().await.unannounce(&signing_pubkey, &sha256, &conn_id);
+        }
+        ClusterEvent::ConnClosed { conn_id } => {
+            cluster.touch(&node, None, now);
+            let conn_id = remote_conn_id(&node, &conn_id);
+            cluster.forget_conn(&node, &conn_id);
+            // The owning node already told everyone the members left.
+            drop_remote_conn(state, &conn_id, false).await;
+        }
+        ClusterEvent::Heartbeat => cluster.touch(&node, None, now),
+        ClusterEvent::SyncRequest => {
+            cluster.touch(&node, None, now);
+            resend_local_members(state, cluster).await;
+        }
+    }
+}
+
+/// Publish VoiceJoined / SwarmUpsert for every member connected to this node.
+async fn resend_local_members(state: &AppState, cluster: &Cluster) {
+    let mut events = Vec::new();
+    {
+        let voice = state.voice.read().await;
+        for ((server_id, chat_id), peers) in &voice.voice_chats {
+            let Some(signing_pubkey) = voice.server_signing_pubkeys.get(server_id) else {
+                continue;
+            };
+            for p in peers.iter().filter(|p| !p.conn_id.starts_with("remote:")) {
+                events.push(ClusterEvent::VoiceJoined {
+                    server_id: server_id.clone(),
+                    chat_id: chat_id.clone(),
+                    signing_pubkey: signing_pubkey.clone(),
+                    peer_id: p.peer_id.clone(),
+                    user_id: p.user_id.clone(),
+                    conn_id: p.conn_id.clone(),
+                });
+            }
+        }
+    }
+    {
+        let swarm = state.swarm.read().await;
+        for ((signing_pubkey, sha256), by_conn) in &swarm.swarms {
+            for peer in by_conn.values().filter(|p| !p.conn_id.starts_with("remote:")) {
+                events.push(ClusterEvent::SwarmUpsert {
+                    signing_pubkey: signing_pubkey.clone(),
+                    sha256: sha256.clone(),
+                    peer: peer.clone(),
+                });
+            }
+        }
+    }
+    for event in events {
+        cluster.publish(event);
+    }
+}
+
+/// Drop the voice and swarm entries mirrored for a remote connection. When its node vanished
+/// (`announce_leave`), local voice members are told the remote members left.
+async fn drop_remote_conn(state: &AppState, conn_id: &ConnId, announce_leave: bool) {
+    let voice_removed = state.voice.write().await.handle_voice_disconnect(conn_id);
+    state.swarm.write().await.remove_conn(conn_id);
+    if !announce_leave {
+        return;
+    }
+    for (server_id, chat_id, peer_id, user_id) in voice_removed {
+        let msg = SignalingMessage::VoicePeerLeft {
+            peer_id,
+            user_id,
+            chat_id: chat_id.clone(),
+        };
+        if let Ok(json) = serde_json::to_string(&msg) {
+            state.send_to_voice_room_local(&server_id, &chat_id, &json, None).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outbound::{channel, OutboundLimits};
+    use crate::security::{ConnectionTracker, ReplayCache, SecurityConfig, WsHeartbeat, WsRateLimits};
+    use tokio::sync::RwLock;
+
+    fn node_state() -> Arc<AppState> {
+        let config = SecurityConfig::from_env();
+        Arc::new(AppState::new(
+            None,
+            Arc::new(RwLock::new(ConnectionTracker::new(0, 0))),
+            Arc::new(WsRateLimits::from_config(&config)),
+            Arc::new(tokio::sync::Mutex::new(ReplayCache::new(16))),
+            Arc::new(OutboundLimits::new(16, Duration::from_secs(10))),
+            WsHeartbeat::default(),
+            Duration::ZERO,
+        ))
+    }
+
+    #[tokio::test]
+    async fn routes_peer_messages_and_mirrors_voice_rooms_across_nodes() {
+        let bus = LocalBus::new();
+        let a = node_state();
+        let b = node_state();
+        start(a.clone(), Transport::Local(bus.clone()), "node-a".to_string());
+        start(b.clone(), Transport::Local(bus), "node-b".to_string());
+
+        let (tx, mut rx) = channel(b.outbound.clone());
+        b.signaling.write().await.peer_senders.insert("peer-b".to_string(), tx);
+        b.publish(ClusterEvent::VoiceJoined {
+            server_id: "srv".to_string(),
+            chat_id: "chat".to_string(),
+            signing_pubkey: "spk".to_string(),
+            peer_id: "peer-b".to_string(),
+            user_id: "user-b".to_string(),
+            conn_id: "conn-b".to_string(),
+        });
+
+        assert!(a.send_to_peer(&"peer-b".to_string(), "{\"type\":\"Ping\"}".to_string()).await);
+        let got = tokio::time::timeout(Duration::from_secs(2), rx.recv()).await.unwrap().unwrap();
+        assert_eq!(got.into_text().unwrap(), "{\"type\":\"Ping\"}");
+
+        let key = ("srv".to_string(), "chat".to_string());
+        let mirrored = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                if let Some(members) = a.voice.read().await.voice_chats.get(&key) {
+                    return members[0].conn_id.clone();
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(mirrored, remote_conn_id("node-b", &"conn-b".to_string()));
+    }
+}
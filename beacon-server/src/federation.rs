@@ -0,0 +1,862 @@
+//! Beacon-to-beacon federation: members of one server who use different beacons still see each
+//! other.
+//!
+//! Each beacon has an Ed25519 identity key. Peers are allow-listed by public key in
+//! BEACON_FEDERATION_PEERS; a peer entry with a URL is dialed, the others may only dial in. Both
+//! ends of a link prove their key (Hello / Welcome over a fresh nonce), then tell each other which
+//! servers (signing_pubkeys) their clients subscribe to. For shared servers a link carries:
+//! - signed server hints, verified and stored like a hint posted by a client;
+//! - server broadcasts (presence, profile, voice presence, ephemeral chat and receipts);
+//! - voice membership, so rosters include remote members, and voice offers/answers/ICE addressed
+//!   to them.
+//!
+//! Messages are relayed one hop only: what arrives over a link is delivered locally, never forwarded
+//! to another federated beacon.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::extract::ws::{Message as AxumMessage, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use base64::Engine;
+use ed25519_dalek::{Signer, SigningKey};
+use futures_util::{SinkExt, StreamExt};
+use log::{info, warn};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Notify};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::handlers::friends::SIGNED_REQUEST_MAX_SKEW_SECS;
+use crate::handlers::http::{load_server_hint, store_server_hint};
+use crate::signing::verify_ed25519_b64;
+use crate::state::signaling::FRIENDS_SIGNING_PUBKEY;
+use crate::state::{chat_route, AppState};
+use crate::{ConnId, EncryptedServerHint, PeerId, ServerId, SignalingMessage, SigningPubkey};
+
+/// Messages queued for one link; beyond this they are dropped.
+const LINK_QUEUE: usize = 1024;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often each side re-sends the servers it is interested in.
+const INTEREST_INTERVAL: Duration = Duration::from_secs(15);
+const MAX_INTEREST: usize = 10_000;
+const MAX_FRAME_BYTES: usize = 512 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct FederationPeer {
+    /// Base64 Ed25519 identity key of the peer beacon.
+    pub pubkey: String,
+    /// Federation endpoint to dial (`ws://host:9001/federation`); None = wait for the peer to dial.
+    pub url: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FederationSettings {
+    pub key_path: PathBuf,
+    pub peers: Vec<FederationPeer>,
+}
+
+impl FederationSettings {
+    /// None unless BEACON_FEDERATION_PEERS lists at least one peer.
+    /// Entries are `pubkey` or `pubkey@ws://host:port/federation`, comma-separated.
+    pub fn from_env() -> Option<Self> {
+        let peers: Vec<FederationPeer> = env::var("BEACON_FEDERATION_PEERS")
+            .ok()?
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|entry| match entry.split_once('@') {
+                Some((pubkey, url)) => FederationPeer {
+                    pubkey: pubkey.trim().to_string(),
+                    url: Some(url.trim().to_string()).filter(|u| !u.is_empty()),
+                },
+                None => FederationPeer {
+                    pubkey: entry.to_string(),
+                    url: None,
+                },
+            })
+            .collect();
+        if peers.is_empty() {
+            return None;
+        }
+        let key_path = env::var("BEACON_FEDERATION_KEY_PATH")
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| PathBuf::from(s.trim()))
+            .unwrap_or_else(|| {
+                env::var("SIGNALING_DATA_DIR")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|_| env::temp_dir())
+                    .join("cordia-beacon-federation-key")
+            });
+        Some(Self { key_path, peers })
+    }
+}
+
+/// Load the beacon identity key (hex), creating it on first start.
+pub fn load_or_create_key(path: &PathBuf) -> std::io::Result<SigningKey> {
+    if let Ok(s) = fs::read_to_string(path) {
+        let bytes = hex::decode(s.trim()).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        let bytes = <[u8; 32]>::try_from(bytes.as_slice())
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "federation key must be 32 bytes"))?;
+        return Ok(SigningKey::from_bytes(&bytes));
+    }
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, hex::encode(bytes))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+fn pubkey_b64(key: &SigningKey) -> String {
+    base64::engine::general_purpose::STANDARD.encode(key.verifying_key().to_bytes())
+}
+
+fn sign_b64(key: &SigningKey, message: &str) -> String {
+    base64::engine::general_purpose::STANDARD.encode(key.sign(message.as_bytes()).to_bytes())
+}
+
+fn hello_message(dialer: &str, target: &str, ts: i64, nonce: &str) -> String {
+    format!("cordia-federation-hello\n{}\n{}\n{}\n{}", dialer, target, ts, nonce)
+}
+
+fn welcome_message(listener: &str, dialer: &str, nonce: &str) -> String {
+    format!("cordia-federation-welcome\n{}\n{}\n{}", listener, dialer, nonce)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum FederationMessage {
+    Hello {
+        pubkey: String,
+        target: String,
+        ts: i64,
+        nonce: String,
+        signature: String,
+    },
+    Welcome {
+        pubkey: String,
+        signature: String,
+    },
+    /// Servers the sender has subscribers for (full set, replaces the previous one).
+    Interest { signing_pubkeys: Vec<SigningPubkey> },
+    Hint { hint: EncryptedServerHint },
+    /// A serialized server broadcast (see `relayable_server_message`).
+    Server {
+        signing_pubkey: SigningPubkey,
+        json: String,
+        coalesce_key: Option<String>,
+    },
+    VoiceJoined {
+        signing_pubkey: SigningPubkey,
+        server_id: ServerId,
+        chat_id: String,
+        peer_id: PeerId,
+        user_id: String,
+    },
+    VoiceLeft {
+        signing_pubkey: SigningPubkey,
+        server_id: ServerId,
+        chat_id: String,
+        peer_id: PeerId,
+    },
+    /// Voice signaling addressed to a voice member on the receiving beacon.
+    Peer { peer_id: PeerId, json: String },
+}
+
+struct Link {
+    id: u64,
+    /// Key of the beacon that dialed; decides which link survives when both sides dial.
+    dialer: String,
+    tx: mpsc::Sender<String>,
+    /// Servers the peer beacon has subscribers for.
+    interest: HashSet<SigningPubkey>,
+    closed: Arc<Notify>,
+}
+
+pub struct Federation {
+    pub pubkey: String,
+    key: SigningKey,
+    /// Allow-listed peer key -> URL to dial.
+    peers: HashMap<String, Option<String>>,
+    links: Mutex<HashMap<String, Link>>,
+    /// Remote voice members -> key of the link they are reachable over.
+    remote_peers: Mutex<HashMap<PeerId, String>>,
+    next_link_id: AtomicU64,
+}
+
+impl Federation {
+    pub fn new(key: SigningKey, peers: &[FederationPeer]) -> Self {
+        Self {
+            pubkey: pubkey_b64(&key),
+            key,
+            peers: peers.iter().map(|p| (p.pubkey.clone(), p.url.clone())).collect(),
+            links: Mutex::new(HashMap::new()),
+            remote_peers: Mutex::new(HashMap::new()),
+            next_link_id: AtomicU64::new(1),
+        }
+    }
+
+    /// (peer key, number of servers it shares with us) for each open link.
+    pub fn link_summary(&self) -> Vec<(String, usize)> {
+        let links = self.links.lock().unwrap_or_else(|e| e.into_inner());
+        links.iter().map(|(k, l)| (k.clone(), l.interest.len())).collect()
+    }
+
+    fn has_link(&self, peer: &str) -> bool {
+        self.links.lock().unwrap_or_else(|e| e.into_inner()).contains_key(peer)
+    }
+
+    fn send_on(link: &Link, json: &str) {
+        if link.tx.try_send(json.to_string()).is_err() {
+            warn!("Federation link {} queue full; dropping message", link.id);
+        }
+    }
+
+    fn send_to_link(&self, peer: &str, msg: &FederationMessage) {
+        let Ok(json) = serde_json::to_string(msg) else {
+            return;
+        };
+        if let Some(link) = self.links.lock().unwrap_or_else(|e| e.into_inner()).get(peer) {
+            Self::send_on(link, &json);
+        }
+    }
+
+    /// Send to every link whose beacon has subscribers for `signing_pubkey`.
+    fn send_interested(&self, signing_pubkey: &str, msg: &FederationMessage) {
+        let links = self.links.lock().unwrap_or_else(|e| e.into_inner());
+        let mut json = None;
+        for link in links.values().filter(|l| l.interest.contains(signing_pubkey)) {
+            let json = json.get_or_insert_with(|| serde_json::to_string(msg).unwrap_or_default());
+            Self::send_on(link, json);
+        }
+    }
+
+    pub fn send_server(&self, signing_pubkey: &SigningPubkey, json: &str, coalesce_key: Option<&str>) {
+        self.send_interested(
+            signing_pubkey,
+            &FederationMessage::Server {
+                signing_pubkey: signing_pubkey.clone(),
+                json: json.to_string(),
+                coalesce_key: coalesce_key.map(str::to_string),
+            },
+        );
+    }
+
+    pub fn send_hint(&self, hint: &EncryptedServerHint) {
+        self.send_interested(&hint.signing_pubkey, &FederationMessage::Hint { hint: hint.clone() });
+    }
+
+    pub fn send_voice_joined(&self, server_id: &ServerId, chat_id: &str, signing_pubkey: &SigningPubkey, peer_id: &PeerId, user_id: &str) {
+        self.send_interested(
+            signing_pubkey,
+            &FederationMessage::VoiceJoined {
+                signing_pubkey: signing_pubkey.clone(),
+                server_id: server_id.clone(),
+                chat_id: chat_id.to_string(),
+                peer_id: peer_id.clone(),
+                user_id: user_id.to_string(),
+            },
+        );
+    }
+
+    pub fn send_voice_left(&self, server_id: &ServerId, chat_id: &str, signing_pubkey: &SigningPubkey, peer_id: &PeerId) {
+        self.send_interested(
+            signing_pubkey,
+            &FederationMessage::VoiceLeft {
+                signing_pubkey: signing_pubkey.clone(),
+                server_id: server_id.clone(),
+                chat_id: chat_id.to_string(),
+                peer_id: peer_id.clone(),
+            },
+        );
+    }
+
+    /// Send to a remote voice member. False if the peer is not reachable over a link.
+    pub fn send_peer(&self, peer_id: &PeerId, json: &str) -> bool {
+        let link = self.remote_peers.lock().unwrap_or_else(|e| e.into_inner()).get(peer_id).cloned();
+        let Some(link) = link else {
+            return false;
+        };
+        self.send_to_link(
+            &link,
+            &FederationMessage::Peer {
+                peer_id: peer_id.clone(),
+                json: json.to_string(),
+            },
+        );
+        true
+    }
+
+    /// Register an authenticated link. When both beacons dialed each other, both keep the link
+    /// dialed by the smaller key; a new link from the same dialer replaces the old one. Returns None
+    /// if this link lost the tie-break.
+    fn add_link(&self, peer: &str, dialer: &str, tx: mpsc::Sender<String>) -> Option<(u64, Arc<Notify>)> {
+        let mut links = self.links.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(existing) = links.get(peer) {
+            if existing.dialer.as_str() < dialer {
+                return None;
+            }
+            existing.closed.notify_one();
+        }
+        let id = self.next_link_id.fetch_add(1, Ordering::Relaxed);
+        let closed = Arc::new(Notify::new());
+        links.insert(
+            peer.to_string(),
+            Link {
+                id,
+                dialer: dialer.to_string(),
+                tx,
+                interest: HashSet::new(),
+                closed: closed.clone(),
+            },
+        );
+        Some((id, closed))
+    }
+
+    /// Replace a link's interest set; returns the servers that were not in it before.
+    fn set_interest(&self, peer: &str, signing_pubkeys: Vec<SigningPubkey>) -> Vec<SigningPubkey> {
+        let mut links = self.links.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(link) = links.get_mut(peer) else {
+            return Vec::new();
+        };
+        let interest: HashSet<SigningPubkey> = signing_pubkeys.into_iter().take(MAX_INTEREST).collect();
+        let added = interest.difference(&link.interest).cloned().collect();
+        link.interest = interest;
+        added
+    }
+
+    fn link_interested(&self, peer: &str, signing_pubkey: &str) -> bool {
+        self.links
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(peer)
+            .is_some_and(|l| l.interest.contains(signing_pubkey))
+    }
+}
+
+/// ConnId under which a federated beacon's voice member is kept in VoiceState.
+fn remote_conn_id(link: &str, peer_id: &PeerId) -> ConnId {
+    format!("fed:{}/{}", link, peer_id)
+}
+
+/// Text frames in and out of a link, independent of which side accepted the WebSocket.
+pub struct LinkIo {
+    pub tx: mpsc::Sender<String>,
+    pub rx: mpsc::Receiver<String>,
+}
+
+fn axum_io(socket: WebSocket) -> LinkIo {
+    let (mut sink, mut stream) = socket.split();
+    let (out_tx, mut out_rx) = mpsc::channel::<String>(LINK_QUEUE);
+    let (in_tx, in_rx) = mpsc::channel::<String>(LINK_QUEUE);
+    tokio::spawn(async move {
+        while let Some(text) = out_rx.recv().await {
+            if sink.send(AxumMessage::Text(text)).await.is_err() {
+                break;
+            }
+        }
+        let _ = sink.close().await;
+    });
+    tokio::spawn(async move {
+        while let Some(Ok(msg)) = stream.next().await {
+            let text = match msg {
+                AxumMessage::Text(text) => text,
+                AxumMessage::Close(_) => break,
+                _ => continue,
+            };
+            if text.len() > MAX_FRAME_BYTES || in_tx.send(text).await.is_err() {
+                break;
+            }
+        }
+    });
+    LinkIo { tx: out_tx, rx: in_rx }
+}
+
+fn tungstenite_io<S>(ws: tokio_tungstenite::WebSocketStream<S>) -> LinkIo
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sink, mut stream) = ws.split();
+    let (out_tx, mut out_rx) = mpsc::channel::<String>(LINK_QUEUE);
+    let (in_tx, in_rx) = mpsc::channel::<String>(LINK_QUEUE);
+    tokio::spawn(async move {
+        while let Some(text) = out_rx.recv().await {
+            if sink.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+        let _ = sink.close().await;
+    });
+    tokio::spawn(async move {
+        while let Some(Ok(msg)) = stream.next().await {
+            let text = match msg {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            if text.len() > MAX_FRAME_BYTES || in_tx.send(text).await.is_err() {
+                break;
+            }
+        }
+    });
+    LinkIo { tx: out_tx, rx: in_rx }
+}
+
+async fn recv_message(io: &mut LinkIo) -> Option<FederationMessage> {
+    let text = tokio::time::timeout(HANDSHAKE_TIMEOUT, io.rx.recv()).await.ok()??;
+    serde_json::from_str(&text).ok()
+}
+
+fn send_message(io: &LinkIo, msg: &FederationMessage) {
+    if let Ok(json) = serde_json::to_string(msg) {
+        let _ = io.tx.try_send(json);
+    }
+}
+
+/// Dialing side of the handshake: prove our key, check the peer proves `peer`. Returns the link
+/// if both succeed.
+pub async fn dial_handshake(federation: &Federation, peer: &str, mut io: LinkIo) -> Option<LinkIo> {
+    let mut nonce = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let nonce = hex::encode(nonce);
+    let ts = chrono::Utc::now().timestamp();
+    send_message(
+        &io,
+        &FederationMessage::Hello {
+            pubkey: federation.pubkey.clone(),
+            target: peer.to_string(),
+            ts,
+            nonce: nonce.clone(),
+            signature: sign_b64(&federation.key, &hello_message(&federation.pubkey, peer, ts, &nonce)),
+        },
+    );
+    match recv_message(&mut io).await? {
+        FederationMessage::Welcome { pubkey, signature }
+            if pubkey == peer
+                && verify_ed25519_b64(peer, welcome_message(peer, &federation.pubkey, &nonce).as_bytes(), &signature) =>
+        {
+            Some(io)
+        }
+        _ => {
+            warn!("Federation peer {} failed the handshake", peer);
+            None
+        }
+    }
+}
+
+/// Accepting side of the handshake. Returns the peer key and the link if the peer is allow-listed
+/// and proved its key.
+pub async fn accept_handshake(state: &AppState, federation: &Federation, mut io: LinkIo) -> Option<(String, LinkIo)> {
+    let FederationMessage::Hello { pubkey, target, ts, nonce, signature } = recv_message(&mut io).await? else {
+        return None;
+    };
+    let now = chrono::Utc::now().timestamp();
+    if !federation.peers.contains_key(&pubkey) {
+        warn!("Federation link from unknown beacon {} refused", pubkey);
+        return None;
+    }
+    if target != federation.pubkey
+        || (ts - now).abs() > SIGNED_REQUEST_MAX_SKEW_SECS
+        || !verify_ed25519_b64(&pubkey, hello_message(&pubkey, &target, ts, &nonce).as_bytes(), &signature)
+    {
+        warn!("Federation link from {} failed the handshake", pubkey);
+        return None;
+    }
+    if let Err(refused) = state
+        .replay_cache
+        .lock()
+        .await
+        .check_and_insert(&signature, ts + SIGNED_REQUEST_MAX_SKEW_SECS, now)
+    {
+        warn!("Federation hello from {} refused: {:?}", pubkey, refused);
+        return None;
+    }
+    send_message(
+        &io,
+        &FederationMessage::Welcome {
+            pubkey: federation.pubkey.clone(),
+            signature: sign_b64(&federation.key, &welcome_message(&federation.pubkey, &pubkey, &nonce)),
+        },
+    );
+    Some((pubkey, io))
+}
+
+/// Servers with subscribers on this beacon.
+async fn local_interest(state: &AppState) -> Vec<SigningPubkey> {
+    let signaling = state.signaling.read().await;
+    signaling
+        .signing_servers
+        .iter()
+        .filter(|(spk, peers)| spk.as_str() != FRIENDS_SIGNING_PUBKEY && !peers.is_empty())
+        .map(|(spk, _)| spk.clone())
+        .take(MAX_INTEREST)
+        .collect()
+}
+
+/// Run an authenticated link until either side closes it.
+pub async fn run_link(state: Arc<AppState>, federation: Arc<Federation>, peer: String, dialer: String, mut io: LinkIo) {
+    let Some((link_id, closed)) = federation.add_link(&peer, &dialer, io.tx.clone()) else {
+        return;
+    };
+    info!("Federation link to {} up", peer);
+
+    let mut interest_tick = tokio::time::interval(INTEREST_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interest_tick.tick() => {
+                let signing_pubkeys = local_interest(&state).await;
+                federation.send_to_link(&peer, &FederationMessage::Interest { signing_pubkeys });
+            }
+            text = io.rx.recv() => {
+                let Some(text) = text else { break };
+                match serde_json::from_str::<FederationMessage>(&text) {
+                    Ok(msg) => handle(&state, &federation, &peer, msg).await,
+                    Err(e) => warn!("Bad federation message from {}: {}", peer, e),
+                }
+            }
+            _ = closed.notified() => break,
+        }
+    }
+
+    {
+        let mut links = federation.links.lock().unwrap_or_else(|e| e.into_inner());
+        if links.get(&peer).is_some_and(|l| l.id == link_id) {
+            links.remove(&peer);
+        } else {
+            // Replaced by another link to the same beacon; its members stay.
+            return;
+        }
+    }
+    info!("Federation link to {} down", peer);
+    let gone: Vec<PeerId> = {
+        let mut remote = federation.remote_peers.lock().unwrap_or_else(|e| e.into_inner());
+        let gone: Vec<PeerId> = remote.iter().filter(|(_, l)| **l == peer).map(|(p, _)| p.clone()).collect();
+        for p in &gone {
+            remote.remove(p);
+        }
+        gone
+    };
+    for peer_id in gone {
+        let removed = state.voice.write().await.handle_voice_disconnect(&remote_conn_id(&peer, &peer_id));
+        for (server_id, chat_id, peer_id, user_id) in removed {
+            deliver_voice_left(&state, &server_id, &chat_id, peer_id, user_id).await;
+        }
+    }
+}
+
+async fn deliver_voice_left(state: &AppState, server_id: &ServerId, chat_id: &str, peer_id: PeerId, user_id: String) {
+    let msg = SignalingMessage::VoicePeerLeft {
+        peer_id,
+        user_id,
+        chat_id: chat_id.to_string(),
+    };
+    if let Ok(json) = serde_json::to_string(&msg) {
+        state.send_to_voice_room_local(server_id, chat_id, &json, None).await;
+    }
+}
+
+/// Server broadcasts a federated beacon may deliver: the kinds AppState::broadcast_to_server and
+/// broadcast_to_chat send, for the server they claim to be for.
+fn relayable_server_message(json: &str, signing_pubkey: &str) -> Option<SignalingMessage> {
+    let msg = serde_json::from_str::<SignalingMessage>(json).ok()?;
+    match &msg {
+        SignalingMessage::PresenceUpdate { signing_pubkey: spk, .. }
+        | SignalingMessage::ProfileUpdate { signing_pubkey: spk, .. }
+        | SignalingMessage::VoicePresenceUpdate { signing_pubkey: spk, .. } if spk == signing_pubkey => Some(msg),
+        _ => match chat_route(&msg) {
+            Some((spk, _)) if spk == signing_pubkey => Some(msg),
+            _ => None,
+        },
+    }
+}
+
+async fn handle(state: &AppState, federation: &Federation, peer: &str, msg: FederationMessage) {
+    match msg {
+        FederationMessage::Hello { .. } | FederationMessage::Welcome { .. } => {}
+        FederationMessage::Interest { signing_pubkeys } => {
+            let added = federation.set_interest(peer, signing_pubkeys);
+            send_snapshot(state, federation, peer, &added).await;
+        }
+        FederationMessage::Hint { hint } => {
+            let signing_pubkey = hint.signing_pubkey.clone();
+            if !state.signaling.read().await.signing_servers.contains_key(&signing_pubkey) {
+                return;
+            }
+            // The hint is signed by the server's keys; the link only carries it.
+            match store_server_hint(state, &signing_pubkey, &hint).await {
+                Ok(stored) => state.deliver_server_hint_updated(&signing_pubkey, &stored).await,
+                Err((StatusCode::CONFLICT, _)) => {}
+                Err((_, reason)) => warn!("Federated hint from {} rejected: {}", peer, reason),
+            }
+        }
+        FederationMessage::Server { signing_pubkey, json, coalesce_key } => {
+            match relayable_server_message(&json, &signing_pubkey) {
+                Some(msg) if chat_route(&msg).is_some() => state.deliver_to_chat(&msg, json, None).await,
+                Some(_) => state.deliver_to_server(&signing_pubkey, json, coalesce_key, None).await,
+                None => {}
+            }
+        }
+        FederationMessage::VoiceJoined { signing_pubkey, server_id, chat_id, peer_id, user_id } => {
+            if state.signaling.read().await.peer_senders.contains_key(&peer_id) {
+                return;
+            }
+            {
+                let mut voice = state.voice.write().await;
+                // Never let a link attach members to a voice chat of another server.
+                if voice
+                    .server_signing_pubkeys
+                    .get(&server_id)
+                    .is_some_and(|spk| *spk != signing_pubkey)
+                {
+                    return;
+                }
+                voice.server_signing_pubkeys.insert(server_id.clone(), signing_pubkey);
+                voice.register_voice_peer(
+                    peer_id.clone(),
+                    user_id.clone(),
+                    server_id.clone(),
+                    chat_id.clone(),
+                    remote_conn_id(peer, &peer_id),
+                );
+            }
+            federation
+                .remote_peers
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(peer_id.clone(), peer.to_string());
+            let msg = SignalingMessage::VoicePeerJoined {
+                peer_id: peer_id.clone(),
+                user_id,
+                chat_id: chat_id.clone(),
+            };
+            if let Ok(json) = serde_json::to_string(&msg) {
+                state.send_to_voice_room_local(&server_id, &chat_id, &json, Some(&peer_id)).await;
+            }
+        }
+        FederationMessage::VoiceLeft { signing_pubkey, server_id, chat_id, peer_id } => {
+            {
+                let mut remote = federation.remote_peers.lock().unwrap_or_else(|e| e.into_inner());
+                if remote.get(&peer_id).map(String::as_str) != Some(peer) {
+                    return;
+                }
+                remote.remove(&peer_id);
+            }
+            let removed = {
+                let mut voice = state.voice.write().await;
+                if voice.server_signing_pubkeys.get(&server_id) != Some(&signing_pubkey) {
+                    return;
+                }
+                voice.unregister_voice_peer(&peer_id, &server_id, &chat_id)
+            };
+            if let Some(user_id) = removed {
+                deliver_voice_left(state, &server_id, &chat_id, peer_id, user_id).await;
+            }
+        }
+        FederationMessage::Peer { peer_id, json } => {
+            // Only voice signaling from a member that came over this link, to the peer it names.
+            let from_ok = |from: &PeerId, to: &PeerId| {
+                to == &peer_id
+                    && federation
+                        .remote_peers
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .get(from)
+                        .is_some_and(|l| l == peer)
+            };
+            let allowed = match serde_json::from_str::<SignalingMessage>(&json) {
+                Ok(SignalingMessage::VoiceOffer { from_peer, to_peer, .. })
+                | Ok(SignalingMessage::VoiceAnswer { from_peer, to_peer, .. })
+                | Ok(SignalingMessage::VoiceIceCandidate { from_peer, to_peer, .. }) => from_ok(&from_peer, &to_peer),
+                _ => false,
+            };
+            if allowed {
+                if let Some(sender) = state.signaling.read().await.peer_senders.get(&peer_id) {
+                    let _ = sender.send(Message::Text(json));
+                }
+            }
+        }
+    }
+}
+
+/// A peer beacon just became interested in `signing_pubkeys`: send the stored hints and the local
+/// voice members for them.
+async fn send_snapshot(state: &AppState, federation: &Federation, peer: &str, signing_pubkeys: &[SigningPubkey]) {
+    if signing_pubkeys.is_empty() {
+        return;
+    }
+    for spk in signing_pubkeys {
+        if let Some(hint) = load_server_hint(state, spk).await {
+            federation.send_to_link(peer, &FederationMessage::Hint { hint });
+        }
+    }
+    let wanted: HashSet<&SigningPubkey> = signing_pubkeys.iter().collect();
+    let mut members = Vec::new();
+    {
+        let voice = state.voice.read().await;
+        for ((server_id, chat_id), peers) in &voice.voice_chats {
+            let Some(spk) = voice.server_signing_pubkeys.get(server_id).filter(|s| wanted.contains(s)) else {
+                continue;
+            };
+            for p in peers.iter().filter(|p| !p.conn_id.starts_with("fed:")) {
+                members.push(FederationMessage::VoiceJoined {
+                    signing_pubkey: spk.clone(),
+                    server_id: server_id.clone(),
+                    chat_id: chat_id.clone(),
+                    peer_id: p.peer_id.clone(),
+                    user_id: p.user_id.clone(),
+                });
+            }
+        }
+    }
+    for msg in members {
+        federation.send_to_link(peer, &msg);
+    }
+}
+
+/// Keep a link to a peer we have a URL for: dial, and re-dial with backoff when it drops.
+async fn dial_loop(state: Arc<AppState>, federation: Arc<Federation>, peer: String, url: String) {
+    let mut backoff = Duration::from_secs(2);
+    loop {
+        if !federation.has_link(&peer) {
+            match tokio_tungstenite::connect_async(url.as_str()).await {
+                Ok((ws, _)) => {
+                    backoff = Duration::from_secs(2);
+                    if let Some(io) = dial_handshake(&federation, &peer, tungstenite_io(ws)).await {
+                        let dialer = federation.pubkey.clone();
+                        run_link(state.clone(), federation.clone(), peer.clone(), dialer, io).await;
+                    }
+                }
+                Err(e) => warn!("Federation dial to {} failed: {}", url, e),
+            }
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(Duration::from_secs(60));
+    }
+}
+
+/// GET /federation: WebSocket endpoint other beacons dial. 404 unless federation is configured.
+pub async fn federation_ws_handler(ws: WebSocketUpgrade, State(state): State<Arc<AppState>>) -> axum::response::Response {
+    let Some(federation) = state.federation.get().cloned() else {
+        return (StatusCode::NOT_FOUND, "Federation not enabled").into_response();
+    };
+    ws.max_message_size(MAX_FRAME_BYTES)
+        .on_upgrade(move |socket| async move {
+            if let Some((peer, io)) = accept_handshake(&state, &federation, axum_io(socket)).await {
+                let dialer = peer.clone();
+                run_link(state, federation, peer, dialer, io).await;
+            }
+        })
+        .into_response()
+}
+
+/// Enable federation: load the identity key, set `state.federation` and dial the peers with a URL.
+pub fn start(state: Arc<AppState>, settings: FederationSettings) {
+    let key = match load_or_create_key(&settings.key_path) {
+        Ok(key) => key,
+        Err(e) => {
+            warn!("Federation disabled: cannot load key {}: {}", settings.key_path.display(), e);
+            return;
+        }
+    };
+    let federation = Arc::new(Federation::new(key, &settings.peers));
+    if state.federation.set(federation.clone()).is_err() {
+        return;
+    }
+    info!("Federation enabled; this beacon's key is {}", federation.pubkey);
+    for peer in settings.peers {
+        if let Some(url) = peer.url {
+            tokio::spawn(dial_loop(state.clone(), federation.clone(), peer.pubkey, url));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outbound::{channel, OutboundLimits};
+    use crate::security::{ConnectionTracker, ReplayCache, SecurityConfig, WsHeartbeat, WsRateLimits};
+    use tokio::sync::RwLock;
+
+    fn beacon() -> Arc<AppState> {
+        let config = SecurityConfig::from_env();
+        Arc::new(AppState::new(
+            None,
+            Arc::new(RwLock::new(ConnectionTracker::new(0, 0))),
+            Arc::new(WsRateLimits::from_config(&config)),
+            Arc::new(tokio::sync::Mutex::new(ReplayCache::new(16))),
+            Arc::new(OutboundLimits::new(16, Duration::from_secs(10))),
+            WsHeartbeat::default(),
+            Duration::ZERO,
+        ))
+    }
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    #[tokio::test]
+    async fn linked_beacons_relay_server_broadcasts_and_refuse_unknown_keys() {
+        let (a, b) = (beacon(), beacon());
+        let (key_a, key_b) = (key(1), key(2));
+        let (pub_a, pub_b) = (pubkey_b64(&key_a), pubkey_b64(&key_b));
+        let fed_a = Arc::new(Federation::new(key_a, &[FederationPeer { pubkey: pub_b.clone(), url: None }]));
+        let fed_b = Arc::new(Federation::new(key_b, &[FederationPeer { pubkey: pub_a.clone(), url: None }]));
+        let _ = a.federation.set(fed_a.clone());
+        let _ = b.federation.set(fed_b.clone());
+
+        // A client on B subscribed to the server.
+        let (tx, mut rx) = channel(b.outbound.clone());
+        {
+            let mut signaling = b.signaling.write().await;
+            signaling.register_peer("peer-b".to_string(), "srv".to_string(), Some("spk".to_string()), "conn-b".to_string());
+            signaling.peer_senders.insert("peer-b".to_string(), tx);
+        }
+
+        let (a_out, b_in) = mpsc::channel(16);
+        let (b_out, a_in) = mpsc::channel(16);
+        let io_a = LinkIo { tx: a_out, rx: a_in };
+        let io_b = LinkIo { tx: b_out, rx: b_in };
+        let accept = tokio::spawn({
+            let (b, fed_b) = (b.clone(), fed_b.clone());
+            async move { accept_handshake(&b, &fed_b, io_b).await }
+        });
+        let io_a = dial_handshake(&fed_a, &pub_b, io_a).await.expect("dialer handshake");
+        let (peer, io_b) = accept.await.unwrap().expect("listener handshake");
+        assert_eq!(peer, pub_a);
+        tokio::spawn(run_link(a.clone(), fed_a.clone(), pub_b.clone(), pub_a.clone(), io_a));
+        tokio::spawn(run_link(b.clone(), fed_b.clone(), pub_a.clone(), pub_a.clone(), io_b));
+
+        // B announces its interest when the link comes up.
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while !fed_a.link_interested(&pub_b, "spk") {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        a.broadcast_presence_update(&"spk".to_string(), "user-a", true, None).await;
+        let got = tokio::time::timeout(Duration::from_secs(2), rx.recv()).await.unwrap().unwrap();
+        assert!(got.into_text().unwrap().contains("\"user_id\":\"user-a\""));
+
+        // A beacon that is not allow-listed cannot link.
+        let stranger = Federation::new(key(3), &[]);
+        let (s_out, b_in) = mpsc::channel(16);
+        let (b_out, s_in) = mpsc::channel(16);
+        let (accepted, dialed) = tokio::join!(
+            accept_handshake(&b, &fed_b, LinkIo { tx: b_out, rx: b_in }),
+            dial_handshake(&stranger, &pub_b, LinkIo { tx: s_out, rx: s_in }),
+        );
+        assert!(accepted.is_none() && dialed.is_none());
+    }
+}
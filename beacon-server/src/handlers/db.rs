@@ -4,6 +4,10 @@ use chrono::{DateTime, Duration, Utc};
 use sqlx::{PgPool, Row};
 #[cfg(feature = "postgres")]
 use crate::{ProfileRecord, ProfileSnapshotRecord, EncryptedServerHint, InviteTokenCreateRequest, InviteTokenRecord, ServerEvent};
+#[cfg(feature = "postgres")]
+use crate::state::events::{check_cursor, event_bytes, CursorTooOld, EventLimits, QuotaExceeded};
+#[cfg(feature = "postgres")]
+use crate::state::mailbox::MailboxLimits;
 
 #[cfg(feature = "postgres")]
 pub async fn init_db(pool: &PgPool) -> Result<(), String> {
@@ -37,6 +41,19 @@ pub async fn init_db(pool: &PgPool) -> Result<(), String> {
     .await
     .map_err(|e| format!("init_db server_hints: {}", e))?;
 
+    sqlx::query(
+        r#"
+        ALTER TABLE server_hints
+          ADD COLUMN IF NOT EXISTS signer_pubkey TEXT,
+          ADD COLUMN IF NOT EXISTS delegate_pubkey TEXT,
+          ADD COLUMN IF NOT EXISTS admin_pubkeys TEXT[] NOT NULL DEFAULT '{}',
+          ADD COLUMN IF NOT EXISTS key_envelopes TEXT NOT NULL DEFAULT '[]';
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| format!("init_db server_hints columns: {}", e))?;
+
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS invite_tokens (
@@ -71,6 +88,56 @@ pub async fn init_db(pool: &PgPool) -> Result<(), String> {
     .await
     .map_err(|e| format!("init_db server_events: {}", e))?;
 
+    // Per-server event sequence: seq column plus a counter row per server, bumped in the same
+    // transaction as the insert so seqs become visible in order. Rows from before seqs existed
+    // are numbered once, by timestamp.
+    sqlx::query("ALTER TABLE server_events ADD COLUMN IF NOT EXISTS seq BIGINT")
+        .execute(pool)
+        .await
+        .map_err(|e| format!("init_db server_events.seq: {}", e))?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS server_event_seqs (
+          signing_pubkey TEXT PRIMARY KEY,
+          last_seq BIGINT NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| format!("init_db server_event_seqs: {}", e))?;
+    sqlx::query(
+        r#"
+        WITH numbered AS (
+          SELECT event_id,
+                 ROW_NUMBER() OVER (PARTITION BY signing_pubkey ORDER BY timestamp, event_id) AS n
+          FROM server_events
+          WHERE seq IS NULL
+        )
+        UPDATE server_events e SET seq = numbered.n
+        FROM numbered
+        WHERE e.event_id = numbered.event_id
+          AND NOT EXISTS (SELECT 1 FROM server_event_seqs s WHERE s.signing_pubkey = e.signing_pubkey);
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| format!("init_db backfill seq: {}", e))?;
+    sqlx::query(
+        r#"
+        INSERT INTO server_event_seqs (signing_pubkey, last_seq)
+        SELECT signing_pubkey, MAX(seq) FROM server_events WHERE seq IS NOT NULL GROUP BY signing_pubkey
+        ON CONFLICT (signing_pubkey) DO NOTHING;
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| format!("init_db seed server_event_seqs: {}", e))?;
+    sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS server_events_seq_idx ON server_events (signing_pubkey, seq)")
+        .execute(pool)
+        .await
+        .map_err(|e| format!("init_db server_events_seq_idx: {}", e))?;
+
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS member_acks (
@@ -85,6 +152,40 @@ pub async fn init_db(pool: &PgPool) -> Result<(), String> {
     .execute(pool)
     .await
     .map_err(|e| format!("init_db member_acks: {}", e))?;
+    sqlx::query("ALTER TABLE member_acks ADD COLUMN IF NOT EXISTS last_seq BIGINT")
+        .execute(pool)
+        .await
+        .map_err(|e| format!("init_db member_acks.last_seq: {}", e))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS mailbox_opt_in (
+          user_id TEXT PRIMARY KEY,
+          updated_at TIMESTAMPTZ NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| format!("init_db mailbox_opt_in: {}", e))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS mailbox (
+          id BIGSERIAL PRIMARY KEY,
+          user_id TEXT NOT NULL,
+          envelope TEXT NOT NULL,
+          expires_at TIMESTAMPTZ NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| format!("init_db mailbox: {}", e))?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS mailbox_user_idx ON mailbox (user_id, id)")
+        .execute(pool)
+        .await
+        .map_err(|e| format!("init_db mailbox_user_idx: {}", e))?;
     Ok(())
 }
 
@@ -149,32 +250,44 @@ pub async fn load_profiles_db(pool: &PgPool, user_ids: &[String]) -> Result<Vec<
 }
 
 #[cfg(feature = "postgres")]
-pub async fn upsert_server_hint_db(pool: &PgPool, hint: &EncryptedServerHint) -> Result<(), String> {
-    sqlx::query(
+/// Returns false when the stored hint is newer, or is a different hint with the same last_updated
+/// (the write is refused, nothing changes).
+pub async fn upsert_server_hint_db(pool: &PgPool, hint: &EncryptedServerHint) -> Result<bool, String> {
+    let result = sqlx::query(
         r#"
-        INSERT INTO server_hints (signing_pubkey, encrypted_state, signature, last_updated)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO server_hints (signing_pubkey, encrypted_state, signature, last_updated, signer_pubkey, delegate_pubkey, admin_pubkeys, key_envelopes)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
         ON CONFLICT (signing_pubkey) DO UPDATE
         SET encrypted_state = EXCLUDED.encrypted_state,
             signature = EXCLUDED.signature,
-            last_updated = EXCLUDED.last_updated;
+            last_updated = EXCLUDED.last_updated,
+            signer_pubkey = EXCLUDED.signer_pubkey,
+            delegate_pubkey = EXCLUDED.delegate_pubkey,
+            admin_pubkeys = EXCLUDED.admin_pubkeys,
+            key_envelopes = EXCLUDED.key_envelopes
+        WHERE server_hints.last_updated < EXCLUDED.last_updated
+           OR (server_hints.last_updated = EXCLUDED.last_updated AND server_hints.signature = EXCLUDED.signature);
         "#,
     )
     .bind(&hint.signing_pubkey)
     .bind(&hint.encrypted_state)
     .bind(&hint.signature)
     .bind(hint.last_updated)
+    .bind(&hint.signer_pubkey)
+    .bind(&hint.delegate_pubkey)
+    .bind(&hint.admin_pubkeys)
+    .bind(serde_json::to_string(&hint.key_envelopes).map_err(|e| format!("upsert_server_hint_db key_envelopes: {}", e))?)
     .execute(pool)
     .await
     .map_err(|e| format!("upsert_server_hint_db: {}", e))?;
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
 
 #[cfg(feature = "postgres")]
 pub async fn get_server_hint_db(pool: &PgPool, signing_pubkey: &str) -> Result<Option<EncryptedServerHint>, String> {
     let row = sqlx::query(
         r#"
-        SELECT signing_pubkey, encrypted_state, signature, last_updated
+        SELECT signing_pubkey, encrypted_state, signature, last_updated, signer_pubkey, delegate_pubkey, admin_pubkeys, key_envelopes
         FROM server_hints
         WHERE signing_pubkey = $1
         "#,
@@ -189,6 +302,14 @@ pub async fn get_server_hint_db(pool: &PgPool, signing_pubkey: &str) -> Result<O
         encrypted_state: r.try_get("encrypted_state").unwrap_or_default(),
         signature: r.try_get("signature").unwrap_or_default(),
         last_updated: r.try_get("last_updated").unwrap_or_else(|_| Utc::now()),
+        signer_pubkey: r.try_get("signer_pubkey").unwrap_or(None),
+        delegate_pubkey: r.try_get("delegate_pubkey").unwrap_or(None),
+        admin_pubkeys: r.try_get("admin_pubkeys").unwrap_or_default(),
+        key_envelopes: r
+            .try_get::<String, _>("key_envelopes")
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default(),
     }))
 }
 
@@ -212,18 +333,18 @@ pub async fn upsert_invite_db(pool: &PgPool, signing_pubkey: &str, req: InviteTo
     let max_uses = req.max_uses;
     let remaining_uses = req.max_uses;
 
-    sqlx::query(
+    let res = sqlx::query(
         r#"
         INSERT INTO invite_tokens (code, signing_pubkey, encrypted_payload, signature, created_at, expires_at, max_uses, remaining_uses)
         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
         ON CONFLICT (code) DO UPDATE
-        SET signing_pubkey = EXCLUDED.signing_pubkey,
-            encrypted_payload = EXCLUDED.encrypted_payload,
+        SET encrypted_payload = EXCLUDED.encrypted_payload,
             signature = EXCLUDED.signature,
             created_at = EXCLUDED.created_at,
             expires_at = EXCLUDED.expires_at,
             max_uses = EXCLUDED.max_uses,
-            remaining_uses = EXCLUDED.remaining_uses;
+            remaining_uses = EXCLUDED.remaining_uses
+        WHERE invite_tokens.signing_pubkey = EXCLUDED.signing_pubkey;
         "#,
     )
     .bind(&code)
@@ -237,6 +358,9 @@ pub async fn upsert_invite_db(pool: &PgPool, signing_pubkey: &str, req: InviteTo
     .execute(pool)
     .await
     .map_err(|e| format!("upsert_invite_db: {}", e))?;
+    if res.rows_affected() == 0 {
+        return Err("Invite code already in use".to_string());
+    }
 
     Ok(InviteTokenRecord {
         code,
@@ -276,6 +400,36 @@ pub async fn get_invite_db(pool: &PgPool, code: &str) -> Result<Option<InviteTok
     }))
 }
 
+#[cfg(feature = "postgres")]
+pub async fn list_invites_db(pool: &PgPool, signing_pubkey: &str) -> Result<Vec<InviteTokenRecord>, String> {
+    let rows = sqlx::query(
+        r#"
+        SELECT code, signing_pubkey, encrypted_payload, signature, created_at, expires_at, max_uses, remaining_uses
+        FROM invite_tokens
+        WHERE signing_pubkey = $1 AND expires_at > NOW()
+        ORDER BY created_at
+        "#,
+    )
+    .bind(signing_pubkey)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("list_invites_db: {}", e))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| InviteTokenRecord {
+            code: r.try_get("code").unwrap_or_default(),
+            signing_pubkey: r.try_get("signing_pubkey").unwrap_or_default(),
+            encrypted_payload: r.try_get("encrypted_payload").unwrap_or_default(),
+            signature: r.try_get("signature").unwrap_or_default(),
+            created_at: r.try_get("created_at").unwrap_or_else(|_| Utc::now()),
+            expires_at: r.try_get("expires_at").unwrap_or_else(|_| Utc::now()),
+            max_uses: r.try_get::<i32, _>("max_uses").unwrap_or(0) as u32,
+            remaining_uses: r.try_get::<i32, _>("remaining_uses").unwrap_or(0) as u32,
+        })
+        .collect())
+}
+
 #[cfg(feature = "postgres")]
 pub async fn redeem_invite_db(pool: &PgPool, code: &str) -> Result<Option<InviteTokenRecord>, String> {
     let row = sqlx::query(
@@ -306,21 +460,49 @@ pub async fn redeem_invite_db(pool: &PgPool, code: &str) -> Result<Option<Invite
 }
 
 #[cfg(feature = "postgres")]
-pub async fn revoke_invite_db(pool: &PgPool, code: &str) -> Result<bool, String> {
-    let res = sqlx::query("DELETE FROM invite_tokens WHERE code = $1")
+pub async fn revoke_invite_db(pool: &PgPool, signing_pubkey: &str, code: &str) -> Result<bool, String> {
+    let res = sqlx::query("DELETE FROM invite_tokens WHERE code = $1 AND signing_pubkey = $2")
         .bind(code)
+        .bind(signing_pubkey)
         .execute(pool)
         .await
         .map_err(|e| format!("revoke_invite_db: {}", e))?;
     Ok(res.rows_affected() > 0)
 }
 
+/// Store an event under the server's next seq (returned). Duplicate event_ids are ignored (None).
+/// A full queue is compacted first; if it is still full the event is refused.
 #[cfg(feature = "postgres")]
-pub async fn insert_event_db(pool: &PgPool, event: &ServerEvent) -> Result<(), String> {
-    sqlx::query(
+pub async fn insert_event_db(
+    pool: &PgPool,
+    event: &ServerEvent,
+    limits: &EventLimits,
+) -> Result<Result<Option<u64>, QuotaExceeded>, String> {
+    let mut tx = pool.begin().await.map_err(|e| format!("insert_event_db begin: {}", e))?;
+    // The counter row lock serializes inserts per server until commit.
+    let seq: i64 = sqlx::query_scalar(
+        r#"
+        INSERT INTO server_event_seqs (signing_pubkey, last_seq)
+        VALUES ($1, 1)
+        ON CONFLICT (signing_pubkey) DO UPDATE SET last_seq = server_event_seqs.last_seq + 1
+        RETURNING last_seq;
+        "#,
+    )
+    .bind(&event.signing_pubkey)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| format!("insert_event_db seq: {}", e))?;
+    if event_quota_db(&mut tx, &event.signing_pubkey, limits, event).await?.is_err() {
+        compact_events_db(&mut *tx, Some(&event.signing_pubkey)).await?;
+        if let Err(quota) = event_quota_db(&mut tx, &event.signing_pubkey, limits, event).await? {
+            tx.rollback().await.map_err(|e| format!("insert_event_db rollback: {}", e))?;
+            return Ok(Err(quota));
+        }
+    }
+    let res = sqlx::query(
         r#"
-        INSERT INTO server_events (event_id, signing_pubkey, event_type, encrypted_payload, signature, timestamp)
-        VALUES ($1, $2, $3, $4, $5, $6)
+        INSERT INTO server_events (event_id, signing_pubkey, event_type, encrypted_payload, signature, timestamp, seq)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
         ON CONFLICT (event_id) DO NOTHING;
         "#,
     )
@@ -330,17 +512,93 @@ pub async fn insert_event_db(pool: &PgPool, event: &ServerEvent) -> Result<(), S
     .bind(&event.encrypted_payload)
     .bind(&event.signature)
     .bind(event.timestamp)
-    .execute(pool)
+    .bind(seq)
+    .execute(&mut *tx)
     .await
     .map_err(|e| format!("insert_event_db: {}", e))?;
-    Ok(())
+    if res.rows_affected() == 0 {
+        // Leave the counter untouched rather than burning a seq.
+        tx.rollback().await.map_err(|e| format!("insert_event_db rollback: {}", e))?;
+        return Ok(Ok(None));
+    }
+    tx.commit().await.map_err(|e| format!("insert_event_db commit: {}", e))?;
+    Ok(Ok(Some(seq as u64)))
 }
 
 #[cfg(feature = "postgres")]
-async fn get_event_timestamp_db(pool: &PgPool, signing_pubkey: &str, event_id: &str) -> Result<Option<DateTime<Utc>>, String> {
+async fn event_quota_db(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    signing_pubkey: &str,
+    limits: &EventLimits,
+    event: &ServerEvent,
+) -> Result<Result<(), QuotaExceeded>, String> {
     let row = sqlx::query(
         r#"
-        SELECT timestamp
+        SELECT COUNT(*) AS count,
+               COALESCE(SUM(octet_length(event_id) + octet_length(event_type)
+                          + octet_length(encrypted_payload) + octet_length(signature)), 0)::BIGINT AS bytes
+        FROM server_events
+        WHERE signing_pubkey = $1
+        "#,
+    )
+    .bind(signing_pubkey)
+    .fetch_one(&mut **tx)
+    .await
+    .map_err(|e| format!("event_quota_db: {}", e))?;
+    let count: i64 = row.try_get("count").unwrap_or(0);
+    let bytes: i64 = row.try_get("bytes").unwrap_or(0);
+    Ok(limits.check(count as usize, bytes as usize, event_bytes(event)))
+}
+
+/// Delete events every known member (anyone with an ack row, see register_member_db) acked, for one server or all.
+#[cfg(feature = "postgres")]
+pub async fn compact_events_db<'e, E>(executor: E, signing_pubkey: Option<&str>) -> Result<u64, String>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let res = sqlx::query(
+        r#"
+        DELETE FROM server_events e
+        USING (
+          SELECT signing_pubkey, MIN(COALESCE(last_seq, 0)) AS acked
+          FROM member_acks
+          GROUP BY signing_pubkey
+        ) a
+        WHERE e.signing_pubkey = a.signing_pubkey
+          AND e.seq <= a.acked
+          AND ($1::TEXT IS NULL OR e.signing_pubkey = $1)
+        "#,
+    )
+    .bind(signing_pubkey)
+    .execute(executor)
+    .await
+    .map_err(|e| format!("compact_events_db: {}", e))?;
+    Ok(res.rows_affected())
+}
+
+/// (oldest stored seq, last assigned seq) for a server.
+#[cfg(feature = "postgres")]
+async fn event_seq_bounds_db(pool: &PgPool, signing_pubkey: &str) -> Result<(Option<u64>, u64), String> {
+    let row = sqlx::query(
+        r#"
+        SELECT (SELECT MIN(seq) FROM server_events WHERE signing_pubkey = $1) AS oldest,
+               (SELECT last_seq FROM server_event_seqs WHERE signing_pubkey = $1) AS latest
+        "#,
+    )
+    .bind(signing_pubkey)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| format!("event_seq_bounds_db: {}", e))?;
+    let oldest: Option<i64> = row.try_get("oldest").unwrap_or(None);
+    let latest: Option<i64> = row.try_get("latest").unwrap_or(None);
+    Ok((oldest.map(|s| s as u64), latest.unwrap_or(0) as u64))
+}
+
+#[cfg(feature = "postgres")]
+async fn get_event_seq_db(pool: &PgPool, signing_pubkey: &str, event_id: &str) -> Result<Option<u64>, String> {
+    let seq: Option<i64> = sqlx::query_scalar(
+        r#"
+        SELECT seq
         FROM server_events
         WHERE signing_pubkey = $1 AND event_id = $2
         "#,
@@ -349,46 +607,53 @@ async fn get_event_timestamp_db(pool: &PgPool, signing_pubkey: &str, event_id: &
     .bind(event_id)
     .fetch_optional(pool)
     .await
-    .map_err(|e| format!("get_event_timestamp_db: {}", e))?;
-
-    Ok(row.and_then(|r| r.try_get("timestamp").ok()))
+    .map_err(|e| format!("get_event_seq_db: {}", e))?
+    .flatten();
+    Ok(seq.map(|s| s as u64))
 }
 
+/// Up to `limit` events after a cursor: `after_seq`, or the legacy `since` event_id (None = from the start).
 #[cfg(feature = "postgres")]
-pub async fn get_events_db(pool: &PgPool, signing_pubkey: &str, since: Option<&str>) -> Result<Vec<ServerEvent>, String> {
-    let rows = if let Some(since_id) = since {
-        let Some(since_ts) = get_event_timestamp_db(pool, signing_pubkey, since_id).await? else {
-            return Ok(Vec::new());
-        };
-        sqlx::query(
-            r#"
-            SELECT event_id, signing_pubkey, event_type, encrypted_payload, signature, timestamp
-            FROM server_events
-            WHERE signing_pubkey = $1
-              AND (timestamp > $2 OR (timestamp = $2 AND event_id > $3))
-            ORDER BY timestamp ASC, event_id ASC
-            "#,
-        )
-        .bind(signing_pubkey)
-        .bind(since_ts)
-        .bind(since_id)
-        .fetch_all(pool)
-        .await
-        .map_err(|e| format!("get_events_db since: {}", e))?
-    } else {
-        sqlx::query(
-            r#"
-            SELECT event_id, signing_pubkey, event_type, encrypted_payload, signature, timestamp
-            FROM server_events
-            WHERE signing_pubkey = $1
-            ORDER BY timestamp ASC, event_id ASC
-            "#,
-        )
-        .bind(signing_pubkey)
-        .fetch_all(pool)
-        .await
-        .map_err(|e| format!("get_events_db: {}", e))?
+pub async fn get_events_db(
+    pool: &PgPool,
+    signing_pubkey: &str,
+    after_seq: Option<u64>,
+    since: Option<&str>,
+    limit: usize,
+) -> Result<Result<Vec<ServerEvent>, CursorTooOld>, String> {
+    let (oldest, latest) = event_seq_bounds_db(pool, signing_pubkey).await?;
+    let after_seq = match (after_seq, since) {
+        (Some(seq), _) => seq,
+        (None, Some(since_id)) => match get_event_seq_db(pool, signing_pubkey, since_id).await? {
+            Some(seq) => seq,
+            None => {
+                return Ok(Err(CursorTooOld {
+                    oldest_seq: oldest.unwrap_or(latest + 1),
+                    latest_seq: latest,
+                }))
+            }
+        },
+        (None, None) => 0,
     };
+    if let Err(too_old) = check_cursor(after_seq, oldest, latest) {
+        return Ok(Err(too_old));
+    }
+
+    let rows = sqlx::query(
+        r#"
+        SELECT event_id, signing_pubkey, event_type, encrypted_payload, signature, timestamp, seq
+        FROM server_events
+        WHERE signing_pubkey = $1 AND seq > $2
+        ORDER BY seq ASC
+        LIMIT $3
+        "#,
+    )
+    .bind(signing_pubkey)
+    .bind(after_seq as i64)
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("get_events_db: {}", e))?;
 
     let mut out = Vec::with_capacity(rows.len());
     for row in rows {
@@ -399,25 +664,65 @@ pub async fn get_events_db(pool: &PgPool, signing_pubkey: &str, since: Option<&s
             encrypted_payload: row.try_get("encrypted_payload").unwrap_or_default(),
             signature: row.try_get("signature").unwrap_or_default(),
             timestamp: row.try_get("timestamp").unwrap_or_else(|_| Utc::now()),
+            seq: row.try_get::<i64, _>("seq").unwrap_or(0) as u64,
         });
     }
-    Ok(out)
+    Ok(Ok(out))
+}
+
+/// Count a member that reached the event routes as known (nothing acked yet), so compaction keeps
+/// its unread events even before its first ack.
+#[cfg(feature = "postgres")]
+pub async fn register_member_db(pool: &PgPool, signing_pubkey: &str, user_id: &str) -> Result<(), String> {
+    sqlx::query(
+        r#"
+        INSERT INTO member_acks (signing_pubkey, user_id, last_event_id, last_seq, updated_at)
+        VALUES ($1, $2, '', 0, NOW())
+        ON CONFLICT (signing_pubkey, user_id) DO NOTHING;
+        "#,
+    )
+    .bind(signing_pubkey)
+    .bind(user_id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("register_member_db: {}", e))?;
+    Ok(())
 }
 
+/// Record a member's ack: `last_seq`, or the seq of `last_event_id`. Acks never move backwards,
+/// nor past the server's last assigned seq.
 #[cfg(feature = "postgres")]
-pub async fn ack_events_db(pool: &PgPool, signing_pubkey: &str, user_id: &str, last_event_id: &str) -> Result<(), String> {
+pub async fn ack_events_db(
+    pool: &PgPool,
+    signing_pubkey: &str,
+    user_id: &str,
+    last_event_id: &str,
+    last_seq: Option<u64>,
+) -> Result<(), String> {
+    let last_seq = match last_seq {
+        Some(seq) => Some(i64::try_from(seq).map_err(|_| "ack_events_db: last_seq out of range".to_string())?),
+        None => get_event_seq_db(pool, signing_pubkey, last_event_id).await?.map(|seq| seq as i64),
+    };
     sqlx::query(
         r#"
-        INSERT INTO member_acks (signing_pubkey, user_id, last_event_id, updated_at)
-        VALUES ($1, $2, $3, NOW())
+        INSERT INTO member_acks (signing_pubkey, user_id, last_event_id, last_seq, updated_at)
+        VALUES (
+          $1, $2, $3,
+          CASE WHEN $4::BIGINT IS NULL THEN NULL
+               ELSE LEAST($4::BIGINT, COALESCE((SELECT last_seq FROM server_event_seqs WHERE signing_pubkey = $1), 0))
+          END,
+          NOW()
+        )
         ON CONFLICT (signing_pubkey, user_id) DO UPDATE
         SET last_event_id = EXCLUDED.last_event_id,
+            last_seq = GREATEST(member_acks.last_seq, EXCLUDED.last_seq),
             updated_at = NOW();
         "#,
     )
     .bind(signing_pubkey)
     .bind(user_id)
     .bind(last_event_id)
+    .bind(last_seq)
     .execute(pool)
     .await
     .map_err(|e| format!("ack_events_db: {}", e))?;
@@ -433,3 +738,87 @@ pub async fn gc_old_events_db(pool: &PgPool, cutoff: DateTime<Utc>) -> Result<()
         .map_err(|e| format!("gc_old_events_db: {}", e))?;
     Ok(())
 }
+
+/// Opt a user in or out of the mailbox. Opting out drops whatever is waiting.
+#[cfg(feature = "postgres")]
+pub async fn set_mailbox_opt_in_db(pool: &PgPool, user_id: &str, enabled: bool) -> Result<(), String> {
+    if enabled {
+        sqlx::query(
+            r#"
+            INSERT INTO mailbox_opt_in (user_id, updated_at) VALUES ($1, NOW())
+            ON CONFLICT (user_id) DO UPDATE SET updated_at = NOW();
+            "#,
+        )
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("set_mailbox_opt_in_db: {}", e))?;
+    } else {
+        sqlx::query("DELETE FROM mailbox_opt_in WHERE user_id = $1")
+            .bind(user_id)
+            .execute(pool)
+            .await
+            .map_err(|e| format!("set_mailbox_opt_in_db: {}", e))?;
+        sqlx::query("DELETE FROM mailbox WHERE user_id = $1")
+            .bind(user_id)
+            .execute(pool)
+            .await
+            .map_err(|e| format!("set_mailbox_opt_in_db: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Keep `envelope` for each of `user_ids` that opted in and whose mailbox has room.
+#[cfg(feature = "postgres")]
+pub async fn put_mailbox_db(pool: &PgPool, user_ids: &[String], envelope: &str, limits: &MailboxLimits) -> Result<(), String> {
+    sqlx::query(
+        r#"
+        INSERT INTO mailbox (user_id, envelope, expires_at)
+        SELECT o.user_id, $2, NOW() + make_interval(secs => $3)
+        FROM mailbox_opt_in o
+        LEFT JOIN LATERAL (
+          SELECT COUNT(*) AS count, COALESCE(SUM(octet_length(m.envelope)), 0) AS bytes
+          FROM mailbox m
+          WHERE m.user_id = o.user_id AND m.expires_at > NOW()
+        ) q ON TRUE
+        WHERE o.user_id = ANY($1)
+          AND ($4 = 0 OR q.count < $4)
+          AND ($5 = 0 OR q.bytes + octet_length($2) <= $5);
+        "#,
+    )
+    .bind(user_ids)
+    .bind(envelope)
+    .bind(limits.ttl_secs as f64)
+    .bind(limits.max_per_user as i64)
+    .bind(limits.max_bytes_per_user as i64)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("put_mailbox_db: {}", e))?;
+    Ok(())
+}
+
+/// Remove and return a user's unexpired envelopes, oldest first.
+#[cfg(feature = "postgres")]
+pub async fn take_mailbox_db(pool: &PgPool, user_id: &str) -> Result<Vec<String>, String> {
+    let rows = sqlx::query("DELETE FROM mailbox WHERE user_id = $1 RETURNING id, envelope, expires_at > NOW() AS live")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("take_mailbox_db: {}", e))?;
+    let mut envelopes: Vec<(i64, String)> = rows
+        .iter()
+        .filter(|row| row.try_get::<bool, _>("live").unwrap_or(false))
+        .map(|row| (row.try_get("id").unwrap_or(0), row.try_get("envelope").unwrap_or_default()))
+        .collect();
+    envelopes.sort_by_key(|(id, _)| *id);
+    Ok(envelopes.into_iter().map(|(_, envelope)| envelope).collect())
+}
+
+#[cfg(feature = "postgres")]
+pub async fn gc_mailbox_db(pool: &PgPool) -> Result<(), String> {
+    sqlx::query("DELETE FROM mailbox WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await
+        .map_err(|e| format!("gc_mailbox_db: {}", e))?;
+    Ok(())
+}
@@ -12,6 +12,8 @@ use serde::Deserialize;
 use sha2::{Sha256, Digest};
 use std::sync::Arc;
 
+use crate::security::{ReplayRefused, SharedReplayCache};
+use crate::signing::user_id_for_public_key;
 use crate::state::AppState;
 use crate::state::friends::{CodeRedemption, FriendCode, FriendRequest};
 use crate::SignalingMessage;
@@ -29,8 +31,30 @@ impl std::ops::Deref for VerifiedFriendUserId {
     }
 }
 
+/// Bytes a signed beacon request covers: method + "\n" + path + "\n" + timestamp + "\n" + sha256(body).hex() (empty body = "").
+pub fn signed_request_envelope(method: &Method, path: &str, ts: i64, body_bytes: &[u8]) -> String {
+    let body_hash = if body_bytes.is_empty() {
+        String::new()
+    } else {
+        let mut hasher = Sha256::new();
+        hasher.update(body_bytes);
+        hex::encode(hasher.finalize())
+    };
+    format!(
+        "{}\n{}\n{}\n{}",
+        method.as_str().to_uppercase(),
+        path.trim(),
+        ts,
+        body_hash,
+    )
+}
+
+/// Max accepted clock skew for X-Timestamp, in seconds.
+pub const SIGNED_REQUEST_MAX_SKEW_SECS: i64 = 300;
+
 /// Verify Ed25519-signed friend API request. Envelope: method + "\n" + path + "\n" + timestamp + "\n" + sha256(body).hex().
-/// Returns verified user_id or error. No shared secret; mailbox-style.
+/// X-User-Id must be derived from X-Public-Key. Returns verified user_id or error. No shared secret; mailbox-style.
+/// Replays are not detected here; call check_signed_request_replay after this succeeds.
 pub fn verify_friend_sig_ed25519(
     method: &Method,
     path: &str,
@@ -63,27 +87,18 @@ pub fn verify_friend_sig_ed25519(
         .parse()
         .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid X-Timestamp"))?;
     let now = Utc::now().timestamp();
-    if (ts - now).abs() > 300 {
+    if (ts - now).abs() > SIGNED_REQUEST_MAX_SKEW_SECS {
         return Err((StatusCode::UNAUTHORIZED, "X-Timestamp expired"));
     }
 
-    let body_hash = if body_bytes.is_empty() {
-        String::new()
-    } else {
-        let mut hasher = Sha256::new();
-        hasher.update(body_bytes);
-        hex::encode(hasher.finalize())
-    };
-    let envelope = format!(
-        "{}\n{}\n{}\n{}",
-        method.as_str().to_uppercase(),
-        path.trim(),
-        ts,
-        body_hash,
-    );
+    let envelope = signed_request_envelope(method, path, ts, body_bytes);
 
     let pubkey_bytes = hex::decode(public_key_hex)
         .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid X-Public-Key hex"))?;
+    // user_id is hex(sha256(pubkey)[..16]); without this any keypair could act as any user.
+    if user_id_for_public_key(&pubkey_bytes) != user_id {
+        return Err((StatusCode::UNAUTHORIZED, "X-User-Id does not match X-Public-Key"));
+    }
     let verifying_key = ed25519_dalek::VerifyingKey::from_bytes(
         pubkey_bytes
             .as_slice()
@@ -106,6 +121,35 @@ pub fn verify_friend_sig_ed25519(
     Ok(user_id)
 }
 
+/// Refuse a signed request whose signature (header `signature_header`, e.g. "x-signature") was already
+/// accepted: an exact replay inside the timestamp window.
+/// Call only after the signature verified, so unverified garbage never enters the cache.
+pub async fn check_signed_request_replay(
+    cache: &SharedReplayCache,
+    headers: &axum::http::HeaderMap,
+    signature_header: &str,
+) -> Result<(), (StatusCode, &'static str)> {
+    let signature = headers
+        .get(signature_header)
+        .and_then(|v| v.to_str().ok())
+        .ok_or((StatusCode::UNAUTHORIZED, "Missing signature"))?
+        .trim();
+    let ts: i64 = headers
+        .get("x-timestamp")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .ok_or((StatusCode::UNAUTHORIZED, "Invalid X-Timestamp"))?;
+    let now = Utc::now().timestamp();
+    cache
+        .lock()
+        .await
+        .check_and_insert(signature, ts + SIGNED_REQUEST_MAX_SKEW_SECS, now)
+        .map_err(|refused| match refused {
+            ReplayRefused::Replayed => (StatusCode::UNAUTHORIZED, "Replayed request"),
+            ReplayRefused::Full => (StatusCode::TOO_MANY_REQUESTS, "Too many signed requests, retry later"),
+        })
+}
+
 // ---------- Request bodies ----------
 
 #[derive(Debug, Deserialize)]
@@ -200,10 +244,10 @@ pub async fn send_friend_request(
             from_account_created_at: None,
         };
         if let Ok(jb) = serde_json::to_string(&msg_to_b) {
-            state.friends.read().await.send_to_user(&to_user_id, &jb);
+            state.send_to_user(&to_user_id, &jb).await;
         }
         if let Ok(ja) = serde_json::to_string(&msg_to_a) {
-            state.friends.read().await.send_to_user(&from_user_id, &ja);
+            state.send_to_user(&from_user_id, &ja).await;
         }
         return (StatusCode::OK, Json(serde_json::json!({ "accepted": true, "mutual": true }))).into_response();
     }
@@ -230,7 +274,7 @@ pub async fn send_friend_request(
         created_at: req.created_at.to_rfc3339(),
     };
     if let Ok(json) = serde_json::to_string(&incoming) {
-        state.friends.read().await.send_to_user(&to_user_id, &json);
+        state.send_to_user(&to_user_id, &json).await;
     }
 
     (StatusCode::OK, Json(serde_json::json!({ "accepted": false, "sent": true }))).into_response()
@@ -262,7 +306,7 @@ pub async fn accept_friend_request(
         from_account_created_at: body.from_account_created_at.clone(),
     };
     if let Ok(json) = serde_json::to_string(&msg_to_requester) {
-        state.friends.read().await.send_to_user(&from_user_id, &json);
+        state.send_to_user(&from_user_id, &json).await;
     }
     (StatusCode::OK, Json(serde_json::json!({ "accepted": true }))).into_response()
 }
@@ -291,7 +335,7 @@ pub async fn cancel_friend_request(
             to_user_id: to_user_id.clone(),
         };
         if let Ok(json) = serde_json::to_string(&msg) {
-            state.friends.read().await.send_to_user(&to_user_id, &json);
+            state.send_to_user(&to_user_id, &json).await;
         }
     }
     // Idempotent: return 200 even when not found (e.g. server restarted, or already cancelled)
@@ -321,7 +365,7 @@ pub async fn decline_friend_request(
         to_user_id: to_user_id.clone(),
     };
     if let Ok(json) = serde_json::to_string(&msg) {
-        state.friends.read().await.send_to_user(&from_user_id, &json);
+        state.send_to_user(&from_user_id, &json).await;
     }
     (StatusCode::OK, Json(serde_json::json!({ "declined": true }))).into_response()
 }
@@ -362,7 +406,7 @@ pub async fn cancel_code_redemption(
             redeemer_user_id: redeemer_user_id.clone(),
         };
         if let Ok(json) = serde_json::to_string(&msg) {
-            state.friends.read().await.send_to_user(&code_owner_id, &json);
+            state.send_to_user(&code_owner_id, &json).await;
         }
     }
     // Idempotent: return 200 even when not found (e.g. server restarted, or already cancelled)
@@ -388,7 +432,7 @@ pub async fn remove_friend(
         from_user_id: from_user_id.clone(),
     };
     if let Ok(json) = serde_json::to_string(&msg) {
-        state.friends.read().await.send_to_user(&friend_user_id, &json);
+        state.send_to_user(&friend_user_id, &json).await;
     }
     (StatusCode::OK, Json(serde_json::json!({ "removed": true }))).into_response()
 }
@@ -493,7 +537,7 @@ pub async fn redeem_friend_code(
         created_at: now.to_rfc3339(),
     };
     if let Ok(json) = serde_json::to_string(&incoming) {
-        state.friends.read().await.send_to_user(&code_owner_id, &json);
+        state.send_to_user(&code_owner_id, &json).await;
     }
     (StatusCode::OK, Json(serde_json::json!({ "pending": true, "code_owner_id": code_owner_id }))).into_response()
 }
@@ -529,7 +573,7 @@ pub async fn accept_code_redemption(
         code_owner_account_created_at: body.code_owner_account_created_at.clone(),
     };
     if let Ok(json) = serde_json::to_string(&msg) {
-        state.friends.read().await.send_to_user(&redeemer_user_id, &json);
+        state.send_to_user(&redeemer_user_id, &json).await;
     }
     (StatusCode::OK, Json(serde_json::json!({ "accepted": true }))).into_response()
 }
@@ -572,7 +616,85 @@ pub async fn decline_code_redemption(
         redeemer_user_id: redeemer_user_id.clone(),
     };
     if let Ok(json) = serde_json::to_string(&msg) {
-        state.friends.read().await.send_to_user(&redeemer_user_id, &json);
+        state.send_to_user(&redeemer_user_id, &json).await;
     }
     (StatusCode::OK, Json(serde_json::json!({ "declined": true }))).into_response()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::ReplayCache;
+    use axum::http::{HeaderMap, HeaderValue};
+    use base64::Engine;
+    use ed25519_dalek::{Signer, SigningKey};
+    use tokio::sync::Mutex;
+
+    fn signed_headers(key: &SigningKey, user_id: &str, method: &Method, path: &str, body: &[u8]) -> HeaderMap {
+        let ts = Utc::now().timestamp();
+        let envelope = signed_request_envelope(method, path, ts, body);
+        let sig = key.sign(envelope.as_bytes());
+        let mut headers = HeaderMap::new();
+        headers.insert("x-user-id", HeaderValue::from_str(user_id).unwrap());
+        headers.insert("x-timestamp", HeaderValue::from_str(&ts.to_string()).unwrap());
+        headers.insert(
+            "x-public-key",
+            HeaderValue::from_str(&hex::encode(key.verifying_key().as_bytes())).unwrap(),
+        );
+        headers.insert(
+            "x-signature",
+            HeaderValue::from_str(&base64::engine::general_purpose::STANDARD.encode(sig.to_bytes())).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn rejects_user_id_not_derived_from_public_key() {
+        let victim = SigningKey::from_bytes(&[1u8; 32]);
+        let attacker = SigningKey::from_bytes(&[2u8; 32]);
+        let victim_id = user_id_for_public_key(victim.verifying_key().as_bytes());
+        let attacker_id = user_id_for_public_key(attacker.verifying_key().as_bytes());
+        let path = "/api/friends/remove";
+        let body = br#"{"user_id":"someone"}"#;
+
+        let spoofed = signed_headers(&attacker, &victim_id, &Method::POST, path, body);
+        let err = verify_friend_sig_ed25519(&Method::POST, path, &spoofed, body).unwrap_err();
+        assert_eq!(err.0, StatusCode::UNAUTHORIZED);
+
+        let honest = signed_headers(&attacker, &attacker_id, &Method::POST, path, body);
+        assert_eq!(verify_friend_sig_ed25519(&Method::POST, path, &honest, body).unwrap(), attacker_id);
+    }
+
+    #[tokio::test]
+    async fn signed_request_is_single_use() {
+        let key = SigningKey::from_bytes(&[3u8; 32]);
+        let user_id = user_id_for_public_key(key.verifying_key().as_bytes());
+        let cache: SharedReplayCache = Arc::new(Mutex::new(ReplayCache::new(16)));
+        let path = "/api/friends/requests/accept";
+        let body = br#"{"from_user_id":"abc"}"#;
+        let headers = signed_headers(&key, &user_id, &Method::POST, path, body);
+
+        assert!(verify_friend_sig_ed25519(&Method::POST, path, &headers, body).is_ok());
+        assert!(check_signed_request_replay(&cache, &headers, "x-signature").await.is_ok());
+        // Exact replay: still a valid signature, but already used.
+        assert!(verify_friend_sig_ed25519(&Method::POST, path, &headers, body).is_ok());
+        assert!(check_signed_request_replay(&cache, &headers, "x-signature").await.is_err());
+    }
+
+    #[test]
+    fn replay_cache_is_bounded_and_expires() {
+        let mut cache = ReplayCache::new(2);
+        assert_eq!(cache.check_and_insert("a", 100, 0), Ok(()));
+        assert_eq!(cache.check_and_insert("b", 50, 0), Ok(()));
+        // Full of live signatures: refuse rather than forget "a", which would make it replayable.
+        assert_eq!(cache.check_and_insert("c", 100, 0), Err(ReplayRefused::Full));
+        assert_eq!(cache.check_and_insert("a", 100, 10), Err(ReplayRefused::Replayed));
+        assert_eq!(cache.len(), 2);
+        // Expired entries free room even behind a live one.
+        assert_eq!(cache.check_and_insert("c", 100, 60), Ok(()));
+        assert_eq!(cache.check_and_insert("a", 100, 60), Err(ReplayRefused::Replayed));
+        // Past expiry the entries are pruned.
+        assert_eq!(cache.check_and_insert("a", 300, 200), Ok(()));
+        assert_eq!(cache.len(), 1);
+    }
+}
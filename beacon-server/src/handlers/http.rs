@@ -1,5 +1,5 @@
 use axum::{
-    extract::{Path, Query, State},
+    extract::{Extension, Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
     Json,
@@ -12,16 +12,21 @@ use sysinfo::{get_current_pid, Networks, System};
 
 use crate::{
     decode_path_segment,
+    signing::{VerifiedServerAdmin, VerifiedServerMember},
+    state::events::{CursorTooOld, EVENTS_PAGE_MAX},
     state::AppState,
     AckRequest, EncryptedServerHint, InviteTokenCreateRequest, ServerEvent,
 };
 
 type SharedState = Arc<AppState>;
 
+#[cfg(feature = "postgres")]
+use crate::signing::{carry_forward_key_envelopes, verify_server_hint};
 #[cfg(feature = "postgres")]
 use crate::handlers::db::{
     ack_events_db, gc_expired_invites_db, get_events_db, get_invite_db, get_server_hint_db,
-    insert_event_db, redeem_invite_db, revoke_invite_db, upsert_invite_db, upsert_server_hint_db,
+    insert_event_db, list_invites_db, redeem_invite_db, register_member_db, revoke_invite_db, upsert_invite_db,
+    upsert_server_hint_db,
 };
 
 // ---------- Status ----------
@@ -43,6 +48,7 @@ pub async fn get_status(State(state): State<SharedState>) -> impl IntoResponse {
             .unwrap_or(0)
     };
     let cpu_percent = *state.cpu_percent_cache.lock().await;
+    let detached_sessions = state.sessions.lock().await.detached_count();
     let (rx_bps, tx_bps) = {
         let networks = Networks::new_with_refreshed_list();
         let cur_rx: u64 = networks.list().values().map(|d| d.total_received()).sum();
@@ -69,7 +75,21 @@ pub async fn get_status(State(state): State<SharedState>) -> impl IntoResponse {
         "memory_bytes": memory_bytes,
         "cpu_percent": cpu_percent,
         "rx_bps": rx_bps,
-        "tx_bps": tx_bps
+        "tx_bps": tx_bps,
+        "ws_rate_limits": state.ws_limits.stats.snapshot(),
+        "ws_outbound": state.outbound.metrics.snapshot(),
+        "ws_detached_sessions": detached_sessions,
+        "cluster": state.cluster.get().map(|c| serde_json::json!({
+            "node_id": c.node_id,
+            "nodes": c.node_count(),
+        })),
+        "federation": state.federation.get().map(|f| serde_json::json!({
+            "pubkey": f.pubkey,
+            "links": f.link_summary().into_iter().map(|(peer, shared)| serde_json::json!({
+                "peer": peer,
+                "shared_servers": shared,
+            })).collect::<Vec<_>>(),
+        }))
     });
     Json(json)
 }
@@ -137,12 +157,10 @@ pub async fn redeem_invite(
     }
 }
 
-pub async fn revoke_invite(
-    State(state): State<SharedState>,
-    Path(code): Path<String>,
-) -> impl IntoResponse {
-    let code = decode_path_segment(&code).trim().to_string();
+// ---------- Servers (signing_pubkey) ----------
 
+/// Currently stored hint for a server (db when configured, memory otherwise).
+pub(crate) async fn load_server_hint(state: &AppState, signing_pubkey: &str) -> Option<EncryptedServerHint> {
     #[cfg(feature = "postgres")]
     {
         let db = {
@@ -150,38 +168,21 @@ pub async fn revoke_invite(
             backends.db.clone()
         };
         if let Some(pool) = db {
-            let _ = gc_expired_invites_db(&pool).await;
-            let existed = revoke_invite_db(&pool, &code).await.unwrap_or(false);
-            if existed {
-                return (
-                    StatusCode::OK,
-                    Json(serde_json::json!({"status": "revoked"})),
-                )
-                    .into_response();
-            }
-            return (StatusCode::NOT_FOUND, "Invite not found").into_response();
+            return get_server_hint_db(&pool, signing_pubkey).await.unwrap_or(None);
         }
     }
 
-    let mut events = state.events.write().await;
-    events.gc_expired_invites();
-    let existed = events.invite_tokens.remove(&code).is_some();
-    if existed {
-        (StatusCode::OK, Json(serde_json::json!({"status": "revoked"}))).into_response()
-    } else {
-        (StatusCode::NOT_FOUND, "Invite not found").into_response()
-    }
+    let events = state.events.read().await;
+    events.get_server_hint(signing_pubkey).cloned()
 }
 
-// ---------- Servers (signing_pubkey) ----------
-
-pub async fn register_server_hint(
-    State(state): State<SharedState>,
-    Path(signing_pubkey): Path<String>,
-    Json(hint): Json<EncryptedServerHint>,
-) -> impl IntoResponse {
-    let signing_pubkey = decode_path_segment(&signing_pubkey);
-
+/// Verify `hint` against the stored one and store it (db when configured, memory otherwise).
+/// Returns the hint as stored.
+pub(crate) async fn store_server_hint(
+    state: &AppState,
+    signing_pubkey: &str,
+    hint: &EncryptedServerHint,
+) -> Result<EncryptedServerHint, (StatusCode, &'static str)> {
     #[cfg(feature = "postgres")]
     {
         let db = {
@@ -189,25 +190,46 @@ pub async fn register_server_hint(
             backends.db.clone()
         };
         if let Some(pool) = db {
-            if let Err(e) = upsert_server_hint_db(&pool, &hint).await {
-                log::warn!("Failed to persist server hint: {}", e);
-            }
-        } else {
-            let mut events = state.events.write().await;
-            events.register_server_hint(signing_pubkey.to_string(), hint.clone());
+            let stored = match get_server_hint_db(&pool, signing_pubkey).await {
+                Ok(stored) => stored,
+                Err(e) => {
+                    log::warn!("Failed to load server hint: {}", e);
+                    return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to load server hint"));
+                }
+            };
+            verify_server_hint(signing_pubkey, hint, stored.as_ref())?;
+            let mut hint = hint.clone();
+            carry_forward_key_envelopes(signing_pubkey, &mut hint, stored.as_ref());
+            return match upsert_server_hint_db(&pool, &hint).await {
+                Ok(true) => Ok(hint),
+                // A newer (or different same-time) hint landed between the load and the conditional upsert.
+                Ok(false) => Err((StatusCode::CONFLICT, "Server hint conflicts with stored hint")),
+                Err(e) => {
+                    log::warn!("Failed to persist server hint: {}", e);
+                    Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to persist server hint"))
+                }
+            };
         }
     }
-    #[cfg(not(feature = "postgres"))]
-    {
-        let mut events = state.events.write().await;
-        events.register_server_hint(signing_pubkey.to_string(), hint.clone());
-    }
-    {
-        let signaling = state.signaling.read().await;
-        signaling.broadcast_server_hint_updated(&signing_pubkey, &hint);
-    }
+
+    let mut events = state.events.write().await;
+    events.register_server_hint(signing_pubkey.to_string(), hint.clone())
+}
+
+pub async fn register_server_hint(
+    State(state): State<SharedState>,
+    Path(signing_pubkey): Path<String>,
+    Json(hint): Json<EncryptedServerHint>,
+) -> impl IntoResponse {
+    let signing_pubkey = decode_path_segment(&signing_pubkey);
+
+    let hint = match store_server_hint(&state, &signing_pubkey, &hint).await {
+        Ok(stored) => stored,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    state.broadcast_server_hint_updated(&signing_pubkey, &hint).await;
     info!("Registered server hint");
-    (StatusCode::OK, Json(serde_json::json!({"status": "ok"})))
+    (StatusCode::OK, Json(serde_json::json!({"status": "ok"}))).into_response()
 }
 
 pub async fn get_server_hint(
@@ -239,6 +261,7 @@ pub async fn get_server_hint(
 
 pub async fn create_server_invite(
     State(state): State<SharedState>,
+    Extension(_admin): Extension<VerifiedServerAdmin>,
     Path(signing_pubkey): Path<String>,
     Json(inv): Json<InviteTokenCreateRequest>,
 ) -> impl IntoResponse {
@@ -269,18 +292,129 @@ pub async fn create_server_invite(
     }
 }
 
+/// Outstanding invites for a server (admin-signed): codes, remaining uses and expiry.
+pub async fn list_server_invites(
+    State(state): State<SharedState>,
+    Extension(_admin): Extension<VerifiedServerAdmin>,
+    Path(signing_pubkey): Path<String>,
+) -> impl IntoResponse {
+    let signing_pubkey = decode_path_segment(&signing_pubkey);
+
+    #[cfg(feature = "postgres")]
+    {
+        let db = {
+            let backends = state.backends.read().await;
+            backends.db.clone()
+        };
+        if let Some(pool) = db {
+            let _ = gc_expired_invites_db(&pool).await;
+            return match list_invites_db(&pool, &signing_pubkey).await {
+                Ok(list) => (StatusCode::OK, Json(serde_json::to_value(&list).unwrap())).into_response(),
+                Err(e) => {
+                    log::warn!("Failed to list invites: {}", e);
+                    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to list invites").into_response()
+                }
+            };
+        }
+    }
+
+    let mut events = state.events.write().await;
+    events.gc_expired_invites();
+    let list = events.list_invite_tokens(&signing_pubkey);
+    (StatusCode::OK, Json(serde_json::to_value(&list).unwrap())).into_response()
+}
+
+/// Revoke one of this server's invites (admin-signed).
+pub async fn revoke_server_invite(
+    State(state): State<SharedState>,
+    Extension(_admin): Extension<VerifiedServerAdmin>,
+    Path((signing_pubkey, code)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let signing_pubkey = decode_path_segment(&signing_pubkey);
+    let code = decode_path_segment(&code).trim().to_string();
+
+    #[cfg(feature = "postgres")]
+    {
+        let db = {
+            let backends = state.backends.read().await;
+            backends.db.clone()
+        };
+        if let Some(pool) = db {
+            let _ = gc_expired_invites_db(&pool).await;
+            let existed = revoke_invite_db(&pool, &signing_pubkey, &code).await.unwrap_or(false);
+            if existed {
+                return (
+                    StatusCode::OK,
+                    Json(serde_json::json!({"status": "revoked"})),
+                )
+                    .into_response();
+            }
+            return (StatusCode::NOT_FOUND, "Invite not found").into_response();
+        }
+    }
+
+    let mut events = state.events.write().await;
+    events.gc_expired_invites();
+    if events.revoke_invite_token(&signing_pubkey, &code) {
+        (StatusCode::OK, Json(serde_json::json!({"status": "revoked"}))).into_response()
+    } else {
+        (StatusCode::NOT_FOUND, "Invite not found").into_response()
+    }
+}
+
 #[derive(serde::Deserialize)]
 pub struct EventsQuery {
+    /// Legacy cursor: last event_id seen.
     pub since: Option<String>,
+    /// Last seq seen (takes precedence over `since`).
+    pub after_seq: Option<u64>,
+    /// Page size, at most EVENTS_PAGE_MAX (the default).
+    pub limit: Option<usize>,
+}
+
+/// 410 Gone: events after the cursor no longer exist, so the client must resync from the hint.
+fn cursor_too_old_response(too_old: CursorTooOld) -> axum::response::Response {
+    (
+        StatusCode::GONE,
+        Json(serde_json::json!({
+            "error": "cursor_too_old",
+            "message": "Events after this cursor were garbage-collected; resync from the server hint",
+            "oldest_seq": too_old.oldest_seq,
+            "latest_seq": too_old.latest_seq,
+        })),
+    )
+        .into_response()
+}
+
+/// Count `member` as a known member of the server so compaction waits for its acks (db when configured).
+async fn register_event_member(state: &AppState, signing_pubkey: &str, member: &VerifiedServerMember) {
+    #[cfg(feature = "postgres")]
+    {
+        let db = {
+            let backends = state.backends.read().await;
+            backends.db.clone()
+        };
+        if let Some(pool) = db {
+            if let Err(e) = register_member_db(&pool, signing_pubkey, &member.user_id).await {
+                log::warn!("register_member_db failed: {}", e);
+            }
+            return;
+        }
+    }
+
+    state.events.write().await.register_member(signing_pubkey, &member.user_id);
 }
 
 pub async fn get_events(
     State(state): State<SharedState>,
+    Extension(member): Extension<VerifiedServerMember>,
     Path(signing_pubkey): Path<String>,
     Query(params): Query<EventsQuery>,
 ) -> impl IntoResponse {
     let signing_pubkey = decode_path_segment(&signing_pubkey);
+    register_event_member(&state, &signing_pubkey, &member).await;
     let since = params.since.as_deref();
+    let limit = params.limit.unwrap_or(EVENTS_PAGE_MAX).clamp(1, EVENTS_PAGE_MAX);
 
     #[cfg(feature = "postgres")]
     {
@@ -289,24 +423,36 @@ pub async fn get_events(
             backends.db.clone()
         };
         if let Some(pool) = db {
-            let events_list = get_events_db(&pool, &signing_pubkey, since)
-                .await
-                .unwrap_or_default();
-            return (StatusCode::OK, Json(serde_json::to_value(&events_list).unwrap())).into_response();
+            return match get_events_db(&pool, &signing_pubkey, params.after_seq, since, limit).await {
+                Ok(Ok(events_list)) => (StatusCode::OK, Json(serde_json::to_value(&events_list).unwrap())).into_response(),
+                Ok(Err(too_old)) => cursor_too_old_response(too_old),
+                Err(e) => {
+                    log::warn!("get_events_db failed: {}", e);
+                    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load events").into_response()
+                }
+            };
         }
     }
 
     let events = state.events.read().await;
-    let events_list = events.get_events(&signing_pubkey, since);
-    (StatusCode::OK, Json(serde_json::to_value(&events_list).unwrap())).into_response()
+    let result = match params.after_seq {
+        Some(after_seq) => events.get_events_after(&signing_pubkey, after_seq, limit),
+        None => events.get_events(&signing_pubkey, since, limit),
+    };
+    match result {
+        Ok(events_list) => (StatusCode::OK, Json(serde_json::to_value(&events_list).unwrap())).into_response(),
+        Err(too_old) => cursor_too_old_response(too_old),
+    }
 }
 
 pub async fn post_event(
     State(state): State<SharedState>,
+    Extension(member): Extension<VerifiedServerMember>,
     Path(signing_pubkey): Path<String>,
     Json(mut event): Json<ServerEvent>,
 ) -> impl IntoResponse {
     let signing_pubkey = decode_path_segment(&signing_pubkey);
+    register_event_member(&state, &signing_pubkey, &member).await;
     event.signing_pubkey = signing_pubkey.clone();
     event.timestamp = Utc::now();
     if event.event_id.is_empty() {
@@ -320,24 +466,45 @@ pub async fn post_event(
             backends.db.clone()
         };
         if let Some(pool) = db {
-            let _ = insert_event_db(&pool, &event).await;
-            info!("Posted server event (db)");
-            return (StatusCode::CREATED, Json(serde_json::json!({"status": "created"})));
+            let limits = state.events.read().await.limits;
+            return match insert_event_db(&pool, &event, &limits).await {
+                Ok(Ok(seq)) => {
+                    info!("Posted server event (db)");
+                    (StatusCode::CREATED, Json(serde_json::json!({"status": "created", "seq": seq}))).into_response()
+                }
+                Ok(Err(quota)) => (StatusCode::INSUFFICIENT_STORAGE, quota.message()).into_response(),
+                Err(e) => {
+                    log::warn!("insert_event_db failed: {}", e);
+                    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store event").into_response()
+                }
+            };
         }
     }
 
     let mut events = state.events.write().await;
-    events.post_event(signing_pubkey.clone(), event);
-    info!("Posted server event");
-    (StatusCode::CREATED, Json(serde_json::json!({"status": "created"})))
+    match events.post_event(signing_pubkey.clone(), event) {
+        Ok(seq) => {
+            info!("Posted server event");
+            (StatusCode::CREATED, Json(serde_json::json!({"status": "created", "seq": seq}))).into_response()
+        }
+        Err(quota) => (StatusCode::INSUFFICIENT_STORAGE, quota.message()).into_response(),
+    }
 }
 
 pub async fn ack_events(
     State(state): State<SharedState>,
+    Extension(member): Extension<VerifiedServerMember>,
     Path(signing_pubkey): Path<String>,
     Json(ack): Json<AckRequest>,
 ) -> impl IntoResponse {
     let signing_pubkey = decode_path_segment(&signing_pubkey);
+    if ack.user_id != member.user_id {
+        return (StatusCode::FORBIDDEN, "Can only ack for your own user_id").into_response();
+    }
+    // Seqs are BIGINT in the database.
+    if ack.last_seq.is_some_and(|seq| i64::try_from(seq).is_err()) {
+        return (StatusCode::BAD_REQUEST, "last_seq out of range").into_response();
+    }
 
     #[cfg(feature = "postgres")]
     {
@@ -346,14 +513,14 @@ pub async fn ack_events(
             backends.db.clone()
         };
         if let Some(pool) = db {
-            let _ = ack_events_db(&pool, &signing_pubkey, &ack.user_id, &ack.last_event_id).await;
+            let _ = ack_events_db(&pool, &signing_pubkey, &ack.user_id, &ack.last_event_id, ack.last_seq).await;
             info!("Acknowledged events (db)");
-            return (StatusCode::OK, Json(serde_json::json!({"status": "ok"})));
+            return (StatusCode::OK, Json(serde_json::json!({"status": "ok"}))).into_response();
         }
     }
 
     let mut events = state.events.write().await;
-    events.ack_events(signing_pubkey.clone(), ack.user_id, ack.last_event_id);
+    events.ack_events(signing_pubkey.clone(), ack.user_id, &ack.last_event_id, ack.last_seq);
     info!("Acknowledged events");
-    (StatusCode::OK, Json(serde_json::json!({"status": "ok"})))
+    (StatusCode::OK, Json(serde_json::json!({"status": "ok"}))).into_response()
 }
@@ -1,27 +1,173 @@
 use std::sync::Arc;
 use log::{info, warn};
+use rand::seq::SliceRandom;
 use crate::{
-    SignalingMessage, ConnId, ServerId, SigningPubkey, WebSocketSender,
+    SignalingMessage, SignalingError, ErrorCode, ConnId, ServerId, SigningPubkey, WebSocketSender,
     ProfileRecord, ProfileSnapshotRecord,
     FriendRequestIncomingItem, CodeRedemptionItem,
     state::AppState,
+    cluster::ClusterEvent,
     state::presence::PresenceUserStatus,
-    state::signaling::{FRIENDS_PEER_PREFIX, FRIENDS_SIGNING_PUBKEY},
+    state::signaling::{send_json, FRIENDS_PEER_PREFIX, FRIENDS_SIGNING_PUBKEY, MAX_CHAT_SUBSCRIPTIONS_PER_CONN},
+    state::protocol::{CAP_CHAT_SUBSCRIPTIONS, CAP_FRIENDS_PRESENCE, CAP_HISTORY_SYNC, CAP_SWARM, MIN_PROTOCOL_VERSION},
+    state::history::{
+        self, ChunkAccepted, ChunkRejected, HISTORY_CHUNK_MAX_BYTES, HISTORY_SYNC_MAX_BYTES, HISTORY_SYNC_MAX_LIMIT,
+        HISTORY_SYNC_MAX_PEERS,
+    },
 };
 
 type SharedState = Arc<AppState>;
 
+/// Longest client-chosen HistorySyncRequest sync_id.
+const MAX_SYNC_ID_LEN: usize = 128;
+/// Largest encrypted_payload of an ephemeral chat message, edit, delete or reaction.
+const MAX_EPHEMERAL_PAYLOAD_BYTES: usize = 256 * 1024;
+
 #[cfg(feature = "postgres")]
 use crate::handlers::db::{upsert_profile_db, load_profiles_db};
 #[cfg(feature = "redis-backend")]
 use crate::handlers::redis::{redis_presence_hello, redis_presence_active, redis_presence_snapshot};
 
+/// Ensure this connection completed AuthResponse and that `claimed_user_id` is the identity it proved.
+async fn require_verified_user(state: &SharedState, conn_id: &ConnId, claimed_user_id: &str, what: &str) -> Result<(), SignalingError> {
+    let auth = state.auth.read().await;
+    let message = match auth.user_id_for_conn(conn_id) {
+        Some(uid) if uid == claimed_user_id => return Ok(()),
+        Some(_) => format!("{} user_id does not match authenticated identity", what),
+        None => format!("{} requires AuthResponse first", what),
+    };
+    Err(SignalingError::new(ErrorCode::Unauthorized, message))
+}
+
+/// Ensure this connection negotiated `capability` (or is a protocol 1 client, which has them all).
+async fn require_capability(state: &SharedState, conn_id: &ConnId, capability: &str, what: &str) -> Result<(), SignalingError> {
+    if state.protocol.read().await.has_capability(conn_id, capability) {
+        Ok(())
+    } else {
+        Err(SignalingError::new(
+            ErrorCode::Unsupported,
+            format!("{} requires the '{}' capability (not negotiated in Hello)", what, capability),
+        ))
+    }
+}
+
+/// TypingStart / TypingStop. Only changes are relayed; refreshes just push the expiry back.
+async fn set_typing(
+    state: &SharedState,
+    conn_id: &ConnId,
+    signing_pubkey: SigningPubkey,
+    chat_id: String,
+    start: bool,
+) -> Result<(), SignalingError> {
+    let what = if start { "TypingStart" } else { "TypingStop" };
+    let user_id = match state.friends.read().await.get_user_id_for_conn(conn_id) {
+        Some(uid) => uid,
+        None => return Err(SignalingError::not_registered(what)),
+    };
+    if chat_id.trim().is_empty() {
+        return Err(SignalingError::invalid_message(format!("{} requires chat_id", what)));
+    }
+    let key = (signing_pubkey, chat_id, user_id);
+    let changed = {
+        let mut typing = state.typing.lock().await;
+        if start {
+            typing.start(key.clone(), conn_id, std::time::Instant::now())
+        } else {
+            typing.stop(&key)
+        }
+    };
+    if changed {
+        state.broadcast_typing(key, start, Some(conn_id)).await;
+    }
+    Ok(())
+}
+
+/// EphemeralChatEdit / EphemeralChatDelete / EphemeralReactionAdd / EphemeralReactionRemove: checked
+/// like EphemeralChatSend, stamped with the sender and time, and relayed to the chat. The author's
+/// signature is inside the encrypted payload; clients verify it, the beacon cannot.
+async fn relay_chat_envelope(state: &SharedState, conn_id: &ConnId, mut msg: SignalingMessage) -> Result<(), SignalingError> {
+    let (what, chat_id, message_id, encrypted_payload, from_user_id, sent_at) = match &mut msg {
+        SignalingMessage::EphemeralChatEdit { chat_id, message_id, encrypted_payload, from_user_id, sent_at, .. } => {
+            ("EphemeralChatEdit", chat_id, message_id, encrypted_payload, from_user_id, sent_at)
+        }
+        SignalingMessage::EphemeralChatDelete { chat_id, message_id, encrypted_payload, from_user_id, sent_at, .. } => {
+            ("EphemeralChatDelete", chat_id, message_id, encrypted_payload, from_user_id, sent_at)
+        }
+        SignalingMessage::EphemeralReactionAdd { chat_id, message_id, encrypted_payload, from_user_id, sent_at, .. } => {
+            ("EphemeralReactionAdd", chat_id, message_id, encrypted_payload, from_user_id, sent_at)
+        }
+        SignalingMessage::EphemeralReactionRemove { chat_id, message_id, encrypted_payload, from_user_id, sent_at, .. } => {
+            ("EphemeralReactionRemove", chat_id, message_id, encrypted_payload, from_user_id, sent_at)
+        }
+        _ => return Err(SignalingError::new(ErrorCode::Unsupported, "Invalid message type")),
+    };
+    let user_id = match state.friends.read().await.get_user_id_for_conn(conn_id) {
+        Some(uid) => uid,
+        None => return Err(SignalingError::not_registered(what)),
+    };
+    if chat_id.trim().is_empty() {
+        return Err(SignalingError::invalid_message(format!("{} requires chat_id", what)));
+    }
+    if message_id.trim().is_empty() {
+        return Err(SignalingError::invalid_message(format!("{} requires message_id", what)));
+    }
+    if encrypted_payload.trim().is_empty() {
+        return Err(SignalingError::invalid_message(format!("{} requires encrypted_payload", what)));
+    }
+    if encrypted_payload.len() > MAX_EPHEMERAL_PAYLOAD_BYTES {
+        return Err(SignalingError::new(
+            ErrorCode::PayloadTooLarge,
+            format!("{} encrypted_payload exceeds {} bytes", what, MAX_EPHEMERAL_PAYLOAD_BYTES),
+        ));
+    }
+    *from_user_id = Some(user_id);
+    *sent_at = Some(chrono::Utc::now().to_rfc3339());
+
+    state.broadcast_to_chat(&msg, Some(conn_id)).await;
+    Ok(())
+}
+
+/// Mirror this connection's current entry for a swarm to the other cluster nodes.
+async fn publish_swarm_peer(state: &SharedState, signing_pubkey: SigningPubkey, sha256: String, conn_id: &ConnId) {
+    if state.cluster.get().is_none() {
+        return;
+    }
+    let peer = state.swarm.read().await.peer(&signing_pubkey, &sha256, conn_id);
+    if let Some(peer) = peer {
+        state.publish(ClusterEvent::SwarmUpsert { signing_pubkey, sha256, peer });
+    }
+}
+
+/// Serialize a reply with the client's request_id added, so it can match the reply to its request.
+pub fn with_request_id(msg: &SignalingMessage, request_id: Option<&str>) -> Result<String, SignalingError> {
+    let mut value = serde_json::to_value(msg).map_err(|e| format!("Failed to serialize reply: {}", e))?;
+    if let Some(request_id) = request_id {
+        let fields = value
+            .as_object_mut()
+            .ok_or_else(|| SignalingError::from("Reply is not a JSON object".to_string()))?;
+        fields.insert("request_id".to_string(), serde_json::Value::String(request_id.to_string()));
+    }
+    Ok(value.to_string())
+}
+
+/// Send a reply to the requesting connection, echoing its request_id.
+fn send_reply(sender: &WebSocketSender, request_id: Option<&str>, msg: &SignalingMessage) -> Result<(), SignalingError> {
+    let json = with_request_id(msg, request_id)?;
+    sender
+        .send(tokio_tungstenite::tungstenite::Message::Text(json))
+        .map_err(|e| format!("Failed to send reply: {}", e))?;
+    Ok(())
+}
+
+/// Handle one client message. `request_id` (optional, from the message envelope) is echoed in
+/// direct replies; the caller echoes it in the Error sent for a failure.
 pub async fn handle_message(
     msg: SignalingMessage,
     conn_id: &ConnId,
     state: &SharedState,
     sender: &WebSocketSender,
-) -> Result<(), String> {
+    request_id: Option<&str>,
+) -> Result<(), SignalingError> {
     match msg {
         SignalingMessage::Register { server_id, peer_id, signing_pubkey } => {
             let mut signaling = state.signaling.write().await;
@@ -37,17 +183,47 @@ pub async fn handle_message(
                 peer_id: peer_id.clone(),
                 peers,
             };
+            send_reply(sender, request_id, &response)
+        }
+        SignalingMessage::Hello { protocol_version, capabilities } => {
+            let negotiated = state
+                .protocol
+                .write()
+                .await
+                .negotiate(conn_id, protocol_version, &capabilities)
+                .map_err(|e| SignalingError::new(ErrorCode::Unsupported, e))?;
+            let mut capabilities: Vec<String> = negotiated.capabilities.into_iter().collect();
+            capabilities.sort();
+            let welcome = SignalingMessage::Welcome {
+                protocol_version: negotiated.version,
+                min_protocol_version: MIN_PROTOCOL_VERSION,
+                capabilities,
+            };
+            send_reply(sender, request_id, &welcome)
+        }
 
-            let json = serde_json::to_string(&response)
-                .map_err(|e| format!("Failed to serialize response: {}", e))?;
-
-            sender
-                .send(tokio_tungstenite::tungstenite::Message::Text(json))
-                .map_err(|e| format!("Failed to send response: {}", e))?;
-
-            Ok(())
+        SignalingMessage::AuthResponse { user_id, public_key, signature } => {
+            let identity = {
+                let mut auth = state.auth.write().await;
+                if auth.user_id_for_conn(conn_id).is_some() {
+                    return Err(SignalingError::invalid_message("Connection already authenticated"));
+                }
+                match auth.verify_response(conn_id, &user_id, &public_key, &signature) {
+                    Ok(identity) => identity,
+                    Err(e) => {
+                        // Issue a fresh nonce so the client can retry without reconnecting.
+                        let nonce = auth.issue_challenge(conn_id);
+                        drop(auth);
+                        let _ = send_reply(sender, request_id, &SignalingMessage::AuthChallenge { nonce });
+                        return Err(SignalingError::new(ErrorCode::Unauthorized, format!("Authentication failed: {}", e)));
+                    }
+                }
+            };
+            info!("Connection {} authenticated as {}", conn_id, identity.user_id);
+            send_reply(sender, request_id, &SignalingMessage::AuthOk { user_id: identity.user_id })
         }
         SignalingMessage::PresenceHello { user_id, signing_pubkeys, active_signing_pubkey, friend_user_ids } => {
+            require_verified_user(state, conn_id, &user_id, "PresenceHello").await?;
             let (affected_spks, redis_client, redis_ttl, local_snaps) = {
                 let mut presence = state.presence.write().await;
                 // Upsert presence
@@ -96,9 +272,7 @@ pub async fn handle_message(
                         signing_pubkey: spk.clone(),
                         users,
                     };
-                    if let Ok(json) = serde_json::to_string(&snap) {
-                        let _ = sender.send(tokio_tungstenite::tungstenite::Message::Text(json));
-                    }
+                    let _ = send_reply(sender, request_id, &snap);
                 }
             } else {
                 for (spk, users) in local_snaps {
@@ -106,9 +280,7 @@ pub async fn handle_message(
                         signing_pubkey: spk,
                         users,
                     };
-                    if let Ok(json) = serde_json::to_string(&snap) {
-                        let _ = sender.send(tokio_tungstenite::tungstenite::Message::Text(json));
-                    }
+                    let _ = send_reply(sender, request_id, &snap);
                 }
             }
 
@@ -119,9 +291,7 @@ pub async fn handle_message(
                         signing_pubkey: spk,
                         users,
                     };
-                    if let Ok(json) = serde_json::to_string(&snap) {
-                        let _ = sender.send(tokio_tungstenite::tungstenite::Message::Text(json));
-                    }
+                    let _ = send_reply(sender, request_id, &snap);
                 }
             }
 
@@ -133,7 +303,8 @@ pub async fn handle_message(
             // Friend-scoped presence: subscribe this connection to friend_user_ids and broadcast this user to friends
             const MAX_FRIEND_IDS: usize = 1000;
             let friend_user_ids: Vec<String> = friend_user_ids.into_iter().take(MAX_FRIEND_IDS).collect();
-            if !friend_user_ids.is_empty() {
+            let friends_presence = state.protocol.read().await.has_capability(conn_id, CAP_FRIENDS_PRESENCE);
+            if friends_presence && !friend_user_ids.is_empty() {
                 let friend_snap: Vec<PresenceUserStatus> = {
                     let presence = state.presence.read().await;
                     friend_user_ids
@@ -171,9 +342,7 @@ pub async fn handle_message(
                     signing_pubkey: FRIENDS_SIGNING_PUBKEY.to_string(),
                     users: friend_snap,
                 };
-                if let Ok(json) = serde_json::to_string(&snap) {
-                    let _ = sender.send(tokio_tungstenite::tungstenite::Message::Text(json));
-                }
+                let _ = send_reply(sender, request_id, &snap);
                 state.broadcast_friend_presence_update(&user_id, true, active_signing_pubkey.clone()).await;
             }
 
@@ -227,14 +396,31 @@ pub async fn handle_message(
                     pending_outgoing,
                     pending_code_redemptions,
                 };
-                if let Ok(json) = serde_json::to_string(&snap) {
-                    let _ = sender.send(tokio_tungstenite::tungstenite::Message::Text(json));
-                }
+                let _ = send_reply(sender, request_id, &snap);
+            }
+
+            // Direct messages kept while this user was offline (opt-in mailbox).
+            for json in state.take_mailbox(&user_id).await {
+                send_json(sender, &json, None);
             }
 
             Ok(())
         }
+        SignalingMessage::MailboxOptIn { enabled } => {
+            let user_id = match state.auth.read().await.user_id_for_conn(conn_id).map(str::to_string) {
+                Some(uid) => uid,
+                None => {
+                    return Err(SignalingError::new(ErrorCode::Unauthorized, "MailboxOptIn requires AuthResponse first"));
+                }
+            };
+            if enabled && !state.mailbox.lock().await.limits.enabled() {
+                return Err(SignalingError::new(ErrorCode::Unsupported, "The offline mailbox is disabled on this beacon"));
+            }
+            state.set_mailbox_opt_in(&user_id, enabled).await?;
+            Ok(())
+        }
         SignalingMessage::PresenceActive { user_id, active_signing_pubkey } => {
+            require_verified_user(state, conn_id, &user_id, "PresenceActive").await?;
             let (spks, redis_client, redis_ttl) = {
                 let mut presence = state.presence.write().await;
                 let spks = presence.update_presence_active(&user_id, active_signing_pubkey.clone());
@@ -273,6 +459,7 @@ pub async fn handle_message(
             Ok(())
         }
         SignalingMessage::ProfileAnnounce { user_id, display_name, real_name, show_real_name, rev, signing_pubkeys } => {
+            require_verified_user(state, conn_id, &user_id, "ProfileAnnounce").await?;
             let (rec_opt, db_opt) = {
                 let mut profiles = state.profiles.write().await;
                 let update = match profiles.profiles.get(&user_id) {
@@ -366,29 +553,38 @@ pub async fn handle_message(
             };
 
             let snap = SignalingMessage::ProfileSnapshot { signing_pubkey, profiles: out };
-            if let Ok(json) = serde_json::to_string(&snap) {
-                let _ = sender.send(tokio_tungstenite::tungstenite::Message::Text(json));
-            }
+            let _ = send_reply(sender, request_id, &snap);
             Ok(())
         }
         SignalingMessage::EphemeralChatSend { signing_pubkey, chat_id, message_id, encrypted_payload } => {
             // PresenceHello registers conn -> user mapping. Enforce it so user_id cannot be spoofed.
             let from_user_id = match state.friends.read().await.get_user_id_for_conn(conn_id) {
                 Some(uid) => uid,
-                None => return Err("EphemeralChatSend requires PresenceHello first".to_string()),
+                None => return Err(SignalingError::not_registered("EphemeralChatSend")),
             };
             if chat_id.trim().is_empty() {
-                return Err("EphemeralChatSend requires chat_id".to_string());
+                return Err(SignalingError::invalid_message("EphemeralChatSend requires chat_id"));
             }
             if message_id.trim().is_empty() {
-                return Err("EphemeralChatSend requires message_id".to_string());
+                return Err(SignalingError::invalid_message("EphemeralChatSend requires message_id"));
             }
             if encrypted_payload.trim().is_empty() {
-                return Err("EphemeralChatSend requires encrypted_payload".to_string());
+                return Err(SignalingError::invalid_message("EphemeralChatSend requires encrypted_payload"));
+            }
+            if encrypted_payload.len() > MAX_EPHEMERAL_PAYLOAD_BYTES {
+                return Err(SignalingError::new(
+                    ErrorCode::PayloadTooLarge,
+                    format!("EphemeralChatSend encrypted_payload exceeds {} bytes", MAX_EPHEMERAL_PAYLOAD_BYTES),
+                ));
+            }
+
+            let typing_key = (signing_pubkey.clone(), chat_id.clone(), from_user_id.clone());
+            if state.typing.lock().await.stop(&typing_key) {
+                state.broadcast_typing(typing_key, false, Some(conn_id)).await;
             }
 
             let outgoing = SignalingMessage::EphemeralChatIncoming {
-                signing_pubkey: signing_pubkey.clone(),
+                signing_pubkey,
                 chat_id,
                 message_id,
                 from_user_id,
@@ -396,27 +592,26 @@ pub async fn handle_message(
                 sent_at: chrono::Utc::now().to_rfc3339(),
             };
 
-            let signaling = state.signaling.read().await;
-            signaling.broadcast_ephemeral_chat_message(&signing_pubkey, &outgoing, Some(conn_id));
+            state.broadcast_to_chat(&outgoing, Some(conn_id)).await;
             Ok(())
         }
         SignalingMessage::EphemeralReceiptSend { signing_pubkey, chat_id, message_id, receipt_type } => {
             let from_user_id = match state.friends.read().await.get_user_id_for_conn(conn_id) {
                 Some(uid) => uid,
-                None => return Err("EphemeralReceiptSend requires PresenceHello first".to_string()),
+                None => return Err(SignalingError::not_registered("EphemeralReceiptSend")),
             };
             if chat_id.trim().is_empty() {
-                return Err("EphemeralReceiptSend requires chat_id".to_string());
+                return Err(SignalingError::invalid_message("EphemeralReceiptSend requires chat_id"));
             }
             if message_id.trim().is_empty() {
-                return Err("EphemeralReceiptSend requires message_id".to_string());
+                return Err(SignalingError::invalid_message("EphemeralReceiptSend requires message_id"));
             }
             if receipt_type != "delivered" {
-                return Err("EphemeralReceiptSend only supports delivered receipts".to_string());
+                return Err(SignalingError::invalid_message("EphemeralReceiptSend only supports delivered receipts"));
             }
 
             let outgoing = SignalingMessage::EphemeralReceiptIncoming {
-                signing_pubkey: signing_pubkey.clone(),
+                signing_pubkey,
                 chat_id,
                 message_id,
                 from_user_id,
@@ -424,52 +619,210 @@ pub async fn handle_message(
                 sent_at: chrono::Utc::now().to_rfc3339(),
             };
 
-            let signaling = state.signaling.read().await;
-            signaling.broadcast_ephemeral_chat_message(&signing_pubkey, &outgoing, Some(conn_id));
+            state.broadcast_to_chat(&outgoing, Some(conn_id)).await;
+            Ok(())
+        }
+        msg @ (SignalingMessage::EphemeralChatEdit { .. }
+        | SignalingMessage::EphemeralChatDelete { .. }
+        | SignalingMessage::EphemeralReactionAdd { .. }
+        | SignalingMessage::EphemeralReactionRemove { .. }) => relay_chat_envelope(state, conn_id, msg).await,
+        SignalingMessage::TypingStart { signing_pubkey, chat_id } => {
+            set_typing(state, conn_id, signing_pubkey, chat_id, true).await
+        }
+        SignalingMessage::TypingStop { signing_pubkey, chat_id } => {
+            set_typing(state, conn_id, signing_pubkey, chat_id, false).await
+        }
+        SignalingMessage::ChatSubscribe { signing_pubkey, chat_ids } => {
+            require_capability(state, conn_id, CAP_CHAT_SUBSCRIPTIONS, "ChatSubscribe").await?;
+            if chat_ids.iter().any(|chat_id| chat_id.trim().is_empty()) {
+                return Err(SignalingError::invalid_message("ChatSubscribe chat_ids must not be empty"));
+            }
+            let mut signaling = state.signaling.write().await;
+            // Only servers this connection registered for (Register with signing_pubkey).
+            if !signaling.conn_subscribed_to_server(conn_id, &signing_pubkey) {
+                return Err(SignalingError::new(
+                    ErrorCode::NotRegistered,
+                    "ChatSubscribe requires Register for this signing_pubkey first",
+                ));
+            }
+            if !signaling.subscribe_chats(conn_id, &signing_pubkey, &chat_ids) {
+                return Err(SignalingError::invalid_message(format!(
+                    "ChatSubscribe exceeds {} chats per connection",
+                    MAX_CHAT_SUBSCRIPTIONS_PER_CONN
+                )));
+            }
+            Ok(())
+        }
+        SignalingMessage::ChatUnsubscribe { signing_pubkey, chat_ids } => {
+            require_capability(state, conn_id, CAP_CHAT_SUBSCRIPTIONS, "ChatUnsubscribe").await?;
+            state.signaling.write().await.unsubscribe_chats(conn_id, &signing_pubkey, &chat_ids);
+            Ok(())
+        }
+        SignalingMessage::HistorySyncRequest { sync_id, signing_pubkey, chat_id, direction, cursor, limit, .. } => {
+            require_capability(state, conn_id, CAP_HISTORY_SYNC, "HistorySyncRequest").await?;
+            let from_user_id = match state.friends.read().await.get_user_id_for_conn(conn_id) {
+                Some(uid) => uid,
+                None => return Err(SignalingError::not_registered("HistorySyncRequest")),
+            };
+            if sync_id.trim().is_empty() || sync_id.len() > MAX_SYNC_ID_LEN {
+                return Err(SignalingError::invalid_message(format!(
+                    "HistorySyncRequest requires a sync_id of at most {} bytes",
+                    MAX_SYNC_ID_LEN
+                )));
+            }
+            if chat_id.trim().is_empty() {
+                return Err(SignalingError::invalid_message("HistorySyncRequest requires chat_id"));
+            }
+            if direction != "before" && direction != "after" {
+                return Err(SignalingError::invalid_message("HistorySyncRequest direction must be \"before\" or \"after\""));
+            }
+            if limit == 0 || limit > HISTORY_SYNC_MAX_LIMIT {
+                return Err(SignalingError::invalid_message(format!(
+                    "HistorySyncRequest limit must be 1..={}",
+                    HISTORY_SYNC_MAX_LIMIT
+                )));
+            }
+            let members = {
+                let signaling = state.signaling.read().await;
+                if !signaling.conn_subscribed_to_server(conn_id, &signing_pubkey) {
+                    return Err(SignalingError::new(
+                        ErrorCode::NotRegistered,
+                        "HistorySyncRequest requires Register for this signing_pubkey first",
+                    ));
+                }
+                signaling.chat_members(&signing_pubkey, &chat_id, Some(conn_id))
+            };
+            // Peers that can answer: negotiated history_sync and sent PresenceHello.
+            let mut peers = {
+                let protocol = state.protocol.read().await;
+                let friends = state.friends.read().await;
+                members
+                    .into_iter()
+                    .filter(|(peer_conn, _, _)| {
+                        protocol.has_capability(peer_conn, CAP_HISTORY_SYNC)
+                            && friends.get_user_id_for_conn(peer_conn).is_some()
+                    })
+                    .collect::<Vec<_>>()
+            };
+            // Random pick, preferring members that follow the chat (they are likelier to have it).
+            peers.shuffle(&mut rand::thread_rng());
+            peers.sort_by_key(|(_, _, follows)| !follows);
+            peers.truncate(HISTORY_SYNC_MAX_PEERS);
+
+            if !peers.is_empty() {
+                let peer_conns = peers.iter().map(|(peer_conn, _, _)| peer_conn.clone()).collect();
+                state
+                    .history
+                    .lock()
+                    .await
+                    .start(&sync_id, conn_id, sender.clone(), peer_conns, std::time::Instant::now())
+                    .map_err(|e| SignalingError::invalid_message(format!("HistorySyncRequest: {}", e)))?;
+            }
+            send_reply(sender, request_id, &SignalingMessage::HistorySyncStarted {
+                sync_id: sync_id.clone(),
+                peer_count: peers.len() as u32,
+            })?;
+
+            let forward = SignalingMessage::HistorySyncRequest {
+                sync_id,
+                signing_pubkey,
+                chat_id,
+                direction,
+                cursor,
+                limit,
+                from_user_id: Some(from_user_id),
+            };
+            let json = serde_json::to_string(&forward)
+                .map_err(|e| format!("Failed to serialize HistorySyncRequest: {}", e))?;
+            for (_, peer_sender, _) in &peers {
+                send_json(peer_sender, &json, None);
+            }
+            Ok(())
+        }
+        SignalingMessage::HistorySyncResponse { sync_id, encrypted_payload, done, .. } => {
+            let from_user_id = match state.friends.read().await.get_user_id_for_conn(conn_id) {
+                Some(uid) => uid,
+                None => return Err(SignalingError::not_registered("HistorySyncResponse")),
+            };
+            let accepted = state
+                .history
+                .lock()
+                .await
+                .accept_chunk(&sync_id, conn_id, encrypted_payload.len(), done);
+            let accepted = match accepted {
+                Ok(accepted) => accepted,
+                Err(ChunkRejected::NotAsked) => {
+                    return Err(SignalingError::invalid_message(
+                        "HistorySyncResponse for a sync this connection was not asked for (or that already ended)",
+                    ));
+                }
+                Err(ChunkRejected::TooLarge) => {
+                    return Err(SignalingError::new(
+                        ErrorCode::PayloadTooLarge,
+                        format!("HistorySyncResponse encrypted_payload exceeds {} bytes", HISTORY_CHUNK_MAX_BYTES),
+                    ));
+                }
+            };
+            let relay = SignalingMessage::HistorySyncResponse {
+                sync_id: sync_id.clone(),
+                encrypted_payload,
+                done,
+                from_user_id: Some(from_user_id),
+            };
+            let json = serde_json::to_string(&relay)
+                .map_err(|e| format!("Failed to serialize HistorySyncResponse: {}", e))?;
+            match accepted {
+                ChunkAccepted::Relay(requester) => send_json(&requester, &json, None),
+                ChunkAccepted::Finish(requester) => {
+                    send_json(&requester, &json, None);
+                    history::send_done(&requester, sync_id, false);
+                }
+                ChunkAccepted::Exhausted(requester) => {
+                    history::send_done(&requester, sync_id, true);
+                    return Err(SignalingError::new(
+                        ErrorCode::PayloadTooLarge,
+                        format!("History sync exceeded {} bytes and was ended", HISTORY_SYNC_MAX_BYTES),
+                    ));
+                }
+            }
             Ok(())
         }
         SignalingMessage::Offer { from_peer, to_peer, sdp } => {
             info!("Forwarding offer from {} to {}", from_peer, to_peer);
 
-            let target_sender = state.signaling.read().await.validate_and_get_target_sender(&from_peer, conn_id, &to_peer);
-            match target_sender {
-                Ok(Some(sender)) => {
-                    let forward_msg = SignalingMessage::Offer { from_peer, to_peer, sdp };
-                    let json = serde_json::to_string(&forward_msg).map_err(|e| format!("Failed to serialize offer: {}", e))?;
-                    sender.send(tokio_tungstenite::tungstenite::Message::Text(json)).map_err(|e| format!("Failed to forward offer: {}", e))?;
-                }
-                Ok(None) => warn!("Target peer {} not found for offer", to_peer),
-                Err(()) => return Err(format!("Invalid peer_id {} for connection {}", from_peer, conn_id)),
+            if !state.signaling.read().await.validate_peer_connection(&from_peer, conn_id) {
+                return Err(SignalingError::invalid_peer(&from_peer, conn_id));
+            }
+            let forward_msg = SignalingMessage::Offer { from_peer, to_peer: to_peer.clone(), sdp };
+            let json = serde_json::to_string(&forward_msg).map_err(|e| format!("Failed to serialize offer: {}", e))?;
+            if !state.send_to_peer(&to_peer, json).await {
+                warn!("Target peer {} not found for offer", to_peer);
             }
             Ok(())
         }
         SignalingMessage::Answer { from_peer, to_peer, sdp } => {
             info!("Forwarding answer from {} to {}", from_peer, to_peer);
 
-            let target_sender = state.signaling.read().await.validate_and_get_target_sender(&from_peer, conn_id, &to_peer);
-            match target_sender {
-                Ok(Some(sender)) => {
-                    let forward_msg = SignalingMessage::Answer { from_peer, to_peer, sdp };
-                    let json = serde_json::to_string(&forward_msg).map_err(|e| format!("Failed to serialize answer: {}", e))?;
-                    sender.send(tokio_tungstenite::tungstenite::Message::Text(json)).map_err(|e| format!("Failed to forward answer: {}", e))?;
-                }
-                Ok(None) => warn!("Target peer {} not found for answer", to_peer),
-                Err(()) => return Err(format!("Invalid peer_id {} for connection {}", from_peer, conn_id)),
+            if !state.signaling.read().await.validate_peer_connection(&from_peer, conn_id) {
+                return Err(SignalingError::invalid_peer(&from_peer, conn_id));
+            }
+            let forward_msg = SignalingMessage::Answer { from_peer, to_peer: to_peer.clone(), sdp };
+            let json = serde_json::to_string(&forward_msg).map_err(|e| format!("Failed to serialize answer: {}", e))?;
+            if !state.send_to_peer(&to_peer, json).await {
+                warn!("Target peer {} not found for answer", to_peer);
             }
             Ok(())
         }
         SignalingMessage::IceCandidate { from_peer, to_peer, candidate } => {
             info!("Forwarding ICE candidate from {} to {}", from_peer, to_peer);
 
-            let target_sender = state.signaling.read().await.validate_and_get_target_sender(&from_peer, conn_id, &to_peer);
-            match target_sender {
-                Ok(Some(sender)) => {
-                    let forward_msg = SignalingMessage::IceCandidate { from_peer, to_peer, candidate };
-                    let json = serde_json::to_string(&forward_msg).map_err(|e| format!("Failed to serialize ICE candidate: {}", e))?;
-                    sender.send(tokio_tungstenite::tungstenite::Message::Text(json)).map_err(|e| format!("Failed to forward ICE candidate: {}", e))?;
-                }
-                Ok(None) => warn!("Target peer {} not found for ICE candidate", to_peer),
-                Err(()) => return Err(format!("Invalid peer_id {} for connection {}", from_peer, conn_id)),
+            if !state.signaling.read().await.validate_peer_connection(&from_peer, conn_id) {
+                return Err(SignalingError::invalid_peer(&from_peer, conn_id));
+            }
+            let forward_msg = SignalingMessage::IceCandidate { from_peer, to_peer: to_peer.clone(), candidate };
+            let json = serde_json::to_string(&forward_msg).map_err(|e| format!("Failed to serialize ICE candidate: {}", e))?;
+            if !state.send_to_peer(&to_peer, json).await {
+                warn!("Target peer {} not found for ICE candidate", to_peer);
             }
             Ok(())
         }
@@ -477,6 +830,7 @@ pub async fn handle_message(
         // === Voice Chat Messages ===
 
         SignalingMessage::VoiceRegister { server_id, chat_id, peer_id, user_id, signing_pubkey } => {
+            require_verified_user(state, conn_id, &user_id, "VoiceRegister").await?;
             info!("Voice register: peer={} user={} server={} chat={}", peer_id, user_id, server_id, chat_id);
 
             let peers = {
@@ -487,7 +841,7 @@ pub async fn handle_message(
                     signaling.register_peer(peer_id.clone(), server_id.clone(), Some(signing_pubkey.clone()), conn_id.clone());
                 } else {
                     if !signaling.validate_peer_connection(&peer_id, conn_id) {
-                        return Err(format!("Invalid peer_id {} for connection {}", peer_id, conn_id));
+                        return Err(SignalingError::invalid_peer(&peer_id, conn_id));
                     }
                 }
 
@@ -509,17 +863,14 @@ pub async fn handle_message(
                     conn_id.clone(),
                 )
             };
+            state.announce_voice_join(&server_id, &chat_id, &signing_pubkey, &peer_id, &user_id, conn_id);
 
             let response = SignalingMessage::VoiceRegistered {
                 peer_id: peer_id.clone(),
                 chat_id: chat_id.clone(),
                 peers: peers.clone(),
             };
-            let json = serde_json::to_string(&response)
-                .map_err(|e| format!("Failed to serialize VoiceRegistered: {}", e))?;
-            sender
-                .send(tokio_tungstenite::tungstenite::Message::Text(json))
-                .map_err(|e| format!("Failed to send VoiceRegistered: {}", e))?;
+            send_reply(sender, request_id, &response)?;
 
             let join_msg = SignalingMessage::VoicePeerJoined {
                 peer_id: peer_id.clone(),
@@ -539,7 +890,7 @@ pub async fn handle_message(
             {
                 let signaling = state.signaling.read().await;
                 if !signaling.validate_peer_connection(&peer_id, conn_id) {
-                    return Err(format!("Invalid peer_id {} for connection {}", peer_id, conn_id));
+                    return Err(SignalingError::invalid_peer(&peer_id, conn_id));
                 }
             }
 
@@ -568,6 +919,7 @@ pub async fn handle_message(
             };
 
             if let Some((server_id, user_id, signing_pubkey_opt)) = removed {
+                state.announce_voice_leave(&server_id, &chat_id, signing_pubkey_opt.as_ref(), &peer_id);
                 let leave_msg = SignalingMessage::VoicePeerLeft {
                     peer_id,
                     user_id: user_id.clone(),
@@ -583,46 +935,32 @@ pub async fn handle_message(
         }
 
         SignalingMessage::VoiceOffer { from_peer, from_user, to_peer, chat_id, sdp } => {
+            require_verified_user(state, conn_id, &from_user, "VoiceOffer").await?;
             info!("Voice offer from {} to {} in chat {}", from_peer, to_peer, chat_id);
 
             {
                 let signaling = state.signaling.read().await;
                 if !signaling.validate_peer_connection(&from_peer, conn_id) {
-                    return Err(format!("Invalid peer_id {} for connection {}", from_peer, conn_id));
+                    return Err(SignalingError::invalid_peer(&from_peer, conn_id));
                 }
             }
 
-            let target_sender = {
-                let voice = state.voice.read().await;
-                let mut found_server: Option<ServerId> = None;
-                for ((server_id, c), _) in voice.voice_chats.iter() {
-                    if c == &chat_id {
-                        found_server = Some(server_id.clone());
-                        break;
-                    }
-                }
-                drop(voice);
-                if let Some(server_id) = found_server {
-                    state.get_voice_peer_sender(&server_id, &chat_id, &to_peer).await
-                } else {
-                    None
-                }
-            };
+            let server_id = state.voice.read().await.server_for_chat(&chat_id);
 
-            if let Some(target) = target_sender {
-                let forward_msg = SignalingMessage::VoiceOffer {
-                    from_peer,
-                    from_user,
-                    to_peer,
-                    chat_id,
-                    sdp,
-                };
-                let json = serde_json::to_string(&forward_msg)
-                    .map_err(|e| format!("Failed to serialize VoiceOffer: {}", e))?;
-                target
-                    .send(tokio_tungstenite::tungstenite::Message::Text(json))
-                    .map_err(|e| format!("Failed to forward VoiceOffer: {}", e))?;
-            } else {
+            let forward_msg = SignalingMessage::VoiceOffer {
+                from_peer,
+                from_user,
+                to_peer: to_peer.clone(),
+                chat_id: chat_id.clone(),
+                sdp,
+            };
+            let json = serde_json::to_string(&forward_msg)
+                .map_err(|e| format!("Failed to serialize VoiceOffer: {}", e))?;
+            let delivered = match server_id {
+                Some(server_id) => state.send_to_voice_peer(&server_id, &chat_id, &to_peer, json).await,
+                None => false,
+            };
+            if !delivered {
                 warn!("Target peer {} not found in chat {} for VoiceOffer", to_peer, chat_id);
             }
 
@@ -630,46 +968,32 @@ pub async fn handle_message(
         }
 
         SignalingMessage::VoiceAnswer { from_peer, from_user, to_peer, chat_id, sdp } => {
+            require_verified_user(state, conn_id, &from_user, "VoiceAnswer").await?;
             info!("Voice answer from {} to {} in chat {}", from_peer, to_peer, chat_id);
 
             {
                 let signaling = state.signaling.read().await;
                 if !signaling.validate_peer_connection(&from_peer, conn_id) {
-                    return Err(format!("Invalid peer_id {} for connection {}", from_peer, conn_id));
+                    return Err(SignalingError::invalid_peer(&from_peer, conn_id));
                 }
             }
 
-            let target_sender = {
-                let voice = state.voice.read().await;
-                let mut found_server: Option<ServerId> = None;
-                for ((server_id, c), _) in voice.voice_chats.iter() {
-                    if c == &chat_id {
-                        found_server = Some(server_id.clone());
-                        break;
-                    }
-                }
-                drop(voice);
-                if let Some(server_id) = found_server {
-                    state.get_voice_peer_sender(&server_id, &chat_id, &to_peer).await
-                } else {
-                    None
-                }
-            };
+            let server_id = state.voice.read().await.server_for_chat(&chat_id);
 
-            if let Some(target) = target_sender {
-                let forward_msg = SignalingMessage::VoiceAnswer {
-                    from_peer,
-                    from_user,
-                    to_peer,
-                    chat_id,
-                    sdp,
-                };
-                let json = serde_json::to_string(&forward_msg)
-                    .map_err(|e| format!("Failed to serialize VoiceAnswer: {}", e))?;
-                target
-                    .send(tokio_tungstenite::tungstenite::Message::Text(json))
-                    .map_err(|e| format!("Failed to forward VoiceAnswer: {}", e))?;
-            } else {
+            let forward_msg = SignalingMessage::VoiceAnswer {
+                from_peer,
+                from_user,
+                to_peer: to_peer.clone(),
+                chat_id: chat_id.clone(),
+                sdp,
+            };
+            let json = serde_json::to_string(&forward_msg)
+                .map_err(|e| format!("Failed to serialize VoiceAnswer: {}", e))?;
+            let delivered = match server_id {
+                Some(server_id) => state.send_to_voice_peer(&server_id, &chat_id, &to_peer, json).await,
+                None => false,
+            };
+            if !delivered {
                 warn!("Target peer {} not found in chat {} for VoiceAnswer", to_peer, chat_id);
             }
 
@@ -680,39 +1004,22 @@ pub async fn handle_message(
             {
                 let signaling = state.signaling.read().await;
                 if !signaling.validate_peer_connection(&from_peer, conn_id) {
-                    return Err(format!("Invalid peer_id {} for connection {}", from_peer, conn_id));
+                    return Err(SignalingError::invalid_peer(&from_peer, conn_id));
                 }
             }
 
-            let target_sender = {
-                let voice = state.voice.read().await;
-                let mut found_server: Option<ServerId> = None;
-                for ((server_id, c), _) in voice.voice_chats.iter() {
-                    if c == &chat_id {
-                        found_server = Some(server_id.clone());
-                        break;
-                    }
-                }
-                drop(voice);
-                if let Some(server_id) = found_server {
-                    state.get_voice_peer_sender(&server_id, &chat_id, &to_peer).await
-                } else {
-                    None
-                }
-            };
+            let server_id = state.voice.read().await.server_for_chat(&chat_id);
 
-            if let Some(target) = target_sender {
+            if let Some(server_id) = server_id {
                 let forward_msg = SignalingMessage::VoiceIceCandidate {
                     from_peer,
-                    to_peer,
-                    chat_id,
+                    to_peer: to_peer.clone(),
+                    chat_id: chat_id.clone(),
                     candidate,
                 };
                 let json = serde_json::to_string(&forward_msg)
                     .map_err(|e| format!("Failed to serialize VoiceIceCandidate: {}", e))?;
-                target
-                    .send(tokio_tungstenite::tungstenite::Message::Text(json))
-                    .map_err(|e| format!("Failed to forward VoiceIceCandidate: {}", e))?;
+                state.send_to_voice_peer(&server_id, &chat_id, &to_peer, json).await;
             }
             // Don't warn on missing peer for ICE candidates - they may have left
 
@@ -721,12 +1028,7 @@ pub async fn handle_message(
 
         SignalingMessage::Ping => {
             // Client keepalive - respond with Pong
-            let pong = SignalingMessage::Pong;
-            let json = serde_json::to_string(&pong)
-                .map_err(|e| format!("Failed to serialize Pong: {}", e))?;
-            sender.send(tokio_tungstenite::tungstenite::Message::Text(json))
-                .map_err(|e| format!("Failed to send Pong: {}", e))?;
-            Ok(())
+            send_reply(sender, request_id, &SignalingMessage::Pong)
         }
 
         SignalingMessage::Pong => {
@@ -736,11 +1038,11 @@ pub async fn handle_message(
 
         SignalingMessage::FriendMutualCheck { to_user_id } => {
             if to_user_id.trim().is_empty() {
-                return Err("FriendMutualCheck requires to_user_id".to_string());
+                return Err(SignalingError::invalid_message("FriendMutualCheck requires to_user_id"));
             }
             let from_user_id = match state.friends.read().await.get_user_id_for_conn(conn_id) {
                 Some(uid) => uid,
-                None => return Err("FriendMutualCheck requires PresenceHello first".to_string()),
+                None => return Err(SignalingError::not_registered("FriendMutualCheck")),
             };
             if from_user_id == to_user_id {
                 return Ok(());
@@ -748,18 +1050,17 @@ pub async fn handle_message(
             let incoming = SignalingMessage::FriendMutualCheckIncoming { from_user_id };
             let json = serde_json::to_string(&incoming)
                 .map_err(|e| format!("Failed to serialize FriendMutualCheckIncoming: {}", e))?;
-            let friends = state.friends.read().await;
-            friends.send_to_user(&to_user_id, &json);
+            state.send_to_user_or_mailbox(&to_user_id, &json).await;
             Ok(())
         }
 
         SignalingMessage::FriendMutualCheckReply { to_user_id, accepted } => {
             if to_user_id.trim().is_empty() {
-                return Err("FriendMutualCheckReply requires to_user_id".to_string());
+                return Err(SignalingError::invalid_message("FriendMutualCheckReply requires to_user_id"));
             }
             let from_user_id = match state.friends.read().await.get_user_id_for_conn(conn_id) {
                 Some(uid) => uid,
-                None => return Err("FriendMutualCheckReply requires PresenceHello first".to_string()),
+                None => return Err(SignalingError::not_registered("FriendMutualCheckReply")),
             };
             if from_user_id == to_user_id {
                 return Ok(());
@@ -770,24 +1071,23 @@ pub async fn handle_message(
             };
             let json = serde_json::to_string(&incoming)
                 .map_err(|e| format!("Failed to serialize FriendMutualCheckReplyIncoming: {}", e))?;
-            let friends = state.friends.read().await;
-            friends.send_to_user(&to_user_id, &json);
+            state.send_to_user_or_mailbox(&to_user_id, &json).await;
             Ok(())
         }
 
         SignalingMessage::AttachmentTransferRequest { to_user_id, request_id, attachment_id } => {
             if to_user_id.trim().is_empty() {
-                return Err("AttachmentTransferRequest requires to_user_id".to_string());
+                return Err(SignalingError::invalid_message("AttachmentTransferRequest requires to_user_id"));
             }
             if request_id.trim().is_empty() {
-                return Err("AttachmentTransferRequest requires request_id".to_string());
+                return Err(SignalingError::invalid_message("AttachmentTransferRequest requires request_id"));
             }
             if attachment_id.trim().is_empty() {
-                return Err("AttachmentTransferRequest requires attachment_id".to_string());
+                return Err(SignalingError::invalid_message("AttachmentTransferRequest requires attachment_id"));
             }
             let from_user_id = match state.friends.read().await.get_user_id_for_conn(conn_id) {
                 Some(uid) => uid,
-                None => return Err("AttachmentTransferRequest requires PresenceHello first".to_string()),
+                None => return Err(SignalingError::not_registered("AttachmentTransferRequest")),
             };
             if from_user_id == to_user_id {
                 return Ok(());
@@ -799,21 +1099,20 @@ pub async fn handle_message(
             };
             let json = serde_json::to_string(&incoming)
                 .map_err(|e| format!("Failed to serialize AttachmentTransferRequestIncoming: {}", e))?;
-            let friends = state.friends.read().await;
-            friends.send_to_user(&to_user_id, &json);
+            state.send_to_user_or_mailbox(&to_user_id, &json).await;
             Ok(())
         }
 
         SignalingMessage::AttachmentTransferResponse { to_user_id, request_id, accepted } => {
             if to_user_id.trim().is_empty() {
-                return Err("AttachmentTransferResponse requires to_user_id".to_string());
+                return Err(SignalingError::invalid_message("AttachmentTransferResponse requires to_user_id"));
             }
             if request_id.trim().is_empty() {
-                return Err("AttachmentTransferResponse requires request_id".to_string());
+                return Err(SignalingError::invalid_message("AttachmentTransferResponse requires request_id"));
             }
             let from_user_id = match state.friends.read().await.get_user_id_for_conn(conn_id) {
                 Some(uid) => uid,
-                None => return Err("AttachmentTransferResponse requires PresenceHello first".to_string()),
+                None => return Err(SignalingError::not_registered("AttachmentTransferResponse")),
             };
             if from_user_id == to_user_id {
                 return Ok(());
@@ -825,24 +1124,23 @@ pub async fn handle_message(
             };
             let json = serde_json::to_string(&incoming)
                 .map_err(|e| format!("Failed to serialize AttachmentTransferResponseIncoming: {}", e))?;
-            let friends = state.friends.read().await;
-            friends.send_to_user(&to_user_id, &json);
+            state.send_to_user_or_mailbox(&to_user_id, &json).await;
             Ok(())
         }
 
         SignalingMessage::AttachmentTransferSignal { to_user_id, request_id, signal } => {
             if to_user_id.trim().is_empty() {
-                return Err("AttachmentTransferSignal requires to_user_id".to_string());
+                return Err(SignalingError::invalid_message("AttachmentTransferSignal requires to_user_id"));
             }
             if request_id.trim().is_empty() {
-                return Err("AttachmentTransferSignal requires request_id".to_string());
+                return Err(SignalingError::invalid_message("AttachmentTransferSignal requires request_id"));
             }
             if signal.trim().is_empty() {
-                return Err("AttachmentTransferSignal requires signal".to_string());
+                return Err(SignalingError::invalid_message("AttachmentTransferSignal requires signal"));
             }
             let from_user_id = match state.friends.read().await.get_user_id_for_conn(conn_id) {
                 Some(uid) => uid,
-                None => return Err("AttachmentTransferSignal requires PresenceHello first".to_string()),
+                None => return Err(SignalingError::not_registered("AttachmentTransferSignal")),
             };
             if from_user_id == to_user_id {
                 return Ok(());
@@ -854,27 +1152,27 @@ pub async fn handle_message(
             };
             let json = serde_json::to_string(&incoming)
                 .map_err(|e| format!("Failed to serialize AttachmentTransferSignalIncoming: {}", e))?;
-            let friends = state.friends.read().await;
-            friends.send_to_user(&to_user_id, &json);
+            state.send_to_user_or_mailbox(&to_user_id, &json).await;
             Ok(())
         }
 
         SignalingMessage::SwarmAnnounce { signing_pubkey, sha256, seeding, piece_count, upload_kbps, quality_score } => {
             if signing_pubkey.trim().is_empty() {
-                return Err("SwarmAnnounce requires signing_pubkey".to_string());
+                return Err(SignalingError::invalid_message("SwarmAnnounce requires signing_pubkey"));
             }
             if sha256.trim().is_empty() {
-                return Err("SwarmAnnounce requires sha256".to_string());
+                return Err(SignalingError::invalid_message("SwarmAnnounce requires sha256"));
             }
+            require_capability(state, conn_id, CAP_SWARM, "SwarmAnnounce").await?;
             let user_id = match state.friends.read().await.get_user_id_for_conn(conn_id) {
                 Some(uid) => uid,
-                None => return Err("SwarmAnnounce requires PresenceHello first".to_string()),
+                None => return Err(SignalingError::not_registered("SwarmAnnounce")),
             };
             {
                 let mut swarm = state.swarm.write().await;
                 swarm.announce(
-                    signing_pubkey,
-                    sha256,
+                    signing_pubkey.clone(),
+                    sha256.clone(),
                     conn_id.clone(),
                     user_id,
                     seeding,
@@ -883,33 +1181,40 @@ pub async fn handle_message(
                     quality_score,
                 );
             }
+            publish_swarm_peer(state, signing_pubkey, sha256, conn_id).await;
             Ok(())
         }
 
         SignalingMessage::SwarmUnannounce { signing_pubkey, sha256 } => {
             if signing_pubkey.trim().is_empty() {
-                return Err("SwarmUnannounce requires signing_pubkey".to_string());
+                return Err(SignalingError::invalid_message("SwarmUnannounce requires signing_pubkey"));
             }
             if sha256.trim().is_empty() {
-                return Err("SwarmUnannounce requires sha256".to_string());
+                return Err(SignalingError::invalid_message("SwarmUnannounce requires sha256"));
             }
             {
                 let mut swarm = state.swarm.write().await;
                 swarm.unannounce(&signing_pubkey, &sha256, conn_id);
             }
+            state.publish(ClusterEvent::SwarmRemove {
+                signing_pubkey,
+                sha256,
+                conn_id: conn_id.clone(),
+            });
             Ok(())
         }
 
         SignalingMessage::SwarmPeerListRequest { signing_pubkey, sha256, max_peers } => {
             if signing_pubkey.trim().is_empty() {
-                return Err("SwarmPeerListRequest requires signing_pubkey".to_string());
+                return Err(SignalingError::invalid_message("SwarmPeerListRequest requires signing_pubkey"));
             }
             if sha256.trim().is_empty() {
-                return Err("SwarmPeerListRequest requires sha256".to_string());
+                return Err(SignalingError::invalid_message("SwarmPeerListRequest requires sha256"));
             }
+            require_capability(state, conn_id, CAP_SWARM, "SwarmPeerListRequest").await?;
             // Require authenticated connection identity.
             if state.friends.read().await.get_user_id_for_conn(conn_id).is_none() {
-                return Err("SwarmPeerListRequest requires PresenceHello first".to_string());
+                return Err(SignalingError::not_registered("SwarmPeerListRequest"));
             }
             const DEFAULT_MAX_SWARM_PEERS: usize = 24;
             const ABSOLUTE_MAX_SWARM_PEERS: usize = 64;
@@ -926,24 +1231,20 @@ pub async fn handle_message(
                 sha256,
                 peers,
             };
-            let json = serde_json::to_string(&outgoing)
-                .map_err(|e| format!("Failed to serialize SwarmPeerListResponse: {}", e))?;
-            sender
-                .send(tokio_tungstenite::tungstenite::Message::Text(json))
-                .map_err(|e| format!("Failed to send SwarmPeerListResponse: {}", e))?;
-            Ok(())
+            send_reply(sender, request_id, &outgoing)
         }
 
         SignalingMessage::SwarmHealthUpdate { signing_pubkey, sha256, upload_kbps, quality_score, leechers } => {
             if signing_pubkey.trim().is_empty() {
-                return Err("SwarmHealthUpdate requires signing_pubkey".to_string());
+                return Err(SignalingError::invalid_message("SwarmHealthUpdate requires signing_pubkey"));
             }
             if sha256.trim().is_empty() {
-                return Err("SwarmHealthUpdate requires sha256".to_string());
+                return Err(SignalingError::invalid_message("SwarmHealthUpdate requires sha256"));
             }
+            require_capability(state, conn_id, CAP_SWARM, "SwarmHealthUpdate").await?;
             // Require authenticated connection identity.
             if state.friends.read().await.get_user_id_for_conn(conn_id).is_none() {
-                return Err("SwarmHealthUpdate requires PresenceHello first".to_string());
+                return Err(SignalingError::not_registered("SwarmHealthUpdate"));
             }
             {
                 let mut swarm = state.swarm.write().await;
@@ -956,6 +1257,7 @@ pub async fn handle_message(
                     leechers,
                 );
             }
+            publish_swarm_peer(state, signing_pubkey, sha256, conn_id).await;
             Ok(())
         }
 
@@ -963,7 +1265,7 @@ pub async fn handle_message(
             const MAX_PROFILE_PUSH_RECIPIENTS: usize = 500;
             let from_user_id = match state.friends.read().await.get_user_id_for_conn(conn_id) {
                 Some(uid) => uid,
-                None => return Err("ProfilePush requires PresenceHello first".to_string()),
+                None => return Err(SignalingError::not_registered("ProfilePush")),
             };
             let incoming = SignalingMessage::ProfilePushIncoming {
                 from_user_id: from_user_id.clone(),
@@ -977,16 +1279,49 @@ pub async fn handle_message(
             };
             let json = serde_json::to_string(&incoming)
                 .map_err(|e| format!("Failed to serialize ProfilePushIncoming: {}", e))?;
-            let friends = state.friends.read().await;
-            for to_id in to_user_ids.into_iter().take(MAX_PROFILE_PUSH_RECIPIENTS) {
-                if to_id.is_empty() || to_id == from_user_id {
-                    continue;
-                }
-                friends.send_to_user(&to_id, &json);
-            }
+            let recipients: Vec<String> = to_user_ids
+                .into_iter()
+                .take(MAX_PROFILE_PUSH_RECIPIENTS)
+                .filter(|to_id| !to_id.is_empty() && *to_id != from_user_id)
+                .collect();
+            state.send_to_users_or_mailbox(&recipients, &json).await;
             Ok(())
         }
 
-        _ => Err("Invalid message type".to_string()),
+        _ => Err(SignalingError::new(ErrorCode::Unsupported, "Invalid message type")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_and_errors_carry_request_id_and_code() {
+        let pong = serde_json::to_string(&SignalingMessage::Pong).unwrap();
+        assert_eq!(with_request_id(&SignalingMessage::Pong, None).unwrap(), pong);
+        let tagged: serde_json::Value =
+            serde_json::from_str(&with_request_id(&SignalingMessage::Pong, Some("r\"1")).unwrap()).unwrap();
+        assert_eq!(tagged, serde_json::json!({"type": "Pong", "request_id": "r\"1"}));
+
+        // A reply that already has a request_id field keeps a single key.
+        let errored = SignalingMessage::Error {
+            code: ErrorCode::Internal,
+            message: "x".to_string(),
+            request_id: Some("old".to_string()),
+        };
+        let json = with_request_id(&errored, Some("new")).unwrap();
+        assert_eq!(json.matches("request_id").count(), 1);
+        assert!(json.contains(r#""request_id":"new""#));
+
+        let error = SignalingMessage::Error {
+            code: ErrorCode::NotRegistered,
+            message: "x".to_string(),
+            request_id: Some("r2".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"type":"Error","code":"not_registered","message":"x","request_id":"r2"}"#
+        );
     }
 }
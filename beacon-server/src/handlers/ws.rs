@@ -1,16 +1,21 @@
 use axum::extract::ws::{Message as AxumMessage, WebSocket, WebSocketUpgrade};
-use axum::extract::{State, Extension};
+use axum::extract::{Extension, Query, State};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use futures_util::{SinkExt, StreamExt};
 use log::{error, info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::time::Instant;
+use tokio::sync::Notify;
 
 use crate::handlers::message::handle_message;
-use crate::security::ClientIp;
+use crate::security::{ClientIp, ConnRateLimiter, WsBudget, WsLimitHit};
+use crate::state::protocol::CAP_BINARY_FRAMES;
+use crate::state::sessions::Detach;
+use crate::cluster::ClusterEvent;
 use crate::state::AppState;
-use crate::{ConnId, SignalingMessage};
+use crate::{ConnId, ErrorCode, SignalingError, SignalingMessage, WebSocketSender};
 
 type SharedState = Arc<AppState>;
 
@@ -29,10 +34,17 @@ fn tungstenite_to_axum(msg: tokio_tungstenite::tungstenite::Message) -> AxumMess
     }
 }
 
+#[derive(serde::Deserialize)]
+pub struct WsQuery {
+    /// Resume token from SessionStarted / Resumed, when reconnecting after a drop.
+    pub resume: Option<String>,
+}
+
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<SharedState>,
     Extension(ClientIp(client_ip)): Extension<ClientIp>,
+    Query(params): Query<WsQuery>,
 ) -> axum::response::Response {
     {
         let tracker = state.connection_tracker.read().await;
@@ -40,71 +52,141 @@ pub async fn ws_handler(
             return (StatusCode::SERVICE_UNAVAILABLE, "Connection limit reached").into_response();
         }
     }
-    ws.on_upgrade(move |socket| handle_connection_axum(socket, state, client_ip))
+    ws.on_upgrade(move |socket| handle_connection_axum(socket, state, client_ip, params.resume))
 }
 
-async fn handle_connection_axum(socket: WebSocket, state: SharedState, client_ip: String) {
+async fn handle_connection_axum(socket: WebSocket, state: SharedState, client_ip: String, resume: Option<String>) {
     if state.connection_tracker.write().await.try_register(&client_ip).is_err() {
         return;
     }
 
     info!("WebSocket connection established");
 
-    let conn_id: ConnId = uuid::Uuid::new_v4().to_string();
-    let (tx, mut rx) = mpsc::unbounded_channel::<tokio_tungstenite::tungstenite::Message>();
-
     let (mut ws_sender, mut ws_receiver) = socket.split();
 
+    let resumed = match resume.as_deref() {
+        Some(token) => state.sessions.lock().await.resume(token, Instant::now()),
+        None => None,
+    };
+
+    let (conn_id, tx, mut rx, takeover) = match resumed {
+        Some(session) => {
+            // Same login, protocol and registrations as before the drop: no challenge, no join/leave.
+            info!("WebSocket session {} resumed", session.conn_id);
+            if let Ok(json) = serde_json::to_string(&SignalingMessage::Resumed { resume_token: session.token }) {
+                let _ = ws_sender.send(AxumMessage::Text(json)).await;
+            }
+            (session.conn_id, session.sender, session.receiver, session.takeover)
+        }
+        None => {
+            let conn_id: ConnId = uuid::Uuid::new_v4().to_string();
+            let (tx, rx) = crate::outbound::channel(state.outbound.clone());
+            let mut sessions = state.sessions.lock().await;
+            let takeover = if sessions.enabled() {
+                let (resume_token, takeover) = sessions.open(&conn_id, tx.clone());
+                let started = SignalingMessage::SessionStarted {
+                    resume_token,
+                    resume_grace_secs: sessions.grace.as_secs(),
+                };
+                if let Ok(json) = serde_json::to_string(&started) {
+                    let _ = tx.send(tokio_tungstenite::tungstenite::Message::Text(json));
+                }
+                takeover
+            } else {
+                Arc::new(Notify::new())
+            };
+            drop(sessions);
+
+            // Login: the client must answer with AuthResponse before presence, profile or voice messages are accepted.
+            let nonce = state.auth.write().await.issue_challenge(&conn_id);
+            if let Ok(json) = serde_json::to_string(&SignalingMessage::AuthChallenge { nonce }) {
+                let _ = tx.send(tokio_tungstenite::tungstenite::Message::Text(json));
+            }
+            (conn_id, tx, rx, takeover)
+        }
+    };
+
+    // MessagePack frames once the connection negotiated binary_frames (a resumed session keeps it).
+    let binary = Arc::new(AtomicBool::new(
+        state.protocol.read().await.has_capability(&conn_id, CAP_BINARY_FRAMES),
+    ));
+    let writer_binary = binary.clone();
     let mut send_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
-            let axum_msg = tungstenite_to_axum(msg);
+            let axum_msg = match msg {
+                tokio_tungstenite::tungstenite::Message::Text(text) if writer_binary.load(Ordering::Relaxed) => {
+                    match crate::wire::encode(&text) {
+                        Some(bytes) => AxumMessage::Binary(bytes),
+                        None => AxumMessage::Text(text),
+                    }
+                }
+                msg => tungstenite_to_axum(msg),
+            };
             if ws_sender.send(axum_msg).await.is_err() {
                 break;
             }
         }
     });
 
+    let conn_limiter = state.ws_limits.conn_limiter();
+
+    // Heartbeat: any frame from the client counts as activity; a half-open connection that stays
+    // silent past the deadline is dropped like any other lost socket (held for resume, then cleaned up).
+    let heartbeat = state.ws_heartbeat;
+    state.liveness.lock().await.touch(&conn_id, Instant::now());
+    let mut heartbeat_tick = tokio::time::interval(heartbeat.tick());
+    heartbeat_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    heartbeat_tick.tick().await;
+
+    // A Close with "normal closure" / "going away" is a logout or app exit: clean up right away
+    // instead of holding the session for resume.
+    let mut resumable = true;
+    let mut taken_over = false;
+
     loop {
         tokio::select! {
+            _ = takeover.notified() => {
+                info!("WebSocket session {} resumed on another socket", conn_id);
+                taken_over = true;
+                break;
+            }
+            _ = heartbeat_tick.tick() => {
+                if let Some(timeout) = heartbeat.idle_timeout {
+                    let idle = state.liveness.lock().await.idle_for(&conn_id, Instant::now());
+                    if let Some(idle) = idle.filter(|idle| *idle > timeout) {
+                        info!("Reaping WebSocket connection {}: no frames for {}s", conn_id, idle.as_secs());
+                        break;
+                    }
+                }
+                if heartbeat.ping_interval.is_some() {
+                    let _ = tx.send(tokio_tungstenite::tungstenite::Message::Ping(Vec::new()));
+                }
+            }
             msg_opt = ws_receiver.next() => {
+                if matches!(msg_opt, Some(Ok(_))) {
+                    state.liveness.lock().await.touch(&conn_id, Instant::now());
+                }
                 match msg_opt {
                     Some(Ok(AxumMessage::Text(text))) => {
-                        if let Some(ref limiter) = state.ws_rate_limiter {
-                            if !limiter.check_key(&client_ip) {
-                                let error_msg = SignalingMessage::Error {
-                                    message: "Rate limit exceeded".to_string(),
-                                };
-                                if let Ok(json) = serde_json::to_string(&error_msg) {
-                                    let _ = tx.send(tokio_tungstenite::tungstenite::Message::Text(json));
-                                }
-                                continue;
-                            }
+                        handle_frame(&state, &conn_id, &tx, &client_ip, conn_limiter.as_ref(), &text, &binary).await;
+                    }
+                    Some(Ok(AxumMessage::Binary(bytes))) => {
+                        if !binary.load(Ordering::Relaxed) {
+                            send_error(&tx, SignalingError::new(ErrorCode::Unsupported, "Binary frames require the 'binary_frames' capability"), None);
+                            continue;
                         }
-                        match serde_json::from_str::<SignalingMessage>(&text) {
-                            Ok(msg) => {
-                                if let Err(e) = handle_message(msg, &conn_id, &state, &tx).await {
-                                    warn!("Error handling message: {}", e);
-                                    let error_msg = SignalingMessage::Error {
-                                        message: e.to_string(),
-                                    };
-                                    if let Ok(json) = serde_json::to_string(&error_msg) {
-                                        let _ = tx.send(tokio_tungstenite::tungstenite::Message::Text(json));
-                                    }
-                                }
-                            }
+                        let text = match crate::wire::decode(&bytes) {
+                            Ok(value) => value.to_string(),
                             Err(e) => {
-                                warn!("Failed to parse message: {}", e);
-                                let error_msg = SignalingMessage::Error {
-                                    message: format!("Invalid message format: {}", e),
-                                };
-                                if let Ok(json) = serde_json::to_string(&error_msg) {
-                                    let _ = tx.send(tokio_tungstenite::tungstenite::Message::Text(json));
-                                }
+                                send_error(&tx, SignalingError::invalid_message(format!("Invalid binary frame: {}", e)), None);
+                                continue;
                             }
-                        }
+                        };
+                        handle_frame(&state, &conn_id, &tx, &client_ip, conn_limiter.as_ref(), &text, &binary).await;
                     }
-                    Some(Ok(AxumMessage::Close(_))) => {
+                    Some(Ok(AxumMessage::Close(frame))) => {
                         info!("Client closed connection");
+                        resumable = !frame.is_some_and(|f| f.code == 1000 || f.code == 1001);
                         break;
                     }
                     Some(Ok(AxumMessage::Ping(data))) => {
@@ -122,6 +204,94 @@ async fn handle_connection_axum(socket: WebSocket, state: SharedState, client_ip
         }
     }
 
+    let outcome = if taken_over {
+        Detach::TakenOver
+    } else {
+        state.sessions.lock().await.detach(&conn_id, &takeover, resumable, Instant::now())
+    };
+    match outcome {
+        Detach::Kept => info!("WebSocket session {} detached; held for resume", conn_id),
+        Detach::TakenOver => {}
+        Detach::Cleanup => {
+            cleanup_connection(&state, &conn_id).await;
+            tx.close();
+        }
+    }
+    send_task.abort();
+
+    state.connection_tracker.write().await.unregister(&client_ip);
+}
+
+/// Parse and dispatch one client message (a text frame, or a decoded binary frame).
+async fn handle_frame(
+    state: &SharedState,
+    conn_id: &ConnId,
+    tx: &WebSocketSender,
+    client_ip: &str,
+    conn_limiter: Option<&ConnRateLimiter>,
+    text: &str,
+    binary: &AtomicBool,
+) {
+    let request_id = request_id_of(text);
+    let request_id = request_id.as_deref();
+    match serde_json::from_str::<SignalingMessage>(text) {
+        Ok(msg) => {
+            let (cost, budget) = ws_message_cost(&msg);
+            let user_id = state.auth.read().await.user_id_for_conn(conn_id).map(str::to_string);
+            if let Err(hit) = state.ws_limits.check(conn_limiter, client_ip, user_id.as_deref(), cost, budget) {
+                let message = match hit {
+                    WsLimitHit::Fanout => "Rate limit exceeded (fan-out)",
+                    _ => "Rate limit exceeded",
+                };
+                send_error(tx, SignalingError::new(ErrorCode::RateLimited, message), request_id);
+                return;
+            }
+            let is_hello = matches!(msg, SignalingMessage::Hello { .. });
+            if let Err(e) = handle_message(msg, conn_id, state, tx, request_id).await {
+                warn!("Error handling message: {}", e);
+                send_error(tx, e, request_id);
+            } else if is_hello {
+                // From here on the writer sends MessagePack if the client asked for it.
+                let granted = state.protocol.read().await.has_capability(conn_id, CAP_BINARY_FRAMES);
+                binary.store(granted, Ordering::Relaxed);
+            }
+        }
+        Err(e) => {
+            // Malformed frames still cost a unit so they cannot be used to flood.
+            let user_id = state.auth.read().await.user_id_for_conn(conn_id).map(str::to_string);
+            if state.ws_limits.check(conn_limiter, client_ip, user_id.as_deref(), 1, WsBudget::General).is_err() {
+                return;
+            }
+            warn!("Failed to parse message: {}", e);
+            // Name the type so an older/newer peer can tell "unsupported" from "malformed".
+            let msg_type = serde_json::from_str::<serde_json::Value>(text)
+                .ok()
+                .and_then(|v| v.get("type").and_then(|t| t.as_str()).map(str::to_string));
+            let error = match msg_type {
+                Some(t) => SignalingError::new(
+                    // serde reports a type this beacon does not know as "unknown variant".
+                    if e.to_string().starts_with("unknown variant") {
+                        ErrorCode::Unsupported
+                    } else {
+                        ErrorCode::InvalidMessage
+                    },
+                    format!(
+                        "Unsupported or invalid {} message (beacon protocol {}): {}",
+                        t,
+                        crate::state::protocol::PROTOCOL_VERSION,
+                        e
+                    ),
+                ),
+                None => SignalingError::invalid_message(format!("Invalid message format: {}", e)),
+            };
+            send_error(tx, error, request_id);
+        }
+    }
+}
+
+/// Drop everything registered by a connection and tell the rest of the server it left
+/// (voice leave, offline presence). Runs on disconnect, or when a detached session expires.
+pub async fn cleanup_connection(state: &AppState, conn_id: &ConnId) {
     let server_signing_map = {
         let voice = state.voice.read().await;
         voice.server_signing_pubkeys.clone()
@@ -130,7 +300,7 @@ async fn handle_connection_axum(socket: WebSocket, state: SharedState, client_ip
     let (presence_removed, voice_removed, redis_client) = {
         let mut signaling = state.signaling.write().await;
 
-        let peer_ids = if let Some(peer_ids) = signaling.conn_peers.remove(&conn_id) {
+        let peer_ids = if let Some(peer_ids) = signaling.conn_peers.remove(conn_id) {
             let ids: Vec<_> = peer_ids.iter().cloned().collect();
             for peer_id in &ids {
                 signaling.unregister_peer(peer_id);
@@ -139,21 +309,25 @@ async fn handle_connection_axum(socket: WebSocket, state: SharedState, client_ip
         } else {
             Vec::new()
         };
+        signaling.chat_subscriptions.remove(conn_id);
 
         drop(signaling);
 
         let mut voice = state.voice.write().await;
-        let voice_removed = voice.handle_voice_disconnect(&conn_id);
+        let voice_removed = voice.handle_voice_disconnect(conn_id);
         drop(voice);
 
         let mut presence = state.presence.write().await;
-        let presence_removed = presence.remove_presence_conn(&conn_id);
+        let presence_removed = presence.remove_presence_conn(conn_id);
         drop(presence);
 
         let mut swarm = state.swarm.write().await;
-        swarm.remove_conn(&conn_id);
+        swarm.remove_conn(conn_id);
         drop(swarm);
 
+        state.auth.write().await.remove_conn(conn_id);
+        state.protocol.write().await.remove_conn(conn_id);
+
         #[cfg(feature = "redis-backend")]
         let redis_client = {
             let backends = state.backends.read().await;
@@ -165,8 +339,20 @@ async fn handle_connection_axum(socket: WebSocket, state: SharedState, client_ip
         (presence_removed, voice_removed, redis_client)
     };
 
+    state.liveness.lock().await.remove_conn(conn_id);
+    let typing_stopped = state.typing.lock().await.take_conn(conn_id);
+    for key in typing_stopped {
+        state.broadcast_typing(key, false, None).await;
+    }
+
+    let syncs_ended = state.history.lock().await.remove_conn(conn_id);
+    for (sync_id, sync) in syncs_ended {
+        crate::state::history::send_done(&sync.sender, sync_id, true);
+    }
+
     if !voice_removed.is_empty() {
         for (server_id, chat_id, peer_id, user_id) in voice_removed.clone() {
+            state.announce_voice_leave(&server_id, &chat_id, server_signing_map.get(&server_id), &peer_id);
             info!(
                 "Voice peer {} (user {}) disconnected from chat {}",
                 peer_id, user_id, chat_id
@@ -191,7 +377,7 @@ async fn handle_connection_axum(socket: WebSocket, state: SharedState, client_ip
     }
 
     if let Some((user_id, spks)) = presence_removed {
-        state.friends.write().await.unregister_connection(&user_id, &conn_id);
+        state.friends.write().await.unregister_connection(&user_id, conn_id);
 
         #[cfg(feature = "redis-backend")]
         if let Some(client) = redis_client.as_ref() {
@@ -208,7 +394,89 @@ async fn handle_connection_axum(socket: WebSocket, state: SharedState, client_ip
         state.broadcast_friend_presence_update(&user_id, false, None).await;
     }
 
-    send_task.abort();
+    state.publish(ClusterEvent::ConnClosed {
+        conn_id: conn_id.clone(),
+    });
+}
 
-    state.connection_tracker.write().await.unregister(&client_ip);
+/// Longest client request_id echoed back; longer ones are ignored.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+#[derive(serde::Deserialize)]
+struct RequestEnvelope {
+    #[serde(default, rename = "type")]
+    kind: Option<String>,
+    #[serde(default)]
+    request_id: Option<serde_json::Value>,
+}
+
+/// Optional correlation id a client can put on any message (next to `type`). AttachmentTransfer*
+/// messages already use `request_id` for the transfer itself, so they never get one echoed.
+fn request_id_of(text: &str) -> Option<String> {
+    let envelope = serde_json::from_str::<RequestEnvelope>(text).ok()?;
+    if envelope.kind.as_deref().is_some_and(|kind| kind.starts_with("AttachmentTransfer")) {
+        return None;
+    }
+    match envelope.request_id? {
+        serde_json::Value::String(id) if !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN => Some(id),
+        _ => None,
+    }
+}
+
+fn send_error(tx: &WebSocketSender, error: SignalingError, request_id: Option<&str>) {
+    let error_msg = SignalingMessage::Error {
+        code: error.code,
+        message: error.message,
+        request_id: request_id.map(str::to_string),
+    };
+    if let Ok(json) = serde_json::to_string(&error_msg) {
+        let _ = tx.send(tokio_tungstenite::tungstenite::Message::Text(json));
+    }
+}
+
+/// Rate-limit cost of a client message: relays are cheap, messages the beacon fans out to whole
+/// servers or friend lists are charged more and also count against the fan-out budget.
+fn ws_message_cost(msg: &SignalingMessage) -> (u32, WsBudget) {
+    match msg {
+        SignalingMessage::Ping
+        | SignalingMessage::Pong
+        | SignalingMessage::Hello { .. }
+        | SignalingMessage::AuthResponse { .. }
+        | SignalingMessage::IceCandidate { .. }
+        | SignalingMessage::VoiceIceCandidate { .. }
+        | SignalingMessage::SwarmHealthUpdate { .. }
+        | SignalingMessage::HistorySyncResponse { .. } => (1, WsBudget::General),
+        SignalingMessage::EphemeralChatSend { .. }
+        | SignalingMessage::EphemeralReceiptSend { .. }
+        | SignalingMessage::EphemeralChatEdit { .. }
+        | SignalingMessage::EphemeralChatDelete { .. }
+        | SignalingMessage::EphemeralReactionAdd { .. }
+        | SignalingMessage::EphemeralReactionRemove { .. }
+        | SignalingMessage::TypingStart { .. }
+        | SignalingMessage::TypingStop { .. } => (1, WsBudget::Fanout),
+        SignalingMessage::PresenceActive { .. } | SignalingMessage::HistorySyncRequest { .. } => (3, WsBudget::Fanout),
+        SignalingMessage::ProfileAnnounce { .. } | SignalingMessage::ProfilePush { .. } => (5, WsBudget::Fanout),
+        SignalingMessage::PresenceHello { .. } => (10, WsBudget::Fanout),
+        SignalingMessage::ProfileHello { .. } | SignalingMessage::SwarmPeerListRequest { .. } => (3, WsBudget::General),
+        _ => (2, WsBudget::General),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfer_ids_are_not_echoed_as_correlation_ids() {
+        assert_eq!(request_id_of(r#"{"type":"Ping","request_id":"r1"}"#).as_deref(), Some("r1"));
+        assert_eq!(request_id_of(r#"{"type":"Ping","request_id":7}"#), None);
+        assert_eq!(
+            request_id_of(r#"{"type":"AttachmentTransferRequest","to_user_id":"u","request_id":"t1","attachment_id":"a"}"#),
+            None
+        );
+        assert_eq!(
+            request_id_of(r#"{"type":"AttachmentTransferSignal","to_user_id":"u","request_id":"t1","signal":"s"}"#),
+            None
+        );
+    }
 }
@@ -22,7 +22,6 @@ use chrono::{DateTime, Duration, Utc};
 use http_body_util::BodyExt;
 use log::{error, info};
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
 use tower_http::limit::RequestBodyLimitLayer;
 use tower_http::set_header::SetResponseHeaderLayer;
 use tower_http::trace::TraceLayer;
@@ -33,11 +32,18 @@ use sqlx::postgres::PgPoolOptions;
 pub mod state;
 pub mod handlers;
 pub mod security;
+pub mod signing;
+pub mod outbound;
+pub mod cluster;
+pub mod federation;
+pub mod wire;
+#[cfg(feature = "tls")]
+pub mod tls;
 
 pub type PeerId = String;
 pub type ServerId = String;
 pub type SigningPubkey = String;
-pub type WebSocketSender = mpsc::UnboundedSender<tokio_tungstenite::tungstenite::Message>;
+pub type WebSocketSender = outbound::OutboundSender;
 pub type ConnId = String;
 
 pub(crate) fn decode_path_segment(seg: &str) -> String {
@@ -47,8 +53,12 @@ pub(crate) fn decode_path_segment(seg: &str) -> String {
     }
 }
 
-/// Middleware for /api/friends/*: verify Ed25519-signed request, then insert VerifiedFriendUserId into request extensions.
-async fn friend_auth_middleware(request: Request, next: Next) -> Response {
+/// Middleware for /api/friends/*: verify Ed25519-signed request (single use), then insert VerifiedFriendUserId into request extensions.
+async fn friend_auth_middleware(
+    axum::extract::State(state): axum::extract::State<Arc<state::AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
     let (mut parts, body) = request.into_parts();
     let body_bytes = match body.collect().await {
         Ok(collected) => collected.to_bytes(),
@@ -68,12 +78,95 @@ async fn friend_auth_middleware(request: Request, next: Next) -> Response {
         Ok(uid) => uid,
         Err((code, msg)) => return (code, msg).into_response(),
     };
+    if let Err((code, msg)) = handlers::friends::check_signed_request_replay(&state.replay_cache, &parts.headers, "x-signature").await {
+        return (code, msg).into_response();
+    }
     // Insert the inner type T; Extension<T> extractor looks up extensions.get::<T>(), not Extension<T>
     parts.extensions.insert(handlers::friends::VerifiedFriendUserId(verified_user_id));
     let request = Request::from_parts(parts, Body::from(body_bytes));
     next.run(request).await
 }
 
+/// Middleware for /api/servers/:signing_pubkey/events*: verify identity + server membership signatures
+/// (single use), then insert VerifiedServerMember into request extensions.
+///
+/// Membership is proven with the hint delegate key, which derives from the shared server key. A
+/// removed member can still derive it and pass this check until the owner rotates the server key:
+/// the rotation hint names a new delegate and the old one is refused from then on. The beacon has
+/// no member list of its own to revoke anyone sooner.
+async fn server_member_auth_middleware(
+    axum::extract::State(state): axum::extract::State<Arc<state::AppState>>,
+    axum::extract::Path(signing_pubkey): axum::extract::Path<String>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let body_bytes = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(_) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Body read failed").into_response();
+        }
+    };
+    let signing_pubkey = decode_path_segment(&signing_pubkey);
+    let stored_hint = handlers::http::load_server_hint(&state, &signing_pubkey).await;
+    // Full path as received (merged, not nested), e.g. /api/servers/<urlencoded spk>/events
+    let path = parts.uri.path().to_string();
+    let member = match signing::verify_server_member_sig(
+        &parts.method,
+        &path,
+        &parts.headers,
+        &body_bytes,
+        &signing_pubkey,
+        stored_hint.as_ref(),
+    ) {
+        Ok(member) => member,
+        Err((code, msg)) => return (code, msg).into_response(),
+    };
+    if let Err((code, msg)) = handlers::friends::check_signed_request_replay(&state.replay_cache, &parts.headers, "x-signature").await {
+        return (code, msg).into_response();
+    }
+    parts.extensions.insert(member);
+    let request = Request::from_parts(parts, Body::from(body_bytes));
+    next.run(request).await
+}
+
+/// Middleware for server-admin routes (/api/servers/:signing_pubkey/invites*): verify the request is signed
+/// by the server key or a hint admin key (single use), then insert VerifiedServerAdmin into request extensions.
+async fn server_admin_auth_middleware(
+    axum::extract::State(state): axum::extract::State<Arc<state::AppState>>,
+    axum::extract::Path(params): axum::extract::Path<std::collections::HashMap<String, String>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let body_bytes = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(_) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Body read failed").into_response();
+        }
+    };
+    let signing_pubkey = decode_path_segment(params.get("signing_pubkey").map(String::as_str).unwrap_or(""));
+    let stored_hint = handlers::http::load_server_hint(&state, &signing_pubkey).await;
+    let path = parts.uri.path().to_string();
+    let admin = match signing::verify_server_admin_sig(
+        &parts.method,
+        &path,
+        &parts.headers,
+        &body_bytes,
+        &signing_pubkey,
+        stored_hint.as_ref(),
+    ) {
+        Ok(admin) => admin,
+        Err((code, msg)) => return (code, msg).into_response(),
+    };
+    if let Err((code, msg)) = handlers::friends::check_signed_request_replay(&state.replay_cache, &parts.headers, "x-server-signature").await {
+        return (code, msg).into_response();
+    }
+    parts.extensions.insert(admin);
+    let request = Request::from_parts(parts, Body::from(body_bytes));
+    next.run(request).await
+}
+
 // Invite tokens are temporary and opaque to the server. Clients encrypt payloads; the server only stores/forwards.
 
 // ============================================
@@ -113,9 +206,12 @@ pub enum SignalingMessage {
         peer_id: PeerId,
         peers: Vec<PeerId>,
     },
-    /// Error message from server
+    /// Error message from server. request_id echoes the failed client message's request_id.
     Error {
+        code: ErrorCode,
         message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
     },
     /// Broadcast when a new member joins the server
     ServerMemberJoined {
@@ -169,6 +265,141 @@ pub enum SignalingMessage {
         sent_at: String,
     },
 
+    /// Edit of message_id. The encrypted payload carries the new text signed with the author's
+    /// identity key; clients apply it only if that matches the original author. Like the other
+    /// envelopes below, the client sends it without from_user_id / sent_at and the beacon relays
+    /// it to the chat with both filled in.
+    EphemeralChatEdit {
+        signing_pubkey: SigningPubkey,
+        chat_id: String,
+        message_id: String,
+        encrypted_payload: String,
+        #[serde(default)]
+        from_user_id: Option<String>,
+        #[serde(default)]
+        sent_at: Option<String>,
+    },
+
+    /// Deletion of message_id (signed by the author inside the encrypted payload, as for edits).
+    EphemeralChatDelete {
+        signing_pubkey: SigningPubkey,
+        chat_id: String,
+        message_id: String,
+        encrypted_payload: String,
+        #[serde(default)]
+        from_user_id: Option<String>,
+        #[serde(default)]
+        sent_at: Option<String>,
+    },
+
+    /// Reaction added to message_id (the encrypted payload carries the signed reaction).
+    EphemeralReactionAdd {
+        signing_pubkey: SigningPubkey,
+        chat_id: String,
+        message_id: String,
+        encrypted_payload: String,
+        #[serde(default)]
+        from_user_id: Option<String>,
+        #[serde(default)]
+        sent_at: Option<String>,
+    },
+
+    /// Reaction removed from message_id.
+    EphemeralReactionRemove {
+        signing_pubkey: SigningPubkey,
+        chat_id: String,
+        message_id: String,
+        encrypted_payload: String,
+        #[serde(default)]
+        from_user_id: Option<String>,
+        #[serde(default)]
+        sent_at: Option<String>,
+    },
+
+    /// Client follows chats of a server it is registered for (chat_subscriptions capability).
+    /// From the first ChatSubscribe on, the connection gets ephemeral messages and receipts only
+    /// for followed chats, and ChatActivity for the others. An empty list just opts in.
+    ChatSubscribe {
+        signing_pubkey: SigningPubkey,
+        chat_ids: Vec<String>,
+    },
+
+    /// Client stops following chats (it keeps getting ChatActivity for them).
+    ChatUnsubscribe {
+        signing_pubkey: SigningPubkey,
+        chat_ids: Vec<String>,
+    },
+
+    /// Unread ping for a chat the connection does not follow: no payload, coalesced per chat.
+    ChatActivity {
+        signing_pubkey: SigningPubkey,
+        chat_id: String,
+        message_id: String,
+        from_user_id: String,
+        sent_at: String,
+    },
+
+    /// Client is typing in a chat. Repeat every few seconds while typing; the beacon stops the
+    /// indicator after state::typing::TYPING_TTL without a refresh.
+    TypingStart {
+        signing_pubkey: SigningPubkey,
+        chat_id: String,
+    },
+
+    /// Client stopped typing (also implied by EphemeralChatSend and by disconnecting).
+    TypingStop {
+        signing_pubkey: SigningPubkey,
+        chat_id: String,
+    },
+
+    /// Beacon relays typing changes to the chat's subscribers (never stored, coalesced per user).
+    TypingUpdate {
+        signing_pubkey: SigningPubkey,
+        chat_id: String,
+        user_id: String,
+        typing: bool,
+    },
+
+    /// Client asks online members of a server for encrypted history of a chat (history_sync
+    /// capability). The beacon forwards it to a few of them, with from_user_id filled in.
+    HistorySyncRequest {
+        sync_id: String,
+        signing_pubkey: SigningPubkey,
+        chat_id: String,
+        /// "before" or "after" the cursor.
+        direction: String,
+        /// message_id to page from (none = newest).
+        #[serde(default)]
+        cursor: Option<String>,
+        limit: u32,
+        #[serde(default)]
+        from_user_id: Option<String>,
+    },
+
+    /// Beacon tells the requester how many peers were asked (0 = nobody online to ask).
+    HistorySyncStarted {
+        sync_id: String,
+        peer_count: u32,
+    },
+
+    /// Peer answers a HistorySyncRequest with one encrypted batch (repeat, last one with done).
+    /// Beacon relays it to the requester with from_user_id filled in.
+    HistorySyncResponse {
+        sync_id: String,
+        encrypted_payload: String,
+        #[serde(default)]
+        done: bool,
+        #[serde(default)]
+        from_user_id: Option<String>,
+    },
+
+    /// Beacon ends a sync: every peer was done, or (truncated) the byte budget ran out, the peers
+    /// took too long or left.
+    HistorySyncDone {
+        sync_id: String,
+        truncated: bool,
+    },
+
     /// Receiver requests attachment bytes from original sender.
     AttachmentTransferRequest {
         to_user_id: String,
@@ -257,6 +488,61 @@ pub enum SignalingMessage {
         leechers: Option<u32>,
     },
 
+    // ============================
+    // Handshake (protocol version + capabilities)
+    // ============================
+
+    /// Optional first client message. Clients that skip it are treated as protocol 1.
+    Hello {
+        protocol_version: u32,
+        #[serde(default)]
+        capabilities: Vec<String>,
+    },
+
+    /// Beacon reply to Hello: negotiated version and the capabilities granted to this connection.
+    Welcome {
+        protocol_version: u32,
+        min_protocol_version: u32,
+        capabilities: Vec<String>,
+    },
+
+    // ============================
+    // Login (identity challenge-response)
+    // ============================
+
+    /// Sent by the beacon right after connect. Client signs state::auth::auth_challenge_message(nonce).
+    AuthChallenge {
+        nonce: String,
+    },
+
+    /// Client proves its identity: user_id must be hex(sha256(public_key)[..16]).
+    AuthResponse {
+        user_id: String,
+        public_key: String, // hex Ed25519
+        signature: String,  // base64
+    },
+
+    /// Beacon confirms the connection is bound to user_id.
+    AuthOk {
+        user_id: String,
+    },
+
+    // ============================
+    // Session resume
+    // ============================
+
+    /// Sent before AuthChallenge on a new session. After a drop, reconnecting to /ws?resume=<token>
+    /// within resume_grace_secs reattaches to this session (same login, registrations and queued messages).
+    SessionStarted {
+        resume_token: String,
+        resume_grace_secs: u64,
+    },
+
+    /// Reply to a successful resume instead of AuthChallenge. Tokens are single use: keep the new one.
+    Resumed {
+        resume_token: String,
+    },
+
     // ============================
     // Presence (online/offline + active server)
     // ============================
@@ -272,6 +558,12 @@ pub enum SignalingMessage {
         friend_user_ids: Vec<String>,
     },
 
+    /// Authenticated client opts in or out of the offline mailbox (state::mailbox): direct
+    /// messages that find none of its connections wait for its next PresenceHello.
+    MailboxOptIn {
+        enabled: bool,
+    },
+
     /// Client updates which server is currently active (or clears it to indicate "home").
     PresenceActive {
         user_id: String,
@@ -550,6 +842,65 @@ pub enum SignalingMessage {
     },
 }
 
+/// Stable reason carried by SignalingMessage::Error so clients can react without parsing `message`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Malformed message or missing / invalid field.
+    InvalidMessage,
+    /// Message type or feature this beacon or connection does not support (see Hello capabilities).
+    Unsupported,
+    RateLimited,
+    /// AuthResponse missing or failed, or user_id is not the connection's verified identity.
+    Unauthorized,
+    /// The message needs an earlier registration on this connection (PresenceHello).
+    NotRegistered,
+    /// peer_id is not registered by this connection.
+    InvalidPeer,
+    PayloadTooLarge,
+    /// Beacon-side failure (serialization, connection closing).
+    Internal,
+}
+
+/// Failure from a WebSocket message handler; sent to the client as SignalingMessage::Error.
+#[derive(Debug, Clone)]
+pub struct SignalingError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl SignalingError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+
+    pub fn invalid_message(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidMessage, message)
+    }
+
+    /// `what` requires PresenceHello first.
+    pub fn not_registered(what: &str) -> Self {
+        Self::new(ErrorCode::NotRegistered, format!("{} requires PresenceHello first", what))
+    }
+
+    pub fn invalid_peer(peer_id: &str, conn_id: &str) -> Self {
+        Self::new(ErrorCode::InvalidPeer, format!("Invalid peer_id {} for connection {}", peer_id, conn_id))
+    }
+}
+
+/// Serialization / send failures surface as internal errors.
+impl From<String> for SignalingError {
+    fn from(message: String) -> Self {
+        Self::new(ErrorCode::Internal, message)
+    }
+}
+
+impl std::fmt::Display for SignalingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FriendRequestIncomingItem {
     pub from_user_id: String,
@@ -609,7 +960,8 @@ pub struct VoicePeer {
 // ============================================
 
 /// Server hint - NOT authoritative, just a cache/recovery aid
-/// Any member can overwrite at any time (no creator lock)
+/// Writers must sign with the server key or its authorized delegate (see signing::verify_server_hint);
+/// older hints never replace newer ones.
 /// 
 /// Trust boundary: Clients MUST treat local state as authoritative even if server state differs.
 /// The server is not the source of truth - this is just a cache/recovery aid.
@@ -617,8 +969,33 @@ pub struct VoicePeer {
 pub struct EncryptedServerHint {
     pub signing_pubkey: String,
     pub encrypted_state: String,  // Beacon cannot decrypt
-    pub signature: String,        // Signed by member's Ed25519 key
+    pub signature: String,        // Base64 Ed25519 over signing::server_hint_message
     pub last_updated: DateTime<Utc>,
+    /// Key that produced `signature` (base64 Ed25519). None = the server signing key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer_pubkey: Option<String>,
+    /// Member delegate key the server key authorizes to publish hints (base64 Ed25519).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delegate_pubkey: Option<String>,
+    /// Admin keys the server key authorizes to manage invites (base64 Ed25519).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub admin_pubkeys: Vec<String>,
+    /// Server symmetric key of the current epoch, sealed to each remaining member after a rotation.
+    /// Only the server key sets these; delegate-signed hints keep the stored ones
+    /// (see signing::carry_forward_key_envelopes).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_envelopes: Vec<SealedServerKey>,
+}
+
+/// A server key sealed to one member's X25519 key. Covered by server-key hint signatures (see
+/// signing::server_hint_message); a member also only accepts it if the unsealed key decrypts the
+/// signed `encrypted_state`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SealedServerKey {
+    pub user_id: String,
+    pub epoch: u32,
+    /// Base64 ephemeral X25519 public key || nonce || ciphertext.
+    pub sealed_key: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -649,12 +1026,18 @@ pub struct ServerEvent {
     pub encrypted_payload: String, // Beacon cannot decrypt
     pub signature: String,         // Signed by member's Ed25519 key
     pub timestamp: DateTime<Utc>,
+    /// Assigned by the beacon: strictly increasing per signing_pubkey, starting at 1.
+    #[serde(default)]
+    pub seq: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AckRequest {
     pub user_id: String,
     pub last_event_id: String,
+    /// Seq of the last event seen (preferred over last_event_id, which may be compacted away).
+    #[serde(default)]
+    pub last_seq: Option<u64>,
 }
 
 // ============================================
@@ -699,7 +1082,6 @@ pub struct ProfileRecord {
 // ============================================
 // Moved to handlers/db.rs and handlers/redis.rs
 
-const EVENT_RETENTION_DAYS: i64 = 30;
 #[cfg(feature = "redis-backend")]
 pub const DEFAULT_REDIS_PRESENCE_TTL_SECS: u64 = 120;
 
@@ -714,7 +1096,7 @@ use state::voice::VoicePeerInfo;
 #[cfg(feature = "postgres")]
 use handlers::db::init_db;
 #[cfg(feature = "postgres")]
-use handlers::db::gc_old_events_db;
+use handlers::db::{compact_events_db, gc_mailbox_db, gc_old_events_db};
 #[cfg(feature = "redis-backend")]
 use handlers::redis::redis_presence_refresh;
 
@@ -926,17 +1308,44 @@ async fn main() {
 
     let rest_rate_limiter = security::build_rest_rate_limiter(security_config.rate_limit_rest_per_min);
     let rest_rate_limiter_for_layer = Arc::new(rest_rate_limiter);
-    let ws_rate_limiter = security::build_ws_rate_limiter(security_config.rate_limit_ws_per_min);
+    let ws_limits = Arc::new(security::WsRateLimits::from_config(&security_config));
     if rest_rate_limiter_for_layer.is_some() {
         info!("REST rate limit: {} requests/min per IP", security_config.rate_limit_rest_per_min);
     }
-    if ws_rate_limiter.is_some() {
-        info!("WebSocket rate limit: {} messages/min per IP", security_config.rate_limit_ws_per_min);
+    if ws_limits.is_enabled() {
+        info!(
+            "WebSocket rate limits (cost units/min): ip={}, user={}, conn={}, fanout={}",
+            security_config.rate_limit_ws_per_min,
+            security_config.rate_limit_ws_user_per_min,
+            security_config.rate_limit_ws_conn_per_min,
+            security_config.rate_limit_ws_fanout_per_min
+        );
     }
 
     let downtime_secs = read_downtime_secs();
     let addr: SocketAddr = "0.0.0.0:9001".parse().expect("Invalid address");
-    let state = Arc::new(AppState::new(downtime_secs, connection_tracker, ws_rate_limiter));
+    let replay_cache = Arc::new(tokio::sync::Mutex::new(security::ReplayCache::new(
+        security_config.replay_cache_size,
+    )));
+    let outbound_limits = Arc::new(outbound::OutboundLimits::new(
+        security_config.ws_outbound_queue_max,
+        std::time::Duration::from_secs(security_config.ws_slow_consumer_grace_secs),
+    ));
+    let state = Arc::new(AppState::new(downtime_secs, connection_tracker, ws_limits, replay_cache, outbound_limits, security::WsHeartbeat::from_config(&security_config), std::time::Duration::from_secs(security_config.ws_resume_grace_secs)));
+    let event_limits = state::events::EventLimits::from_env();
+    info!(
+        "Server event queues: retention={}d, max_events={}, max_bytes={} (0 = no cap)",
+        event_limits.retention_days, event_limits.max_events_per_server, event_limits.max_bytes_per_server
+    );
+    state.events.write().await.limits = event_limits;
+    let mailbox_limits = state::mailbox::MailboxLimits::from_env();
+    if mailbox_limits.enabled() {
+        info!(
+            "Offline mailbox: ttl={}s, max={}, max_bytes={} (0 = no cap)",
+            mailbox_limits.ttl_secs, mailbox_limits.max_per_user, mailbox_limits.max_bytes_per_user
+        );
+    }
+    state.mailbox.lock().await.limits = mailbox_limits;
 
     // Optional Postgres durability (profiles first; others later)
     #[cfg(feature = "postgres")]
@@ -993,6 +1402,36 @@ async fn main() {
         }
     }
 
+    // Optional cluster mode: route peer/user/server messages between beacons over Redis pub/sub
+    if let Some(settings) = cluster::ClusterSettings::from_env() {
+        #[cfg(feature = "redis-backend")]
+        {
+            let client = state.backends.read().await.redis.clone();
+            match client {
+                Some(client) => cluster::start(
+                    state.clone(),
+                    cluster::Transport::Redis {
+                        client,
+                        channel_prefix: settings.channel_prefix,
+                    },
+                    settings.node_id,
+                ),
+                None => log::warn!("BEACON_CLUSTER is set but Redis is not connected; running standalone."),
+            }
+        }
+        #[cfg(not(feature = "redis-backend"))]
+        log::warn!("BEACON_CLUSTER is set but this build lacks the redis-backend feature; running standalone.");
+    }
+
+    // Optional federation with allow-listed beacons (members of one server on different beacons)
+    if let Some(settings) = federation::FederationSettings::from_env() {
+        if state.cluster.get().is_some() {
+            log::warn!("BEACON_FEDERATION_PEERS is ignored in cluster mode.");
+        } else {
+            federation::start(state.clone(), settings);
+        }
+    }
+
     // Spawn background task for garbage collection
     let gc_state = state.clone();
     tokio::spawn(async move {
@@ -1001,7 +1440,9 @@ async fn main() {
             let (db, cutoff) = {
                 let mut events = gc_state.events.write().await;
                 events.gc_old_events();
+                let retention_days = events.limits.retention_days;
                 drop(events);
+                gc_state.mailbox.lock().await.gc(Utc::now());
                 #[cfg(feature = "postgres")]
                 let db = {
                     let backends = gc_state.backends.read().await;
@@ -1009,21 +1450,68 @@ async fn main() {
                 };
                 #[cfg(not(feature = "postgres"))]
                 let db: Option<()> = None;
-                let cutoff = Utc::now() - Duration::days(EVENT_RETENTION_DAYS);
+                let cutoff = Utc::now() - Duration::days(retention_days);
                 (db, cutoff)
             };
 
             #[cfg(feature = "postgres")]
             if let Some(pool) = db {
+                if let Err(e) = compact_events_db(&pool, None).await {
+                    log::warn!("DB event compaction failed: {}", e);
+                }
                 if let Err(e) = gc_old_events_db(&pool, cutoff).await {
                     log::warn!("DB GC failed: {}", e);
                 }
+                if let Err(e) = gc_mailbox_db(&pool).await {
+                    log::warn!("DB mailbox GC failed: {}", e);
+                }
             }
 
+            gc_state.ws_limits.retain_recent();
             info!("Garbage collected old events");
         }
     });
 
+    // Sessions whose socket dropped and were not resumed within the grace period: run the normal
+    // disconnect cleanup (leave / offline broadcasts) for them.
+    if security_config.ws_resume_grace_secs > 0 {
+        let session_state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                let expired = session_state.sessions.lock().await.take_expired(std::time::Instant::now());
+                for conn_id in expired {
+                    info!("WebSocket session {} not resumed; cleaning up", conn_id);
+                    handlers::ws::cleanup_connection(&session_state, &conn_id).await;
+                }
+            }
+        });
+    }
+
+    // Typing indicators not refreshed in time (client crashed or lost the network).
+    let typing_state = state.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+            let expired = typing_state.typing.lock().await.take_expired(std::time::Instant::now());
+            for key in expired {
+                typing_state.broadcast_typing(key, false, None).await;
+            }
+        }
+    });
+
+    // History syncs whose peers did not finish in time.
+    let history_state = state.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+            let expired = history_state.history.lock().await.take_expired(std::time::Instant::now());
+            for (sync_id, sync) in expired {
+                state::history::send_done(&sync.sender, sync_id, true);
+            }
+        }
+    });
+
     // Background CPU sampling (sysinfo needs two refreshes with delay for non-zero process CPU).
     // Smooth over last 5 samples so the status page doesn't flicker 0 ↔ small %.
     let cpu_state = state.clone();
@@ -1092,11 +1580,20 @@ async fn main() {
 
     let server_routes = Router::new()
         .route("/register", axum::routing::post(handlers::http::register_server_hint))
-        .route("/hint", get(handlers::http::get_server_hint))
-        .route("/invites", axum::routing::post(handlers::http::create_server_invite))
-        .route("/events", get(handlers::http::get_events).post(handlers::http::post_event))
-        .route("/events/ack", axum::routing::post(handlers::http::ack_events))
-        .route("/ack", axum::routing::post(handlers::http::ack_events));
+        .route("/hint", get(handlers::http::get_server_hint));
+
+    // Invite management needs the server key (or a hint admin key).
+    let server_admin_routes = Router::new()
+        .route("/api/servers/:signing_pubkey/invites", get(handlers::http::list_server_invites).post(handlers::http::create_server_invite))
+        .route("/api/servers/:signing_pubkey/invites/:code/revoke", axum::routing::post(handlers::http::revoke_server_invite))
+        .route_layer(middleware::from_fn_with_state(state.clone(), server_admin_auth_middleware));
+
+    // Event queue routes need member auth; full paths + merge, same as friend routes below.
+    let server_event_routes = Router::new()
+        .route("/api/servers/:signing_pubkey/events", get(handlers::http::get_events).post(handlers::http::post_event))
+        .route("/api/servers/:signing_pubkey/events/ack", axum::routing::post(handlers::http::ack_events))
+        .route("/api/servers/:signing_pubkey/ack", axum::routing::post(handlers::http::ack_events))
+        .route_layer(middleware::from_fn_with_state(state.clone(), server_member_auth_middleware));
 
     // Friend routes with full paths and auth middleware. Merge (don't nest) so the same request
     // with extensions reaches the handler (nest was stripping and forwarding a new request).
@@ -1111,27 +1608,33 @@ async fn main() {
         .route("/api/friends/codes/redemptions/cancel", axum::routing::post(handlers::friends::cancel_code_redemption))
         .route("/api/friends/codes/redemptions/decline", axum::routing::post(handlers::friends::decline_code_redemption))
         .route("/api/friends/remove", axum::routing::post(handlers::friends::remove_friend))
-        .layer(middleware::from_fn(friend_auth_middleware));
+        .layer(middleware::from_fn_with_state(state.clone(), friend_auth_middleware));
 
     let app = Router::new()
         .route("/api/status", get(handlers::http::get_status))
         .route("/api/invites/:code", get(handlers::http::get_invite))
         .route("/api/invites/:code/redeem", axum::routing::post(handlers::http::redeem_invite))
-        .route("/api/invites/:code/revoke", axum::routing::post(handlers::http::revoke_invite))
         .merge(friend_routes)
+        .merge(server_event_routes)
+        .merge(server_admin_routes)
         .nest("/api/servers/:signing_pubkey", server_routes)
         .route("/health", get(|| async { "ok" }))
         .route("/", get(status_page_handler))
         .route("/status", get(status_page_handler))
         .route("/ws", get(handlers::ws::ws_handler))
+        .route("/federation", get(federation::federation_ws_handler))
         .fallback(|| async { (StatusCode::NOT_FOUND, "Not found. Use / or /status, /health, /api/*, or /ws for WebSocket.") })
-        .layer(middleware::from_fn(security::client_ip_middleware))
         .layer(middleware::from_fn(move |req: axum::extract::Request, next: axum::middleware::Next| {
             let limiter = Arc::clone(&rest_rate_limiter_for_layer);
             async move {
                 security::rest_rate_limit_middleware_optional(req, next, (*limiter).clone()).await
             }
         }))
+        // Outside the rate limiter so it sees ClientIp.
+        .layer(middleware::from_fn_with_state(
+            Arc::new(security_config.trusted_proxies.clone()),
+            security::client_ip_middleware,
+        ))
         .layer(security::build_cors_layer(&security_config))
         .layer(SetResponseHeaderLayer::if_not_present(
             axum::http::header::X_CONTENT_TYPE_OPTIONS,
@@ -1145,13 +1648,50 @@ async fn main() {
         .layer(TraceLayer::new_for_http())
         .with_state(state);
 
+    #[cfg(feature = "tls")]
+    if let Some(tls_settings) = tls::TlsSettings::from_env() {
+        let rustls_config = match tls::load_rustls_config(&tls_settings).await {
+            Ok(c) => c,
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        tls::spawn_reload_tasks(rustls_config.clone(), tls_settings.clone());
+        tls::spawn_redirect_listener(&tls_settings);
+
+        info!("Beacon listening on https://{}", addr);
+        info!("WebSocket endpoint: wss://{}/ws", addr);
+        info!("Health check: https://{}/health", addr);
+
+        let handle = axum_server::Handle::new();
+        let shutdown_handle = handle.clone();
+        tokio::spawn(async move {
+            tokio::signal::ctrl_c().await.ok();
+            write_last_stop_file();
+            shutdown_handle.graceful_shutdown(None);
+        });
+        if let Err(e) = axum_server::bind_rustls(addr, rustls_config)
+            .handle(handle)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+        {
+            error!("Server error: {}", e);
+        }
+        return;
+    }
+    #[cfg(not(feature = "tls"))]
+    if env::var("BEACON_TLS_CERT_PATH").is_ok() {
+        log::warn!("BEACON_TLS_CERT_PATH is set but the beacon was built without the \"tls\" feature; serving plain HTTP.");
+    }
+
     let listener = tokio::net::TcpListener::bind(&addr).await.expect("bind");
     info!("Beacon listening on http://{}", addr);
     info!("WebSocket endpoint: ws://{}/ws", addr);
     info!("REST API: http://{}/api/servers/{{signing_pubkey}}/... (server hints)", addr);
     info!("Health check: http://{}/health", addr);
 
-    let graceful = axum::serve(listener, app).with_graceful_shutdown(async {
+    let graceful = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).with_graceful_shutdown(async {
         tokio::signal::ctrl_c().await.ok();
         write_last_stop_file();
     });
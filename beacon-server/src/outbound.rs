@@ -0,0 +1,333 @@
+//! Bounded per-connection outbound queues.
+//!
+//! Each WebSocket connection gets one `OutboundSender` (cloned into every state map that pushes to
+//! it) and one writer task draining it. Signaling, ICE and replies go ahead of presence / profile
+//! noise; a presence or profile update for the same (server, user) replaces the one still queued.
+//! When a queue is full, noise is dropped; a consumer that stays over the limit for the grace
+//! period (or reaches twice the limit) is disconnected instead of growing beacon memory.
+//! While a resumable session has no socket attached, the queue keeps filling under the same limits
+//! and doubles as its replay buffer; the resuming socket takes it over with `resubscribe`.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Server → client message types that only carry superseded state and may be delayed or dropped.
+const LOW_PRIORITY_TYPES: &[&str] = &["PresenceUpdate", "VoicePresenceUpdate", "ProfileUpdate", "ChatActivity", "TypingUpdate"];
+
+/// Queue limits shared by all connections, plus global counters (reported by /api/status).
+pub struct OutboundLimits {
+    /// Max queued messages per connection before noise is dropped; 0 = unbounded.
+    pub max_queued: usize,
+    /// How long a connection may stay over max_queued before it is disconnected.
+    pub slow_consumer_grace: Duration,
+    pub metrics: OutboundMetrics,
+}
+
+impl OutboundLimits {
+    pub fn new(max_queued: usize, slow_consumer_grace: Duration) -> Self {
+        Self {
+            max_queued,
+            slow_consumer_grace,
+            metrics: OutboundMetrics::default(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct OutboundMetrics {
+    /// Messages currently queued across all connections.
+    pub queued: AtomicI64,
+    /// Deepest single-connection queue seen since start.
+    pub max_depth: AtomicU64,
+    pub sent_high: AtomicU64,
+    pub sent_low: AtomicU64,
+    pub coalesced: AtomicU64,
+    pub dropped_low: AtomicU64,
+    pub slow_consumer_disconnects: AtomicU64,
+}
+
+impl OutboundMetrics {
+    pub fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!({
+            "queued": self.queued.load(Ordering::Relaxed),
+            "max_depth": self.max_depth.load(Ordering::Relaxed),
+            "sent_high": self.sent_high.load(Ordering::Relaxed),
+            "sent_low": self.sent_low.load(Ordering::Relaxed),
+            "coalesced": self.coalesced.load(Ordering::Relaxed),
+            "dropped_low": self.dropped_low.load(Ordering::Relaxed),
+            "slow_consumer_disconnects": self.slow_consumer_disconnects.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// The connection is gone (closed, or disconnected as a slow consumer).
+#[derive(Debug)]
+pub struct SendError(pub Message);
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "connection closed")
+    }
+}
+
+impl std::error::Error for SendError {}
+
+struct Queue {
+    high: VecDeque<Message>,
+    /// (coalescing key, message)
+    low: VecDeque<(Option<String>, Message)>,
+    closed: bool,
+    over_limit_since: Option<Instant>,
+    /// Bumped by `resubscribe`; receivers from an older generation stop.
+    generation: u64,
+}
+
+impl Queue {
+    fn depth(&self) -> usize {
+        self.high.len() + self.low.len()
+    }
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    notify: Notify,
+    limits: Arc<OutboundLimits>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        let left = self.queue.lock().map(|q| q.depth()).unwrap_or(0);
+        self.limits.metrics.queued.fetch_sub(left as i64, Ordering::Relaxed);
+    }
+}
+
+/// Handle used everywhere a message is pushed to a connection (the beacon's WebSocketSender).
+#[derive(Clone)]
+pub struct OutboundSender(Arc<Shared>);
+
+/// Drained by the connection's writer task.
+pub struct OutboundReceiver {
+    shared: Arc<Shared>,
+    generation: u64,
+}
+
+pub fn channel(limits: Arc<OutboundLimits>) -> (OutboundSender, OutboundReceiver) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(Queue {
+            high: VecDeque::new(),
+            low: VecDeque::new(),
+            closed: false,
+            over_limit_since: None,
+            generation: 0,
+        }),
+        notify: Notify::new(),
+        limits,
+    });
+    (
+        OutboundSender(shared.clone()),
+        OutboundReceiver {
+            shared,
+            generation: 0,
+        },
+    )
+}
+
+/// `type` tag of a serialized SignalingMessage (serde puts it first).
+fn message_type(text: &str) -> Option<&str> {
+    let rest = text.strip_prefix("{\"type\":\"")?;
+    rest.split('"').next()
+}
+
+fn is_low_priority(msg: &Message) -> bool {
+    match msg {
+        Message::Text(text) => message_type(text).is_some_and(|t| LOW_PRIORITY_TYPES.contains(&t)),
+        _ => false,
+    }
+}
+
+impl OutboundSender {
+    /// Queue a message. Presence / profile updates go behind everything else.
+    pub fn send(&self, msg: Message) -> Result<(), SendError> {
+        let low = is_low_priority(&msg);
+        self.push(None, low, msg)
+    }
+
+    /// Queue a low-priority state update, replacing a still-queued one with the same key
+    /// (e.g. presence of one user on one server).
+    pub fn send_coalesced(&self, key: String, msg: Message) -> Result<(), SendError> {
+        self.push(Some(key), true, msg)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.queue.lock().map(|q| q.closed).unwrap_or(true)
+    }
+
+    /// Stop the writer; further sends fail.
+    pub fn close(&self) {
+        if let Ok(mut q) = self.0.queue.lock() {
+            q.closed = true;
+        }
+        self.0.notify.notify_one();
+    }
+
+    /// New receiver for a resumed session; the previous writer's receiver stops. Messages still
+    /// queued are delivered to the new receiver.
+    pub fn resubscribe(&self) -> Option<OutboundReceiver> {
+        let generation = {
+            let mut q = self.0.queue.lock().ok()?;
+            if q.closed {
+                return None;
+            }
+            q.generation += 1;
+            q.generation
+        };
+        self.0.notify.notify_waiters();
+        Some(OutboundReceiver {
+            shared: self.0.clone(),
+            generation,
+        })
+    }
+
+    fn push(&self, key: Option<String>, low: bool, msg: Message) -> Result<(), SendError> {
+        let limits = &self.0.limits;
+        let metrics = &limits.metrics;
+        let Ok(mut q) = self.0.queue.lock() else {
+            return Err(SendError(msg));
+        };
+        if q.closed {
+            return Err(SendError(msg));
+        }
+
+        if let Some(key) = key.as_deref() {
+            if let Some(slot) = q.low.iter_mut().find(|(k, _)| k.as_deref() == Some(key)) {
+                slot.1 = msg;
+                metrics.coalesced.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+        }
+
+        let max = limits.max_queued;
+        let depth = q.depth();
+        if max > 0 && depth >= max {
+            if low {
+                metrics.dropped_low.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            let now = Instant::now();
+            let since = *q.over_limit_since.get_or_insert(now);
+            if depth >= max.saturating_mul(2) || now.duration_since(since) > limits.slow_consumer_grace {
+                q.closed = true;
+                metrics.slow_consumer_disconnects.fetch_add(1, Ordering::Relaxed);
+                drop(q);
+                self.0.notify.notify_one();
+                return Err(SendError(msg));
+            }
+        }
+
+        if low {
+            q.low.push_back((key, msg));
+        } else {
+            q.high.push_back(msg);
+        }
+        metrics.queued.fetch_add(1, Ordering::Relaxed);
+        metrics.max_depth.fetch_max((depth + 1) as u64, Ordering::Relaxed);
+        drop(q);
+        self.0.notify.notify_one();
+        Ok(())
+    }
+}
+
+impl OutboundReceiver {
+    /// Next message, high priority first. None once the queue is closed (queued messages are
+    /// discarded: a closed queue means the connection is going away).
+    pub async fn recv(&mut self) -> Option<Message> {
+        loop {
+            {
+                let limits = &self.shared.limits;
+                let mut q = self.shared.queue.lock().ok()?;
+                if q.closed {
+                    return None;
+                }
+                if q.generation != self.generation {
+                    // Pass on a wakeup this stale receiver may have taken from the current one.
+                    self.shared.notify.notify_one();
+                    return None;
+                }
+                let next = match q.high.pop_front() {
+                    Some(m) => {
+                        limits.metrics.sent_high.fetch_add(1, Ordering::Relaxed);
+                        Some(m)
+                    }
+                    None => q.low.pop_front().map(|(_, m)| {
+                        limits.metrics.sent_low.fetch_add(1, Ordering::Relaxed);
+                        m
+                    }),
+                };
+                if let Some(m) = next {
+                    limits.metrics.queued.fetch_sub(1, Ordering::Relaxed);
+                    if limits.max_queued == 0 || q.depth() < limits.max_queued {
+                        q.over_limit_since = None;
+                    }
+                    return Some(m);
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Message {
+        Message::Text(s.to_string())
+    }
+
+    #[tokio::test]
+    async fn prioritizes_coalesces_and_disconnects_slow_consumers() {
+        let limits = Arc::new(OutboundLimits::new(4, Duration::from_secs(60)));
+        let (tx, mut rx) = channel(limits.clone());
+
+        tx.send_coalesced("p:a".into(), text(r#"{"type":"PresenceUpdate","n":1}"#)).unwrap();
+        tx.send(text(r#"{"type":"VoiceOffer"}"#)).unwrap();
+        tx.send_coalesced("p:a".into(), text(r#"{"type":"PresenceUpdate","n":2}"#)).unwrap();
+        assert_eq!(rx.recv().await, Some(text(r#"{"type":"VoiceOffer"}"#)));
+        assert_eq!(rx.recv().await, Some(text(r#"{"type":"PresenceUpdate","n":2}"#)));
+
+        // Full queue: noise is dropped, signaling is kept up to twice the limit, then the consumer is cut off.
+        for _ in 0..4 {
+            tx.send(text(r#"{"type":"Offer"}"#)).unwrap();
+        }
+        tx.send(text(r#"{"type":"ProfileUpdate"}"#)).unwrap();
+        assert_eq!(limits.metrics.dropped_low.load(Ordering::Relaxed), 1);
+        for _ in 0..4 {
+            tx.send(text(r#"{"type":"Offer"}"#)).unwrap();
+        }
+        assert!(tx.send(text(r#"{"type":"Offer"}"#)).is_err());
+        assert!(tx.is_closed());
+        assert_eq!(rx.recv().await, None);
+        assert_eq!(limits.metrics.slow_consumer_disconnects.load(Ordering::Relaxed), 1);
+
+        drop(tx);
+        drop(rx);
+        assert_eq!(limits.metrics.queued.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn resubscribe_hands_queued_messages_to_the_new_receiver() {
+        let limits = Arc::new(OutboundLimits::new(4, Duration::from_secs(60)));
+        let (tx, mut old) = channel(limits);
+        tx.send(text(r#"{"type":"Offer"}"#)).unwrap();
+
+        let mut new = tx.resubscribe().unwrap();
+        assert_eq!(old.recv().await, None);
+        assert_eq!(new.recv().await, Some(text(r#"{"type":"Offer"}"#)));
+    }
+}
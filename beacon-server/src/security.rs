@@ -2,24 +2,27 @@
 //!
 //! All settings are env-driven and future-forward: CORS, headers, body limit,
 //! connection limits, and (optional) rate limiting. Designed to work behind
-//! Cloudflare Zero Trust (CF-Connecting-IP / X-Forwarded-For) and to be
+//! Cloudflare Zero Trust (CF-Connecting-IP / X-Forwarded-For from trusted proxies) and to be
 //! extended later (e.g. auth, stricter limits) without replacing this layer.
 
 use axum::{
-    extract::Request,
-    http::{HeaderValue, StatusCode},
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
 };
 use governor::Quota;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::env;
+use std::net::{IpAddr, SocketAddr};
 use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
 use tower_http::cors::{AllowOrigin, CorsLayer};
 
-/// Client IP as extracted from CF-Connecting-IP, X-Forwarded-For, or "unknown".
+/// Client IP: the socket peer, or the forwarded client when the peer is a trusted proxy.
 /// Injected into request extensions by client_ip_middleware for use in handlers.
 #[derive(Clone, Debug)]
 pub struct ClientIp(pub String);
@@ -38,8 +41,28 @@ pub struct SecurityConfig {
     pub max_ws_per_ip: u32,
     /// REST requests per minute per IP; 0 = no limit.
     pub rate_limit_rest_per_min: u32,
-    /// WebSocket messages per minute per IP; 0 = no limit.
+    /// WebSocket message cost units per minute per IP before login; 0 = no limit.
     pub rate_limit_ws_per_min: u32,
+    /// WebSocket message cost units per minute per verified user; 0 = no limit.
+    pub rate_limit_ws_user_per_min: u32,
+    /// WebSocket message cost units per minute per connection; 0 = no limit.
+    pub rate_limit_ws_conn_per_min: u32,
+    /// Cost units per minute per user for fan-out messages (chat, presence, profiles); 0 = no limit.
+    pub rate_limit_ws_fanout_per_min: u32,
+    /// Max signed-request signatures remembered for replay protection; 0 = unbounded.
+    pub replay_cache_size: usize,
+    /// Max messages queued per WebSocket connection before presence/profile noise is dropped; 0 = unbounded.
+    pub ws_outbound_queue_max: usize,
+    /// Seconds a connection may stay over ws_outbound_queue_max before it is disconnected.
+    pub ws_slow_consumer_grace_secs: u64,
+    /// Seconds between beacon-initiated WebSocket pings; 0 = no pings.
+    pub ws_ping_interval_secs: u64,
+    /// Seconds without any frame from a client before its connection is reaped; 0 = never.
+    pub ws_idle_timeout_secs: u64,
+    /// Seconds a dropped WebSocket session is held for resume; 0 = no resume.
+    pub ws_resume_grace_secs: u64,
+    /// Peers whose CF-Connecting-IP / X-Forwarded-For headers are honored.
+    pub trusted_proxies: TrustedProxies,
 }
 
 impl SecurityConfig {
@@ -71,6 +94,59 @@ impl SecurityConfig {
             .and_then(|v| v.parse().ok())
             .unwrap_or(250);
 
+        let rate_limit_ws_user_per_min = env::var("BEACON_RATE_LIMIT_WS_USER_PER_MIN")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(600);
+
+        let rate_limit_ws_conn_per_min = env::var("BEACON_RATE_LIMIT_WS_CONN_PER_MIN")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(400);
+
+        let rate_limit_ws_fanout_per_min = env::var("BEACON_RATE_LIMIT_WS_FANOUT_PER_MIN")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(120);
+
+        let replay_cache_size = env::var("BEACON_REPLAY_CACHE_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(100_000);
+
+        let ws_outbound_queue_max = env::var("BEACON_WS_OUTBOUND_QUEUE_MAX")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1024);
+
+        let ws_slow_consumer_grace_secs = env::var("BEACON_WS_SLOW_CONSUMER_GRACE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10);
+
+        let ws_ping_interval_secs = env::var("BEACON_WS_PING_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(20);
+
+        let ws_idle_timeout_secs = env::var("BEACON_WS_IDLE_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60);
+
+        let ws_resume_grace_secs = env::var("BEACON_WS_RESUME_GRACE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+
+        let mut trusted_proxies = match env::var("BEACON_TRUSTED_PROXIES") {
+            Ok(v) => TrustedProxies::parse(&v),
+            Err(_) => TrustedProxies::parse(DEFAULT_TRUSTED_PROXIES),
+        };
+        trusted_proxies.cf_connecting_ip = env::var("BEACON_TRUST_CF_CONNECTING_IP")
+            .map(|v| matches!(v.trim(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+
         Self {
             cors_origins,
             max_body_bytes,
@@ -78,6 +154,160 @@ impl SecurityConfig {
             max_ws_per_ip,
             rate_limit_rest_per_min,
             rate_limit_ws_per_min,
+            rate_limit_ws_user_per_min,
+            rate_limit_ws_conn_per_min,
+            rate_limit_ws_fanout_per_min,
+            replay_cache_size,
+            ws_outbound_queue_max,
+            ws_slow_consumer_grace_secs,
+            ws_ping_interval_secs,
+            ws_idle_timeout_secs,
+            ws_resume_grace_secs,
+            trusted_proxies,
+        }
+    }
+}
+
+/// Loopback only: a tunnel or reverse proxy on the same host is trusted out of the box. Private
+/// ranges are not, since any client on the same LAN or Docker network could then spoof its IP;
+/// list the proxy's network in BEACON_TRUSTED_PROXIES instead.
+pub const DEFAULT_TRUSTED_PROXIES: &str = "127.0.0.0/8,::1/128";
+
+/// One CIDR block (e.g. 10.0.0.0/8, 2001:db8::/32; a bare address is a single host).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpCidr {
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr.trim().parse::<IpAddr>().ok()?, Some(len.trim().parse::<u8>().ok()?)),
+            None => (s.parse::<IpAddr>().ok()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max);
+        if prefix_len > max {
+            return None;
+        }
+        Some(Self { addr, prefix_len })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Trusted proxy CIDRs (BEACON_TRUSTED_PROXIES, comma-separated; empty = trust no one).
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    cidrs: Vec<IpCidr>,
+    /// Honor CF-Connecting-IP from trusted peers (BEACON_TRUST_CF_CONNECTING_IP). Only safe when the
+    /// trusted proxy is Cloudflare / cloudflared, which overwrites the header; other proxies pass it through.
+    pub cf_connecting_ip: bool,
+}
+
+impl TrustedProxies {
+    pub fn parse(list: &str) -> Self {
+        let mut cidrs = Vec::new();
+        for entry in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match IpCidr::parse(entry) {
+                Some(cidr) => cidrs.push(cidr),
+                None => log::warn!("Ignoring invalid BEACON_TRUSTED_PROXIES entry: {}", entry),
+            }
+        }
+        Self {
+            cidrs,
+            cf_connecting_ip: false,
+        }
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.cidrs.iter().any(|c| c.contains(ip))
+    }
+
+    /// Client address for a request whose TCP peer is `peer`. Forwarding headers are only honored
+    /// when the peer is trusted. X-Forwarded-For is read right to left, skipping trusted hops;
+    /// the first untrusted entry is the client (entries further left are client-controlled).
+    pub fn resolve_client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let peer = peer.to_canonical();
+        if !self.contains(&peer) {
+            return peer;
+        }
+        if let Some(ip) = headers
+            .get("cf-connecting-ip")
+            .filter(|_| self.cf_connecting_ip)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_forwarded_ip)
+        {
+            return ip;
+        }
+        let mut client = peer;
+        let hops: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .collect();
+        for hop in hops.into_iter().rev() {
+            let Some(ip) = parse_forwarded_ip(hop) else {
+                break;
+            };
+            client = ip;
+            if !self.contains(&ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Parse one forwarded address: "1.2.3.4", "1.2.3.4:5678", "2001:db8::1" or "[2001:db8::1]:5678".
+fn parse_forwarded_ip(s: &str) -> Option<IpAddr> {
+    let s = s.trim();
+    if let Ok(ip) = s.parse::<IpAddr>() {
+        return Some(ip.to_canonical());
+    }
+    s.parse::<SocketAddr>().ok().map(|a| a.ip().to_canonical())
+}
+
+/// Beacon-initiated keepalive: ping every `ping_interval`, reap connections silent for `idle_timeout`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WsHeartbeat {
+    /// None = no pings.
+    pub ping_interval: Option<Duration>,
+    /// None = never reap.
+    pub idle_timeout: Option<Duration>,
+}
+
+impl WsHeartbeat {
+    pub fn from_config(config: &SecurityConfig) -> Self {
+        let secs = |s: u64| (s > 0).then(|| Duration::from_secs(s));
+        Self {
+            ping_interval: secs(config.ws_ping_interval_secs),
+            idle_timeout: secs(config.ws_idle_timeout_secs),
+        }
+    }
+
+    /// How often the connection task wakes to ping and check the deadline.
+    pub fn tick(&self) -> Duration {
+        match (self.ping_interval, self.idle_timeout) {
+            (Some(p), _) => p,
+            (None, Some(t)) => (t / 4).max(Duration::from_secs(1)),
+            (None, None) => Duration::from_secs(3600),
         }
     }
 }
@@ -108,17 +338,22 @@ pub fn build_cors_layer(config: &SecurityConfig) -> CorsLayer {
     }
 }
 
-/// Middleware that extracts client IP from CF-Connecting-IP, X-Forwarded-For, or "unknown"
-/// and inserts it into request extensions. Run this before handlers that need ClientIp.
-pub async fn client_ip_middleware(request: Request, next: Next) -> Response {
-    let ip = request
-        .headers()
-        .get("cf-connecting-ip")
-        .or_else(|| request.headers().get("x-forwarded-for"))
-        .and_then(|v| v.to_str().ok())
-        .and_then(|s| s.split(',').next())
-        .map(|s| s.trim().to_string())
-        .unwrap_or_else(|| "unknown".to_string());
+/// Middleware that resolves the client IP from the socket peer (ConnectInfo) and, for trusted proxies,
+/// CF-Connecting-IP / X-Forwarded-For, and inserts it into request extensions. Run this before handlers
+/// and rate limiting that need ClientIp.
+pub async fn client_ip_middleware(
+    State(trusted): State<Arc<TrustedProxies>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let ip = match peer {
+        Some(peer) => trusted.resolve_client_ip(peer, request.headers()).to_string(),
+        None => "unknown".to_string(),
+    };
 
     let mut request = request;
     request.extensions_mut().insert(ClientIp(ip));
@@ -146,6 +381,19 @@ impl KeyedRateLimiter {
     pub fn check_key(&self, key: &str) -> bool {
         self.0.check_key(&key.to_string()).is_ok()
     }
+
+    /// Like check_key but consumes `n` units. A cost above the burst size is always refused.
+    pub fn check_key_n(&self, key: &str, n: u32) -> bool {
+        match NonZeroU32::new(n) {
+            None => true,
+            Some(n) => matches!(self.0.check_key_n(&key.to_string(), n), Ok(Ok(()))),
+        }
+    }
+
+    /// Forget keys whose buckets are full again (bounds memory for per-user keys).
+    pub fn retain_recent(&self) {
+        self.0.retain_recent();
+    }
 }
 
 /// Build REST rate limiter: N requests per minute per IP. None if n == 0 (disabled).
@@ -153,9 +401,157 @@ pub fn build_rest_rate_limiter(requests_per_minute: u32) -> Option<Arc<KeyedRate
     KeyedRateLimiter::per_minute(requests_per_minute)
 }
 
-/// Build WebSocket message rate limiter: N messages per minute per IP. None if n == 0 (disabled).
-pub fn build_ws_rate_limiter(messages_per_minute: u32) -> Option<Arc<KeyedRateLimiter>> {
-    KeyedRateLimiter::per_minute(messages_per_minute)
+/// Budget a WebSocket message is charged against, in addition to the general one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WsBudget {
+    General,
+    /// Messages the beacon fans out to whole servers or friend lists (chat, presence, profiles).
+    Fanout,
+}
+
+/// Which bucket refused a WebSocket message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WsLimitHit {
+    Ip,
+    User,
+    Conn,
+    Fanout,
+}
+
+/// Counters for WebSocket rate limiting (reported by /api/status).
+#[derive(Default)]
+pub struct WsRateLimitStats {
+    pub allowed: AtomicU64,
+    pub limited_ip: AtomicU64,
+    pub limited_user: AtomicU64,
+    pub limited_conn: AtomicU64,
+    pub limited_fanout: AtomicU64,
+}
+
+impl WsRateLimitStats {
+    pub fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!({
+            "allowed": self.allowed.load(Ordering::Relaxed),
+            "limited_ip": self.limited_ip.load(Ordering::Relaxed),
+            "limited_user": self.limited_user.load(Ordering::Relaxed),
+            "limited_conn": self.limited_conn.load(Ordering::Relaxed),
+            "limited_fanout": self.limited_fanout.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Per-connection bucket; owned by the connection task and dropped with it.
+pub struct ConnRateLimiter(governor::DefaultDirectRateLimiter);
+
+impl ConnRateLimiter {
+    fn check_n(&self, n: u32) -> bool {
+        match NonZeroU32::new(n) {
+            None => true,
+            Some(n) => matches!(self.0.check_n(n), Ok(Ok(()))),
+        }
+    }
+}
+
+/// WebSocket rate limits. Messages carry a weighted cost and are charged to the connection's bucket
+/// and to the verified user's bucket (or the IP bucket before login, so users behind one NAT do not
+/// share a budget once authenticated). Fan-out messages are also charged to a per-user fan-out budget.
+/// Every limit is in cost units per minute; None = disabled.
+#[derive(Default)]
+pub struct WsRateLimits {
+    per_ip: Option<Arc<KeyedRateLimiter>>,
+    per_user: Option<Arc<KeyedRateLimiter>>,
+    fanout: Option<Arc<KeyedRateLimiter>>,
+    per_conn: Option<Quota>,
+    pub stats: WsRateLimitStats,
+}
+
+impl WsRateLimits {
+    pub fn from_config(config: &SecurityConfig) -> Self {
+        Self {
+            per_ip: KeyedRateLimiter::per_minute(config.rate_limit_ws_per_min),
+            per_user: KeyedRateLimiter::per_minute(config.rate_limit_ws_user_per_min),
+            fanout: KeyedRateLimiter::per_minute(config.rate_limit_ws_fanout_per_min),
+            per_conn: NonZeroU32::new(config.rate_limit_ws_conn_per_min).map(Quota::per_minute),
+            stats: WsRateLimitStats::default(),
+        }
+    }
+
+    /// Fresh bucket for a new connection; None if per-connection limiting is disabled.
+    pub fn conn_limiter(&self) -> Option<ConnRateLimiter> {
+        self.per_conn
+            .map(|q| ConnRateLimiter(governor::RateLimiter::direct(q)))
+    }
+
+    /// Charge `cost` for one message. `user_id` is the connection's verified identity, if any.
+    pub fn check(
+        &self,
+        conn: Option<&ConnRateLimiter>,
+        client_ip: &str,
+        user_id: Option<&str>,
+        cost: u32,
+        budget: WsBudget,
+    ) -> Result<(), WsLimitHit> {
+        let result = self.check_buckets(conn, client_ip, user_id, cost, budget);
+        let counter = match result {
+            Ok(()) => &self.stats.allowed,
+            Err(WsLimitHit::Ip) => &self.stats.limited_ip,
+            Err(WsLimitHit::User) => &self.stats.limited_user,
+            Err(WsLimitHit::Conn) => &self.stats.limited_conn,
+            Err(WsLimitHit::Fanout) => &self.stats.limited_fanout,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+
+    fn check_buckets(
+        &self,
+        conn: Option<&ConnRateLimiter>,
+        client_ip: &str,
+        user_id: Option<&str>,
+        cost: u32,
+        budget: WsBudget,
+    ) -> Result<(), WsLimitHit> {
+        if let Some(conn) = conn {
+            if !conn.check_n(cost) {
+                return Err(WsLimitHit::Conn);
+            }
+        }
+        match user_id {
+            Some(user_id) => {
+                if let Some(l) = &self.per_user {
+                    if !l.check_key_n(user_id, cost) {
+                        return Err(WsLimitHit::User);
+                    }
+                }
+            }
+            None => {
+                if let Some(l) = &self.per_ip {
+                    if !l.check_key_n(client_ip, cost) {
+                        return Err(WsLimitHit::Ip);
+                    }
+                }
+            }
+        }
+        if budget == WsBudget::Fanout {
+            if let Some(l) = &self.fanout {
+                if !l.check_key_n(user_id.unwrap_or(client_ip), cost) {
+                    return Err(WsLimitHit::Fanout);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop idle per-IP / per-user buckets.
+    pub fn retain_recent(&self) {
+        for l in [&self.per_ip, &self.per_user, &self.fanout].into_iter().flatten() {
+            l.retain_recent();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.per_ip.is_some() || self.per_user.is_some() || self.fanout.is_some() || self.per_conn.is_some()
+    }
 }
 
 /// Middleware: reject REST request with 429 if client IP is over rate limit.
@@ -248,3 +644,161 @@ impl ConnectionTracker {
 
 /// Shared connection tracker for use in AppState and ws_handler.
 pub type SharedConnectionTracker = Arc<RwLock<ConnectionTracker>>;
+
+/// Signatures of signed REST requests already accepted inside their timestamp window.
+/// A signed request is single use: an exact replay carries the same signature and is refused.
+/// Bounded: when full of unexpired signatures, new requests are refused rather than forgetting a
+/// live signature (which would make it replayable again).
+pub struct ReplayCache {
+    seen: HashSet<String>,
+    /// (expires_at unix secs, signature) in insertion order.
+    order: VecDeque<(i64, String)>,
+    capacity: usize,
+}
+
+/// Why ReplayCache::check_and_insert refused a signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayRefused {
+    /// Already accepted inside its window.
+    Replayed,
+    /// The cache is full of unexpired signatures; retry once some expire.
+    Full,
+}
+
+impl ReplayCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Refuses `signature` if it was already seen (replay) or if there is no room for it.
+    /// Otherwise remembers it until `expires_at`.
+    pub fn check_and_insert(&mut self, signature: &str, expires_at: i64, now: i64) -> Result<(), ReplayRefused> {
+        self.prune_expired(now, false);
+        if self.seen.contains(signature) {
+            return Err(ReplayRefused::Replayed);
+        }
+        if self.capacity > 0 && self.order.len() >= self.capacity {
+            // Timestamps are only roughly ordered, so expired entries may sit behind live ones.
+            self.prune_expired(now, true);
+            if self.order.len() >= self.capacity {
+                return Err(ReplayRefused::Full);
+            }
+        }
+        self.seen.insert(signature.to_string());
+        self.order.push_back((expires_at, signature.to_string()));
+        Ok(())
+    }
+
+    /// Forget signatures expired at `now`: from the front only, or everywhere when `full_scan`.
+    fn prune_expired(&mut self, now: i64, full_scan: bool) {
+        if full_scan {
+            let seen = &mut self.seen;
+            self.order.retain(|(exp, sig)| {
+                if *exp > now {
+                    return true;
+                }
+                seen.remove(sig);
+                false
+            });
+            return;
+        }
+        while let Some((exp, _)) = self.order.front() {
+            if *exp > now {
+                break;
+            }
+            if let Some((_, sig)) = self.order.pop_front() {
+                self.seen.remove(&sig);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+/// Shared replay cache for signed REST middleware.
+pub type SharedReplayCache = Arc<Mutex<ReplayCache>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xff(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for v in values {
+            headers.append("x-forwarded-for", HeaderValue::from_str(v).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn forwarding_headers_only_from_trusted_hops() {
+        let mut trusted = TrustedProxies::parse("10.0.0.0/8, ::1");
+        trusted.cf_connecting_ip = true;
+        let direct: IpAddr = "203.0.113.7".parse().unwrap();
+        let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+
+        // Direct client: spoofed headers are ignored.
+        let mut headers = xff(&["198.51.100.1"]);
+        headers.insert("cf-connecting-ip", HeaderValue::from_static("198.51.100.1"));
+        assert_eq!(trusted.resolve_client_ip(direct, &headers), direct);
+
+        // Via proxy: rightmost untrusted entry wins, client-supplied prefix is ignored.
+        let headers = xff(&["198.51.100.1, 203.0.113.9", "10.0.0.5"]);
+        assert_eq!(trusted.resolve_client_ip(proxy, &headers), "203.0.113.9".parse::<IpAddr>().unwrap());
+
+        // Via proxy with CF-Connecting-IP only honored when enabled.
+        let mut headers = xff(&["203.0.113.9"]);
+        headers.insert("cf-connecting-ip", HeaderValue::from_static("198.51.100.1"));
+        assert_eq!(trusted.resolve_client_ip(proxy, &headers), "198.51.100.1".parse::<IpAddr>().unwrap());
+        trusted.cf_connecting_ip = false;
+        assert_eq!(trusted.resolve_client_ip(proxy, &headers), "203.0.113.9".parse::<IpAddr>().unwrap());
+
+        // Via proxy without headers: the proxy itself.
+        assert_eq!(trusted.resolve_client_ip(proxy, &HeaderMap::new()), proxy);
+
+        // Garbage stops the walk at the last trusted hop.
+        let headers = xff(&["203.0.113.9, nonsense, 10.0.0.5"]);
+        assert_eq!(trusted.resolve_client_ip(proxy, &headers), "10.0.0.5".parse::<IpAddr>().unwrap());
+
+        // IPv4-mapped IPv6 peers match IPv4 ranges.
+        let mapped: IpAddr = "::ffff:10.1.2.3".parse().unwrap();
+        assert!(trusted.contains(&mapped.to_canonical()));
+    }
+
+    #[test]
+    fn ws_limits_split_users_behind_one_ip_and_cap_fanout() {
+        let limits = WsRateLimits {
+            per_ip: KeyedRateLimiter::per_minute(5),
+            per_user: KeyedRateLimiter::per_minute(10),
+            fanout: KeyedRateLimiter::per_minute(4),
+            per_conn: None,
+            stats: WsRateLimitStats::default(),
+        };
+        let ip = "203.0.113.7";
+
+        // Before login everyone behind the IP shares its bucket.
+        assert!(limits.check(None, ip, None, 5, WsBudget::General).is_ok());
+        assert_eq!(limits.check(None, ip, None, 1, WsBudget::General), Err(WsLimitHit::Ip));
+
+        // Verified users get their own buckets.
+        assert!(limits.check(None, ip, Some("alice"), 10, WsBudget::General).is_ok());
+        assert_eq!(limits.check(None, ip, Some("alice"), 1, WsBudget::General), Err(WsLimitHit::User));
+        assert!(limits.check(None, ip, Some("bob"), 4, WsBudget::Fanout).is_ok());
+        assert_eq!(limits.check(None, ip, Some("bob"), 1, WsBudget::Fanout), Err(WsLimitHit::Fanout));
+        assert!(limits.check(None, ip, Some("bob"), 1, WsBudget::General).is_ok());
+
+        let stats = limits.stats.snapshot();
+        assert_eq!(stats["allowed"], 4);
+        assert_eq!(stats["limited_fanout"], 1);
+    }
+}
@@ -0,0 +1,389 @@
+//! Ed25519 verification for server-key-authorized beacon requests.
+//!
+//! Server signing pubkeys are base64 (standard, padded) Ed25519 verifying keys, matching
+//! what the client puts in the `/api/servers/:signing_pubkey` path.
+
+use axum::http::{HeaderMap, Method, StatusCode};
+use base64::Engine;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::handlers::friends::{signed_request_envelope, verify_friend_sig_ed25519, SIGNED_REQUEST_MAX_SKEW_SECS};
+use crate::{EncryptedServerHint, SealedServerKey};
+
+/// Verify a base64 Ed25519 signature against a base64 Ed25519 public key.
+pub fn verify_ed25519_b64(pubkey_b64: &str, message: &[u8], signature_b64: &str) -> bool {
+    let engine = &base64::engine::general_purpose::STANDARD;
+    let Ok(pubkey_bytes) = engine.decode(pubkey_b64.trim()) else {
+        return false;
+    };
+    let Ok(pubkey_array) = <[u8; 32]>::try_from(pubkey_bytes.as_slice()) else {
+        return false;
+    };
+    let Ok(verifying_key) = VerifyingKey::from_bytes(&pubkey_array) else {
+        return false;
+    };
+    let Ok(sig_bytes) = engine.decode(signature_b64.trim()) else {
+        return false;
+    };
+    let Ok(sig_array) = <[u8; 64]>::try_from(sig_bytes.as_slice()) else {
+        return false;
+    };
+    verifying_key
+        .verify(message, &Signature::from_bytes(&sig_array))
+        .is_ok()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hex::encode(hasher.finalize())
+}
+
+/// Most sealed keys one hint may carry (one per remaining member).
+pub const MAX_KEY_ENVELOPES: usize = 1024;
+
+/// Canonical form of sealed keys: one "user_id:epoch:sealed_key" line each, in order.
+pub fn key_envelopes_canonical(envelopes: &[SealedServerKey]) -> String {
+    envelopes
+        .iter()
+        .map(|e| format!("{}:{}:{}", e.user_id, e.epoch, e.sealed_key))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Canonical bytes a hint signature covers:
+/// "cordia-server-hint\n" + signing_pubkey + "\n" + last_updated (unix millis) + "\n" + delegate_pubkey + "\n"
+/// + admin_pubkeys joined by "," + "\n" + sha256(encrypted_state).hex() + "\n" + sha256(key_envelopes).hex()
+///
+/// Delegate-signed hints cover no envelopes (an empty list): the beacon replaces theirs with the
+/// stored ones (see carry_forward_key_envelopes), so they can be re-verified after that.
+pub fn server_hint_message(hint: &EncryptedServerHint) -> String {
+    let by_delegate = hint.signer_pubkey.as_deref().is_some_and(|signer| signer != hint.signing_pubkey);
+    let envelopes = if by_delegate { &[] } else { hint.key_envelopes.as_slice() };
+    format!(
+        "cordia-server-hint\n{}\n{}\n{}\n{}\n{}\n{}",
+        hint.signing_pubkey,
+        hint.last_updated.timestamp_millis(),
+        hint.delegate_pubkey.as_deref().unwrap_or(""),
+        hint.admin_pubkeys.join(","),
+        sha256_hex(hint.encrypted_state.as_bytes()),
+        sha256_hex(key_envelopes_canonical(envelopes).as_bytes()),
+    )
+}
+
+/// Check that `hint` may replace `stored` for `signing_pubkey`.
+///
+/// Authorized signers are the server signing key itself, or the delegate key the server key
+/// last published in `delegate_pubkey`. A delegate can publish state but cannot change the
+/// delegate or the invite admins.
+/// Hints older than the stored one are refused so a replayed hint cannot roll state back, and hints
+/// dated more than SIGNED_REQUEST_MAX_SKEW_SECS ahead are refused so one cannot lock out every later hint.
+/// A hint with the stored `last_updated` is only accepted if it is the stored hint again.
+pub fn verify_server_hint(
+    signing_pubkey: &str,
+    hint: &EncryptedServerHint,
+    stored: Option<&EncryptedServerHint>,
+) -> Result<(), (StatusCode, &'static str)> {
+    if hint.signing_pubkey != signing_pubkey {
+        return Err((StatusCode::BAD_REQUEST, "signing_pubkey does not match path"));
+    }
+    if hint.key_envelopes.len() > MAX_KEY_ENVELOPES {
+        return Err((StatusCode::BAD_REQUEST, "Too many key envelopes"));
+    }
+    if hint.last_updated > chrono::Utc::now() + chrono::Duration::seconds(SIGNED_REQUEST_MAX_SKEW_SECS) {
+        return Err((StatusCode::BAD_REQUEST, "Server hint last_updated is in the future"));
+    }
+    if let Some(prev) = stored {
+        if hint.last_updated < prev.last_updated {
+            return Err((StatusCode::CONFLICT, "Server hint is older than stored hint"));
+        }
+        let same_signer = hint.signer_pubkey.as_deref().unwrap_or(signing_pubkey)
+            == prev.signer_pubkey.as_deref().unwrap_or(signing_pubkey);
+        if hint.last_updated == prev.last_updated
+            && (!same_signer || server_hint_message(hint) != server_hint_message(prev))
+        {
+            return Err((StatusCode::CONFLICT, "A different server hint with this last_updated is stored"));
+        }
+    }
+
+    let signer = hint.signer_pubkey.as_deref().unwrap_or(signing_pubkey);
+    if signer != signing_pubkey {
+        let authorized = stored.and_then(|p| p.delegate_pubkey.as_deref());
+        if authorized != Some(signer) {
+            return Err((StatusCode::FORBIDDEN, "Hint signer not authorized"));
+        }
+        if hint.delegate_pubkey.as_deref() != Some(signer) {
+            return Err((StatusCode::FORBIDDEN, "Only the server key can change the hint delegate"));
+        }
+        if stored.map(|p| &p.admin_pubkeys) != Some(&hint.admin_pubkeys) {
+            return Err((StatusCode::FORBIDDEN, "Only the server key can change invite admins"));
+        }
+    }
+
+    if !verify_ed25519_b64(signer, server_hint_message(hint).as_bytes(), &hint.signature) {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid hint signature"));
+    }
+    Ok(())
+}
+
+/// Only the server key rotates the server symmetric key, so only it may replace `key_envelopes`.
+/// A delegate-signed hint (any member's ordinary update) keeps the stored envelopes, so members who
+/// were offline during a rotation can still pick up their sealed key.
+pub fn carry_forward_key_envelopes(
+    signing_pubkey: &str,
+    hint: &mut EncryptedServerHint,
+    stored: Option<&EncryptedServerHint>,
+) {
+    if hint.signer_pubkey.as_deref().is_some_and(|signer| signer != signing_pubkey) {
+        hint.key_envelopes = stored.map(|p| p.key_envelopes.clone()).unwrap_or_default();
+    }
+}
+
+/// Identity user_id for an Ed25519 public key: hex(sha256(pubkey)[..16]), as the desktop client derives it.
+pub fn user_id_for_public_key(pubkey: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(pubkey);
+    hex::encode(&hasher.finalize()[..16])
+}
+
+/// Server member verified by server_member_auth_middleware (inserted into request extensions).
+#[derive(Clone, Debug)]
+pub struct VerifiedServerMember {
+    pub user_id: String,
+}
+
+/// Verify a server-scoped request (event queue routes).
+///
+/// Two signatures over the same envelope as the friend API (see signed_request_envelope):
+/// - X-Signature by the member's identity key (X-User-Id must be the hash of X-Public-Key);
+/// - X-Member-Signature by the server key, or by the hint delegate key named in X-Member-Key.
+///
+/// The delegate key is derived from the server symmetric key, so a valid member signature
+/// proves the caller holds the server secret, i.e. is a member.
+pub fn verify_server_member_sig(
+    method: &Method,
+    path: &str,
+    headers: &HeaderMap,
+    body_bytes: &[u8],
+    signing_pubkey: &str,
+    stored_hint: Option<&EncryptedServerHint>,
+) -> Result<VerifiedServerMember, (StatusCode, &'static str)> {
+    // Also binds X-User-Id to the hash of X-Public-Key.
+    let user_id = verify_friend_sig_ed25519(method, path, headers, body_bytes)?;
+
+    let member_signature = headers
+        .get("x-member-signature")
+        .and_then(|v| v.to_str().ok())
+        .ok_or((StatusCode::UNAUTHORIZED, "Missing X-Member-Signature"))?
+        .trim();
+    let member_key = headers
+        .get("x-member-key")
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .unwrap_or(signing_pubkey);
+    if member_key != signing_pubkey
+        && stored_hint.and_then(|h| h.delegate_pubkey.as_deref()) != Some(member_key)
+    {
+        return Err((StatusCode::FORBIDDEN, "X-Member-Key not authorized for this server"));
+    }
+
+    // verify_friend_sig_ed25519 already validated the timestamp.
+    let ts: i64 = headers
+        .get("x-timestamp")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or_default();
+    let envelope = signed_request_envelope(method, path, ts, body_bytes);
+    if !verify_ed25519_b64(member_key, envelope.as_bytes(), member_signature) {
+        return Err((StatusCode::FORBIDDEN, "Invalid X-Member-Signature"));
+    }
+
+    Ok(VerifiedServerMember { user_id })
+}
+
+/// Signer of a server-admin request, inserted into request extensions by server_admin_auth_middleware.
+#[derive(Clone, Debug)]
+pub struct VerifiedServerAdmin {
+    /// Base64 Ed25519 key that signed (the server key or one of the hint's admin_pubkeys).
+    pub signer_pubkey: String,
+}
+
+/// Verify a server-admin request (invite create, list, revoke).
+///
+/// X-Server-Signature is a base64 Ed25519 signature over signed_request_envelope (X-Timestamp for the
+/// timestamp), by the server signing key or by an admin key listed in the stored hint (named in X-Server-Signer).
+pub fn verify_server_admin_sig(
+    method: &Method,
+    path: &str,
+    headers: &HeaderMap,
+    body_bytes: &[u8],
+    signing_pubkey: &str,
+    stored_hint: Option<&EncryptedServerHint>,
+) -> Result<VerifiedServerAdmin, (StatusCode, &'static str)> {
+    let signature = headers
+        .get("x-server-signature")
+        .and_then(|v| v.to_str().ok())
+        .ok_or((StatusCode::UNAUTHORIZED, "Missing X-Server-Signature"))?
+        .trim();
+    let ts: i64 = headers
+        .get("x-timestamp")
+        .and_then(|v| v.to_str().ok())
+        .ok_or((StatusCode::UNAUTHORIZED, "Missing X-Timestamp"))?
+        .trim()
+        .parse()
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid X-Timestamp"))?;
+    if (ts - chrono::Utc::now().timestamp()).abs() > SIGNED_REQUEST_MAX_SKEW_SECS {
+        return Err((StatusCode::UNAUTHORIZED, "X-Timestamp expired"));
+    }
+    let signer = headers
+        .get("x-server-signer")
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .unwrap_or(signing_pubkey);
+    let is_admin = stored_hint.is_some_and(|h| h.admin_pubkeys.iter().any(|k| k == signer));
+    if signer != signing_pubkey && !is_admin {
+        return Err((StatusCode::FORBIDDEN, "X-Server-Signer not authorized for this server"));
+    }
+
+    let envelope = signed_request_envelope(method, path, ts, body_bytes);
+    if !verify_ed25519_b64(signer, envelope.as_bytes(), signature) {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid X-Server-Signature"));
+    }
+    Ok(VerifiedServerAdmin {
+        signer_pubkey: signer.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn b64(bytes: &[u8]) -> String {
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    }
+
+    fn member_headers(identity: &SigningKey, member: &SigningKey, member_key: Option<&str>, path: &str, body: &[u8]) -> HeaderMap {
+        let ts = chrono::Utc::now().timestamp();
+        let envelope = signed_request_envelope(&Method::POST, path, ts, body);
+        let public_key = identity.verifying_key().to_bytes();
+        let mut headers = HeaderMap::new();
+        let mut set = |name: &'static str, value: String| {
+            headers.insert(name, HeaderValue::from_str(&value).unwrap());
+        };
+        set("x-user-id", user_id_for_public_key(&public_key));
+        set("x-timestamp", ts.to_string());
+        set("x-public-key", hex::encode(public_key));
+        set("x-signature", b64(&identity.sign(envelope.as_bytes()).to_bytes()));
+        set("x-member-signature", b64(&member.sign(envelope.as_bytes()).to_bytes()));
+        if let Some(key) = member_key {
+            set("x-member-key", key.to_string());
+        }
+        headers
+    }
+
+    /// Same vector as src-tauri/src/server_hint.rs; the desktop client signs exactly this message.
+    #[test]
+    fn server_hint_message_matches_the_client_format() {
+        let mut hint = EncryptedServerHint {
+            signing_pubkey: "spk".to_string(),
+            encrypted_state: "1.state".to_string(),
+            signature: String::new(),
+            last_updated: "2024-01-01T00:00:00.000Z".parse().unwrap(),
+            signer_pubkey: None,
+            delegate_pubkey: Some("delegate".to_string()),
+            admin_pubkeys: vec!["admin-a".to_string(), "admin-b".to_string()],
+            key_envelopes: vec![SealedServerKey { user_id: "bob".to_string(), epoch: 1, sealed_key: "sealed".to_string() }],
+        };
+        assert_eq!(
+            server_hint_message(&hint),
+            "cordia-server-hint\nspk\n1704067200000\ndelegate\nadmin-a,admin-b\n\
+             2938188fc771708152683740878ffe79790e726fd21e0c8fa04eeffdd1aedea1\n\
+             46884bafaac4b61842e64b1c88d4e616d896e102f2c75e8871b0905e8a81c466"
+        );
+        hint.signer_pubkey = Some("delegate".to_string());
+        assert!(server_hint_message(&hint)
+            .ends_with("\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"));
+    }
+
+    #[test]
+    fn member_sig_requires_server_key_or_delegate_and_bound_user_id() {
+        let identity = SigningKey::from_bytes(&[5u8; 32]);
+        let server_key = SigningKey::from_bytes(&[1u8; 32]);
+        let delegate = SigningKey::from_bytes(&[3u8; 32]);
+        let outsider = SigningKey::from_bytes(&[9u8; 32]);
+        let spk = b64(server_key.verifying_key().as_bytes());
+        let delegate_pk = b64(delegate.verifying_key().as_bytes());
+        let outsider_pk = b64(outsider.verifying_key().as_bytes());
+        let hint = EncryptedServerHint {
+            signing_pubkey: spk.clone(),
+            encrypted_state: String::new(),
+            signature: String::new(),
+            last_updated: chrono::Utc::now(),
+            signer_pubkey: None,
+            delegate_pubkey: Some(delegate_pk.clone()),
+            admin_pubkeys: Vec::new(),
+            key_envelopes: Vec::new(),
+        };
+        let path = "/api/servers/x/events";
+        let body = br#"{"event_type":"MemberJoin"}"#;
+
+        let headers = member_headers(&identity, &server_key, None, path, body);
+        let member = verify_server_member_sig(&Method::POST, path, &headers, body, &spk, None).unwrap();
+        assert_eq!(member.user_id, user_id_for_public_key(&identity.verifying_key().to_bytes()));
+
+        let headers = member_headers(&identity, &delegate, Some(&delegate_pk), path, body);
+        assert!(verify_server_member_sig(&Method::POST, path, &headers, body, &spk, Some(&hint)).is_ok());
+        // Delegate is only trusted once the server key has published it.
+        assert!(verify_server_member_sig(&Method::POST, path, &headers, body, &spk, None).is_err());
+
+        let headers = member_headers(&identity, &outsider, Some(&outsider_pk), path, body);
+        assert!(verify_server_member_sig(&Method::POST, path, &headers, body, &spk, Some(&hint)).is_err());
+
+        let mut headers = member_headers(&identity, &server_key, None, path, body);
+        headers.insert("x-user-id", HeaderValue::from_static("00000000000000000000000000000000"));
+        assert!(verify_server_member_sig(&Method::POST, path, &headers, body, &spk, None).is_err());
+    }
+
+    #[test]
+    fn admin_sig_requires_server_key_or_listed_admin() {
+        let server_key = SigningKey::from_bytes(&[1u8; 32]);
+        let admin = SigningKey::from_bytes(&[2u8; 32]);
+        let delegate = SigningKey::from_bytes(&[3u8; 32]);
+        let spk = b64(server_key.verifying_key().as_bytes());
+        let admin_pk = b64(admin.verifying_key().as_bytes());
+        let delegate_pk = b64(delegate.verifying_key().as_bytes());
+        let hint = EncryptedServerHint {
+            signing_pubkey: spk.clone(),
+            encrypted_state: String::new(),
+            signature: String::new(),
+            last_updated: chrono::Utc::now(),
+            signer_pubkey: None,
+            delegate_pubkey: Some(delegate_pk.clone()),
+            admin_pubkeys: vec![admin_pk.clone()],
+            key_envelopes: Vec::new(),
+        };
+        let path = "/api/servers/x/invites";
+        let admin_headers = |key: &SigningKey, signer: Option<&str>| {
+            let ts = chrono::Utc::now().timestamp();
+            let envelope = signed_request_envelope(&Method::GET, path, ts, b"");
+            let mut headers = HeaderMap::new();
+            headers.insert("x-timestamp", HeaderValue::from_str(&ts.to_string()).unwrap());
+            headers.insert("x-server-signature", HeaderValue::from_str(&b64(&key.sign(envelope.as_bytes()).to_bytes())).unwrap());
+            if let Some(signer) = signer {
+                headers.insert("x-server-signer", HeaderValue::from_str(signer).unwrap());
+            }
+            headers
+        };
+
+        let headers = admin_headers(&server_key, None);
+        assert!(verify_server_admin_sig(&Method::GET, path, &headers, b"", &spk, None).is_ok());
+        let headers = admin_headers(&admin, Some(&admin_pk));
+        assert!(verify_server_admin_sig(&Method::GET, path, &headers, b"", &spk, Some(&hint)).is_ok());
+        // Any member can derive the delegate key, so it must not manage invites.
+        let headers = admin_headers(&delegate, Some(&delegate_pk));
+        assert!(verify_server_admin_sig(&Method::GET, path, &headers, b"", &spk, Some(&hint)).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! WebSocket login: per-connection challenge nonces and the identity each connection proved.
+
+use std::collections::HashMap;
+use base64::Engine;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use rand::RngCore;
+
+use crate::signing::user_id_for_public_key;
+use crate::ConnId;
+
+/// Bytes the client signs with its identity key: "cordia-beacon-auth\n" + nonce.
+pub fn auth_challenge_message(nonce: &str) -> String {
+    format!("cordia-beacon-auth\n{}", nonce)
+}
+
+/// Identity a connection proved via AuthResponse.
+#[derive(Debug, Clone)]
+pub struct VerifiedIdentity {
+    pub user_id: String,
+    /// Hex Ed25519 identity public key.
+    pub public_key: String,
+}
+
+#[derive(Default)]
+pub struct AuthState {
+    /// Outstanding challenge per connection (single use).
+    pub challenges: HashMap<ConnId, String>,
+    /// Verified identity per connection.
+    pub identities: HashMap<ConnId, VerifiedIdentity>,
+}
+
+impl AuthState {
+    pub fn new() -> Self {
+        Self {
+            challenges: HashMap::new(),
+            identities: HashMap::new(),
+        }
+    }
+
+    /// Create a fresh nonce for this connection (replaces any outstanding one).
+    pub fn issue_challenge(&mut self, conn_id: &ConnId) -> String {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let nonce = hex::encode(bytes);
+        self.challenges.insert(conn_id.clone(), nonce.clone());
+        nonce
+    }
+
+    /// Check an AuthResponse against the outstanding challenge. The challenge is consumed either way.
+    /// user_id must be hex(sha256(public_key)[..16]), the derivation the desktop identity uses.
+    pub fn verify_response(
+        &mut self,
+        conn_id: &ConnId,
+        user_id: &str,
+        public_key_hex: &str,
+        signature_b64: &str,
+    ) -> Result<VerifiedIdentity, String> {
+        let nonce = self
+            .challenges
+            .remove(conn_id)
+            .ok_or_else(|| "No outstanding auth challenge".to_string())?;
+
+        let public_key = hex::decode(public_key_hex.trim())
+            .map_err(|_| "Invalid public_key hex".to_string())?;
+        let public_key: [u8; 32] = public_key
+            .as_slice()
+            .try_into()
+            .map_err(|_| "Invalid public_key length".to_string())?;
+        if user_id_for_public_key(&public_key) != user_id {
+            return Err("user_id does not match public_key".to_string());
+        }
+        let verifying_key = VerifyingKey::from_bytes(&public_key)
+            .map_err(|_| "Invalid public_key".to_string())?;
+        let sig_bytes = base64::engine::general_purpose::STANDARD
+            .decode(signature_b64.trim())
+            .map_err(|_| "Invalid signature base64".to_string())?;
+        let sig: [u8; 64] = sig_bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| "Invalid signature length".to_string())?;
+        verifying_key
+            .verify(auth_challenge_message(&nonce).as_bytes(), &Signature::from_bytes(&sig))
+            .map_err(|_| "Invalid auth signature".to_string())?;
+
+        let identity = VerifiedIdentity {
+            user_id: user_id.to_string(),
+            public_key: hex::encode(public_key),
+        };
+        self.identities.insert(conn_id.clone(), identity.clone());
+        Ok(identity)
+    }
+
+    pub fn user_id_for_conn(&self, conn_id: &ConnId) -> Option<&str> {
+        self.identities.get(conn_id).map(|i| i.user_id.as_str())
+    }
+
+    /// Drop challenge and identity for a closed connection.
+    pub fn remove_conn(&mut self, conn_id: &ConnId) {
+        self.challenges.remove(conn_id);
+        self.identities.remove(conn_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn binds_conn_to_key_holder_only() {
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let public_key = key.verifying_key().to_bytes();
+        let user_id = user_id_for_public_key(&public_key);
+        let conn: ConnId = "conn-a".to_string();
+        let mut auth = AuthState::new();
+
+        // Claiming someone else's user_id with our own key fails.
+        let nonce = auth.issue_challenge(&conn);
+        let sig = base64::engine::general_purpose::STANDARD
+            .encode(key.sign(auth_challenge_message(&nonce).as_bytes()).to_bytes());
+        assert!(auth
+            .verify_response(&conn, "ffffffffffffffffffffffffffffffff", &hex::encode(public_key), &sig)
+            .is_err());
+        // Challenge is single use.
+        assert!(auth.verify_response(&conn, &user_id, &hex::encode(public_key), &sig).is_err());
+
+        let nonce = auth.issue_challenge(&conn);
+        let sig = base64::engine::general_purpose::STANDARD
+            .encode(key.sign(auth_challenge_message(&nonce).as_bytes()).to_bytes());
+        assert!(auth.verify_response(&conn, &user_id, &hex::encode(public_key), &sig).is_ok());
+        assert_eq!(auth.user_id_for_conn(&conn), Some(user_id.as_str()));
+
+        auth.remove_conn(&conn);
+        assert_eq!(auth.user_id_for_conn(&conn), None);
+    }
+}
@@ -1,8 +1,96 @@
 use std::collections::HashMap;
+use axum::http::StatusCode;
 use chrono::{Duration, Utc};
+use crate::signing::{carry_forward_key_envelopes, verify_server_hint};
 use crate::{SigningPubkey, EncryptedServerHint, InviteTokenRecord, ServerEvent, InviteTokenCreateRequest};
 
-const EVENT_RETENTION_DAYS: i64 = 30;
+/// Most events returned by one GET /events page.
+pub const EVENTS_PAGE_MAX: usize = 500;
+
+/// The events right after a client's cursor are gone (garbage-collected, or lost by a beacon
+/// restart without a database). The client has to resync from the server hint and continue
+/// from `latest_seq`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct CursorTooOld {
+    /// Oldest event still stored (latest_seq + 1 if none are).
+    pub oldest_seq: u64,
+    pub latest_seq: u64,
+}
+
+/// Check a cursor against what is stored for a server: `oldest` retained seq, `latest` assigned seq.
+pub fn check_cursor(after_seq: u64, oldest: Option<u64>, latest: u64) -> Result<(), CursorTooOld> {
+    let oldest_seq = oldest.unwrap_or(latest + 1);
+    if after_seq > latest || after_seq + 1 < oldest_seq {
+        return Err(CursorTooOld { oldest_seq, latest_seq: latest });
+    }
+    Ok(())
+}
+
+/// Event retention and per-server queue quotas (both in memory and in Postgres). 0 = no cap.
+#[derive(Debug, Clone, Copy)]
+pub struct EventLimits {
+    /// Events older than this are dropped even if not everyone acked them.
+    pub retention_days: i64,
+    pub max_events_per_server: usize,
+    /// Sum of event_id, event_type, encrypted_payload and signature lengths.
+    pub max_bytes_per_server: usize,
+}
+
+impl Default for EventLimits {
+    fn default() -> Self {
+        Self {
+            retention_days: 30,
+            max_events_per_server: 10_000,
+            max_bytes_per_server: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl EventLimits {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok().and_then(|v| v.trim().parse::<usize>().ok());
+        Self {
+            retention_days: var("BEACON_EVENT_RETENTION_DAYS")
+                .filter(|days| *days > 0)
+                .map_or(defaults.retention_days, |days| days as i64),
+            max_events_per_server: var("BEACON_EVENT_QUEUE_MAX").unwrap_or(defaults.max_events_per_server),
+            max_bytes_per_server: var("BEACON_EVENT_QUEUE_MAX_BYTES").unwrap_or(defaults.max_bytes_per_server),
+        }
+    }
+
+    /// Whether a server holding `count` events of `bytes` total can take one more of `new_bytes`.
+    pub fn check(&self, count: usize, bytes: usize, new_bytes: usize) -> Result<(), QuotaExceeded> {
+        if self.max_events_per_server > 0 && count >= self.max_events_per_server {
+            return Err(QuotaExceeded::Count);
+        }
+        if self.max_bytes_per_server > 0 && bytes + new_bytes > self.max_bytes_per_server {
+            return Err(QuotaExceeded::Bytes);
+        }
+        Ok(())
+    }
+}
+
+/// A server's event queue is full, even after dropping what all its members acked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaExceeded {
+    Count,
+    Bytes,
+}
+
+impl QuotaExceeded {
+    pub fn message(self) -> &'static str {
+        match self {
+            QuotaExceeded::Count => "Server event queue is full (event count quota); members must ack pending events",
+            QuotaExceeded::Bytes => "Server event queue is full (byte quota); members must ack pending events",
+        }
+    }
+}
+
+/// Size of an event as counted against max_bytes_per_server.
+pub fn event_bytes(event: &ServerEvent) -> usize {
+    event.event_id.len() + event.event_type.len() + event.encrypted_payload.len() + event.signature.len()
+}
 
 /// Event queue state (REST API)
 /// Hints only - clients treat local state as authoritative
@@ -11,11 +99,14 @@ pub struct EventState {
     pub server_hints: HashMap<SigningPubkey, EncryptedServerHint>,
     /// Temporary invite tokens (short code -> encrypted payload)
     pub invite_tokens: HashMap<String, InviteTokenRecord>,
-    /// Event queue - time-limited, not consensus-based
-    /// Best-effort sync - timestamp collisions possible
+    /// Event queue - time-limited, not consensus-based. Ordered by seq.
     pub event_queues: HashMap<SigningPubkey, Vec<ServerEvent>>,
-    /// Best-effort acks - soft tracking, not hard requirement
-    pub member_acks: HashMap<(SigningPubkey, String), String>, // (signing_pubkey, user_id) -> last_event_id
+    /// Last seq assigned per server (kept when its events are garbage-collected).
+    pub event_seqs: HashMap<SigningPubkey, u64>,
+    /// Highest seq each member acked: (signing_pubkey, user_id) -> seq. Members that used the event
+    /// routes at least once are the server's known members; events all of them acked are compacted away.
+    pub member_acks: HashMap<(SigningPubkey, String), u64>,
+    pub limits: EventLimits,
 }
 
 impl EventState {
@@ -24,13 +115,24 @@ impl EventState {
             server_hints: HashMap::new(),
             invite_tokens: HashMap::new(),
             event_queues: HashMap::new(),
+            event_seqs: HashMap::new(),
             member_acks: HashMap::new(),
+            limits: EventLimits::default(),
         }
     }
 
-    /// Register/update server hint (any member can call this at any time)
-    pub fn register_server_hint(&mut self, signing_pubkey: String, hint: EncryptedServerHint) {
-        self.server_hints.insert(signing_pubkey, hint);
+    /// Register/update server hint after verifying its signer and that it is not older than the stored hint.
+    /// Returns the hint as stored (delegate-signed hints keep the stored key envelopes).
+    pub fn register_server_hint(
+        &mut self,
+        signing_pubkey: String,
+        mut hint: EncryptedServerHint,
+    ) -> Result<EncryptedServerHint, (StatusCode, &'static str)> {
+        let stored = self.server_hints.get(&signing_pubkey);
+        verify_server_hint(&signing_pubkey, &hint, stored)?;
+        carry_forward_key_envelopes(&signing_pubkey, &mut hint, stored);
+        self.server_hints.insert(signing_pubkey, hint.clone());
+        Ok(hint)
     }
 
     /// Get server hint
@@ -43,6 +145,13 @@ impl EventState {
         if code.len() < 6 || code.len() > 64 {
             return Err("Invalid invite code length".to_string());
         }
+        if self
+            .invite_tokens
+            .get(&code)
+            .is_some_and(|existing| existing.signing_pubkey != signing_pubkey)
+        {
+            return Err("Invite code already in use".to_string());
+        }
         let now = Utc::now();
         // Keep server-side cleanup; not user-facing.
         let expires_at = now + Duration::days(30);
@@ -66,6 +175,32 @@ impl EventState {
         self.invite_tokens.get(code)
     }
 
+    /// Outstanding invites for a server, oldest first.
+    pub fn list_invite_tokens(&self, signing_pubkey: &str) -> Vec<InviteTokenRecord> {
+        let mut out: Vec<InviteTokenRecord> = self
+            .invite_tokens
+            .values()
+            .filter(|rec| rec.signing_pubkey == signing_pubkey)
+            .cloned()
+            .collect();
+        out.sort_by_key(|rec| rec.created_at);
+        out
+    }
+
+    /// Remove an invite if it belongs to `signing_pubkey`. Returns whether it existed.
+    pub fn revoke_invite_token(&mut self, signing_pubkey: &str, code: &str) -> bool {
+        if self
+            .invite_tokens
+            .get(code)
+            .is_some_and(|rec| rec.signing_pubkey == signing_pubkey)
+        {
+            self.invite_tokens.remove(code);
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn redeem_invite_token(&mut self, code: &str) -> Option<InviteTokenRecord> {
         let Some(rec) = self.invite_tokens.get_mut(code) else {
             return None;
@@ -86,45 +221,107 @@ impl EventState {
         self.invite_tokens.retain(|_, v| v.expires_at > now);
     }
 
-    /// Post event to queue
-    pub fn post_event(&mut self, signing_pubkey: String, mut event: ServerEvent) {
+    /// Post event to queue, assigning the server's next seq. A full queue is compacted first.
+    pub fn post_event(&mut self, signing_pubkey: String, mut event: ServerEvent) -> Result<u64, QuotaExceeded> {
         event.timestamp = Utc::now();
         if event.event_id.is_empty() {
             event.event_id = uuid::Uuid::new_v4().to_string();
         }
+        if self.check_quota(&signing_pubkey, &event).is_err() {
+            self.compact(&signing_pubkey);
+            self.check_quota(&signing_pubkey, &event)?;
+        }
+        let seq = self.event_seqs.entry(signing_pubkey.clone()).or_insert(0);
+        *seq += 1;
+        event.seq = *seq;
         self.event_queues
             .entry(signing_pubkey)
             .or_insert_with(Vec::new)
             .push(event);
+        Ok(*seq)
+    }
+
+    fn check_quota(&self, signing_pubkey: &str, event: &ServerEvent) -> Result<(), QuotaExceeded> {
+        let events = self.event_queues.get(signing_pubkey).map(Vec::as_slice).unwrap_or_default();
+        let bytes = events.iter().map(event_bytes).sum();
+        self.limits.check(events.len(), bytes, event_bytes(event))
     }
 
-    /// Get events since a given event ID
-    pub fn get_events(&self, signing_pubkey: &str, since: Option<&str>) -> Vec<ServerEvent> {
-        let events = match self.event_queues.get(signing_pubkey) {
-            Some(events) => events.clone(),
-            None => return Vec::new(),
+    /// Up to `limit` events with seq > after_seq.
+    pub fn get_events_after(&self, signing_pubkey: &str, after_seq: u64, limit: usize) -> Result<Vec<ServerEvent>, CursorTooOld> {
+        let events = self.event_queues.get(signing_pubkey).map(Vec::as_slice).unwrap_or_default();
+        let latest = self.event_seqs.get(signing_pubkey).copied().unwrap_or(0);
+        check_cursor(after_seq, events.first().map(|e| e.seq), latest)?;
+        Ok(events.iter().filter(|e| e.seq > after_seq).take(limit).cloned().collect())
+    }
+
+    /// Get events since a given event ID (legacy cursor). An unknown ID means it was garbage-collected.
+    pub fn get_events(&self, signing_pubkey: &str, since: Option<&str>, limit: usize) -> Result<Vec<ServerEvent>, CursorTooOld> {
+        let after_seq = match since {
+            Some(since_id) => {
+                let events = self.event_queues.get(signing_pubkey).map(Vec::as_slice).unwrap_or_default();
+                match events.iter().find(|e| e.event_id == since_id) {
+                    Some(e) => e.seq,
+                    None => {
+                        let latest = self.event_seqs.get(signing_pubkey).copied().unwrap_or(0);
+                        return Err(CursorTooOld {
+                            oldest_seq: events.first().map_or(latest + 1, |e| e.seq),
+                            latest_seq: latest,
+                        });
+                    }
+                }
+            }
+            None => 0,
         };
+        self.get_events_after(signing_pubkey, after_seq, limit)
+    }
 
-        if let Some(since_id) = since {
-            events
-                .into_iter()
-                .skip_while(|e| e.event_id != since_id)
-                .skip(1)
-                .collect()
-        } else {
-            events
-        }
+    /// Count a member that reached the event routes as known, with nothing acked yet, so compaction
+    /// keeps its unread events even before its first ack.
+    pub fn register_member(&mut self, signing_pubkey: &str, user_id: &str) {
+        self.member_acks
+            .entry((signing_pubkey.to_string(), user_id.to_string()))
+            .or_insert(0);
+    }
+
+    /// Record a member's ack: `last_seq`, or the seq of `last_event_id`. Acks never move backwards,
+    /// nor past the last assigned seq (acking ahead would compact events before this member sees them).
+    pub fn ack_events(&mut self, signing_pubkey: String, user_id: String, last_event_id: &str, last_seq: Option<u64>) {
+        let latest = self.event_seqs.get(&signing_pubkey).copied().unwrap_or(0);
+        let seq = last_seq.or_else(|| {
+            self.event_queues
+                .get(&signing_pubkey)
+                .and_then(|events| events.iter().find(|e| e.event_id == last_event_id))
+                .map(|e| e.seq)
+        });
+        let Some(seq) = seq else {
+            return;
+        };
+        let acked = self.member_acks.entry((signing_pubkey, user_id)).or_insert(0);
+        *acked = (*acked).max(seq.min(latest));
     }
 
-    /// Acknowledge events (best-effort)
-    pub fn ack_events(&mut self, signing_pubkey: String, user_id: String, last_event_id: String) {
-        self.member_acks.insert((signing_pubkey, user_id), last_event_id);
+    /// Drop a server's events that every known member acked.
+    fn compact(&mut self, signing_pubkey: &str) {
+        let acked = self
+            .member_acks
+            .iter()
+            .filter(|((spk, _), _)| spk == signing_pubkey)
+            .map(|(_, seq)| *seq)
+            .min();
+        if let (Some(acked), Some(events)) = (acked, self.event_queues.get_mut(signing_pubkey)) {
+            events.retain(|e| e.seq > acked);
+        }
     }
 
-    /// Garbage collect old events (called periodically)
+    /// Garbage collect acked and expired events (called periodically)
     pub fn gc_old_events(&mut self) {
-        let cutoff = Utc::now() - Duration::days(EVENT_RETENTION_DAYS);
+        let cutoff = Utc::now() - Duration::days(self.limits.retention_days);
 
+        let servers: Vec<SigningPubkey> = self.event_queues.keys().cloned().collect();
+        for signing_pubkey in &servers {
+            self.compact(signing_pubkey);
+        }
         for events in self.event_queues.values_mut() {
             events.retain(|e| e.timestamp > cutoff);
         }
@@ -133,3 +330,205 @@ impl EventState {
         self.event_queues.retain(|_, events| !events.is_empty());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use chrono::DateTime;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn b64(bytes: &[u8]) -> String {
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    }
+
+    fn signed_hint(
+        key: &SigningKey,
+        server_key: &SigningKey,
+        last_updated: DateTime<Utc>,
+        delegate: Option<&SigningKey>,
+    ) -> EncryptedServerHint {
+        let spk = b64(server_key.verifying_key().as_bytes());
+        let signer = b64(key.verifying_key().as_bytes());
+        let mut hint = EncryptedServerHint {
+            signing_pubkey: spk.clone(),
+            encrypted_state: "opaque".to_string(),
+            signature: String::new(),
+            last_updated,
+            signer_pubkey: (signer != spk).then_some(signer),
+            delegate_pubkey: delegate.map(|d| b64(d.verifying_key().as_bytes())),
+            admin_pubkeys: Vec::new(),
+            key_envelopes: Vec::new(),
+        };
+        sign_hint(&mut hint, key);
+        hint
+    }
+
+    fn sign_hint(hint: &mut EncryptedServerHint, key: &SigningKey) {
+        let msg = crate::signing::server_hint_message(hint);
+        hint.signature = b64(&key.sign(msg.as_bytes()).to_bytes());
+    }
+
+    #[test]
+    fn rejects_unsigned_foreign_and_stale_hints() {
+        let server_key = SigningKey::from_bytes(&[1u8; 32]);
+        let attacker = SigningKey::from_bytes(&[2u8; 32]);
+        let spk = b64(server_key.verifying_key().as_bytes());
+        let now = Utc::now();
+        let mut state = EventState::new();
+
+        let mut unsigned = signed_hint(&server_key, &server_key, now, None);
+        unsigned.signature = String::new();
+        assert!(state.register_server_hint(spk.clone(), unsigned).is_err());
+
+        let forged = signed_hint(&attacker, &server_key, now, None);
+        assert!(state.register_server_hint(spk.clone(), forged).is_err());
+
+        let good = signed_hint(&server_key, &server_key, now, None);
+        assert!(state.register_server_hint(spk.clone(), good).is_ok());
+
+        let stale = signed_hint(&server_key, &server_key, now - Duration::seconds(5), None);
+        let (status, _) = state.register_server_hint(spk.clone(), stale).unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // A far-future hint would make every later one look stale.
+        let future = signed_hint(&server_key, &server_key, now + Duration::days(365), None);
+        let (status, _) = state.register_server_hint(spk.clone(), future).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let later = signed_hint(&server_key, &server_key, now + Duration::seconds(1), None);
+        assert!(state.register_server_hint(spk, later).is_ok());
+    }
+
+    #[test]
+    fn delegate_can_publish_but_not_reassign() {
+        let server_key = SigningKey::from_bytes(&[1u8; 32]);
+        let delegate = SigningKey::from_bytes(&[3u8; 32]);
+        let other = SigningKey::from_bytes(&[4u8; 32]);
+        let spk = b64(server_key.verifying_key().as_bytes());
+        let now = Utc::now();
+        let mut state = EventState::new();
+
+        // No delegate authorized yet.
+        let early = signed_hint(&delegate, &server_key, now, Some(&delegate));
+        assert!(state.register_server_hint(spk.clone(), early).is_err());
+
+        let owner = signed_hint(&server_key, &server_key, now, Some(&delegate));
+        assert!(state.register_server_hint(spk.clone(), owner).is_ok());
+
+        let member = signed_hint(&delegate, &server_key, now + Duration::seconds(1), Some(&delegate));
+        assert!(state.register_server_hint(spk.clone(), member).is_ok());
+
+        let reassign = signed_hint(&delegate, &server_key, now + Duration::seconds(2), Some(&other));
+        assert!(state.register_server_hint(spk, reassign).is_err());
+    }
+
+    #[test]
+    fn only_the_server_key_replaces_key_envelopes() {
+        let server_key = SigningKey::from_bytes(&[1u8; 32]);
+        let delegate = SigningKey::from_bytes(&[3u8; 32]);
+        let spk = b64(server_key.verifying_key().as_bytes());
+        let now = Utc::now();
+        let mut state = EventState::new();
+        let sealed = crate::SealedServerKey { user_id: "bob".to_string(), epoch: 1, sealed_key: "sealed".to_string() };
+
+        let mut rotation = signed_hint(&server_key, &server_key, now, Some(&delegate));
+        rotation.key_envelopes = vec![sealed.clone()];
+        sign_hint(&mut rotation, &server_key);
+        state.register_server_hint(spk.clone(), rotation.clone()).unwrap();
+
+        // The hint is public: re-posting it with the envelopes stripped or swapped must not wipe them.
+        let mut stripped = rotation.clone();
+        stripped.key_envelopes = Vec::new();
+        assert!(state.register_server_hint(spk.clone(), stripped.clone()).is_err());
+        sign_hint(&mut stripped, &server_key);
+        let (status, _) = state.register_server_hint(spk.clone(), stripped).unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(state.register_server_hint(spk.clone(), rotation).is_ok());
+
+        let mut member = signed_hint(&delegate, &server_key, now + Duration::seconds(1), Some(&delegate));
+        member.key_envelopes = Vec::new();
+        let stored = state.register_server_hint(spk.clone(), member).unwrap();
+        assert_eq!(stored.key_envelopes, vec![sealed]);
+        // Still verifies with the carried-forward envelopes, e.g. when a federated beacon sends it back.
+        assert!(state.register_server_hint(spk.clone(), stored).is_ok());
+
+        let owner = signed_hint(&server_key, &server_key, now + Duration::seconds(2), Some(&delegate));
+        assert!(state.register_server_hint(spk, owner).unwrap().key_envelopes.is_empty());
+    }
+
+    fn event(id: &str) -> ServerEvent {
+        ServerEvent {
+            event_id: id.to_string(),
+            signing_pubkey: "spk".to_string(),
+            event_type: "MemberJoin".to_string(),
+            encrypted_payload: String::new(),
+            signature: String::new(),
+            timestamp: Utc::now(),
+            seq: 0,
+        }
+    }
+
+    #[test]
+    fn pages_by_seq_and_reports_collected_cursors() {
+        let mut state = EventState::new();
+        for id in ["a", "b", "c", "d"] {
+            state.post_event("spk".to_string(), event(id)).unwrap();
+        }
+        let page = state.get_events_after("spk", 1, 2).unwrap();
+        assert_eq!(page.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(state.get_events("spk", Some("c"), 10).unwrap()[0].event_id, "d");
+
+        // "a" and "b" are garbage-collected: cursor 0 now has a gap, 1 (only "b" missing) too.
+        state.event_queues.get_mut("spk").unwrap().drain(..2);
+        let gone = CursorTooOld { oldest_seq: 3, latest_seq: 4 };
+        assert_eq!(state.get_events_after("spk", 1, 10).unwrap_err(), gone);
+        assert_eq!(state.get_events("spk", Some("a"), 10).unwrap_err(), gone);
+        assert_eq!(state.get_events_after("spk", 2, 10).unwrap().len(), 2);
+        assert!(state.get_events_after("spk", 4, 10).unwrap().is_empty());
+        // A cursor from before a restart without a database.
+        assert!(state.get_events_after("spk", 9, 10).is_err());
+        assert!(state.get_events_after("other", 0, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn compacts_what_every_member_acked_before_refusing_over_quota() {
+        let mut state = EventState::new();
+        state.limits.max_events_per_server = 3;
+        for id in ["a", "b", "c"] {
+            state.post_event("spk".to_string(), event(id)).unwrap();
+        }
+        state.ack_events("spk".to_string(), "alice".to_string(), "c", None);
+        state.ack_events("spk".to_string(), "bob".to_string(), "", Some(1));
+        // Acks never move backwards.
+        state.ack_events("spk".to_string(), "alice".to_string(), "a", None);
+        assert_eq!(state.member_acks[&("spk".to_string(), "alice".to_string())], 3);
+
+        // Only "a" is acked by everyone: one slot frees up, then the queue is full again.
+        assert_eq!(state.post_event("spk".to_string(), event("d")), Ok(4));
+        assert_eq!(state.post_event("spk".to_string(), event("e")), Err(QuotaExceeded::Count));
+
+        state.ack_events("spk".to_string(), "alice".to_string(), "d", None);
+        state.ack_events("spk".to_string(), "bob".to_string(), "d", None);
+        state.gc_old_events();
+        assert!(!state.event_queues.contains_key("spk"));
+        assert!(state.get_events_after("spk", 4, 10).unwrap().is_empty());
+
+        // Acking ahead is clamped to the last assigned seq, so it cannot pre-ack future events.
+        state.ack_events("spk".to_string(), "alice".to_string(), "", Some(u64::MAX));
+        assert_eq!(state.member_acks[&("spk".to_string(), "alice".to_string())], 4);
+        // A member that has not acked yet still holds its unread events.
+        state.register_member("spk", "carol");
+        state.post_event("spk".to_string(), event("g")).unwrap();
+        state.ack_events("spk".to_string(), "alice".to_string(), "g", None);
+        state.ack_events("spk".to_string(), "bob".to_string(), "g", None);
+        state.gc_old_events();
+        assert_eq!(state.get_events_after("spk", 4, 10).unwrap().len(), 1);
+        state.register_member("spk", "alice");
+        assert_eq!(state.member_acks[&("spk".to_string(), "alice".to_string())], 5);
+
+        state.limits.max_bytes_per_server = 10;
+        let mut big = event("f");
+        big.encrypted_payload = "x".repeat(16);
+        assert_eq!(state.post_event("spk".to_string(), big), Err(QuotaExceeded::Bytes));
+    }
+}
@@ -0,0 +1,200 @@
+//! Peer-to-peer history sync (HistorySyncRequest / HistorySyncResponse).
+//!
+//! The beacon stores no history: it forwards a request to a few online members of the server and
+//! relays their encrypted batches back to the requester, enforcing size caps. A sync only lives
+//! on the node the requester is connected to and ends when every asked peer said done, when the
+//! byte budget runs out, after HISTORY_SYNC_TTL, or when the requester disconnects.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::state::signaling::send_json;
+use crate::{ConnId, SignalingMessage, WebSocketSender};
+
+/// Online members asked per request.
+pub const HISTORY_SYNC_MAX_PEERS: usize = 3;
+/// Most messages a request may ask for.
+pub const HISTORY_SYNC_MAX_LIMIT: u32 = 200;
+/// Largest encrypted_payload in one HistorySyncResponse.
+pub const HISTORY_CHUNK_MAX_BYTES: usize = 256 * 1024;
+/// Most encrypted bytes relayed for one sync, all peers together.
+pub const HISTORY_SYNC_MAX_BYTES: usize = 4 * 1024 * 1024;
+/// How long peers have to answer.
+pub const HISTORY_SYNC_TTL: Duration = Duration::from_secs(30);
+/// Open syncs per requesting connection.
+pub const HISTORY_SYNCS_PER_CONN: usize = 8;
+
+pub struct PendingSync {
+    pub requester: ConnId,
+    /// Requester's sender; chunks go straight to it.
+    pub sender: WebSocketSender,
+    /// Asked peers that have not sent done yet.
+    waiting: HashSet<ConnId>,
+    bytes: usize,
+    expires_at: Instant,
+}
+
+/// Why a response chunk was not relayed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChunkRejected {
+    /// No open sync with this id asked this connection.
+    NotAsked,
+    /// The chunk alone exceeds HISTORY_CHUNK_MAX_BYTES (the sync goes on).
+    TooLarge,
+}
+
+/// What to do after a relayed chunk.
+pub enum ChunkAccepted {
+    /// Forward the chunk; more may follow.
+    Relay(WebSocketSender),
+    /// Forward the chunk, then tell the requester the sync is over (every peer was done).
+    Finish(WebSocketSender),
+    /// Budget already spent: drop the chunk and end the sync as truncated.
+    Exhausted(WebSocketSender),
+}
+
+#[derive(Default)]
+pub struct HistoryState {
+    pending: HashMap<String, PendingSync>,
+}
+
+impl HistoryState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a sync. Fails if the id is taken or the requester has too many open syncs.
+    pub fn start(
+        &mut self,
+        sync_id: &str,
+        requester: &ConnId,
+        sender: WebSocketSender,
+        peers: HashSet<ConnId>,
+        now: Instant,
+    ) -> Result<(), String> {
+        if self.pending.contains_key(sync_id) {
+            return Err(format!("sync_id {} is already in use", sync_id));
+        }
+        if self.pending.values().filter(|s| s.requester == *requester).count() >= HISTORY_SYNCS_PER_CONN {
+            return Err(format!("at most {} history syncs may be open per connection", HISTORY_SYNCS_PER_CONN));
+        }
+        self.pending.insert(
+            sync_id.to_string(),
+            PendingSync {
+                requester: requester.clone(),
+                sender,
+                waiting: peers,
+                bytes: 0,
+                expires_at: now + HISTORY_SYNC_TTL,
+            },
+        );
+        Ok(())
+    }
+
+    /// Account for a chunk from `responder` of `bytes` encrypted bytes.
+    pub fn accept_chunk(
+        &mut self,
+        sync_id: &str,
+        responder: &ConnId,
+        bytes: usize,
+        done: bool,
+    ) -> Result<ChunkAccepted, ChunkRejected> {
+        let Some(sync) = self.pending.get_mut(sync_id) else {
+            return Err(ChunkRejected::NotAsked);
+        };
+        if !sync.waiting.contains(responder) {
+            return Err(ChunkRejected::NotAsked);
+        }
+        if bytes > HISTORY_CHUNK_MAX_BYTES {
+            return Err(ChunkRejected::TooLarge);
+        }
+        if sync.bytes + bytes > HISTORY_SYNC_MAX_BYTES {
+            let sync = self.pending.remove(sync_id).expect("checked above");
+            return Ok(ChunkAccepted::Exhausted(sync.sender));
+        }
+        sync.bytes += bytes;
+        if done {
+            sync.waiting.remove(responder);
+        }
+        if sync.waiting.is_empty() {
+            let sync = self.pending.remove(sync_id).expect("checked above");
+            return Ok(ChunkAccepted::Finish(sync.sender));
+        }
+        Ok(ChunkAccepted::Relay(sync.sender.clone()))
+    }
+
+    /// Remove and return syncs whose peers did not finish within HISTORY_SYNC_TTL.
+    pub fn take_expired(&mut self, now: Instant) -> Vec<(String, PendingSync)> {
+        self.take_where(|sync| sync.expires_at <= now)
+    }
+
+    /// A connection went away: drop the syncs it requested, and stop waiting for it as a peer.
+    /// Returns the syncs left with nobody to wait for (tell their requesters they are done).
+    pub fn remove_conn(&mut self, conn_id: &ConnId) -> Vec<(String, PendingSync)> {
+        self.pending.retain(|_, sync| sync.requester != *conn_id);
+        for sync in self.pending.values_mut() {
+            sync.waiting.remove(conn_id);
+        }
+        self.take_where(|sync| sync.waiting.is_empty())
+    }
+
+    fn take_where(&mut self, pred: impl Fn(&PendingSync) -> bool) -> Vec<(String, PendingSync)> {
+        let ids: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, sync)| pred(sync))
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.into_iter()
+            .filter_map(|id| self.pending.remove(&id).map(|sync| (id, sync)))
+            .collect()
+    }
+}
+
+/// Tell a requester its sync is over.
+pub fn send_done(sender: &WebSocketSender, sync_id: String, truncated: bool) {
+    if let Ok(json) = serde_json::to_string(&SignalingMessage::HistorySyncDone { sync_id, truncated }) {
+        send_json(sender, &json, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outbound::{channel, OutboundLimits};
+    use std::sync::Arc;
+
+    #[test]
+    fn relays_only_asked_peers_within_the_byte_budget() {
+        let limits = Arc::new(OutboundLimits::new(16, Duration::from_secs(10)));
+        let (tx, _rx) = channel(limits);
+        let mut history = HistoryState::new();
+        let requester = "req".to_string();
+        let (a, b) = ("a".to_string(), "b".to_string());
+        let now = Instant::now();
+
+        history.start("s1", &requester, tx.clone(), [a.clone(), b.clone()].into(), now).unwrap();
+        assert!(history.start("s1", &requester, tx.clone(), HashSet::new(), now).is_err());
+
+        assert_eq!(history.accept_chunk("s1", &requester, 10, false).err(), Some(ChunkRejected::NotAsked));
+        assert_eq!(
+            history.accept_chunk("s1", &a, HISTORY_CHUNK_MAX_BYTES + 1, false).err(),
+            Some(ChunkRejected::TooLarge)
+        );
+        assert!(matches!(history.accept_chunk("s1", &a, 100, true), Ok(ChunkAccepted::Relay(_))));
+        // a is done; only b may still answer.
+        assert_eq!(history.accept_chunk("s1", &a, 100, false).err(), Some(ChunkRejected::NotAsked));
+        assert!(matches!(history.accept_chunk("s1", &b, 100, true), Ok(ChunkAccepted::Finish(_))));
+
+        history.start("s2", &requester, tx.clone(), [a.clone()].into(), now).unwrap();
+        let chunk = HISTORY_CHUNK_MAX_BYTES;
+        for _ in 0..HISTORY_SYNC_MAX_BYTES / chunk {
+            assert!(matches!(history.accept_chunk("s2", &a, chunk, false), Ok(ChunkAccepted::Relay(_))));
+        }
+        assert!(matches!(history.accept_chunk("s2", &a, 1, false), Ok(ChunkAccepted::Exhausted(_))));
+        assert!(history.take_expired(now + HISTORY_SYNC_TTL).is_empty());
+
+        history.start("s3", &requester, tx, [a.clone()].into(), now).unwrap();
+        assert_eq!(history.remove_conn(&a).len(), 1);
+    }
+}
@@ -0,0 +1,76 @@
+//! Last frame seen per WebSocket connection.
+//!
+//! The socket task stamps every frame it reads and reaps its connection once the stamp is older
+//! than the idle deadline. Kept here rather than in the task so anything holding the state can ask
+//! how long a connection has been silent. A detached (resumable) session keeps its last stamp
+//! until it is resumed or cleaned up.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::ConnId;
+
+#[derive(Default)]
+pub struct LivenessState {
+    last_seen: HashMap<ConnId, Instant>,
+}
+
+impl LivenessState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a frame (or a fresh / resumed socket) from `conn_id`.
+    pub fn touch(&mut self, conn_id: &ConnId, now: Instant) {
+        self.last_seen.insert(conn_id.clone(), now);
+    }
+
+    /// How long `conn_id` has been silent; None for an unknown connection.
+    pub fn idle_for(&self, conn_id: &ConnId, now: Instant) -> Option<Duration> {
+        self.last_seen.get(conn_id).map(|seen| now.saturating_duration_since(*seen))
+    }
+
+    /// Connections silent for longer than `timeout`.
+    pub fn stale(&self, timeout: Duration, now: Instant) -> Vec<ConnId> {
+        self.last_seen
+            .iter()
+            .filter(|(_, seen)| now.saturating_duration_since(**seen) > timeout)
+            .map(|(conn_id, _)| conn_id.clone())
+            .collect()
+    }
+
+    pub fn remove_conn(&mut self, conn_id: &ConnId) {
+        self.last_seen.remove(conn_id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.last_seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last_seen.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_silence_per_connection() {
+        let mut liveness = LivenessState::new();
+        let start = Instant::now();
+        let (a, b): (ConnId, ConnId) = ("conn-a".to_string(), "conn-b".to_string());
+        liveness.touch(&a, start);
+        liveness.touch(&b, start);
+        liveness.touch(&b, start + Duration::from_secs(50));
+
+        let now = start + Duration::from_secs(61);
+        assert_eq!(liveness.idle_for(&a, now), Some(Duration::from_secs(61)));
+        assert_eq!(liveness.stale(Duration::from_secs(60), now), vec![a.clone()]);
+
+        liveness.remove_conn(&a);
+        assert_eq!(liveness.idle_for(&a, now), None);
+        assert_eq!(liveness.len(), 1);
+    }
+}
@@ -0,0 +1,151 @@
+//! Offline mailbox for direct (user-to-user) messages.
+//!
+//! Off by default: Cordia makes no offline backfill promise. When BEACON_MAILBOX_TTL_SECS is set,
+//! a user can opt in with MailboxOptIn; relayed direct messages (ProfilePush, friend checks,
+//! attachment transfer signals) that find none of their connections are then kept as-is for the
+//! TTL, within a per-user quota, and handed over and deleted on their next authenticated
+//! PresenceHello. With Postgres on, the mailbox lives in the database instead (see handlers::db).
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use chrono::{DateTime, Duration, Utc};
+
+/// Longest TTL accepted from BEACON_MAILBOX_TTL_SECS (mail is meant to be short-lived).
+pub const MAILBOX_MAX_TTL_SECS: u64 = 7 * 24 * 3600;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MailboxLimits {
+    /// How long an envelope is kept (0 = mailbox disabled).
+    pub ttl_secs: u64,
+    /// Envelopes kept per user (0 = no cap).
+    pub max_per_user: usize,
+    /// Bytes kept per user (0 = no cap).
+    pub max_bytes_per_user: usize,
+}
+
+impl Default for MailboxLimits {
+    fn default() -> Self {
+        Self {
+            ttl_secs: 0,
+            max_per_user: 50,
+            max_bytes_per_user: 256 * 1024,
+        }
+    }
+}
+
+impl MailboxLimits {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok().and_then(|v| v.trim().parse::<usize>().ok());
+        Self {
+            ttl_secs: var("BEACON_MAILBOX_TTL_SECS").map_or(defaults.ttl_secs, |secs| (secs as u64).min(MAILBOX_MAX_TTL_SECS)),
+            max_per_user: var("BEACON_MAILBOX_MAX").unwrap_or(defaults.max_per_user),
+            max_bytes_per_user: var("BEACON_MAILBOX_MAX_BYTES").unwrap_or(defaults.max_bytes_per_user),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.ttl_secs > 0
+    }
+
+    /// Whether a mailbox holding `count` envelopes of `bytes` total can take one more of `new_bytes`.
+    pub fn fits(&self, count: usize, bytes: usize, new_bytes: usize) -> bool {
+        (self.max_per_user == 0 || count < self.max_per_user)
+            && (self.max_bytes_per_user == 0 || bytes + new_bytes <= self.max_bytes_per_user)
+    }
+}
+
+struct Envelope {
+    json: String,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+pub struct MailboxState {
+    pub limits: MailboxLimits,
+    opted_in: HashSet<String>,
+    boxes: HashMap<String, VecDeque<Envelope>>,
+}
+
+impl MailboxState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opt a user in or out. Opting out drops whatever is waiting.
+    pub fn set_opt_in(&mut self, user_id: &str, enabled: bool) {
+        if enabled {
+            self.opted_in.insert(user_id.to_string());
+        } else {
+            self.opted_in.remove(user_id);
+            self.boxes.remove(user_id);
+        }
+    }
+
+    /// Keep `json` for an offline user. False if the mailbox is off, the user did not opt in, or
+    /// their mailbox is full.
+    pub fn put(&mut self, user_id: &str, json: &str, now: DateTime<Utc>) -> bool {
+        if !self.limits.enabled() || !self.opted_in.contains(user_id) {
+            return false;
+        }
+        let limits = self.limits;
+        let mailbox = self.boxes.entry(user_id.to_string()).or_default();
+        mailbox.retain(|envelope| envelope.expires_at > now);
+        let bytes: usize = mailbox.iter().map(|envelope| envelope.json.len()).sum();
+        if !limits.fits(mailbox.len(), bytes, json.len()) {
+            return false;
+        }
+        mailbox.push_back(Envelope {
+            json: json.to_string(),
+            expires_at: now + Duration::seconds(limits.ttl_secs as i64),
+        });
+        true
+    }
+
+    /// Remove and return a user's unexpired envelopes, oldest first.
+    pub fn take(&mut self, user_id: &str, now: DateTime<Utc>) -> Vec<String> {
+        self.boxes
+            .remove(user_id)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|envelope| envelope.expires_at > now)
+            .map(|envelope| envelope.json)
+            .collect()
+    }
+
+    /// Drop expired envelopes of users who have not come back.
+    pub fn gc(&mut self, now: DateTime<Utc>) {
+        self.boxes.retain(|_, mailbox| {
+            mailbox.retain(|envelope| envelope.expires_at > now);
+            !mailbox.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_mail_only_for_opted_in_users_within_ttl_and_quota() {
+        let mut mailbox = MailboxState::new();
+        let now = Utc::now();
+        mailbox.set_opt_in("bob", true);
+        // Off by default.
+        assert!(!mailbox.put("bob", "m0", now));
+
+        mailbox.limits = MailboxLimits { ttl_secs: 60, max_per_user: 2, max_bytes_per_user: 0 };
+        assert!(!mailbox.put("carol", "m0", now));
+        assert!(mailbox.put("bob", "m1", now));
+        assert!(mailbox.put("bob", "m2", now + Duration::seconds(30)));
+        assert!(!mailbox.put("bob", "m3", now + Duration::seconds(30)));
+        // m1 expired, which frees a slot.
+        assert!(mailbox.put("bob", "m3", now + Duration::seconds(61)));
+        assert_eq!(mailbox.take("bob", now + Duration::seconds(61)), vec!["m2", "m3"]);
+        assert!(mailbox.take("bob", now).is_empty());
+
+        assert!(mailbox.put("bob", "m4", now));
+        mailbox.set_opt_in("bob", false);
+        assert!(mailbox.take("bob", now).is_empty());
+    }
+}
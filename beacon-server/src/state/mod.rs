@@ -6,6 +6,13 @@ pub mod events;
 pub mod backends;
 pub mod friends;
 pub mod swarm;
+pub mod auth;
+pub mod protocol;
+pub mod sessions;
+pub mod typing;
+pub mod history;
+pub mod mailbox;
+pub mod liveness;
 
 pub use signaling::SignalingState;
 pub use voice::VoiceState;
@@ -15,11 +22,19 @@ pub use events::EventState;
 pub use backends::BackendState;
 pub use friends::FriendState;
 pub use swarm::SwarmState;
+pub use auth::AuthState;
+pub use protocol::ProtocolState;
+pub use sessions::SessionState;
+pub use typing::TypingState;
+pub use history::HistoryState;
+pub use mailbox::MailboxState;
+pub use liveness::LivenessState;
 
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::{Mutex, RwLock};
-use crate::{SigningPubkey, SignalingMessage, ProfileRecord, PeerId, ServerId, WebSocketSender};
+use crate::{SigningPubkey, SignalingMessage, ProfileRecord, PeerId, ServerId, ConnId, EncryptedServerHint};
+use crate::cluster::ClusterEvent;
 use tokio_tungstenite::tungstenite::Message;
 
 use crate::state::signaling::FRIENDS_SIGNING_PUBKEY;
@@ -35,6 +50,24 @@ pub struct AppState {
     pub backends: Arc<RwLock<BackendState>>,
     pub friends: Arc<RwLock<FriendState>>,
     pub swarm: Arc<RwLock<SwarmState>>,
+    /// WebSocket login: challenge nonces and verified identity per connection.
+    pub auth: Arc<RwLock<AuthState>>,
+    /// Protocol version and capabilities negotiated per connection (Hello/Welcome).
+    pub protocol: Arc<RwLock<ProtocolState>>,
+    /// Resume tokens and WebSocket sessions held after their socket dropped.
+    pub sessions: Arc<Mutex<SessionState>>,
+    /// Who is typing in which chat, with expiry.
+    pub typing: Arc<Mutex<TypingState>>,
+    /// History syncs waiting for peers' encrypted batches.
+    pub history: Arc<Mutex<HistoryState>>,
+    /// Opt-in offline mailbox for direct messages (off unless BEACON_MAILBOX_TTL_SECS is set).
+    pub mailbox: Arc<Mutex<MailboxState>>,
+    /// Last frame seen per WebSocket connection (heartbeat deadline).
+    pub liveness: Arc<Mutex<LivenessState>>,
+    /// Set once at startup in cluster mode (several beacons sharing a Redis pub/sub bus).
+    pub cluster: std::sync::OnceLock<Arc<crate::cluster::Cluster>>,
+    /// Set once at startup when allow-listed beacons are configured (BEACON_FEDERATION_PEERS).
+    pub federation: std::sync::OnceLock<Arc<crate::federation::Federation>>,
     /// When the beacon process started (for uptime / status page).
     pub started_at: Instant,
     /// ISO8601 timestamp when the beacon started (for status).
@@ -47,15 +80,25 @@ pub struct AppState {
     pub cpu_percent_cache: Arc<Mutex<Option<f32>>>,
     /// WebSocket connection limits (global and per-IP). Always present; limits 0 = no cap.
     pub connection_tracker: crate::security::SharedConnectionTracker,
-    /// Per-IP WebSocket message rate limiter; None = no limit.
-    pub ws_rate_limiter: Option<Arc<crate::security::KeyedRateLimiter>>,
+    /// Weighted WebSocket message limits (per IP before login, per user, per connection, fan-out).
+    pub ws_limits: Arc<crate::security::WsRateLimits>,
+    /// Signatures of accepted signed REST requests (friend + server event APIs), for replay protection.
+    pub replay_cache: crate::security::SharedReplayCache,
+    /// Per-connection outbound queue limits and queue-depth metrics.
+    pub outbound: Arc<crate::outbound::OutboundLimits>,
+    /// WebSocket ping interval and idle deadline.
+    pub ws_heartbeat: crate::security::WsHeartbeat,
 }
 
 impl AppState {
     pub fn new(
         downtime_secs: Option<u64>,
         connection_tracker: crate::security::SharedConnectionTracker,
-        ws_rate_limiter: Option<Arc<crate::security::KeyedRateLimiter>>,
+        ws_limits: Arc<crate::security::WsRateLimits>,
+        replay_cache: crate::security::SharedReplayCache,
+        outbound: Arc<crate::outbound::OutboundLimits>,
+        ws_heartbeat: crate::security::WsHeartbeat,
+        ws_resume_grace: std::time::Duration,
     ) -> Self {
         let now_utc = chrono::Utc::now();
         Self {
@@ -67,50 +110,153 @@ impl AppState {
             backends: Arc::new(RwLock::new(BackendState::new())),
             friends: Arc::new(RwLock::new(FriendState::new())),
             swarm: Arc::new(RwLock::new(SwarmState::new())),
+            auth: Arc::new(RwLock::new(AuthState::new())),
+            protocol: Arc::new(RwLock::new(ProtocolState::new())),
+            sessions: Arc::new(Mutex::new(SessionState::new(ws_resume_grace))),
+            typing: Arc::new(Mutex::new(TypingState::new())),
+            history: Arc::new(Mutex::new(HistoryState::new())),
+            mailbox: Arc::new(Mutex::new(MailboxState::new())),
+            liveness: Arc::new(Mutex::new(LivenessState::new())),
+            cluster: std::sync::OnceLock::new(),
+            federation: std::sync::OnceLock::new(),
             started_at: Instant::now(),
             started_at_utc: now_utc.to_rfc3339(),
             downtime_secs,
             network_prev: Arc::new(Mutex::new(None)),
             cpu_percent_cache: Arc::new(Mutex::new(None)),
             connection_tracker,
-            ws_rate_limiter,
+            ws_limits,
+            replay_cache,
+            outbound,
+            ws_heartbeat,
         }
     }
 
-    /// Broadcast a presence update to all peers subscribed to a server.
-    /// This coordinates between PresenceState and SignalingState.
-    pub async fn broadcast_presence_update(&self, signing_pubkey: &SigningPubkey, user_id: &str, online: bool, active: Option<SigningPubkey>) {
-        let signaling = self.signaling.read().await;
-        let Some(peers) = signaling.signing_servers.get(signing_pubkey) else {
+    /// Forward a routed event to the other beacon nodes (no-op outside cluster mode).
+    pub fn publish(&self, event: crate::cluster::ClusterEvent) {
+        if let Some(cluster) = self.cluster.get() {
+            cluster.publish(event);
+        }
+    }
+
+    /// Send a message to every connection subscribed to a server, on this node, (in cluster mode)
+    /// on the others and on federated beacons sharing the server. Updates with a coalesce_key
+    /// replace a still-queued one with the same key.
+    pub async fn broadcast_to_server(
+        &self,
+        signing_pubkey: &SigningPubkey,
+        msg: &SignalingMessage,
+        coalesce_key: Option<String>,
+        exclude_conn_id: Option<&ConnId>,
+    ) {
+        let Ok(json) = serde_json::to_string(msg) else {
             return;
         };
+        if let Some(federation) = self.federation.get() {
+            federation.send_server(signing_pubkey, &json, coalesce_key.as_deref());
+        }
+        self.deliver_to_server(signing_pubkey, json, coalesce_key, exclude_conn_id).await;
+    }
 
-        let msg = SignalingMessage::PresenceUpdate {
+    /// Deliver a serialized server message within this beacon (this node and its cluster), not to
+    /// federated beacons.
+    pub(crate) async fn deliver_to_server(
+        &self,
+        signing_pubkey: &SigningPubkey,
+        json: String,
+        coalesce_key: Option<String>,
+        exclude_conn_id: Option<&ConnId>,
+    ) {
+        self.signaling
+            .read()
+            .await
+            .send_to_server_subscribers(signing_pubkey, &json, coalesce_key.as_deref(), exclude_conn_id);
+        self.publish(ClusterEvent::Server {
             signing_pubkey: signing_pubkey.clone(),
-            user_id: user_id.to_string(),
-            online,
-            active_signing_pubkey: active,
-        };
+            json,
+            coalesce_key,
+        });
+    }
 
-        let Ok(json) = serde_json::to_string(&msg) else {
+    /// Relay chat traffic (see chat_route) to the server, like broadcast_to_server, but scoped to
+    /// its chat for connections using chat subscriptions.
+    pub async fn broadcast_to_chat(&self, msg: &SignalingMessage, exclude_conn_id: Option<&ConnId>) {
+        let Some((signing_pubkey, _)) = chat_route(msg) else {
+            return;
+        };
+        let Ok(json) = serde_json::to_string(msg) else {
             return;
         };
+        if let Some(federation) = self.federation.get() {
+            federation.send_server(signing_pubkey, &json, typing_coalesce_key(msg).as_deref());
+        }
+        self.deliver_to_chat(msg, json, exclude_conn_id).await;
+    }
 
-        for peer_id in peers {
-            if let Some(sender) = signaling.peer_senders.get(peer_id) {
-                let _ = sender.send(Message::Text(json.clone()));
+    /// Deliver serialized chat traffic within this beacon (this node and its cluster).
+    pub(crate) async fn deliver_to_chat(&self, msg: &SignalingMessage, json: String, exclude_conn_id: Option<&ConnId>) {
+        let Some((signing_pubkey, chat_id)) = chat_route(msg) else {
+            return;
+        };
+        let activity = match msg {
+            SignalingMessage::EphemeralChatIncoming { message_id, from_user_id, sent_at, .. } => {
+                serde_json::to_string(&SignalingMessage::ChatActivity {
+                    signing_pubkey: signing_pubkey.clone(),
+                    chat_id: chat_id.to_string(),
+                    message_id: message_id.clone(),
+                    from_user_id: from_user_id.clone(),
+                    sent_at: sent_at.clone(),
+                })
+                .ok()
             }
-        }
+            _ => None,
+        };
+        let coalesce_key = typing_coalesce_key(msg);
+        self.signaling.read().await.send_to_chat_subscribers(
+            signing_pubkey,
+            chat_id,
+            &json,
+            coalesce_key.as_deref(),
+            activity.as_deref(),
+            exclude_conn_id,
+        );
+        self.publish(ClusterEvent::Chat {
+            signing_pubkey: signing_pubkey.clone(),
+            chat_id: chat_id.to_string(),
+            json,
+            coalesce_key,
+            activity,
+        });
+    }
+
+    /// Tell a chat's subscribers that a user started or stopped typing.
+    pub async fn broadcast_typing(&self, key: typing::TypingKey, typing: bool, exclude_conn_id: Option<&ConnId>) {
+        let (signing_pubkey, chat_id, user_id) = key;
+        let msg = SignalingMessage::TypingUpdate {
+            signing_pubkey,
+            chat_id,
+            user_id,
+            typing,
+        };
+        self.broadcast_to_chat(&msg, exclude_conn_id).await;
+    }
+
+    /// Broadcast a presence update to all peers subscribed to a server.
+    /// This coordinates between PresenceState and SignalingState.
+    pub async fn broadcast_presence_update(&self, signing_pubkey: &SigningPubkey, user_id: &str, online: bool, active: Option<SigningPubkey>) {
+        let msg = SignalingMessage::PresenceUpdate {
+            signing_pubkey: signing_pubkey.clone(),
+            user_id: user_id.to_string(),
+            online,
+            active_signing_pubkey: active,
+        };
+        let key = format!("presence:{}:{}", signing_pubkey, user_id);
+        self.broadcast_to_server(signing_pubkey, &msg, Some(key), None).await;
     }
 
     /// Broadcast a profile update to all peers subscribed to a server.
     /// This coordinates between ProfileState and SignalingState.
     pub async fn broadcast_profile_update(&self, signing_pubkey: &SigningPubkey, user_id: &str, rec: &ProfileRecord) {
-        let signaling = self.signaling.read().await;
-        let Some(peers) = signaling.signing_servers.get(signing_pubkey) else {
-            return;
-        };
-
         let msg = SignalingMessage::ProfileUpdate {
             signing_pubkey: signing_pubkey.clone(),
             user_id: user_id.to_string(),
@@ -119,28 +265,92 @@ impl AppState {
             show_real_name: rec.show_real_name,
             rev: rec.rev,
         };
+        let key = format!("profile:{}:{}", signing_pubkey, user_id);
+        self.broadcast_to_server(signing_pubkey, &msg, Some(key), None).await;
+    }
 
-        let Ok(json) = serde_json::to_string(&msg) else {
-            return;
+    /// Tell everyone subscribed to a server that its encrypted hint changed. Federated beacons get
+    /// the signed hint itself, so they can verify and store it.
+    pub async fn broadcast_server_hint_updated(&self, signing_pubkey: &SigningPubkey, hint: &EncryptedServerHint) {
+        if let Some(federation) = self.federation.get() {
+            federation.send_hint(hint);
+        }
+        self.deliver_server_hint_updated(signing_pubkey, hint).await;
+    }
+
+    pub(crate) async fn deliver_server_hint_updated(&self, signing_pubkey: &SigningPubkey, hint: &EncryptedServerHint) {
+        let msg = SignalingMessage::ServerHintUpdated {
+            signing_pubkey: signing_pubkey.clone(),
+            encrypted_state: hint.encrypted_state.clone(),
+            signature: hint.signature.clone(),
+            last_updated: hint.last_updated,
         };
+        if let Ok(json) = serde_json::to_string(&msg) {
+            self.deliver_to_server(signing_pubkey, json, None, None).await;
+        }
+    }
 
-        for peer_id in peers {
-            if let Some(sender) = signaling.peer_senders.get(peer_id) {
-                let _ = sender.send(Message::Text(json.clone()));
-            }
+    /// Tell other cluster nodes and federated beacons that a local peer joined a voice chat.
+    pub fn announce_voice_join(
+        &self,
+        server_id: &ServerId,
+        chat_id: &str,
+        signing_pubkey: &SigningPubkey,
+        peer_id: &PeerId,
+        user_id: &str,
+        conn_id: &ConnId,
+    ) {
+        self.publish(ClusterEvent::VoiceJoined {
+            server_id: server_id.clone(),
+            chat_id: chat_id.to_string(),
+            signing_pubkey: signing_pubkey.clone(),
+            peer_id: peer_id.clone(),
+            user_id: user_id.to_string(),
+            conn_id: conn_id.clone(),
+        });
+        if let Some(federation) = self.federation.get() {
+            federation.send_voice_joined(server_id, chat_id, signing_pubkey, peer_id, user_id);
+        }
+    }
+
+    /// Counterpart of announce_voice_join. `signing_pubkey` is needed for federation only.
+    pub fn announce_voice_leave(
+        &self,
+        server_id: &ServerId,
+        chat_id: &str,
+        signing_pubkey: Option<&SigningPubkey>,
+        peer_id: &PeerId,
+    ) {
+        self.publish(ClusterEvent::VoiceLeft {
+            server_id: server_id.clone(),
+            chat_id: chat_id.to_string(),
+            peer_id: peer_id.clone(),
+        });
+        if let (Some(federation), Some(signing_pubkey)) = (self.federation.get(), signing_pubkey) {
+            federation.send_voice_left(server_id, chat_id, signing_pubkey, peer_id);
         }
     }
 
     /// Broadcast a message to all peers in a voice chat.
     /// This coordinates between VoiceState and SignalingState.
     pub async fn broadcast_to_voice_room(&self, server_id: &ServerId, chat_id: &str, msg: &SignalingMessage, exclude_peer: Option<&PeerId>) {
-        let voice = self.voice.read().await;
-        let key = (server_id.clone(), chat_id.to_string());
-        let Some(peers) = voice.voice_chats.get(&key) else {
+        let Ok(json) = serde_json::to_string(msg) else {
             return;
         };
+        self.send_to_voice_room_local(server_id, chat_id, &json, exclude_peer).await;
+        self.publish(ClusterEvent::VoiceRoom {
+            server_id: server_id.clone(),
+            chat_id: chat_id.to_string(),
+            json,
+            exclude_peer: exclude_peer.cloned(),
+        });
+    }
 
-        let Ok(json) = serde_json::to_string(msg) else {
+    /// Deliver to the voice chat members connected to this node.
+    pub async fn send_to_voice_room_local(&self, server_id: &ServerId, chat_id: &str, json: &str, exclude_peer: Option<&PeerId>) {
+        let voice = self.voice.read().await;
+        let key = (server_id.clone(), chat_id.to_string());
+        let Some(peers) = voice.voice_chats.get(&key) else {
             return;
         };
 
@@ -155,86 +365,197 @@ impl AppState {
             }
 
             if let Some(sender) = signaling.peer_senders.get(&peer.peer_id) {
-                let _ = sender.send(Message::Text(json.clone()));
+                let _ = sender.send(Message::Text(json.to_string()));
             }
         }
     }
 
-    /// Get the sender for a specific peer in a voice chat.
-    /// This coordinates between VoiceState and SignalingState.
-    pub async fn get_voice_peer_sender(&self, server_id: &ServerId, chat_id: &str, peer_id: &PeerId) -> Option<WebSocketSender> {
-        let voice = self.voice.read().await;
-        let key = (server_id.clone(), chat_id.to_string());
-        let peers = voice.voice_chats.get(&key)?;
+    /// Deliver a message to a peer in a voice chat, wherever it is connected.
+    /// Returns false if the peer is not in the chat.
+    pub async fn send_to_voice_peer(&self, server_id: &ServerId, chat_id: &str, peer_id: &PeerId, json: String) -> bool {
+        {
+            let voice = self.voice.read().await;
+            let key = (server_id.clone(), chat_id.to_string());
+            let Some(peers) = voice.voice_chats.get(&key) else {
+                return false;
+            };
+            // Verify peer is in this room
+            if !peers.iter().any(|p| &p.peer_id == peer_id) {
+                return false;
+            }
+        }
+        self.send_to_peer(peer_id, json).await;
+        true
+    }
 
-        // Verify peer is in this room
-        if !peers.iter().any(|p| &p.peer_id == peer_id) {
-            return None;
+    /// Deliver a message to a peer_id: directly if it is connected here, over the federation link
+    /// of a remote voice member, else through the cluster.
+    /// Returns false only when the peer is unknown and there is no cluster to ask.
+    pub async fn send_to_peer(&self, peer_id: &PeerId, json: String) -> bool {
+        if let Some(sender) = self.signaling.read().await.peer_senders.get(peer_id) {
+            let _ = sender.send(Message::Text(json));
+            return true;
+        }
+        if let Some(federation) = self.federation.get() {
+            if federation.send_peer(peer_id, &json) {
+                return true;
+            }
         }
+        if self.cluster.get().is_none() {
+            return false;
+        }
+        self.publish(ClusterEvent::Peer {
+            peer_id: peer_id.clone(),
+            json,
+        });
+        true
+    }
 
-        let signaling = self.signaling.read().await;
-        signaling.peer_senders.get(peer_id).cloned()
+    /// Send a message to all of a user's connections on any node.
+    pub async fn send_to_user(&self, user_id: &str, json: &str) {
+        self.send_to_users(&[user_id.to_string()], json).await;
     }
 
-    /// Broadcast voice presence update to all presence connections for a server.
-    /// This coordinates between VoiceState and SignalingState.
-    pub async fn broadcast_voice_presence(&self, signing_pubkey: &SigningPubkey, user_id: &str, chat_id: &str, in_voice: bool) {
-        let signaling = self.signaling.read().await;
-        let Some(peers) = signaling.signing_servers.get(signing_pubkey) else {
+    /// Send one message to all connections of several users (one cluster publish for all of them).
+    pub async fn send_to_users(&self, user_ids: &[String], json: &str) {
+        if user_ids.is_empty() {
             return;
+        }
+        {
+            let friends = self.friends.read().await;
+            for user_id in user_ids {
+                friends.send_to_user(user_id, json);
+            }
+        }
+        self.publish(ClusterEvent::Users {
+            user_ids: user_ids.to_vec(),
+            json: json.to_string(),
+        });
+    }
+
+    /// Like send_to_user, but keep the message in the user's mailbox if none of their connections got it.
+    pub async fn send_to_user_or_mailbox(&self, user_id: &str, json: &str) {
+        self.send_to_users_or_mailbox(&[user_id.to_string()], json).await;
+    }
+
+    /// Like send_to_users, but users with no connection keep the message in their mailbox (when
+    /// the mailbox is enabled and they opted in). Skipped in cluster mode, where another node may
+    /// have delivered it.
+    pub async fn send_to_users_or_mailbox(&self, user_ids: &[String], json: &str) {
+        self.send_to_users(user_ids, json).await;
+        if self.cluster.get().is_some() {
+            return;
+        }
+        let limits = self.mailbox.lock().await.limits;
+        if !limits.enabled() {
+            return;
+        }
+        let offline: Vec<String> = {
+            let friends = self.friends.read().await;
+            user_ids
+                .iter()
+                .filter(|user_id| !friends.user_connections.contains_key(*user_id))
+                .cloned()
+                .collect()
         };
+        if offline.is_empty() {
+            return;
+        }
+
+        #[cfg(feature = "postgres")]
+        {
+            let db = self.backends.read().await.db.clone();
+            if let Some(pool) = db {
+                if let Err(e) = crate::handlers::db::put_mailbox_db(&pool, &offline, json, &limits).await {
+                    log::warn!("Mailbox put failed: {}", e);
+                }
+                return;
+            }
+        }
+
+        let mut mailbox = self.mailbox.lock().await;
+        let now = chrono::Utc::now();
+        for user_id in &offline {
+            mailbox.put(user_id, json, now);
+        }
+    }
+
+    /// Opt a user in or out of the mailbox.
+    pub async fn set_mailbox_opt_in(&self, user_id: &str, enabled: bool) -> Result<(), String> {
+        #[cfg(feature = "postgres")]
+        {
+            let db = self.backends.read().await.db.clone();
+            if let Some(pool) = db {
+                return crate::handlers::db::set_mailbox_opt_in_db(&pool, user_id, enabled).await;
+            }
+        }
+        self.mailbox.lock().await.set_opt_in(user_id, enabled);
+        Ok(())
+    }
+
+    /// Remove and return the messages waiting in a user's mailbox, oldest first.
+    pub async fn take_mailbox(&self, user_id: &str) -> Vec<String> {
+        if !self.mailbox.lock().await.limits.enabled() {
+            return Vec::new();
+        }
+
+        #[cfg(feature = "postgres")]
+        {
+            let db = self.backends.read().await.db.clone();
+            if let Some(pool) = db {
+                return crate::handlers::db::take_mailbox_db(&pool, user_id).await.unwrap_or_else(|e| {
+                    log::warn!("Mailbox take failed: {}", e);
+                    Vec::new()
+                });
+            }
+        }
+        self.mailbox.lock().await.take(user_id, chrono::Utc::now())
+    }
 
+    /// Broadcast voice presence update to all presence connections for a server.
+    /// This coordinates between VoiceState and SignalingState.
+    pub async fn broadcast_voice_presence(&self, signing_pubkey: &SigningPubkey, user_id: &str, chat_id: &str, in_voice: bool) {
         let msg = SignalingMessage::VoicePresenceUpdate {
             signing_pubkey: signing_pubkey.clone(),
             user_id: user_id.to_string(),
             chat_id: chat_id.to_string(),
             in_voice,
         };
+        // Send to all peer connections subscribed to this server (same mechanism as presence updates)
+        let key = format!("voice_presence:{}:{}:{}", signing_pubkey, user_id, chat_id);
+        self.broadcast_to_server(signing_pubkey, &msg, Some(key), None).await;
+    }
 
-        let Ok(json) = serde_json::to_string(&msg) else {
+    /// Send a message to every connection that has user_id in its friend list, on any node.
+    async fn broadcast_to_friend_subscribers(&self, user_id: &str, msg: &SignalingMessage, coalesce_key: String) {
+        let Ok(json) = serde_json::to_string(msg) else {
             return;
         };
-
-        // Send to all peer connections subscribed to this server (same mechanism as presence updates)
-        for peer_id in peers {
-            if let Some(sender) = signaling.peer_senders.get(peer_id) {
-                let _ = sender.send(Message::Text(json.clone()));
-            }
-        }
+        self.signaling
+            .read()
+            .await
+            .send_to_friend_subscribers(user_id, &json, Some(&coalesce_key));
+        self.publish(ClusterEvent::FriendSubscribers {
+            user_id: user_id.to_string(),
+            json,
+            coalesce_key: Some(coalesce_key),
+        });
     }
 
     /// Broadcast a presence update to all peers that have this user_id in their friend list.
     pub async fn broadcast_friend_presence_update(&self, user_id: &str, online: bool, active: Option<SigningPubkey>) {
-        let signaling = self.signaling.read().await;
-        let Some(peers) = signaling.friend_presence_subscribers.get(user_id) else {
-            return;
-        };
-
         let msg = SignalingMessage::PresenceUpdate {
             signing_pubkey: FRIENDS_SIGNING_PUBKEY.to_string(),
             user_id: user_id.to_string(),
             online,
             active_signing_pubkey: active,
         };
-
-        let Ok(json) = serde_json::to_string(&msg) else {
-            return;
-        };
-
-        for peer_id in peers {
-            if let Some(sender) = signaling.peer_senders.get(peer_id) {
-                let _ = sender.send(Message::Text(json.clone()));
-            }
-        }
+        let key = format!("presence:{}:{}", FRIENDS_SIGNING_PUBKEY, user_id);
+        self.broadcast_to_friend_subscribers(user_id, &msg, key).await;
     }
 
     /// Broadcast a profile update to all peers that have this user_id in their friend list.
     pub async fn broadcast_profile_update_to_friends(&self, user_id: &str, rec: &ProfileRecord) {
-        let signaling = self.signaling.read().await;
-        let Some(peers) = signaling.friend_presence_subscribers.get(user_id) else {
-            return;
-        };
-
         let msg = SignalingMessage::ProfileUpdate {
             signing_pubkey: FRIENDS_SIGNING_PUBKEY.to_string(),
             user_id: user_id.to_string(),
@@ -243,15 +564,32 @@ impl AppState {
             show_real_name: rec.show_real_name,
             rev: rec.rev,
         };
+        let key = format!("profile:{}:{}", FRIENDS_SIGNING_PUBKEY, user_id);
+        self.broadcast_to_friend_subscribers(user_id, &msg, key).await;
+    }
+}
 
-        let Ok(json) = serde_json::to_string(&msg) else {
-            return;
-        };
+/// Server and chat of relayed chat traffic: ephemeral messages, receipts, edits, deletes,
+/// reactions and typing updates (None for anything else).
+pub(crate) fn chat_route(msg: &SignalingMessage) -> Option<(&SigningPubkey, &str)> {
+    match msg {
+        SignalingMessage::EphemeralChatIncoming { signing_pubkey, chat_id, .. }
+        | SignalingMessage::EphemeralReceiptIncoming { signing_pubkey, chat_id, .. }
+        | SignalingMessage::EphemeralChatEdit { signing_pubkey, chat_id, .. }
+        | SignalingMessage::EphemeralChatDelete { signing_pubkey, chat_id, .. }
+        | SignalingMessage::EphemeralReactionAdd { signing_pubkey, chat_id, .. }
+        | SignalingMessage::EphemeralReactionRemove { signing_pubkey, chat_id, .. }
+        | SignalingMessage::TypingUpdate { signing_pubkey, chat_id, .. } => Some((signing_pubkey, chat_id.as_str())),
+        _ => None,
+    }
+}
 
-        for peer_id in peers {
-            if let Some(sender) = signaling.peer_senders.get(peer_id) {
-                let _ = sender.send(Message::Text(json.clone()));
-            }
+/// A queued typing update for a user and chat is replaced by the next one.
+fn typing_coalesce_key(msg: &SignalingMessage) -> Option<String> {
+    match msg {
+        SignalingMessage::TypingUpdate { signing_pubkey, chat_id, user_id, .. } => {
+            Some(format!("typing:{}:{}:{}", signing_pubkey, chat_id, user_id))
         }
+        _ => None,
     }
 }
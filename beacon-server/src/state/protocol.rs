@@ -0,0 +1,126 @@
+//! Per-connection protocol version and capabilities, negotiated with Hello/Welcome.
+//!
+//! Hello is optional: a connection that never sends it is a protocol 1 client and keeps the
+//! behaviour it had before negotiation existed. Handlers ask `has_capability` before using
+//! features a client may not understand, so beacon and desktop releases can roll out independently.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ConnId;
+
+/// Protocol version this beacon speaks.
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Oldest client protocol still accepted.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Swarm announce / peer list / health messages.
+pub const CAP_SWARM: &str = "swarm";
+/// Friend-scoped presence and profile updates (the FRIENDS_SIGNING_PUBKEY pseudo server).
+pub const CAP_FRIENDS_PRESENCE: &str = "friends_presence";
+/// MessagePack binary frames with raw ciphertext bytes (see wire.rs); JSON text stays the default.
+pub const CAP_BINARY_FRAMES: &str = "binary_frames";
+/// ChatSubscribe / ChatUnsubscribe and ChatActivity pings instead of whole-server chat fan-out.
+pub const CAP_CHAT_SUBSCRIPTIONS: &str = "chat_subscriptions";
+/// HistorySyncRequest / HistorySyncResponse (asking online members for encrypted history).
+pub const CAP_HISTORY_SYNC: &str = "history_sync";
+
+/// Capabilities this beacon implements and will grant when a client asks for them.
+pub const SERVER_CAPABILITIES: &[&str] = &[
+    CAP_SWARM,
+    CAP_FRIENDS_PRESENCE,
+    CAP_BINARY_FRAMES,
+    CAP_CHAT_SUBSCRIPTIONS,
+    CAP_HISTORY_SYNC,
+];
+
+/// What a connection without Hello gets: everything protocol 1 clients already used.
+const LEGACY_CAPABILITIES: &[&str] = &[CAP_SWARM, CAP_FRIENDS_PRESENCE];
+
+#[derive(Debug, Clone)]
+pub struct ConnProtocol {
+    pub version: u32,
+    pub capabilities: HashSet<String>,
+}
+
+#[derive(Default)]
+pub struct ProtocolState {
+    conns: HashMap<ConnId, ConnProtocol>,
+}
+
+impl ProtocolState {
+    pub fn new() -> Self {
+        Self {
+            conns: HashMap::new(),
+        }
+    }
+
+    /// Record a client's Hello. Granted capabilities are the ones both sides support.
+    pub fn negotiate(
+        &mut self,
+        conn_id: &ConnId,
+        client_version: u32,
+        client_capabilities: &[String],
+    ) -> Result<ConnProtocol, String> {
+        if self.conns.contains_key(conn_id) {
+            return Err("Hello already received on this connection".to_string());
+        }
+        if client_version < MIN_PROTOCOL_VERSION {
+            return Err(format!(
+                "Protocol version {} not supported (minimum {})",
+                client_version, MIN_PROTOCOL_VERSION
+            ));
+        }
+        let capabilities: HashSet<String> = client_capabilities
+            .iter()
+            .filter(|c| SERVER_CAPABILITIES.contains(&c.as_str()))
+            .cloned()
+            .collect();
+        let negotiated = ConnProtocol {
+            version: client_version.min(PROTOCOL_VERSION),
+            capabilities,
+        };
+        self.conns.insert(conn_id.clone(), negotiated.clone());
+        Ok(negotiated)
+    }
+
+    pub fn has_capability(&self, conn_id: &ConnId, capability: &str) -> bool {
+        match self.conns.get(conn_id) {
+            Some(p) => p.capabilities.contains(capability),
+            None => LEGACY_CAPABILITIES.contains(&capability),
+        }
+    }
+
+    /// Negotiated version (1 for connections that never sent Hello).
+    pub fn version_for(&self, conn_id: &ConnId) -> u32 {
+        self.conns.get(conn_id).map(|p| p.version).unwrap_or(1)
+    }
+
+    pub fn remove_conn(&mut self, conn_id: &ConnId) {
+        self.conns.remove(conn_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_intersection_and_defaults_legacy_clients() {
+        let mut protocol = ProtocolState::new();
+        let legacy: ConnId = "conn-legacy".to_string();
+        let modern: ConnId = "conn-modern".to_string();
+
+        assert!(protocol.has_capability(&legacy, CAP_SWARM));
+        assert_eq!(protocol.version_for(&legacy), 1);
+
+        let granted = protocol
+            .negotiate(&modern, 99, &[CAP_FRIENDS_PRESENCE.to_string(), "teleport".to_string()])
+            .unwrap();
+        assert_eq!(granted.version, PROTOCOL_VERSION);
+        assert!(protocol.has_capability(&modern, CAP_FRIENDS_PRESENCE));
+        assert!(!protocol.has_capability(&modern, CAP_SWARM));
+        assert!(!protocol.has_capability(&modern, "teleport"));
+        assert!(protocol.negotiate(&modern, 2, &[]).is_err());
+        assert!(protocol.negotiate(&"conn-old".to_string(), 0, &[]).is_err());
+    }
+}
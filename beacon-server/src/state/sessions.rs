@@ -0,0 +1,199 @@
+//! Resumable WebSocket sessions.
+//!
+//! A session is a ConnId plus its outbound queue. When the socket drops, the session is detached
+//! instead of cleaned up: its registrations (presence, voice, swarm, subscriptions) stay in place
+//! and messages keep queueing (bounded by the outbound queue limits). A new socket presenting the
+//! resume token within the grace period takes the session over; otherwise the sweeper runs the
+//! normal disconnect cleanup.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::RngCore;
+use tokio::sync::Notify;
+
+use crate::outbound::OutboundReceiver;
+use crate::{ConnId, WebSocketSender};
+
+struct Session {
+    sender: WebSocketSender,
+    token: String,
+    /// Set while no socket is attached.
+    detached_at: Option<Instant>,
+    /// Signals the attached socket's task that another socket took the session over.
+    takeover: Arc<Notify>,
+}
+
+/// A session handed to a resuming socket.
+pub struct ResumedSession {
+    pub conn_id: ConnId,
+    pub sender: WebSocketSender,
+    /// Takes over the queue, including messages buffered while detached.
+    pub receiver: OutboundReceiver,
+    /// Fresh token (tokens are single use).
+    pub token: String,
+    pub takeover: Arc<Notify>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Detach {
+    /// Held for resume; nothing to clean up yet.
+    Kept,
+    /// Another socket resumed the session.
+    TakenOver,
+    /// Run the normal disconnect cleanup now.
+    Cleanup,
+}
+
+#[derive(Default)]
+pub struct SessionState {
+    /// How long a detached session is kept; zero disables resume.
+    pub grace: Duration,
+    sessions: HashMap<ConnId, Session>,
+    tokens: HashMap<String, ConnId>,
+}
+
+fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+impl SessionState {
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            grace,
+            sessions: HashMap::new(),
+            tokens: HashMap::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.grace.is_zero()
+    }
+
+    /// Register a new attached session; returns its resume token and takeover signal.
+    pub fn open(&mut self, conn_id: &ConnId, sender: WebSocketSender) -> (String, Arc<Notify>) {
+        let token = new_token();
+        let takeover = Arc::new(Notify::new());
+        self.tokens.insert(token.clone(), conn_id.clone());
+        self.sessions.insert(
+            conn_id.clone(),
+            Session {
+                sender,
+                token: token.clone(),
+                detached_at: None,
+                takeover: takeover.clone(),
+            },
+        );
+        (token, takeover)
+    }
+
+    /// Hand the session for `token` to a new socket. Fails for unknown, expired or dead sessions.
+    /// If a socket is still attached (it has not noticed the drop yet), it is told to let go.
+    pub fn resume(&mut self, token: &str, now: Instant) -> Option<ResumedSession> {
+        let conn_id = self.tokens.remove(token)?;
+        let grace = self.grace;
+        let session = self.sessions.get_mut(&conn_id)?;
+        if session.detached_at.is_some_and(|t| now.duration_since(t) > grace) {
+            return None;
+        }
+        // Fails if the queue was closed (e.g. the replay buffer overflowed while detached).
+        let receiver = session.sender.resubscribe()?;
+        if session.detached_at.is_none() {
+            session.takeover.notify_one();
+        }
+        let token = new_token();
+        let takeover = Arc::new(Notify::new());
+        session.token = token.clone();
+        session.detached_at = None;
+        session.takeover = takeover.clone();
+        let sender = session.sender.clone();
+        self.tokens.insert(token.clone(), conn_id.clone());
+        Some(ResumedSession {
+            conn_id,
+            sender,
+            receiver,
+            token,
+            takeover,
+        })
+    }
+
+    /// The socket owning `takeover` went away. Keeps the session for the grace period when
+    /// `resumable`; otherwise (or when the queue is dead) the session is dropped and the caller runs
+    /// the disconnect cleanup. A socket whose session was already taken over must do neither.
+    pub fn detach(&mut self, conn_id: &ConnId, takeover: &Arc<Notify>, resumable: bool, now: Instant) -> Detach {
+        let enabled = self.enabled();
+        let Some(session) = self.sessions.get_mut(conn_id) else {
+            return Detach::Cleanup;
+        };
+        if !Arc::ptr_eq(&session.takeover, takeover) {
+            return Detach::TakenOver;
+        }
+        if resumable && enabled && !session.sender.is_closed() {
+            session.detached_at = Some(now);
+            return Detach::Kept;
+        }
+        self.remove(conn_id);
+        Detach::Cleanup
+    }
+
+    /// Remove and return detached sessions whose grace period has passed.
+    pub fn take_expired(&mut self, now: Instant) -> Vec<ConnId> {
+        let grace = self.grace;
+        let expired: Vec<ConnId> = self
+            .sessions
+            .iter()
+            .filter(|(_, s)| {
+                s.detached_at
+                    .is_some_and(|t| now.duration_since(t) > grace || s.sender.is_closed())
+            })
+            .map(|(conn_id, _)| conn_id.clone())
+            .collect();
+        for conn_id in &expired {
+            self.remove(conn_id);
+        }
+        expired
+    }
+
+    pub fn remove(&mut self, conn_id: &ConnId) {
+        if let Some(session) = self.sessions.remove(conn_id) {
+            self.tokens.remove(&session.token);
+        }
+    }
+
+    pub fn detached_count(&self) -> usize {
+        self.sessions.values().filter(|s| s.detached_at.is_some()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outbound::{channel, OutboundLimits};
+
+    #[test]
+    fn resume_within_grace_rotates_token_and_expires_after() {
+        let limits = Arc::new(OutboundLimits::new(16, Duration::from_secs(10)));
+        let (tx, _rx) = channel(limits);
+        let mut sessions = SessionState::new(Duration::from_secs(30));
+        let conn: ConnId = "conn-a".to_string();
+        let t0 = Instant::now();
+
+        let (token, first) = sessions.open(&conn, tx);
+        assert_eq!(sessions.detach(&conn, &first, true, t0), Detach::Kept);
+        let resumed = sessions.resume(&token, t0 + Duration::from_secs(5)).unwrap();
+        assert_eq!(resumed.conn_id, conn);
+        assert_ne!(resumed.token, token);
+        // Tokens are single use.
+        assert!(sessions.resume(&token, t0).is_none());
+        // The first socket finishing late must not detach the resumed one.
+        assert_eq!(sessions.detach(&conn, &first, true, t0), Detach::TakenOver);
+
+        assert_eq!(sessions.detach(&conn, &resumed.takeover, true, t0), Detach::Kept);
+        assert!(sessions.take_expired(t0 + Duration::from_secs(10)).is_empty());
+        assert!(sessions.resume(&resumed.token, t0 + Duration::from_secs(31)).is_none());
+        assert_eq!(sessions.take_expired(t0 + Duration::from_secs(31)), vec![conn]);
+    }
+}
@@ -1,11 +1,13 @@
 use std::collections::{HashMap, HashSet};
-use crate::{PeerId, ServerId, SigningPubkey, WebSocketSender, ConnId, PeerConnection, EncryptedServerHint, SignalingMessage};
+use crate::{PeerId, ServerId, SigningPubkey, WebSocketSender, ConnId, PeerConnection};
 use tokio_tungstenite::tungstenite::Message;
 
 /// Synthetic peer_id prefix for friend-scoped presence subscriptions (one per connection).
 pub const FRIENDS_PEER_PREFIX: &str = "friends:";
 /// Signing pubkey value used for friend-scoped presence/profile messages (client merges by user_id).
 pub const FRIENDS_SIGNING_PUBKEY: &str = "_friends";
+/// Most chats one connection may follow at once.
+pub const MAX_CHAT_SUBSCRIPTIONS_PER_CONN: usize = 512;
 
 /// WebSocket signaling state (peer ↔ peer)
 pub struct SignalingState {
@@ -23,6 +25,9 @@ pub struct SignalingState {
     pub conn_friend_ids: HashMap<ConnId, HashSet<String>>,
     /// Friend presence: target user_id -> set of peer_ids (friends:conn_id) that want this user's presence
     pub friend_presence_subscribers: HashMap<String, HashSet<PeerId>>,
+    /// Chat subscriptions: conn_id -> (signing_pubkey, chat_id) it follows. Connections listed here
+    /// opted in with ChatSubscribe; the others still get every chat of their servers.
+    pub chat_subscriptions: HashMap<ConnId, HashSet<(SigningPubkey, String)>>,
 }
 
 impl SignalingState {
@@ -35,6 +40,7 @@ impl SignalingState {
             conn_peers: HashMap::new(),
             conn_friend_ids: HashMap::new(),
             friend_presence_subscribers: HashMap::new(),
+            chat_subscriptions: HashMap::new(),
         }
     }
 
@@ -48,21 +54,6 @@ impl SignalingState {
         }
     }
 
-    /// Validates from_peer belongs to conn_id and returns the sender for to_peer.
-    /// Single lock instead of validate-then-unlock-then-lock-again for forwarding.
-    /// Returns Err(()) if from_peer is invalid; Ok(None) if valid but to_peer not found; Ok(Some(sender)) if found.
-    pub fn validate_and_get_target_sender(
-        &self,
-        from_peer: &PeerId,
-        conn_id: &ConnId,
-        to_peer: &PeerId,
-    ) -> Result<Option<WebSocketSender>, ()> {
-        if !self.validate_peer_connection(from_peer, conn_id) {
-            return Err(());
-        }
-        Ok(self.peer_senders.get(to_peer).cloned())
-    }
-
     pub fn register_peer(
         &mut self,
         peer_id: PeerId,
@@ -145,46 +136,130 @@ impl SignalingState {
         self.peers.get(peer_id).map(|c| c.server_id.clone())
     }
 
-    pub fn broadcast_server_hint_updated(&self, signing_pubkey: &SigningPubkey, hint: &EncryptedServerHint) {
+    /// Send to this node's connections subscribed to signing_pubkey, once per connection.
+    /// With a coalesce_key, a still-queued update with the same key is replaced instead.
+    pub fn send_to_server_subscribers(
+        &self,
+        signing_pubkey: &SigningPubkey,
+        json: &str,
+        coalesce_key: Option<&str>,
+        exclude_conn_id: Option<&ConnId>,
+    ) {
         let Some(peers) = self.signing_servers.get(signing_pubkey) else {
             return;
         };
 
-        let msg = SignalingMessage::ServerHintUpdated {
-            signing_pubkey: signing_pubkey.clone(),
-            encrypted_state: hint.encrypted_state.clone(),
-            signature: hint.signature.clone(),
-            last_updated: hint.last_updated,
-        };
-
-        let Ok(json) = serde_json::to_string(&msg) else {
-            return;
-        };
+        // A single websocket connection can have multiple peer_ids; fan-out once per conn_id.
+        let mut sent_conn_ids: HashSet<&str> = HashSet::new();
 
         for peer_id in peers {
+            let Some(peer) = self.peers.get(peer_id) else {
+                continue;
+            };
+            if exclude_conn_id.is_some_and(|cid| peer.conn_id == *cid) {
+                continue;
+            }
+            if !sent_conn_ids.insert(peer.conn_id.as_str()) {
+                continue;
+            }
             if let Some(sender) = self.peer_senders.get(peer_id) {
-                let _ = sender.send(Message::Text(json.clone()));
+                send_json(sender, json, coalesce_key);
             }
         }
     }
 
-    pub fn broadcast_ephemeral_chat_message(
+    /// True if the connection registered a peer subscribed to signing_pubkey.
+    pub fn conn_subscribed_to_server(&self, conn_id: &ConnId, signing_pubkey: &SigningPubkey) -> bool {
+        self.conn_peers.get(conn_id).is_some_and(|peer_ids| {
+            peer_ids.iter().any(|peer_id| {
+                self.peers
+                    .get(peer_id)
+                    .is_some_and(|peer| peer.signing_pubkey.as_ref() == Some(signing_pubkey))
+            })
+        })
+    }
+
+    /// Follow chats of a server (opting the connection into chat-scoped delivery).
+    /// Returns false, without changes, if that would exceed MAX_CHAT_SUBSCRIPTIONS_PER_CONN.
+    pub fn subscribe_chats(&mut self, conn_id: &ConnId, signing_pubkey: &SigningPubkey, chat_ids: &[String]) -> bool {
+        let chats = self.chat_subscriptions.entry(conn_id.clone()).or_default();
+        let new = chat_ids
+            .iter()
+            .filter(|chat_id| !chats.contains(&(signing_pubkey.clone(), chat_id.to_string())))
+            .collect::<HashSet<_>>();
+        if chats.len() + new.len() > MAX_CHAT_SUBSCRIPTIONS_PER_CONN {
+            return false;
+        }
+        chats.extend(new.into_iter().map(|chat_id| (signing_pubkey.clone(), chat_id.clone())));
+        true
+    }
+
+    /// Stop following chats. The connection stays opted in (ChatActivity for those chats).
+    pub fn unsubscribe_chats(&mut self, conn_id: &ConnId, signing_pubkey: &SigningPubkey, chat_ids: &[String]) {
+        if let Some(chats) = self.chat_subscriptions.get_mut(conn_id) {
+            for chat_id in chat_ids {
+                chats.remove(&(signing_pubkey.clone(), chat_id.clone()));
+            }
+        }
+    }
+
+    /// Send ephemeral chat traffic for one chat to this node's server subscribers, once per
+    /// connection. Connections with chat subscriptions get `json` only for chats they follow and
+    /// `activity` (if any, coalesced per chat) for the others.
+    pub fn send_to_chat_subscribers(
         &self,
         signing_pubkey: &SigningPubkey,
-        msg: &SignalingMessage,
+        chat_id: &str,
+        json: &str,
+        coalesce_key: Option<&str>,
+        activity: Option<&str>,
         exclude_conn_id: Option<&ConnId>,
     ) {
         let Some(peers) = self.signing_servers.get(signing_pubkey) else {
             return;
         };
+        let chat = (signing_pubkey.clone(), chat_id.to_string());
+        let activity_key = format!("chat_activity:{}:{}", signing_pubkey, chat_id);
+        let mut sent_conn_ids: HashSet<&str> = HashSet::new();
 
-        let Ok(json) = serde_json::to_string(msg) else {
-            return;
-        };
+        for peer_id in peers {
+            let Some(peer) = self.peers.get(peer_id) else {
+                continue;
+            };
+            if exclude_conn_id.is_some_and(|cid| peer.conn_id == *cid) {
+                continue;
+            }
+            if !sent_conn_ids.insert(peer.conn_id.as_str()) {
+                continue;
+            }
+            let Some(sender) = self.peer_senders.get(peer_id) else {
+                continue;
+            };
+            match self.chat_subscriptions.get(&peer.conn_id) {
+                Some(chats) if !chats.contains(&chat) => {
+                    if let Some(activity) = activity {
+                        send_json(sender, activity, Some(&activity_key));
+                    }
+                }
+                _ => send_json(sender, json, coalesce_key),
+            }
+        }
+    }
 
-        // A single websocket connection can have multiple peer_ids; fan-out once per conn_id.
+    /// This node's connections subscribed to signing_pubkey, once per connection, with whether
+    /// each one gets chat_id's messages (it follows the chat or does not use chat subscriptions).
+    pub fn chat_members(
+        &self,
+        signing_pubkey: &SigningPubkey,
+        chat_id: &str,
+        exclude_conn_id: Option<&ConnId>,
+    ) -> Vec<(ConnId, WebSocketSender, bool)> {
+        let Some(peers) = self.signing_servers.get(signing_pubkey) else {
+            return Vec::new();
+        };
+        let chat = (signing_pubkey.clone(), chat_id.to_string());
         let mut sent_conn_ids: HashSet<&str> = HashSet::new();
-
+        let mut out = Vec::new();
         for peer_id in peers {
             let Some(peer) = self.peers.get(peer_id) else {
                 continue;
@@ -195,8 +270,70 @@ impl SignalingState {
             if !sent_conn_ids.insert(peer.conn_id.as_str()) {
                 continue;
             }
+            let Some(sender) = self.peer_senders.get(peer_id) else {
+                continue;
+            };
+            let follows = self
+                .chat_subscriptions
+                .get(&peer.conn_id)
+                .is_none_or(|chats| chats.contains(&chat));
+            out.push((peer.conn_id.clone(), sender.clone(), follows));
+        }
+        out
+    }
+
+    /// Send to this node's connections that have user_id in their friend list.
+    pub fn send_to_friend_subscribers(&self, user_id: &str, json: &str, coalesce_key: Option<&str>) {
+        let Some(peers) = self.friend_presence_subscribers.get(user_id) else {
+            return;
+        };
+        for peer_id in peers {
             if let Some(sender) = self.peer_senders.get(peer_id) {
-                let _ = sender.send(Message::Text(json.clone()));
+                send_json(sender, json, coalesce_key);
+            }
+        }
+    }
+}
+
+pub(crate) fn send_json(sender: &WebSocketSender, json: &str, coalesce_key: Option<&str>) {
+    let msg = Message::Text(json.to_string());
+    let _ = match coalesce_key {
+        Some(key) => sender.send_coalesced(key.to_string(), msg),
+        None => sender.send(msg),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outbound::{channel, OutboundLimits};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn chat_subscribers_get_messages_and_others_activity() {
+        let limits = Arc::new(OutboundLimits::new(16, Duration::from_secs(10)));
+        let mut signaling = SignalingState::new();
+        let spk = "spk".to_string();
+        let mut receivers = Vec::new();
+        for conn in ["legacy", "viewer", "elsewhere"] {
+            let (tx, rx) = channel(limits.clone());
+            signaling.register_peer(format!("p-{}", conn), "s".into(), Some(spk.clone()), conn.into());
+            signaling.peer_senders.insert(format!("p-{}", conn), tx);
+            receivers.push(rx);
+        }
+        assert!(signaling.conn_subscribed_to_server(&"viewer".to_string(), &spk));
+        assert!(signaling.subscribe_chats(&"viewer".to_string(), &spk, &["general".to_string()]));
+        assert!(signaling.subscribe_chats(&"elsewhere".to_string(), &spk, &["random".to_string()]));
+
+        signaling.send_to_chat_subscribers(&spk, "general", "full", None, Some("ping"), None);
+        signaling.send_to_chat_subscribers(&spk, "general", "receipt", None, None, None);
+        signaling.send_to_server_subscribers(&spk, "end", None, None);
+        // The activity ping is coalesced, so it queues behind regular messages.
+        let expected = [vec!["full", "receipt", "end"], vec!["full", "receipt", "end"], vec!["end", "ping"]];
+        for (rx, expected) in receivers.iter_mut().zip(expected) {
+            for text in expected {
+                assert_eq!(rx.recv().await, Some(Message::Text(text.to_string())));
             }
         }
     }
@@ -1,8 +1,9 @@
 use std::collections::HashMap;
 use chrono::Utc;
+use serde::{Deserialize, Serialize};
 use crate::{ConnId, SigningPubkey, SwarmPeerInfo};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SwarmPeer {
     pub user_id: String,
     pub conn_id: ConnId,
@@ -53,6 +54,21 @@ impl SwarmState {
         );
     }
 
+    /// Insert or replace a peer entry as-is (mirrored from another cluster node).
+    pub fn upsert(&mut self, signing_pubkey: SigningPubkey, sha256: String, peer: SwarmPeer) {
+        self.swarms
+            .entry((signing_pubkey, sha256))
+            .or_default()
+            .insert(peer.conn_id.clone(), peer);
+    }
+
+    pub fn peer(&self, signing_pubkey: &SigningPubkey, sha256: &str, conn_id: &ConnId) -> Option<SwarmPeer> {
+        self.swarms
+            .get(&(signing_pubkey.clone(), sha256.to_string()))?
+            .get(conn_id)
+            .cloned()
+    }
+
     pub fn unannounce(&mut self, signing_pubkey: &SigningPubkey, sha256: &str, conn_id: &ConnId) {
         let key = (signing_pubkey.clone(), sha256.to_string());
         if let Some(by_conn) = self.swarms.get_mut(&key) {
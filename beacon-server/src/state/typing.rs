@@ -0,0 +1,96 @@
+//! Typing indicators (TypingStart / TypingStop).
+//!
+//! Only transitions are broadcast: a client repeats TypingStart while the user types, which just
+//! pushes the expiry back. An entry that is not refreshed within TYPING_TTL, or whose connection
+//! goes away, is stopped by the beacon so a crashed client cannot leave a stuck indicator.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{ConnId, SigningPubkey};
+
+/// How long a TypingStart lasts without a refresh.
+pub const TYPING_TTL: Duration = Duration::from_secs(8);
+
+/// (signing_pubkey, chat_id, user_id)
+pub type TypingKey = (SigningPubkey, String, String);
+
+struct Typing {
+    conn_id: ConnId,
+    expires_at: Instant,
+}
+
+#[derive(Default)]
+pub struct TypingState {
+    active: HashMap<TypingKey, Typing>,
+}
+
+impl TypingState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start or refresh typing. True if the user was not typing in this chat yet (broadcast it).
+    pub fn start(&mut self, key: TypingKey, conn_id: &ConnId, now: Instant) -> bool {
+        self.active
+            .insert(
+                key,
+                Typing {
+                    conn_id: conn_id.clone(),
+                    expires_at: now + TYPING_TTL,
+                },
+            )
+            .is_none()
+    }
+
+    /// True if the user was typing (broadcast the stop).
+    pub fn stop(&mut self, key: &TypingKey) -> bool {
+        self.active.remove(key).is_some()
+    }
+
+    /// Remove and return entries not refreshed within TYPING_TTL.
+    pub fn take_expired(&mut self, now: Instant) -> Vec<TypingKey> {
+        self.take_where(|typing| typing.expires_at <= now)
+    }
+
+    /// Remove and return entries started by a connection that is gone.
+    pub fn take_conn(&mut self, conn_id: &ConnId) -> Vec<TypingKey> {
+        self.take_where(|typing| typing.conn_id == *conn_id)
+    }
+
+    fn take_where(&mut self, pred: impl Fn(&Typing) -> bool) -> Vec<TypingKey> {
+        let keys: Vec<TypingKey> = self
+            .active
+            .iter()
+            .filter(|(_, typing)| pred(typing))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &keys {
+            self.active.remove(key);
+        }
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_transitions_are_reported_and_stale_entries_expire() {
+        let mut typing = TypingState::new();
+        let key: TypingKey = ("spk".into(), "general".into(), "alice".into());
+        let conn = "c1".to_string();
+        let t0 = Instant::now();
+
+        assert!(typing.start(key.clone(), &conn, t0));
+        assert!(!typing.start(key.clone(), &conn, t0 + Duration::from_secs(5)));
+        // The refresh moved the deadline.
+        assert!(typing.take_expired(t0 + TYPING_TTL).is_empty());
+        assert_eq!(typing.take_expired(t0 + Duration::from_secs(5) + TYPING_TTL), vec![key.clone()]);
+        assert!(!typing.stop(&key));
+
+        assert!(typing.start(key.clone(), &conn, t0));
+        assert_eq!(typing.take_conn(&conn), vec![key]);
+    }
+}
@@ -58,6 +58,14 @@ impl VoiceState {
             .collect()
     }
 
+    /// Server of the voice chat with this chat_id, if anyone is in it.
+    pub fn server_for_chat(&self, chat_id: &str) -> Option<ServerId> {
+        self.voice_chats
+            .keys()
+            .find(|(_, c)| c == chat_id)
+            .map(|(server_id, _)| server_id.clone())
+    }
+
     /// Unregister a peer from voice.
     /// Returns the user_id if found (for broadcasting PeerLeft).
     pub fn unregister_voice_peer(&mut self, peer_id: &PeerId, server_id: &ServerId, chat_id: &str) -> Option<String> {
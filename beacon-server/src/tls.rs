@@ -0,0 +1,183 @@
+//! Native TLS termination (feature "tls"): serve https:// and wss:// straight from the beacon.
+//!
+//! Configured with BEACON_TLS_CERT_PATH / BEACON_TLS_KEY_PATH (PEM). Certificates are reloaded on
+//! SIGHUP and when either file changes; existing connections keep their session, only new
+//! handshakes use the new certificate. An optional plain-HTTP listener redirects to https.
+
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    extract::Request,
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Redirect},
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use log::{error, info, warn};
+
+#[derive(Clone, Debug)]
+pub struct TlsSettings {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// How often to check the cert/key files for changes; 0 = only reload on SIGHUP.
+    pub reload_check_secs: u64,
+    /// Port for the HTTP→HTTPS redirect listener; None = no redirect listener.
+    pub redirect_port: Option<u16>,
+    /// Port clients reach HTTPS on (omitted from redirect URLs when 443).
+    pub public_https_port: u16,
+}
+
+impl TlsSettings {
+    /// None when TLS is not configured (plain HTTP, e.g. behind Cloudflare or a reverse proxy).
+    pub fn from_env() -> Option<Self> {
+        let cert_path = env::var("BEACON_TLS_CERT_PATH").ok().filter(|s| !s.trim().is_empty())?;
+        let key_path = env::var("BEACON_TLS_KEY_PATH").ok().filter(|s| !s.trim().is_empty())?;
+        let reload_check_secs = env::var("BEACON_TLS_RELOAD_CHECK_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+        let redirect_port = env::var("BEACON_TLS_REDIRECT_PORT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|p| *p != 0);
+        let public_https_port = env::var("BEACON_TLS_PUBLIC_PORT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(9001);
+        Some(Self {
+            cert_path: PathBuf::from(cert_path.trim()),
+            key_path: PathBuf::from(key_path.trim()),
+            reload_check_secs,
+            redirect_port,
+            public_https_port,
+        })
+    }
+}
+
+/// Load the certificate chain and key. Installs the ring crypto provider on first use.
+pub async fn load_rustls_config(settings: &TlsSettings) -> Result<RustlsConfig, String> {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    RustlsConfig::from_pem_file(&settings.cert_path, &settings.key_path)
+        .await
+        .map_err(|e| format!("Failed to load TLS certificate/key: {}", e))
+}
+
+fn files_modified(settings: &TlsSettings) -> Option<(SystemTime, SystemTime)> {
+    let cert = std::fs::metadata(&settings.cert_path).and_then(|m| m.modified()).ok()?;
+    let key = std::fs::metadata(&settings.key_path).and_then(|m| m.modified()).ok()?;
+    Some((cert, key))
+}
+
+async fn reload(config: &RustlsConfig, settings: &TlsSettings, reason: &str) {
+    match config
+        .reload_from_pem_file(&settings.cert_path, &settings.key_path)
+        .await
+    {
+        Ok(()) => info!("TLS certificate reloaded ({})", reason),
+        // Keep serving the previous certificate; a half-written renewal is retried next time.
+        Err(e) => warn!("TLS certificate reload failed ({}): {}", reason, e),
+    }
+}
+
+/// Reload certificates on SIGHUP and when the files' modification times change.
+pub fn spawn_reload_tasks(config: RustlsConfig, settings: TlsSettings) {
+    #[cfg(unix)]
+    {
+        let config = config.clone();
+        let settings = settings.clone();
+        tokio::spawn(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut hup = match signal(SignalKind::hangup()) {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("Cannot listen for SIGHUP; TLS reload on signal disabled: {}", e);
+                    return;
+                }
+            };
+            while hup.recv().await.is_some() {
+                reload(&config, &settings, "SIGHUP").await;
+            }
+        });
+    }
+
+    if settings.reload_check_secs == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut last = files_modified(&settings);
+        loop {
+            tokio::time::sleep(Duration::from_secs(settings.reload_check_secs)).await;
+            let current = files_modified(&settings);
+            if current.is_some() && current != last {
+                reload(&config, &settings, "file changed").await;
+                last = current;
+            }
+        }
+    });
+}
+
+/// https URL for a plain-HTTP request (Host header without its port, plus the public HTTPS port).
+fn https_redirect_target(host: Option<&str>, uri: &Uri, public_https_port: u16) -> Option<String> {
+    let host = host?.trim();
+    let authority: axum::http::uri::Authority = host.parse().ok()?;
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    Some(if public_https_port == 443 {
+        format!("https://{}{}", authority.host(), path)
+    } else {
+        format!("https://{}:{}{}", authority.host(), public_https_port, path)
+    })
+}
+
+/// Serve HTTP→HTTPS redirects on the configured port.
+pub fn spawn_redirect_listener(settings: &TlsSettings) {
+    let Some(port) = settings.redirect_port else {
+        return;
+    };
+    let public_https_port = settings.public_https_port;
+    let app = Router::new().fallback(move |request: Request| async move {
+        let host = request
+            .headers()
+            .get(header::HOST)
+            .and_then(|v| v.to_str().ok());
+        match https_redirect_target(host, request.uri(), public_https_port) {
+            Some(target) => Redirect::permanent(&target).into_response(),
+            None => (StatusCode::BAD_REQUEST, "Missing Host header").into_response(),
+        }
+    });
+    tokio::spawn(async move {
+        let addr = SocketAddr::from(([0, 0, 0, 0], port));
+        let listener = match tokio::net::TcpListener::bind(addr).await {
+            Ok(l) => l,
+            Err(e) => {
+                error!("Failed to bind HTTP redirect listener on {}: {}", addr, e);
+                return;
+            }
+        };
+        info!("HTTP→HTTPS redirect listening on http://{}", addr);
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("HTTP redirect listener error: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirect_keeps_path_and_swaps_port() {
+        let uri: Uri = "/api/status?x=1".parse().unwrap();
+        assert_eq!(
+            https_redirect_target(Some("beacon.example.com:8080"), &uri, 443).as_deref(),
+            Some("https://beacon.example.com/api/status?x=1")
+        );
+        assert_eq!(
+            https_redirect_target(Some("beacon.example.com"), &uri, 9001).as_deref(),
+            Some("https://beacon.example.com:9001/api/status?x=1")
+        );
+        assert_eq!(https_redirect_target(None, &uri, 443), None);
+    }
+}
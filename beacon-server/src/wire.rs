@@ -0,0 +1,275 @@
+//! MessagePack encoding of signaling messages for connections that negotiated `binary_frames`.
+//!
+//! A binary frame is the MessagePack form of the same JSON object a text frame carries, with one
+//! difference: ciphertext fields (`BYTE_FIELDS`) travel as raw `bin` bytes instead of base64
+//! strings. On the way in they are turned back into base64 so handlers see the usual message.
+//! Only the subset of MessagePack that JSON can express is accepted (no ext types, string keys).
+
+use base64::Engine;
+use serde_json::{Map, Number, Value};
+
+/// Fields sent as raw bytes in binary frames (base64 strings in JSON).
+const BYTE_FIELDS: &[&str] = &["encrypted_payload", "encrypted_state"];
+/// Deepest nesting accepted in an incoming frame.
+const MAX_DEPTH: usize = 32;
+
+fn b64() -> &'static base64::engine::GeneralPurpose {
+    &base64::engine::general_purpose::STANDARD
+}
+
+/// Encode a serialized JSON message as MessagePack. None if `json` is not valid JSON.
+pub fn encode(json: &str) -> Option<Vec<u8>> {
+    let value: Value = serde_json::from_str(json).ok()?;
+    let mut out = Vec::with_capacity(json.len());
+    write_value(&mut out, &value, false);
+    Some(out)
+}
+
+/// Decode a MessagePack frame into the equivalent JSON value.
+pub fn decode(bytes: &[u8]) -> Result<Value, String> {
+    let mut reader = Reader { bytes, pos: 0 };
+    let value = reader.read_value(0)?;
+    if reader.pos != bytes.len() {
+        return Err("Trailing bytes after MessagePack value".to_string());
+    }
+    Ok(value)
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize, fix: Option<(u8, usize)>, m8: Option<u8>, m16: u8, m32: u8) {
+    match (fix, m8) {
+        (Some((marker, max)), _) if len < max => out.push(marker | len as u8),
+        (_, Some(marker)) if len <= u8::MAX as usize => {
+            out.push(marker);
+            out.push(len as u8);
+        }
+        _ if len <= u16::MAX as usize => {
+            out.push(m16);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            out.push(m32);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_len(out, s.len(), Some((0xa0, 32)), Some(0xd9), 0xda, 0xdb);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_bin(out: &mut Vec<u8>, b: &[u8]) {
+    write_len(out, b.len(), None, Some(0xc4), 0xc5, 0xc6);
+    out.extend_from_slice(b);
+}
+
+fn write_number(out: &mut Vec<u8>, n: &Number) {
+    if let Some(u) = n.as_u64() {
+        if u < 0x80 {
+            out.push(u as u8);
+        } else if u <= u8::MAX as u64 {
+            out.extend_from_slice(&[0xcc, u as u8]);
+        } else if u <= u16::MAX as u64 {
+            out.push(0xcd);
+            out.extend_from_slice(&(u as u16).to_be_bytes());
+        } else if u <= u32::MAX as u64 {
+            out.push(0xce);
+            out.extend_from_slice(&(u as u32).to_be_bytes());
+        } else {
+            out.push(0xcf);
+            out.extend_from_slice(&u.to_be_bytes());
+        }
+    } else if let Some(i) = n.as_i64() {
+        if i >= -32 {
+            out.push(i as i8 as u8);
+        } else if i >= i8::MIN as i64 {
+            out.extend_from_slice(&[0xd0, i as i8 as u8]);
+        } else if i >= i16::MIN as i64 {
+            out.push(0xd1);
+            out.extend_from_slice(&(i as i16).to_be_bytes());
+        } else if i >= i32::MIN as i64 {
+            out.push(0xd2);
+            out.extend_from_slice(&(i as i32).to_be_bytes());
+        } else {
+            out.push(0xd3);
+            out.extend_from_slice(&i.to_be_bytes());
+        }
+    } else {
+        out.push(0xcb);
+        out.extend_from_slice(&n.as_f64().unwrap_or(0.0).to_be_bytes());
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value, byte_field: bool) {
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(b) => out.push(if *b { 0xc3 } else { 0xc2 }),
+        Value::Number(n) => write_number(out, n),
+        Value::String(s) => {
+            // Only canonical base64 becomes bytes, so decoding gives back the exact same string.
+            match b64().decode(s) {
+                Ok(bytes) if byte_field && b64().encode(&bytes) == *s => write_bin(out, &bytes),
+                _ => write_str(out, s),
+            }
+        }
+        Value::Array(items) => {
+            write_len(out, items.len(), Some((0x90, 16)), None, 0xdc, 0xdd);
+            for item in items {
+                write_value(out, item, false);
+            }
+        }
+        Value::Object(map) => {
+            write_len(out, map.len(), Some((0x80, 16)), None, 0xde, 0xdf);
+            for (key, item) in map {
+                write_str(out, key);
+                write_value(out, item, BYTE_FIELDS.contains(&key.as_str()));
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(n).filter(|end| *end <= self.bytes.len());
+        let Some(end) = end else {
+            return Err("Truncated MessagePack frame".to_string());
+        };
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn be<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut buf = [0u8; N];
+        buf.copy_from_slice(self.take(N)?);
+        Ok(buf)
+    }
+
+    fn len(&mut self, width: usize) -> Result<usize, String> {
+        Ok(match width {
+            1 => self.byte()? as usize,
+            2 => u16::from_be_bytes(self.be()?) as usize,
+            _ => u32::from_be_bytes(self.be()?) as usize,
+        })
+    }
+
+    fn string(&mut self, len: usize) -> Result<String, String> {
+        let bytes = self.take(len)?;
+        std::str::from_utf8(bytes)
+            .map(str::to_string)
+            .map_err(|_| "Invalid UTF-8 in MessagePack string".to_string())
+    }
+
+    fn float(f: f64) -> Result<Value, String> {
+        Number::from_f64(f)
+            .map(Value::Number)
+            .ok_or_else(|| "Non-finite float in MessagePack frame".to_string())
+    }
+
+    fn array(&mut self, len: usize, depth: usize) -> Result<Value, String> {
+        // Each element takes at least one byte; reject lengths the frame cannot hold.
+        if len > self.bytes.len() - self.pos {
+            return Err("Truncated MessagePack frame".to_string());
+        }
+        (0..len).map(|_| self.read_value(depth + 1)).collect::<Result<Vec<_>, _>>().map(Value::Array)
+    }
+
+    fn map(&mut self, len: usize, depth: usize) -> Result<Value, String> {
+        if len > self.bytes.len() - self.pos {
+            return Err("Truncated MessagePack frame".to_string());
+        }
+        let mut map = Map::new();
+        for _ in 0..len {
+            let Value::String(key) = self.read_value(depth + 1)? else {
+                return Err("MessagePack map keys must be strings".to_string());
+            };
+            let value = self.read_value(depth + 1)?;
+            map.insert(key, value);
+        }
+        Ok(Value::Object(map))
+    }
+
+    fn read_value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err("MessagePack frame nested too deeply".to_string());
+        }
+        let marker = self.byte()?;
+        match marker {
+            0x00..=0x7f => Ok(Value::from(marker)),
+            0x80..=0x8f => self.map((marker & 0x0f) as usize, depth),
+            0x90..=0x9f => self.array((marker & 0x0f) as usize, depth),
+            0xa0..=0xbf => self.string((marker & 0x1f) as usize).map(Value::String),
+            0xc0 => Ok(Value::Null),
+            0xc2 => Ok(Value::Bool(false)),
+            0xc3 => Ok(Value::Bool(true)),
+            0xc4..=0xc6 => {
+                let len = self.len(1 << (marker - 0xc4))?;
+                Ok(Value::String(b64().encode(self.take(len)?)))
+            }
+            0xca => Self::float(f32::from_be_bytes(self.be()?) as f64),
+            0xcb => Self::float(f64::from_be_bytes(self.be()?)),
+            0xcc => Ok(Value::from(self.byte()?)),
+            0xcd => Ok(Value::from(u16::from_be_bytes(self.be()?))),
+            0xce => Ok(Value::from(u32::from_be_bytes(self.be()?))),
+            0xcf => Ok(Value::from(u64::from_be_bytes(self.be()?))),
+            0xd0 => Ok(Value::from(self.byte()? as i8)),
+            0xd1 => Ok(Value::from(i16::from_be_bytes(self.be()?))),
+            0xd2 => Ok(Value::from(i32::from_be_bytes(self.be()?))),
+            0xd3 => Ok(Value::from(i64::from_be_bytes(self.be()?))),
+            0xd9..=0xdb => {
+                let len = self.len(1 << (marker - 0xd9))?;
+                self.string(len).map(Value::String)
+            }
+            0xdc | 0xdd => {
+                let len = self.len(if marker == 0xdc { 2 } else { 4 })?;
+                self.array(len, depth)
+            }
+            0xde | 0xdf => {
+                let len = self.len(if marker == 0xde { 2 } else { 4 })?;
+                self.map(len, depth)
+            }
+            0xe0..=0xff => Ok(Value::from(marker as i8)),
+            _ => Err(format!("Unsupported MessagePack type 0x{:02x}", marker)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_messages_with_ciphertext_as_raw_bytes() {
+        let ciphertext = b64().encode([7u8; 300]);
+        let json = serde_json::json!({
+            "type": "EphemeralChatIncoming",
+            "signing_pubkey": "spk",
+            "chat_id": "c",
+            "message_id": "m",
+            "from_user_id": "u",
+            "encrypted_payload": ciphertext,
+            "sent_at": "2026-01-01T00:00:00Z",
+            "n": -70000,
+            "list": [1, 300, 70000, 5_000_000_000u64, 1.5, null, true],
+        })
+        .to_string();
+
+        let bytes = encode(&json).unwrap();
+        // 300 raw bytes behind a bin16 header instead of 400 base64 characters.
+        assert!(bytes.windows(3).any(|w| w == [0xc5, 0x01, 0x2c]));
+        assert!(bytes.len() < json.len() - 90);
+        assert_eq!(decode(&bytes).unwrap(), serde_json::from_str::<Value>(&json).unwrap());
+
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode(&[0xdd, 0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+}
@@ -0,0 +1,289 @@
+//! Signed chat plaintexts: chat messages, and ephemeral edits, deletes and reactions.
+//!
+//! The beacon stamps `from_user_id` on what it relays, and anyone holding the server key can
+//! encrypt a payload, so neither says who wrote a message. Chat payloads are wrapped in a
+//! [`SignedChatPayload`] and envelopes are [`ChatEnvelope`]s, both signed with the author's
+//! identity key before being encrypted with the server key. Receivers only accept them when the
+//! signer is the expected author: the sender the beacon reported for chat messages and reactions,
+//! the original sender for edits and deletes.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// `kind` values, matching the beacon's EphemeralChatEdit / EphemeralChatDelete /
+/// EphemeralReactionAdd / EphemeralReactionRemove.
+pub const ENVELOPE_KINDS: &[&str] = &["edit", "delete", "reaction_add", "reaction_remove"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatEnvelope {
+    pub kind: String,
+    pub signing_pubkey: String,
+    pub chat_id: String,
+    pub message_id: String,
+    /// New text for an edit, the emoji for a reaction, empty for a delete.
+    #[serde(default)]
+    pub body: String,
+    pub author_user_id: String,
+    /// Hex Ed25519 identity key of the author.
+    pub author_public_key: String,
+    pub signed_at: String,
+    /// Base64 Ed25519 signature over `signed_bytes`.
+    pub signature: String,
+}
+
+/// user_id for an identity key (first 16 bytes of its SHA-256, hex), as in identity.rs.
+fn user_id_for_public_key(public_key: &[u8]) -> String {
+    let hash = Sha256::digest(public_key);
+    hex::encode(&hash[..16])
+}
+
+/// Check that `public_key_hex` is the identity key behind `user_id` and signed `signed_bytes`.
+fn verify_author(public_key_hex: &str, user_id: &str, signed_bytes: &[u8], signature_b64: &str) -> Result<(), String> {
+    let public_key: [u8; 32] = hex::decode(public_key_hex)
+        .map_err(|e| format!("Invalid author public key: {}", e))?
+        .as_slice()
+        .try_into()
+        .map_err(|_| "Invalid author public key length".to_string())?;
+    if user_id_for_public_key(&public_key) != user_id {
+        return Err("Author public key does not match author user_id".to_string());
+    }
+    let verifying_key = VerifyingKey::from_bytes(&public_key)
+        .map_err(|e| format!("Invalid author public key: {}", e))?;
+    let signature: [u8; 64] = base64::decode(signature_b64)
+        .map_err(|e| format!("Invalid signature encoding: {}", e))?
+        .as_slice()
+        .try_into()
+        .map_err(|_| "Invalid signature length".to_string())?;
+    verifying_key
+        .verify(signed_bytes, &Signature::from_bytes(&signature))
+        .map_err(|_| "Signature is invalid".to_string())
+}
+
+impl ChatEnvelope {
+    /// Bytes covered by the signature: every field but the signature, as a JSON array of strings
+    /// (unambiguous even when the body contains newlines).
+    fn signed_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(&[
+            "cordia-chat-envelope",
+            self.kind.as_str(),
+            self.signing_pubkey.as_str(),
+            self.chat_id.as_str(),
+            self.message_id.as_str(),
+            self.body.as_str(),
+            self.author_user_id.as_str(),
+            self.author_public_key.as_str(),
+            self.signed_at.as_str(),
+        ])
+        .unwrap_or_default()
+    }
+
+    /// Build and sign an envelope with the author's identity key.
+    pub fn sign(
+        signing_key: &SigningKey,
+        kind: &str,
+        signing_pubkey: &str,
+        chat_id: &str,
+        message_id: &str,
+        body: &str,
+    ) -> Result<Self, String> {
+        if !ENVELOPE_KINDS.contains(&kind) {
+            return Err(format!("Unknown chat envelope kind: {}", kind));
+        }
+        let public_key = signing_key.verifying_key().to_bytes();
+        let mut envelope = ChatEnvelope {
+            kind: kind.to_string(),
+            signing_pubkey: signing_pubkey.to_string(),
+            chat_id: chat_id.to_string(),
+            message_id: message_id.to_string(),
+            body: body.to_string(),
+            author_user_id: user_id_for_public_key(&public_key),
+            author_public_key: hex::encode(public_key),
+            signed_at: chrono::Utc::now().to_rfc3339(),
+            signature: String::new(),
+        };
+        let signature = signing_key.sign(&envelope.signed_bytes());
+        envelope.signature = base64::encode(signature.to_bytes());
+        Ok(envelope)
+    }
+
+    /// Check that the envelope is for this server, chat and message, was signed by the key behind
+    /// `expected_author_user_id`, and that the signature holds.
+    pub fn verify(
+        &self,
+        signing_pubkey: &str,
+        chat_id: &str,
+        message_id: &str,
+        expected_author_user_id: &str,
+    ) -> Result<(), String> {
+        if !ENVELOPE_KINDS.contains(&self.kind.as_str()) {
+            return Err(format!("Unknown chat envelope kind: {}", self.kind));
+        }
+        if self.signing_pubkey != signing_pubkey || self.chat_id != chat_id || self.message_id != message_id {
+            return Err("Chat envelope is for a different message".to_string());
+        }
+        if self.author_user_id != expected_author_user_id {
+            return Err("Chat envelope is not signed by the expected author".to_string());
+        }
+        verify_author(&self.author_public_key, &self.author_user_id, &self.signed_bytes(), &self.signature)
+    }
+}
+
+/// `format` tag of a [`SignedChatPayload`]; payloads without it are from clients that predate
+/// signing and have no verified author (refused once the sender is known to sign).
+pub const SIGNED_CHAT_FORMAT: &str = "cordia-signed-chat-v1";
+
+/// A chat payload (the JSON the frontend encrypts) signed by its author.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedChatPayload {
+    pub format: String,
+    /// Server, chat and message the payload was written for, so it cannot be replayed into another
+    /// server, chat or message slot.
+    pub signing_pubkey: String,
+    pub chat_id: String,
+    pub message_id: String,
+    pub payload: String,
+    pub author_user_id: String,
+    /// Hex Ed25519 identity key of the author.
+    pub author_public_key: String,
+    pub signed_at: String,
+    /// Base64 Ed25519 signature over `signed_bytes`.
+    pub signature: String,
+}
+
+/// Result of opening a chat payload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenedChatPayload {
+    pub plaintext: String,
+    /// Author proven by the identity-key signature; None for unsigned (older) payloads.
+    pub verified_author_user_id: Option<String>,
+}
+
+impl SignedChatPayload {
+    fn signed_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(&[
+            self.format.as_str(),
+            self.signing_pubkey.as_str(),
+            self.chat_id.as_str(),
+            self.message_id.as_str(),
+            self.payload.as_str(),
+            self.author_user_id.as_str(),
+            self.author_public_key.as_str(),
+            self.signed_at.as_str(),
+        ])
+        .unwrap_or_default()
+    }
+
+    pub fn sign(signing_key: &SigningKey, signing_pubkey: &str, chat_id: &str, message_id: &str, payload: &str) -> Self {
+        let public_key = signing_key.verifying_key().to_bytes();
+        let mut signed = SignedChatPayload {
+            format: SIGNED_CHAT_FORMAT.to_string(),
+            signing_pubkey: signing_pubkey.to_string(),
+            chat_id: chat_id.to_string(),
+            message_id: message_id.to_string(),
+            payload: payload.to_string(),
+            author_user_id: user_id_for_public_key(&public_key),
+            author_public_key: hex::encode(public_key),
+            signed_at: chrono::Utc::now().to_rfc3339(),
+            signature: String::new(),
+        };
+        let signature = signing_key.sign(&signed.signed_bytes());
+        signed.signature = base64::encode(signature.to_bytes());
+        signed
+    }
+
+    /// Open a decrypted chat plaintext. Signed payloads must verify, be for the server, chat and
+    /// message they were relayed as and (when given) come from `expected_author_user_id`. Unsigned
+    /// payloads are refused when `require_signature` (the sender is known to sign), otherwise
+    /// returned without an author.
+    pub fn open(
+        plaintext: &str,
+        signing_pubkey: &str,
+        chat_id: &str,
+        message_id: &str,
+        expected_author_user_id: Option<&str>,
+        require_signature: bool,
+    ) -> Result<OpenedChatPayload, String> {
+        let tagged = serde_json::from_str::<serde_json::Value>(plaintext)
+            .ok()
+            .and_then(|v| v.get("format").and_then(|f| f.as_str()).map(|f| f == SIGNED_CHAT_FORMAT))
+            .unwrap_or(false);
+        if !tagged {
+            if require_signature {
+                return Err("Unsigned chat payload from a sender that signs".to_string());
+            }
+            return Ok(OpenedChatPayload { plaintext: plaintext.to_string(), verified_author_user_id: None });
+        }
+        let signed: SignedChatPayload = serde_json::from_str(plaintext)
+            .map_err(|e| format!("Invalid signed chat payload: {}", e))?;
+        if signed.signing_pubkey != signing_pubkey {
+            return Err("Signed chat payload is for a different server".to_string());
+        }
+        if signed.chat_id != chat_id || signed.message_id != message_id {
+            return Err("Signed chat payload is for a different message".to_string());
+        }
+        if expected_author_user_id.is_some_and(|expected| expected != signed.author_user_id) {
+            return Err("Signed chat payload author does not match the sender".to_string());
+        }
+        verify_author(&signed.author_public_key, &signed.author_user_id, &signed.signed_bytes(), &signed.signature)?;
+        Ok(OpenedChatPayload { plaintext: signed.payload, verified_author_user_id: Some(signed.author_user_id) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_author_signature_for_the_same_message_verifies() {
+        let author = SigningKey::from_bytes(&[7u8; 32]);
+        let other = SigningKey::from_bytes(&[8u8; 32]);
+        let author_id = user_id_for_public_key(author.verifying_key().as_bytes());
+
+        let edit = ChatEnvelope::sign(&author, "edit", "spk", "general", "m1", "fixed\ntypo").unwrap();
+        assert!(edit.verify("spk", "general", "m1", &author_id).is_ok());
+        assert!(edit.verify("spk", "general", "m2", &author_id).is_err());
+
+        let mut tampered = edit.clone();
+        tampered.body = "something else".to_string();
+        assert!(tampered.verify("spk", "general", "m1", &author_id).is_err());
+
+        // Someone else's valid signature is not the author's.
+        let forged = ChatEnvelope::sign(&other, "delete", "spk", "general", "m1", "").unwrap();
+        assert!(forged.verify("spk", "general", "m1", &author_id).is_err());
+        assert!(ChatEnvelope::sign(&author, "pin", "spk", "general", "m1", "").is_err());
+    }
+
+    #[test]
+    fn signed_chat_payload_names_its_author_and_rejects_impostors() {
+        let author = SigningKey::from_bytes(&[7u8; 32]);
+        let author_id = user_id_for_public_key(author.verifying_key().as_bytes());
+        let signed = SignedChatPayload::sign(&author, "spk", "general", "m1", r#"{"kind":"text","text":"hi"}"#);
+        let json = serde_json::to_string(&signed).unwrap();
+
+        let opened = SignedChatPayload::open(&json, "spk", "general", "m1", Some(&author_id), true).unwrap();
+        assert_eq!(opened.plaintext, r#"{"kind":"text","text":"hi"}"#);
+        assert_eq!(opened.verified_author_user_id.as_deref(), Some(author_id.as_str()));
+        // Beacon claims someone else sent it, or it was replayed into another server.
+        assert!(SignedChatPayload::open(&json, "spk", "general", "m1", Some("someone-else"), false).is_err());
+        assert!(SignedChatPayload::open(&json, "other-spk", "general", "m1", None, false).is_err());
+        // Replayed into another chat or under another message id of the same server.
+        assert!(SignedChatPayload::open(&json, "spk", "random", "m1", None, false).is_err());
+        assert!(SignedChatPayload::open(&json, "spk", "general", "m2", None, false).is_err());
+        // Rewriting the ids inside the payload to match breaks the signature.
+        let mut moved = signed.clone();
+        moved.chat_id = "random".to_string();
+        let moved = serde_json::to_string(&moved).unwrap();
+        assert!(SignedChatPayload::open(&moved, "spk", "random", "m1", None, false).is_err());
+
+        let tampered = json.replace("hi", "bye");
+        assert!(SignedChatPayload::open(&tampered, "spk", "general", "m1", None, false).is_err());
+
+        // Older clients' payloads still open, but are never attributed to the sender the beacon claims.
+        let unsigned = r#"{"kind":"text","text":"hi"}"#;
+        let legacy = SignedChatPayload::open(unsigned, "spk", "general", "m1", Some(&author_id), false).unwrap();
+        assert!(legacy.verified_author_user_id.is_none());
+        // Once the claimed sender is known to sign, an unsigned payload is a forgery.
+        assert!(SignedChatPayload::open(unsigned, "spk", "general", "m1", Some(&author_id), true).is_err());
+    }
+}
@@ -12,6 +12,8 @@ mod waveform;
 
 #[cfg(windows)]
 mod file_association;
+mod chat_envelope;
+mod server_hint;
 
 use tauri::Manager;
 use identity::{IdentityManager, UserIdentity};
@@ -19,6 +21,7 @@ use audio_settings::{AudioSettingsManager, AudioSettings};
 use audio_capture::{enumerate_devices, start_capture, stop_capture, AudioDevice, AudioDropStats};
 use audio_dsp::{get_dsp, InputMode};
 use server::{ServerManager, ServerInfo};
+use server_hint::{EncryptedServerHint, SealedServerKey};
 use beacon::{check_beacon_health, get_default_beacon_url};
 use account_manager::{AccountManager, SessionState, AccountInfo, KnownProfile, KnownProfileForExport};
 use serde::{Deserialize, Serialize};
@@ -37,14 +40,6 @@ use std::sync::{Mutex, OnceLock};
 #[cfg(windows)]
 use std::os::windows::process::CommandExt;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct EncryptedServerHint {
-    signing_pubkey: String,
-    encrypted_state: String,
-    signature: String,
-    last_updated: String,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct InviteTokenCreateRequest {
     code: String,
@@ -61,6 +56,10 @@ struct InviteTokenRecord {
     signature: String,
     created_at: String,
     expires_at: String,
+    #[serde(default)]
+    max_uses: u32,
+    #[serde(default)]
+    remaining_uses: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -69,6 +68,8 @@ struct InviteTokenPayload {
     server: ServerInfo,
     #[serde(rename = "house_symmetric_key_b64")]
     server_symmetric_key_b64: String,
+    #[serde(default)]
+    key_epoch: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1576,25 +1577,22 @@ fn decrypt_invite_payload(code: &str, encrypted_payload_b64: &str) -> Result<Inv
     serde_json::from_slice::<InviteTokenPayload>(&plaintext).map_err(|e| format!("Invite payload JSON parse failed: {}", e))
 }
 
-fn encrypt_server_hint(symmetric_key: &[u8], server: &ServerInfo) -> Result<String, String> {
-    if symmetric_key.len() != 32 {
-        return Err("Invalid server symmetric key length".to_string());
-    }
-    let cipher = XChaCha20Poly1305::new(symmetric_key.into());
-    let mut nonce_bytes = [0u8; 24];
-    rand::thread_rng().fill_bytes(&mut nonce_bytes);
-    let nonce = nonce_bytes.into();
-    let plaintext = serde_json::to_vec(server).map_err(|e| format!("Failed to serialize server: {}", e))?;
-    let ciphertext = cipher.encrypt(&nonce, plaintext.as_ref()).map_err(|_| "Server hint encryption failed".to_string())?;
-    let mut out = nonce_bytes.to_vec();
-    out.extend(ciphertext);
-    Ok(base64::encode(&out))
+/// Encrypt hint state with the current server key; the result names its key epoch ("<epoch>.<base64>").
+fn encrypt_server_hint(server: &server::Server, info: &ServerInfo) -> Result<String, String> {
+    let plaintext = serde_json::to_vec(info).map_err(|e| format!("Failed to serialize server: {}", e))?;
+    server.encrypt_tagged(&plaintext).map_err(|_| "Server hint encryption failed".to_string())
 }
 
-fn decrypt_server_hint(symmetric_key: &[u8], encrypted_state_b64: &str) -> Result<ServerInfo, String> {
+/// Decrypt hint state with a raw key of epoch `key_epoch` (e.g. from an invite).
+fn decrypt_server_hint(symmetric_key: &[u8], key_epoch: u32, encrypted_state: &str) -> Result<ServerInfo, String> {
     if symmetric_key.len() != 32 {
         return Err("Invalid server symmetric key length".to_string());
     }
+    let (epoch, encrypted_state_b64) = server::split_key_epoch(encrypted_state)
+        .map_err(|e| format!("Invalid server hint: {}", e))?;
+    if epoch != key_epoch {
+        return Err(format!("Server hint uses key epoch {}, have {}", epoch, key_epoch));
+    }
     let cipher = XChaCha20Poly1305::new(symmetric_key.into());
     let data = base64::decode(encrypted_state_b64).map_err(|e| format!("Server hint base64 decode failed: {}", e))?;
     if data.len() < 24 {
@@ -1608,6 +1606,42 @@ fn decrypt_server_hint(symmetric_key: &[u8], encrypted_state_b64: &str) -> Resul
     serde_json::from_slice::<ServerInfo>(&plaintext).map_err(|e| format!("Server hint JSON parse failed: {}", e))
 }
 
+/// Key epoch and earlier-epoch keys of an exported server (so history stays readable after restore).
+fn add_key_epochs_to_export(server: &server::Server, server_export: &mut serde_json::Value) {
+    if server.key_epoch == 0 {
+        return;
+    }
+    server_export["key_epoch"] = serde_json::Value::from(server.key_epoch);
+    server_export["previous_keys"] = serde_json::Value::Array(
+        server
+            .get_previous_keys()
+            .into_iter()
+            .map(|(epoch, key)| serde_json::json!({ "epoch": epoch, "key_b64": base64::encode(&key) }))
+            .collect(),
+    );
+}
+
+/// Inverse of `add_key_epochs_to_export`.
+fn key_epochs_from_export(server_json: &serde_json::Value) -> (u32, Vec<(u32, Vec<u8>)>) {
+    let key_epoch = server_json.get("key_epoch")
+        .and_then(|v| v.as_u64())
+        .and_then(|v| u32::try_from(v).ok())
+        .unwrap_or(0);
+    let previous_keys = server_json.get("previous_keys")
+        .and_then(|v| v.as_array())
+        .map(|keys| {
+            keys.iter()
+                .filter_map(|k| {
+                    let epoch = u32::try_from(k.get("epoch")?.as_u64()?).ok()?;
+                    let key = base64::decode(k.get("key_b64")?.as_str()?).ok()?;
+                    Some((epoch, key))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    (key_epoch, previous_keys)
+}
+
 fn merge_server_infos(mut base: ServerInfo, other: ServerInfo) -> ServerInfo {
     // Members: union by user_id (keep first seen)
     let mut seen = std::collections::HashSet::<String>::new();
@@ -1765,6 +1799,7 @@ fn export_full_identity_for_account(
             if let Some(signing_secret) = server.get_signing_secret() {
                 server_export["signing_secret_b64"] = serde_json::Value::String(base64::encode(&signing_secret));
             }
+            add_key_epochs_to_export(server, &mut server_export);
             if !invite_code.is_empty() {
                 server_export["invite_code"] = serde_json::Value::String(invite_code);
             }
@@ -1835,6 +1870,7 @@ fn export_full_identity(profile_json: Option<serde_json::Value>) -> Result<Vec<u
             if let Some(signing_secret) = server.get_signing_secret() {
                 server_export["signing_secret_b64"] = serde_json::Value::String(base64::encode(&signing_secret));
             }
+            add_key_epochs_to_export(server, &mut server_export);
             if !invite_code.is_empty() {
                 server_export["invite_code"] = serde_json::Value::String(invite_code);
             }
@@ -1907,6 +1943,7 @@ fn export_full_identity_debug(profile_json: Option<serde_json::Value>) -> Result
             if let Some(signing_secret) = server.get_signing_secret() {
                 server_export["signing_secret_b64"] = serde_json::Value::String(base64::encode(&signing_secret));
             }
+            add_key_epochs_to_export(&server, &mut server_export);
             if !invite_code.is_empty() {
                 server_export["invite_code"] = serde_json::Value::String(invite_code);
             }
@@ -2076,7 +2113,8 @@ fn import_identity(data: Vec<u8>) -> Result<ImportResult, String> {
                     
                     // Restore server using ServerManager method (will encrypt keys with device key)
                     // Rooms/members will be empty initially - signaling server will populate them
-                    server_manager.restore_server_from_export(&minimal_server_json, symmetric_key, signing_secret)
+                    let (key_epoch, previous_keys) = key_epochs_from_export(&server_json);
+                    server_manager.restore_server_from_export(&minimal_server_json, symmetric_key, signing_secret, key_epoch, previous_keys)
                         .map_err(|e| format!("Failed to restore server {}: {}", signing_pubkey, e))?;
                 }
             }
@@ -2208,8 +2246,75 @@ fn load_server(server_id: String) -> Result<ServerInfo, String> {
     Ok(srv.to_info())
 }
 
+/// Sign a chat payload for `chat_id` / `message_id` with the identity key, then encrypt it with the
+/// server key.
+fn seal_chat_payload(server: &server::Server, chat_id: &str, message_id: &str, plaintext: &str) -> Result<String, String> {
+    let (_, signing_key) = load_identity_signing_key()?;
+    let signed =
+        chat_envelope::SignedChatPayload::sign(&signing_key, &server.signing_pubkey, chat_id, message_id, plaintext);
+    let json = serde_json::to_string(&signed)
+        .map_err(|e| format!("Failed to serialize signed chat payload: {}", e))?;
+    server
+        .encrypt_tagged(json.as_bytes())
+        .map_err(|e| format!("Failed to encrypt ephemeral message: {}", e))
+}
+
+/// Whether an unsigned payload claiming to be from `user_id` must be a forgery. Clients sign since
+/// before key rotation and X25519 publishing existed, so anything under a rotated key, or from a
+/// member that published an X25519 key, is signed; so is anyone this server record saw sign.
+fn chat_sender_signs(server: &server::Server, encrypted_payload_b64: &str, user_id: &str) -> bool {
+    let rotated = server::split_key_epoch(encrypted_payload_b64).is_ok_and(|(epoch, _)| epoch > 0);
+    let published_x25519 = server
+        .members
+        .iter()
+        .any(|m| m.user_id == user_id && m.x25519_pubkey.is_some());
+    rotated || published_x25519 || server.chat_signers.iter().any(|u| u == user_id)
+}
+
+/// Decrypt a chat payload and verify its author signature. `chat_id` / `message_id` are the ones the
+/// beacon relayed it under and `expected_author_user_id` the sender it reported; a signed payload
+/// for another message or from anyone else is rejected, and so is an unsigned one when that sender
+/// is known to sign. A sender seen signing is remembered in the server record.
+fn open_chat_payload(
+    manager: &ServerManager,
+    server: &mut server::Server,
+    chat_id: &str,
+    message_id: &str,
+    encrypted_payload_b64: &str,
+    expected_author_user_id: Option<&str>,
+) -> Result<chat_envelope::OpenedChatPayload, String> {
+    // Payloads name the key epoch they were encrypted with, so history from before a rotation still opens.
+    let plaintext = server
+        .decrypt_tagged(encrypted_payload_b64)
+        .map_err(|e| format!("Failed to decrypt ephemeral message: {}", e))?;
+    let plaintext = String::from_utf8(plaintext).map_err(|e| format!("Invalid UTF-8 message payload: {}", e))?;
+    let require_signature = expected_author_user_id
+        .is_some_and(|user_id| chat_sender_signs(server, encrypted_payload_b64, user_id));
+    let opened = chat_envelope::SignedChatPayload::open(
+        &plaintext,
+        &server.signing_pubkey,
+        chat_id,
+        message_id,
+        expected_author_user_id,
+        require_signature,
+    )?;
+    if let Some(user_id) = &opened.verified_author_user_id {
+        if server.note_chat_signer(user_id) {
+            manager
+                .save_server(server)
+                .map_err(|e| format!("Failed to save server: {}", e))?;
+        }
+    }
+    Ok(opened)
+}
+
 #[tauri::command]
-fn encrypt_ephemeral_chat_message(server_id: String, plaintext: String) -> Result<String, String> {
+fn encrypt_ephemeral_chat_message(
+    server_id: String,
+    chat_id: String,
+    message_id: String,
+    plaintext: String,
+) -> Result<String, String> {
     // GUARDED: Requires active session
     require_session()?;
 
@@ -2218,14 +2323,16 @@ fn encrypt_ephemeral_chat_message(server_id: String, plaintext: String) -> Resul
     let server = manager.load_server(&server_id)
         .map_err(|e| format!("Failed to load server: {}", e))?;
 
-    let encrypted = server
-        .encrypt(plaintext.as_bytes())
-        .map_err(|e| format!("Failed to encrypt ephemeral message: {}", e))?;
-    Ok(base64::encode(encrypted))
+    seal_chat_payload(&server, &chat_id, &message_id, &plaintext)
 }
 
 #[tauri::command]
-fn encrypt_ephemeral_chat_message_by_signing_pubkey(signing_pubkey: String, plaintext: String) -> Result<String, String> {
+fn encrypt_ephemeral_chat_message_by_signing_pubkey(
+    signing_pubkey: String,
+    chat_id: String,
+    message_id: String,
+    plaintext: String,
+) -> Result<String, String> {
     // GUARDED: Requires active session
     require_session()?;
 
@@ -2239,32 +2346,36 @@ fn encrypt_ephemeral_chat_message_by_signing_pubkey(signing_pubkey: String, plai
         .load_server(&server_id)
         .map_err(|e| format!("Failed to load server: {}", e))?;
 
-    let encrypted = server
-        .encrypt(plaintext.as_bytes())
-        .map_err(|e| format!("Failed to encrypt ephemeral message: {}", e))?;
-    Ok(base64::encode(encrypted))
+    seal_chat_payload(&server, &chat_id, &message_id, &plaintext)
 }
 
 #[tauri::command]
-fn decrypt_ephemeral_chat_message(server_id: String, encrypted_payload_b64: String) -> Result<String, String> {
+fn decrypt_ephemeral_chat_message(
+    server_id: String,
+    chat_id: String,
+    message_id: String,
+    encrypted_payload_b64: String,
+    expected_author_user_id: Option<String>,
+) -> Result<chat_envelope::OpenedChatPayload, String> {
     // GUARDED: Requires active session
     require_session()?;
 
     let manager = ServerManager::new()
         .map_err(|e| format!("Failed to initialize server manager: {}", e))?;
-    let server = manager.load_server(&server_id)
+    let mut server = manager.load_server(&server_id)
         .map_err(|e| format!("Failed to load server: {}", e))?;
 
-    let encrypted = base64::decode(encrypted_payload_b64)
-        .map_err(|e| format!("Failed to decode encrypted payload: {}", e))?;
-    let plaintext = server
-        .decrypt(&encrypted)
-        .map_err(|e| format!("Failed to decrypt ephemeral message: {}", e))?;
-    String::from_utf8(plaintext).map_err(|e| format!("Invalid UTF-8 message payload: {}", e))
+    open_chat_payload(&manager, &mut server, &chat_id, &message_id, &encrypted_payload_b64, expected_author_user_id.as_deref())
 }
 
 #[tauri::command]
-fn decrypt_ephemeral_chat_message_by_signing_pubkey(signing_pubkey: String, encrypted_payload_b64: String) -> Result<String, String> {
+fn decrypt_ephemeral_chat_message_by_signing_pubkey(
+    signing_pubkey: String,
+    chat_id: String,
+    message_id: String,
+    encrypted_payload_b64: String,
+    expected_author_user_id: Option<String>,
+) -> Result<chat_envelope::OpenedChatPayload, String> {
     // GUARDED: Requires active session
     require_session()?;
 
@@ -2274,16 +2385,57 @@ fn decrypt_ephemeral_chat_message_by_signing_pubkey(signing_pubkey: String, encr
         .find_server_id_by_signing_pubkey(&signing_pubkey)
         .map_err(|e| format!("Failed to resolve signing pubkey: {}", e))?
         .ok_or_else(|| "No local server for signing pubkey".to_string())?;
-    let server = manager
+    let mut server = manager
         .load_server(&server_id)
         .map_err(|e| format!("Failed to load server: {}", e))?;
 
-    let encrypted = base64::decode(encrypted_payload_b64)
-        .map_err(|e| format!("Failed to decode encrypted payload: {}", e))?;
-    let plaintext = server
-        .decrypt(&encrypted)
-        .map_err(|e| format!("Failed to decrypt ephemeral message: {}", e))?;
-    String::from_utf8(plaintext).map_err(|e| format!("Invalid UTF-8 message payload: {}", e))
+    open_chat_payload(&manager, &mut server, &chat_id, &message_id, &encrypted_payload_b64, expected_author_user_id.as_deref())
+}
+
+/// Sign an edit / delete / reaction for `message_id` with the identity key, then encrypt it with
+/// the server key (encrypted_payload for EphemeralChatEdit, EphemeralChatDelete, EphemeralReactionAdd
+/// or EphemeralReactionRemove).
+#[tauri::command]
+fn encrypt_signed_chat_envelope(
+    signing_pubkey: String,
+    chat_id: String,
+    message_id: String,
+    kind: String,
+    body: Option<String>,
+) -> Result<String, String> {
+    let (_, signing_key) = load_identity_signing_key()?;
+    let envelope = chat_envelope::ChatEnvelope::sign(
+        &signing_key,
+        &kind,
+        &signing_pubkey,
+        &chat_id,
+        &message_id,
+        body.as_deref().unwrap_or(""),
+    )?;
+    let plaintext = serde_json::to_string(&envelope)
+        .map_err(|e| format!("Failed to serialize chat envelope: {}", e))?;
+    encrypt_ephemeral_chat_message_by_signing_pubkey(signing_pubkey, plaintext)
+}
+
+/// Decrypt an edit / delete / reaction and check it is signed by `expected_author_user_id` (the
+/// original message's sender for edits and deletes). Errors mean the envelope must be ignored.
+#[tauri::command]
+fn decrypt_verified_chat_envelope(
+    signing_pubkey: String,
+    chat_id: String,
+    message_id: String,
+    encrypted_payload_b64: String,
+    expected_author_user_id: String,
+) -> Result<chat_envelope::ChatEnvelope, String> {
+    let opened = decrypt_ephemeral_chat_message_by_signing_pubkey(
+        signing_pubkey.clone(),
+        encrypted_payload_b64,
+        Some(expected_author_user_id.clone()),
+    )?;
+    let envelope: chat_envelope::ChatEnvelope = serde_json::from_str(&opened.plaintext)
+        .map_err(|e| format!("Invalid chat envelope: {}", e))?;
+    envelope.verify(&signing_pubkey, &chat_id, &message_id, &expected_author_user_id)?;
+    Ok(envelope)
 }
 
 #[tauri::command]
@@ -2396,6 +2548,97 @@ async fn get_server_hint(beacon_url: String, signing_pubkey: String) -> Result<O
     Ok(Some(hint))
 }
 
+/// Sign a hint with the server key when we hold it (also authorizing the member delegate key),
+/// otherwise with the delegate key. Must match the beacon's signing::server_hint_message.
+fn sign_server_hint(server: &server::Server, encrypted_state: String) -> Result<EncryptedServerHint, String> {
+    use ed25519_dalek::Signer;
+
+    let delegate = server.hint_delegate_key()
+        .ok_or_else(|| "Server missing symmetric key".to_string())?;
+    let delegate_pubkey = base64::encode(delegate.verifying_key().as_bytes());
+    let last_updated = chrono::Utc::now();
+
+    // A server-key hint replaces the stored key envelopes, so it must always carry the current ones.
+    // Delegate-signed hints carry none (the beacon keeps the stored ones).
+    let key_envelopes: Vec<SealedServerKey> = if server.has_signing_key() && server.key_epoch > 0 {
+        server
+            .seal_key_for_members()
+            .into_iter()
+            .map(|(user_id, sealed_key)| SealedServerKey { user_id, epoch: server.key_epoch, sealed_key })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // Envelopes and invite admins are signed too, so nobody can re-post our hint with them stripped
+    // or swapped. Admins are only changed by the server key; we repeat the ones it last published.
+    let mut hint = EncryptedServerHint {
+        signing_pubkey: server.signing_pubkey.clone(),
+        encrypted_state,
+        signature: String::new(),
+        last_updated: last_updated.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        signer_pubkey: (!server.has_signing_key()).then(|| delegate_pubkey.clone()),
+        delegate_pubkey: Some(delegate_pubkey),
+        admin_pubkeys: server.admin_pubkeys.clone(),
+        key_envelopes,
+    };
+    let message = server_hint::server_hint_message(&hint)?;
+    hint.signature = if server.has_signing_key() {
+        server.sign(message.as_bytes())
+            .map_err(|e| format!("Failed to sign server hint: {}", e))?
+    } else {
+        base64::encode(delegate.sign(message.as_bytes()).to_bytes())
+    };
+
+    Ok(hint)
+}
+
+/// Our X25519 public key (derived from the identity key), published in our member entry so
+/// rotated server keys can be sealed to us. Returns whether the entry changed.
+fn publish_own_x25519_pubkey(server: &mut server::Server) -> Result<bool, String> {
+    let (identity, signing_key) = load_identity_signing_key()?;
+    let (_, x25519_public) = server::x25519_keypair_from_identity(&signing_key);
+    let x25519_pubkey = base64::encode(x25519_public);
+    match server.members.iter_mut().find(|m| m.user_id == identity.user_id) {
+        Some(member) if member.x25519_pubkey.as_deref() != Some(x25519_pubkey.as_str()) => {
+            member.x25519_pubkey = Some(x25519_pubkey);
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Remove members, rotate the server key to a new epoch, and publish a hint with the new key sealed
+/// to every remaining member. Needs the server signing key: the hint delegate key derives from the
+/// symmetric key, so only the server key can authorize the new one (and removed members still know the old one).
+async fn rotate_server_key(beacon_url: String, server_id: String, removed_user_ids: &[String]) -> Result<(), String> {
+    let manager = ServerManager::new()
+        .map_err(|e| format!("Failed to initialize server manager: {}", e))?;
+    let mut server = manager.load_server(&server_id)
+        .map_err(|e| format!("Failed to load server: {}", e))?;
+    if !server.has_signing_key() {
+        return Err("Only the server owner can rotate the server key".to_string());
+    }
+
+    server.members.retain(|m| !removed_user_ids.contains(&m.user_id));
+    publish_own_x25519_pubkey(&mut server)?;
+    server.rotate_symmetric_key()
+        .map_err(|e| format!("Failed to rotate server key: {}", e))?;
+    manager.save_server(&server)
+        .map_err(|e| format!("Failed to save server: {}", e))?;
+
+    let encrypted_state = encrypt_server_hint(&server, &server.to_info())?;
+    let hint = sign_server_hint(&server, encrypted_state)?;
+    register_server_hint(beacon_url, hint).await
+}
+
+/// Remove a member and rotate the server key so they cannot decrypt anything sent afterwards.
+#[tauri::command]
+async fn remove_server_member(beacon_url: String, server_id: String, user_id: String) -> Result<(), String> {
+    require_session()?;
+    rotate_server_key(beacon_url, server_id, &[user_id]).await
+}
+
 #[tauri::command]
 async fn publish_server_hint_opaque(beacon_url: String, server_id: String) -> Result<(), String> {
     require_session()?;
@@ -2403,21 +2646,18 @@ async fn publish_server_hint_opaque(beacon_url: String, server_id: String) -> Re
     let manager = ServerManager::new()
         .map_err(|e| format!("Failed to initialize server manager: {}", e))?;
 
-    let server = manager.load_server(&server_id)
+    let mut server = manager.load_server(&server_id)
         .map_err(|e| format!("Failed to load server: {}", e))?;
 
-    let symmetric_key = server.get_symmetric_key()
-        .ok_or_else(|| "Server missing symmetric key".to_string())?;
+    if publish_own_x25519_pubkey(&mut server)? {
+        manager.save_server(&server)
+            .map_err(|e| format!("Failed to save server: {}", e))?;
+    }
 
     let server_info = server.to_info();
-    let encrypted_state = encrypt_server_hint(&symmetric_key, &server_info)?;
+    let encrypted_state = encrypt_server_hint(&server, &server_info)?;
 
-    let hint = EncryptedServerHint {
-        signing_pubkey: server_info.signing_pubkey.clone(),
-        encrypted_state,
-        signature: "".to_string(),
-        last_updated: chrono::Utc::now().to_rfc3339(),
-    };
+    let hint = sign_server_hint(&server, encrypted_state)?;
 
     register_server_hint(beacon_url, hint).await
 }
@@ -2432,19 +2672,20 @@ async fn publish_server_hint_member_left(beacon_url: String, server_id: String,
     let server = manager.load_server(&server_id)
         .map_err(|e| format!("Failed to load server: {}", e))?;
 
-    let symmetric_key = server.get_symmetric_key()
-        .ok_or_else(|| "Server missing symmetric key".to_string())?;
+    // Only we can leave: the owner rotates on a leave record signed with our identity key.
+    let (identity, signing_key) = load_identity_signing_key()?;
+    if user_id != identity.user_id {
+        return Err("Can only publish your own leave (the owner removes other members)".to_string());
+    }
 
+    // The leaving member cannot rotate the key (they would know the new one); the owner does when
+    // they next import a hint carrying this record (see fetch_and_import_server_hint_opaque).
     let mut server_info = server.to_info();
     server_info.members.retain(|m| m.user_id != user_id);
+    server_info.left_members.push(server::SignedLeave::sign(&signing_key, &server.signing_pubkey));
 
-    let encrypted_state = encrypt_server_hint(&symmetric_key, &server_info)?;
-    let hint = EncryptedServerHint {
-        signing_pubkey: server_info.signing_pubkey.clone(),
-        encrypted_state,
-        signature: "".to_string(),
-        last_updated: chrono::Utc::now().to_rfc3339(),
-    };
+    let encrypted_state = encrypt_server_hint(&server, &server_info)?;
+    let hint = sign_server_hint(&server, encrypted_state)?;
 
     register_server_hint(beacon_url, hint).await
 }
@@ -2468,18 +2709,83 @@ async fn fetch_and_import_server_hint_opaque(beacon_url: String, signing_pubkey:
         return Err("Cannot decrypt hint: server not present locally (join via invite first)".to_string());
     };
 
-    let local_server = manager.load_server(&server_id)
+    let mut local_server = manager.load_server(&server_id)
         .map_err(|e| format!("Failed to load local server: {}", e))?;
 
-    let Some(symmetric_key) = local_server.get_symmetric_key() else {
+    if !local_server.has_symmetric_key() {
         // Server exists but has no key (e.g. created in a bad state). Can't decrypt hint; skip without error.
         return Ok(false);
-    };
+    }
 
-    let decrypted = decrypt_server_hint(&symmetric_key, &hint.encrypted_state)?;
+    // The key was rotated since we last synced: unseal the new key from our envelope.
+    let (hint_epoch, _) = server::split_key_epoch(&hint.encrypted_state)
+        .map_err(|e| format!("Invalid server hint: {}", e))?;
+    if hint_epoch > local_server.key_epoch {
+        let (identity, signing_key) = load_identity_signing_key()?;
+        let envelope = hint.key_envelopes
+            .iter()
+            .find(|e| e.user_id == identity.user_id && e.epoch == hint_epoch)
+            .ok_or_else(|| "Server key was rotated and none was sealed for us (ask for a new invite)".to_string())?;
+        let (x25519_secret, _) = server::x25519_keypair_from_identity(&signing_key);
+        let key = server::Server::decrypt_invite(&envelope.sealed_key, &x25519_secret)
+            .map_err(|e| format!("Failed to unseal rotated server key: {}", e))?;
+        // We do not check the hint signature here; the key is only trusted if it opens the hint state.
+        decrypt_server_hint(&key, hint_epoch, &hint.encrypted_state)?;
+        local_server.adopt_symmetric_key(hint_epoch, key);
+        manager.save_server(&local_server)
+            .map_err(|e| format!("Failed to save rotated server key: {}", e))?;
+    }
+
+    let decrypted_bytes = local_server.decrypt_tagged(&hint.encrypted_state)
+        .map_err(|e| format!("Server hint decryption failed: {}", e))?;
+    let mut decrypted: ServerInfo = serde_json::from_slice(&decrypted_bytes)
+        .map_err(|e| format!("Server hint JSON parse failed: {}", e))?;
+
+    // Members who left still hold the current key, so the owner rotates it. Any member can write a
+    // hint naming someone else, so only a leave record signed by the leaver themselves counts. We keep
+    // verified records (and repeat them in our own hints) until a rotation clears them, so a hint
+    // written before the owner syncs does not lose them; a member who rejoined since is dropped.
+    let mut pending_leaves = local_server.pending_leaves.clone();
+    for leave in &decrypted.left_members {
+        let verified = local_server.members.iter().any(|m| {
+            m.user_id == leave.user_id && leave.verify(&local_server.signing_pubkey, m.joined_at).is_ok()
+        });
+        if verified && !pending_leaves.iter().any(|l| l.user_id == leave.user_id) {
+            pending_leaves.push(leave.clone());
+        }
+    }
+    pending_leaves.retain(|leave| {
+        decrypted.members.iter().all(|m| m.user_id != leave.user_id || leave.left_at > m.joined_at)
+    });
+    let removed: Vec<String> = pending_leaves.iter().map(|leave| leave.user_id.clone()).collect();
+    // Likewise, as owner keep members the hint dropped without such a record.
+    if local_server.has_signing_key() {
+        for member in &local_server.members {
+            if !removed.contains(&member.user_id) && !decrypted.members.iter().any(|m| m.user_id == member.user_id) {
+                decrypted.members.push(member.clone());
+            }
+        }
+    }
 
     manager.import_server_hint(decrypted)
         .map_err(|e| format!("Failed to import decrypted hint: {}", e))?;
+    if removed.iter().ne(local_server.pending_leaves.iter().map(|leave| &leave.user_id)) {
+        manager.set_pending_leaves(&server_id, pending_leaves)
+            .map_err(|e| format!("Failed to save leave records: {}", e))?;
+    }
+
+    // Our hints must repeat the invite admins the beacon holds. Only the server key changes them, so
+    // as owner we take them from hints it signed, never from a delegate-signed one.
+    if hint.admin_pubkeys != local_server.admin_pubkeys
+        && (!local_server.has_signing_key() || server_hint::signed_by_server_key(&hint))
+    {
+        manager.set_admin_pubkeys(&server_id, hint.admin_pubkeys.clone())
+            .map_err(|e| format!("Failed to save invite admins: {}", e))?;
+    }
+
+    if !removed.is_empty() && local_server.has_signing_key() {
+        rotate_server_key(beacon_url, server_id, &removed).await?;
+    }
 
     Ok(true)
 }
@@ -2555,6 +2861,7 @@ async fn create_temporary_invite(beacon_url: String, server_id: String, max_uses
     let payload = InviteTokenPayload {
         server: server_info.clone(),
         server_symmetric_key_b64: base64::encode(&symmetric_key),
+        key_epoch: server.key_epoch,
     };
     let encrypted_payload = encrypt_invite_payload(&code, &payload)?;
 
@@ -2566,16 +2873,26 @@ async fn create_temporary_invite(beacon_url: String, server_id: String, max_uses
         urlencoding::encode(&server_info.signing_pubkey)
     );
 
-    let client = reqwest::Client::new();
+    let path = format!("/api/servers/{}/invites", urlencoding::encode(&server_info.signing_pubkey));
     let req = InviteTokenCreateRequest {
         code: code.clone(),
         max_uses,
         encrypted_payload,
         signature: "".to_string(),
     };
-    let resp = client
+    let body = serde_json::to_string(&req)
+        .map_err(|e| format!("Failed to serialize invite: {}", e))?;
+    let headers = server_admin_headers(&server, "POST", &path, &body)?;
+
+    let client = reqwest::Client::new();
+    let mut builder = client
         .post(url)
-        .json(&req)
+        .header("Content-Type", "application/json")
+        .body(body);
+    for (name, value) in headers {
+        builder = builder.header(name, value);
+    }
+    let resp = builder
         .send()
         .await
         .map_err(|e| format!("Failed to create invite on signaling server: {}", e))?;
@@ -2616,9 +2933,18 @@ async fn revoke_active_invite(beacon_url: String, server_id: String) -> Result<(
 
     if let Some(code) = code {
         let base = normalize_beacon_to_http(&beacon_url)?;
-        let url = format!("{}/api/invites/{}/revoke", base, urlencoding::encode(code.trim()));
+        let path = format!(
+            "/api/servers/{}/invites/{}/revoke",
+            urlencoding::encode(&server.signing_pubkey),
+            urlencoding::encode(code.trim())
+        );
+        let headers = server_admin_headers(&server, "POST", &path, "")?;
         let client = reqwest::Client::new();
-        let _ = client.post(url).send().await;
+        let mut builder = client.post(format!("{}{}", base, path));
+        for (name, value) in headers {
+            builder = builder.header(name, value);
+        }
+        let _ = builder.send().await;
     }
 
     manager
@@ -2631,6 +2957,37 @@ async fn revoke_active_invite(beacon_url: String, server_id: String) -> Result<(
     Ok(())
 }
 
+/// Outstanding invites for a server (owner only; the beacon requires a server-key signature).
+#[tauri::command]
+async fn list_server_invites(beacon_url: String, server_id: String) -> Result<Vec<InviteTokenRecord>, String> {
+    require_session()?;
+
+    let manager = ServerManager::new()
+        .map_err(|e| format!("Failed to initialize server manager: {}", e))?;
+    let server = manager.load_server(&server_id)
+        .map_err(|e| format!("Failed to load server: {}", e))?;
+
+    let base = normalize_beacon_to_http(&beacon_url)?;
+    let path = format!("/api/servers/{}/invites", urlencoding::encode(&server.signing_pubkey));
+    let headers = server_admin_headers(&server, "GET", &path, "")?;
+
+    let client = reqwest::Client::new();
+    let mut builder = client.get(format!("{}{}", base, path));
+    for (name, value) in headers {
+        builder = builder.header(name, value);
+    }
+    let resp = builder
+        .send()
+        .await
+        .map_err(|e| format!("Failed to list invites: {}", e))?;
+    if !resp.status().is_success() {
+        return Err(format!("Failed to list invites: HTTP {}", resp.status()));
+    }
+    resp.json::<Vec<InviteTokenRecord>>()
+        .await
+        .map_err(|e| format!("Failed to parse invite list: {}", e))
+}
+
 #[tauri::command]
 async fn redeem_temporary_invite(beacon_url: String, code: String, user_id: String, display_name: String) -> Result<ServerInfo, String> {
     require_session()?;
@@ -2667,14 +3024,14 @@ async fn redeem_temporary_invite(beacon_url: String, code: String, user_id: Stri
     // don't overwrite the server's member list with "creator + me".
     let mut merged_server = payload.server.clone();
     if let Some(latest_hint) = get_server_hint(beacon_url.clone(), merged_server.signing_pubkey.clone()).await? {
-        if let Ok(server_from_hint) = decrypt_server_hint(&symmetric_key, &latest_hint.encrypted_state) {
+        if let Ok(server_from_hint) = decrypt_server_hint(&symmetric_key, payload.key_epoch, &latest_hint.encrypted_state) {
             merged_server = merge_server_infos(server_from_hint, merged_server);
         }
     }
 
     // Import merged server + symmetric key locally
     // Returns the actual server ID used (may differ from merged_server.id if server already existed)
-    let actual_server_id = manager.import_server_invite(merged_server.clone(), symmetric_key)
+    let actual_server_id = manager.import_server_invite(merged_server.clone(), symmetric_key, payload.key_epoch)
         .map_err(|e| format!("Failed to import server from invite: {}", e))?;
 
     // Add member locally using the actual server ID
@@ -2960,8 +3317,12 @@ fn get_friend_auth_headers(
     path: String,
     body: Option<String>,
 ) -> Result<std::collections::HashMap<String, String>, String> {
-    use ed25519_dalek::Signer;
+    let (headers, _) = signed_identity_headers(&method, &path, body.as_deref())?;
+    Ok(headers)
+}
 
+/// Current session identity and its Ed25519 signing key.
+fn load_identity_signing_key() -> Result<(UserIdentity, ed25519_dalek::SigningKey), String> {
     let _ = require_session()?;
     let manager = IdentityManager::new()
         .map_err(|e| format!("Identity manager: {}", e))?;
@@ -2976,9 +3337,21 @@ fn get_friend_auth_headers(
         private_key_bytes.as_slice().try_into()
             .map_err(|_| "Invalid private key length")?,
     );
+    Ok((identity, signing_key))
+}
+
+/// Identity-signed request headers plus the envelope they cover (shared by friend and server event auth).
+fn signed_identity_headers(
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+) -> Result<(std::collections::HashMap<String, String>, String), String> {
+    use ed25519_dalek::Signer;
+
+    let (identity, signing_key) = load_identity_signing_key()?;
 
     let timestamp = chrono::Utc::now().timestamp();
-    let body_hash = match body.as_deref().unwrap_or("") {
+    let body_hash = match body.unwrap_or("") {
         "" => String::new(),
         b => {
             use sha2::Digest;
@@ -3000,6 +3373,99 @@ fn get_friend_auth_headers(
     headers.insert("X-Timestamp".to_string(), timestamp.to_string());
     headers.insert("X-Public-Key".to_string(), identity.public_key.clone());
     headers.insert("X-Signature".to_string(), base64::encode(signature.to_bytes()));
+    Ok((headers, envelope))
+}
+
+/// Headers for server-admin routes (invite create/revoke/list): X-Server-Signature is the server key's
+/// signature over the same envelope as the friend API. Only the owner holds the server key.
+fn server_admin_headers(
+    server: &server::Server,
+    method: &str,
+    path: &str,
+    body: &str,
+) -> Result<std::collections::HashMap<String, String>, String> {
+    if !server.has_signing_key() {
+        return Err("Only the server owner can manage invites".to_string());
+    }
+    let timestamp = chrono::Utc::now().timestamp();
+    let body_hash = if body.is_empty() {
+        String::new()
+    } else {
+        let mut hasher = Sha256::new();
+        hasher.update(body.as_bytes());
+        hex::encode(hasher.finalize())
+    };
+    let envelope = format!("{}\n{}\n{}\n{}",
+        method.to_uppercase(),
+        path.trim(),
+        timestamp,
+        body_hash,
+    );
+    let signature = server.sign(envelope.as_bytes())
+        .map_err(|e| format!("Failed to sign request: {}", e))?;
+
+    let mut headers = std::collections::HashMap::new();
+    headers.insert("X-Timestamp".to_string(), timestamp.to_string());
+    headers.insert("X-Server-Signature".to_string(), signature);
+    Ok(headers)
+}
+
+#[derive(Debug, Serialize)]
+struct BeaconAuthResponse {
+    user_id: String,
+    public_key: String,
+    signature: String,
+}
+
+/// Answer the beacon's WebSocket login challenge: sign "cordia-beacon-auth\n" + nonce with the identity key.
+#[tauri::command]
+fn sign_beacon_auth_challenge(nonce: String) -> Result<BeaconAuthResponse, String> {
+    use ed25519_dalek::Signer;
+
+    let (identity, signing_key) = load_identity_signing_key()?;
+
+    let message = format!("cordia-beacon-auth\n{}", nonce.trim());
+    let signature = signing_key.sign(message.as_bytes());
+    Ok(BeaconAuthResponse {
+        user_id: identity.user_id,
+        public_key: identity.public_key,
+        signature: base64::encode(signature.to_bytes()),
+    })
+}
+
+/// Headers for server event queue routes (/api/servers/:signing_pubkey/events*).
+/// Identity headers as for the friend API, plus X-Member-Signature over the same envelope proving
+/// server membership: signed with the server key if we hold it, else with the hint delegate key (X-Member-Key).
+#[tauri::command]
+fn get_server_member_auth_headers(
+    signing_pubkey: String,
+    method: String,
+    path: String,
+    body: Option<String>,
+) -> Result<std::collections::HashMap<String, String>, String> {
+    use ed25519_dalek::Signer;
+
+    let (mut headers, envelope) = signed_identity_headers(&method, &path, body.as_deref())?;
+
+    let manager = ServerManager::new()
+        .map_err(|e| format!("Failed to initialize server manager: {}", e))?;
+    let server_id = manager.find_server_id_by_signing_pubkey(&signing_pubkey)
+        .map_err(|e| format!("Failed to find server: {}", e))?
+        .ok_or_else(|| "Not a member of this server".to_string())?;
+    let server = manager.load_server(&server_id)
+        .map_err(|e| format!("Failed to load server: {}", e))?;
+
+    if server.has_signing_key() {
+        let signature = server.sign(envelope.as_bytes())
+            .map_err(|e| format!("Failed to sign request: {}", e))?;
+        headers.insert("X-Member-Signature".to_string(), signature);
+    } else {
+        let delegate = server.hint_delegate_key()
+            .ok_or_else(|| "Server missing symmetric key".to_string())?;
+        let signature = delegate.sign(envelope.as_bytes());
+        headers.insert("X-Member-Signature".to_string(), base64::encode(signature.to_bytes()));
+        headers.insert("X-Member-Key".to_string(), base64::encode(delegate.verifying_key().as_bytes()));
+    }
     Ok(headers)
 }
 
@@ -3163,6 +3629,8 @@ fn main() {
             load_known_profiles,
             save_known_profiles,
             get_friend_auth_headers,
+            get_server_member_auth_headers,
+            sign_beacon_auth_challenge,
             register_key_file_association_command,
             // Audio settings commands
             load_audio_settings,
@@ -3186,6 +3654,8 @@ fn main() {
             encrypt_ephemeral_chat_message_by_signing_pubkey,
             decrypt_ephemeral_chat_message,
             decrypt_ephemeral_chat_message_by_signing_pubkey,
+            encrypt_signed_chat_envelope,
+            decrypt_verified_chat_envelope,
             get_file_metadata,
             get_audio_stream_info,
             ensure_music_cover_thumbnail,
@@ -3218,9 +3688,11 @@ fn main() {
             resolve_invite_code,
             publish_server_hint_opaque,
             publish_server_hint_member_left,
+            remove_server_member,
             fetch_and_import_server_hint_opaque,
             create_temporary_invite,
             revoke_active_invite,
+            list_server_invites,
             redeem_temporary_invite,
             // Beacon commands
             check_beacon,
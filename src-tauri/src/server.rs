@@ -44,6 +44,8 @@ pub enum ServerError {
     KeyConversion,
     #[error("Invalid invite URI")]
     InvalidInviteUri,
+    #[error("No server key for epoch {0}")]
+    UnknownKeyEpoch(u32),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -93,6 +95,23 @@ pub struct ServerStorage {
     pub encrypted_signing_secret: Option<String>,  // Base64-encoded encrypted Ed25519 signing key
     #[serde(default)]
     pub encrypted_symmetric_key: Option<String>,   // Base64-encoded encrypted XChaCha20 key
+    /// Epoch of `encrypted_symmetric_key` (bumped each time the key is rotated; 0 = never rotated).
+    #[serde(default)]
+    pub key_epoch: u32,
+    /// Keys of earlier epochs, kept to decrypt history.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub encrypted_previous_keys: Vec<StoredEpochKey>,
+    /// Members whose chat payloads have verified; unsigned payloads claiming to be from them are
+    /// refused. Local state, never taken from a hint.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chat_signers: Vec<String>,
+    /// Invite admin keys the server key last published in its hint; our own hints repeat them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub admin_pubkeys: Vec<String>,
+    /// Verified leave records the owner has not rotated the key for yet. Repeated in the hints we
+    /// publish so a hint written before the owner syncs does not lose them; cleared by a rotation.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending_leaves: Vec<SignedLeave>,
 
     pub invite_uri: String,
     #[serde(default)]
@@ -115,6 +134,13 @@ pub struct ServerStorage {
     pub public_key: String,
 }
 
+/// A symmetric key of an earlier epoch, encrypted with the device key like the current one.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoredEpochKey {
+    pub epoch: u32,
+    pub encrypted_key: String,
+}
+
 impl ServerStorage {
     fn generate_legacy_invite_code() -> String {
         use rand::Rng;
@@ -150,6 +176,11 @@ pub struct ServerInfo {
     pub public_key: String,
     pub has_symmetric_key: bool,
     pub has_signing_key: bool,
+    /// Members who left, each with their signed leave record: first in the hint they write on the way
+    /// out, then in every member's hint until the owner rotates the server key (see
+    /// `Server::pending_leaves`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub left_members: Vec<SignedLeave>,
 }
 
 /// Runtime server struct with decrypted secrets in memory
@@ -168,6 +199,11 @@ pub struct Server {
 
     // === ENCRYPTION (XChaCha20-Poly1305) - THE shared secret ===
     server_symmetric_key: Option<Vec<u8>>,  // 256-bit symmetric key (local only, zeroized on drop)
+    pub key_epoch: u32,  // Epoch of server_symmetric_key; rotated when a member is removed
+    previous_keys: Vec<(u32, Vec<u8>)>,  // Older epochs, for decrypting history (zeroized on drop)
+    pub chat_signers: Vec<String>,  // Members seen signing chat payloads (local only)
+    pub admin_pubkeys: Vec<String>,  // Invite admins from the last server-key hint
+    pub pending_leaves: Vec<SignedLeave>,  // Leaves the owner has not rotated for yet
 
     pub invite_uri: String,
     pub connection_mode: ConnectionMode,
@@ -189,9 +225,138 @@ impl Drop for Server {
         if let Some(ref mut key) = self.server_symmetric_key {
             key.zeroize();
         }
+        for (_, key) in self.previous_keys.iter_mut() {
+            key.zeroize();
+        }
+    }
+}
+
+/// Split an epoch-tagged ciphertext ("<epoch>.<base64>") into its epoch and base64 part.
+/// Untagged ciphertexts (servers that never rotated, older clients) are epoch 0.
+pub fn split_key_epoch(tagged: &str) -> Result<(u32, &str), ServerError> {
+    match tagged.split_once('.') {
+        Some((epoch, b64)) => {
+            let epoch = epoch.parse::<u32>().map_err(|_| ServerError::InvalidCiphertext)?;
+            Ok((epoch, b64))
+        }
+        None => Ok((0, tagged)),
+    }
+}
+
+/// A member's statement, signed with their identity key, that they left a server. The delegate key
+/// that signs hints is derivable by every member, so a user id named in a hint proves nothing; the
+/// owner only rotates a member out on a record that verifies.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignedLeave {
+    pub user_id: String,
+    /// Hex Ed25519 identity key of the member who left.
+    pub public_key: String,
+    pub signing_pubkey: String,
+    pub left_at: DateTime<Utc>,
+    /// Base64 Ed25519 signature over `signed_bytes`.
+    pub signature: String,
+}
+
+impl SignedLeave {
+    fn signed_bytes(&self) -> Vec<u8> {
+        let left_at = self.left_at.to_rfc3339();
+        serde_json::to_vec(&[
+            "cordia-member-leave",
+            self.user_id.as_str(),
+            self.public_key.as_str(),
+            self.signing_pubkey.as_str(),
+            left_at.as_str(),
+        ])
+        .unwrap_or_default()
+    }
+
+    pub fn sign(identity_key: &SigningKey, signing_pubkey: &str) -> Self {
+        use sha2::{Sha256, Digest};
+        let public_key = identity_key.verifying_key().to_bytes();
+        let mut leave = SignedLeave {
+            user_id: hex::encode(&Sha256::digest(public_key)[..16]),
+            public_key: hex::encode(public_key),
+            signing_pubkey: signing_pubkey.to_string(),
+            left_at: Utc::now(),
+            signature: String::new(),
+        };
+        leave.signature = base64::encode(identity_key.sign(&leave.signed_bytes()).to_bytes());
+        leave
+    }
+
+    /// Check the record is for this server, was signed by the identity key behind `user_id`, and is
+    /// newer than `joined_at`, so a replayed old record cannot evict a member who rejoined.
+    pub fn verify(&self, signing_pubkey: &str, joined_at: DateTime<Utc>) -> Result<(), String> {
+        use sha2::{Sha256, Digest};
+        if self.signing_pubkey != signing_pubkey {
+            return Err("Leave record is for a different server".to_string());
+        }
+        if self.left_at <= joined_at {
+            return Err("Leave record predates the member's join".to_string());
+        }
+        let public_key: [u8; 32] = hex::decode(&self.public_key)
+            .map_err(|e| format!("Invalid leave public key: {}", e))?
+            .as_slice()
+            .try_into()
+            .map_err(|_| "Invalid leave public key length".to_string())?;
+        if hex::encode(&Sha256::digest(public_key)[..16]) != self.user_id {
+            return Err("Leave public key does not match user_id".to_string());
+        }
+        let verifying_key = VerifyingKey::from_bytes(&public_key)
+            .map_err(|e| format!("Invalid leave public key: {}", e))?;
+        let signature: [u8; 64] = base64::decode(&self.signature)
+            .map_err(|e| format!("Invalid leave signature encoding: {}", e))?
+            .as_slice()
+            .try_into()
+            .map_err(|_| "Invalid leave signature length".to_string())?;
+        verifying_key
+            .verify(&self.signed_bytes(), &Signature::from_bytes(&signature))
+            .map_err(|_| "Leave signature is invalid".to_string())
     }
 }
 
+/// X25519 keypair (secret, public) derived from an identity Ed25519 key, used to seal rotated
+/// server keys to members. Members publish the public half as `ServerMember::x25519_pubkey`.
+pub fn x25519_keypair_from_identity(identity_key: &SigningKey) -> ([u8; 32], [u8; 32]) {
+    use sha2::{Sha256, Digest};
+    let mut hasher = Sha256::new();
+    hasher.update(b"cordia-x25519-v1");
+    hasher.update(identity_key.to_bytes());
+    let secret_bytes: [u8; 32] = hasher.finalize().into();
+    let public = X25519PublicKey::from(&StaticSecret::from(secret_bytes));
+    (secret_bytes, *public.as_bytes())
+}
+
+fn encrypt_with_key(key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, ServerError> {
+    let key_array: [u8; 32] = key.try_into()
+        .map_err(|_| ServerError::KeyConversion)?;
+    let cipher = XChaCha20Poly1305::new((&key_array).into());
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, plaintext)
+        .map_err(|_| ServerError::EncryptionFailed)?;
+
+    // Prepend nonce to ciphertext
+    let mut result = nonce.to_vec();
+    result.extend(ciphertext);
+    Ok(result)
+}
+
+fn decrypt_with_key(key: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, ServerError> {
+    if ciphertext.len() < 24 {
+        return Err(ServerError::InvalidCiphertext);
+    }
+
+    let nonce: [u8; 24] = ciphertext[..24].try_into()
+        .map_err(|_| ServerError::KeyConversion)?;
+    let encrypted = &ciphertext[24..];
+
+    let key_array: [u8; 32] = key.try_into()
+        .map_err(|_| ServerError::KeyConversion)?;
+    let cipher = XChaCha20Poly1305::new((&key_array).into());
+    cipher.decrypt((&nonce).into(), encrypted)
+        .map_err(|_| ServerError::DecryptionFailed)
+}
+
 impl Server {
     /// Create a new server with fresh cryptographic keys
     pub fn new(
@@ -245,6 +410,11 @@ impl Server {
             signing_pubkey: signing_pubkey.clone(),
             signing_secret: Some(signing_secret),
             server_symmetric_key: Some(symmetric_key),
+            key_epoch: 0,
+            previous_keys: Vec::new(),
+            chat_signers: Vec::new(),
+            admin_pubkeys: Vec::new(),
+            pending_leaves: Vec::new(),
             invite_uri,
             connection_mode: ConnectionMode::Signaling,
             signaling_url,
@@ -374,38 +544,89 @@ impl Server {
     pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, ServerError> {
         let key = self.server_symmetric_key.as_ref()
             .ok_or(ServerError::MissingSymmetricKey)?;
-
-        let key_array: [u8; 32] = key.as_slice().try_into()
-            .map_err(|_| ServerError::KeyConversion)?;
-        let cipher = XChaCha20Poly1305::new((&key_array).into());
-        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
-        let ciphertext = cipher.encrypt(&nonce, plaintext)
-            .map_err(|_| ServerError::EncryptionFailed)?;
-
-        // Prepend nonce to ciphertext
-        let mut result = nonce.to_vec();
-        result.extend(ciphertext);
-        Ok(result)
+        encrypt_with_key(key, plaintext)
     }
 
     /// Decrypt data with server symmetric key (XChaCha20-Poly1305)
     pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, ServerError> {
         let key = self.server_symmetric_key.as_ref()
             .ok_or(ServerError::MissingSymmetricKey)?;
+        decrypt_with_key(key, ciphertext)
+    }
 
-        if ciphertext.len() < 24 {
-            return Err(ServerError::InvalidCiphertext);
+    /// Symmetric key of `epoch`: the current one or a kept earlier one.
+    fn key_for_epoch(&self, epoch: u32) -> Option<&Vec<u8>> {
+        if epoch == self.key_epoch {
+            return self.server_symmetric_key.as_ref();
         }
+        self.previous_keys.iter().find(|(e, _)| *e == epoch).map(|(_, key)| key)
+    }
 
-        let nonce: [u8; 24] = ciphertext[..24].try_into()
-            .map_err(|_| ServerError::KeyConversion)?;
-        let encrypted = &ciphertext[24..];
+    /// Encrypt with the current key and encode as "<epoch>.<base64>" (plain base64 at epoch 0, so
+    /// servers that never rotated stay readable by older clients).
+    pub fn encrypt_tagged(&self, plaintext: &[u8]) -> Result<String, ServerError> {
+        let encoded = base64::encode(self.encrypt(plaintext)?);
+        if self.key_epoch == 0 {
+            Ok(encoded)
+        } else {
+            Ok(format!("{}.{}", self.key_epoch, encoded))
+        }
+    }
 
-        let key_array: [u8; 32] = key.as_slice().try_into()
-            .map_err(|_| ServerError::KeyConversion)?;
-        let cipher = XChaCha20Poly1305::new((&key_array).into());
-        cipher.decrypt((&nonce).into(), encrypted)
-            .map_err(|_| ServerError::DecryptionFailed)
+    /// Decrypt an `encrypt_tagged` ciphertext with the key of the epoch it names.
+    pub fn decrypt_tagged(&self, tagged: &str) -> Result<Vec<u8>, ServerError> {
+        let (epoch, b64) = split_key_epoch(tagged)?;
+        let key = self.key_for_epoch(epoch)
+            .ok_or(ServerError::UnknownKeyEpoch(epoch))?;
+        let ciphertext = base64::decode(b64)
+            .map_err(|e| ServerError::Base64Decode(e.to_string()))?;
+        decrypt_with_key(key, &ciphertext)
+    }
+
+    /// Replace the symmetric key with a fresh one for the next epoch, keeping the old one for
+    /// history. Used when a member is removed so they cannot read anything sent afterwards.
+    pub fn rotate_symmetric_key(&mut self) -> Result<(), ServerError> {
+        let old = self.server_symmetric_key.take()
+            .ok_or(ServerError::MissingSymmetricKey)?;
+        self.previous_keys.push((self.key_epoch, old));
+        let mut key = vec![0u8; 32];
+        use rand::RngCore;
+        OsRng.fill_bytes(&mut key);
+        self.server_symmetric_key = Some(key);
+        self.key_epoch += 1;
+        self.pending_leaves.clear();
+        Ok(())
+    }
+
+    /// Switch to a key another member rotated to (newer epochs only), keeping the current one for history.
+    pub fn adopt_symmetric_key(&mut self, epoch: u32, key: Vec<u8>) {
+        if epoch <= self.key_epoch {
+            return;
+        }
+        if let Some(old) = self.server_symmetric_key.take() {
+            self.previous_keys.push((self.key_epoch, old));
+        }
+        self.server_symmetric_key = Some(key);
+        self.key_epoch = epoch;
+        // The owner rotated, so the leaves we were carrying have been acted on.
+        self.pending_leaves.clear();
+    }
+
+    /// The current key sealed (with `generate_invite`) to every member that published an X25519 key.
+    /// Members without one cannot receive it and need a new invite.
+    pub fn seal_key_for_members(&self) -> Vec<(String, String)> {
+        self.members
+            .iter()
+            .filter_map(|m| {
+                let pubkey: [u8; 32] = base64::decode(m.x25519_pubkey.as_deref()?).ok()?.try_into().ok()?;
+                Some((m.user_id.clone(), self.generate_invite(&pubkey).ok()?))
+            })
+            .collect()
+    }
+
+    /// Keys of earlier epochs (for account export).
+    pub fn get_previous_keys(&self) -> Vec<(u32, Vec<u8>)> {
+        self.previous_keys.clone()
     }
 
     pub fn add_member(&mut self, user_id: String, display_name: String) {
@@ -418,6 +639,15 @@ impl Server {
         self.members.push(member);
     }
 
+    /// Remember that `user_id` signs its chat payloads. Returns true if it was not known yet.
+    pub fn note_chat_signer(&mut self, user_id: &str) -> bool {
+        if self.chat_signers.iter().any(|u| u == user_id) {
+            return false;
+        }
+        self.chat_signers.push(user_id.to_string());
+        true
+    }
+
     /// Returns the single group chat for this server (v1: one implicit chat per server).
     /// For backward compat, we use the first chat if present; otherwise a default.
     fn get_single_chat(&self) -> Chat {
@@ -458,6 +688,16 @@ impl Server {
             None => return Err(ServerError::MissingSymmetricKey),
         };
 
+        let encrypted_previous_keys = self.previous_keys
+            .iter()
+            .map(|(epoch, key)| {
+                Ok(StoredEpochKey {
+                    epoch: *epoch,
+                    encrypted_key: base64::encode(encrypt_with_key(device_key, key)?),
+                })
+            })
+            .collect::<Result<Vec<_>, ServerError>>()?;
+
         Ok(ServerStorage {
             id: self.id.clone(),
             name: self.name.clone(),
@@ -467,6 +707,11 @@ impl Server {
             signing_pubkey: self.signing_pubkey.clone(),
             encrypted_signing_secret,
             encrypted_symmetric_key,
+            key_epoch: self.key_epoch,
+            encrypted_previous_keys,
+            chat_signers: self.chat_signers.clone(),
+            admin_pubkeys: self.admin_pubkeys.clone(),
+            pending_leaves: self.pending_leaves.clone(),
             invite_uri: self.invite_uri.clone(),
             connection_mode: self.connection_mode.clone(),
             signaling_url: self.signaling_url.clone(),
@@ -516,6 +761,15 @@ impl Server {
             None => return Err(ServerError::MissingSymmetricKey),
         };
 
+        let previous_keys = storage.encrypted_previous_keys
+            .iter()
+            .map(|stored| {
+                let data = base64::decode(&stored.encrypted_key)
+                    .map_err(|e| ServerError::Base64Decode(e.to_string()))?;
+                Ok((stored.epoch, decrypt_with_key(device_key, &data)?))
+            })
+            .collect::<Result<Vec<_>, ServerError>>()?;
+
         Ok(Server {
             id: storage.id,
             name: storage.name,
@@ -525,6 +779,11 @@ impl Server {
             signing_pubkey: storage.signing_pubkey.clone(),
             signing_secret,
             server_symmetric_key,
+            key_epoch: storage.key_epoch,
+            previous_keys,
+            chat_signers: storage.chat_signers,
+            admin_pubkeys: storage.admin_pubkeys,
+            pending_leaves: storage.pending_leaves,
             invite_uri: storage.invite_uri,
             connection_mode: storage.connection_mode,
             signaling_url: storage.signaling_url,
@@ -546,6 +805,11 @@ impl Server {
             signing_pubkey: storage.signing_pubkey.clone(),
             signing_secret: None,
             server_symmetric_key: None,
+            key_epoch: storage.key_epoch,
+            previous_keys: Vec::new(),
+            chat_signers: storage.chat_signers,
+            admin_pubkeys: storage.admin_pubkeys,
+            pending_leaves: storage.pending_leaves,
             invite_uri: storage.invite_uri,
             connection_mode: storage.connection_mode,
             signaling_url: storage.signaling_url,
@@ -569,6 +833,18 @@ impl Server {
         self.signing_secret.clone()
     }
 
+    /// Hint delegate key: an Ed25519 key every member derives from the symmetric key.
+    /// The signing-key holder authorizes it on the beacon so members without the server key can publish hints.
+    pub fn hint_delegate_key(&self) -> Option<SigningKey> {
+        use sha2::{Sha256, Digest};
+        let key = self.server_symmetric_key.as_ref()?;
+        let mut hasher = Sha256::new();
+        hasher.update(b"cordia-hint-delegate-v1");
+        hasher.update(key);
+        let seed: [u8; 32] = hasher.finalize().into();
+        Some(SigningKey::from_bytes(&seed))
+    }
+
     /// Convert to ServerInfo for frontend serialization
     pub fn to_info(&self) -> ServerInfo {
         fn derive_simple_invite_code(signing_pubkey: &str) -> String {
@@ -598,6 +874,7 @@ impl Server {
             public_key: self.public_key.clone(),
             has_symmetric_key: self.server_symmetric_key.is_some(),
             has_signing_key: self.signing_secret.is_some(),
+            left_members: self.pending_leaves.clone(),
         }
     }
 }
@@ -870,6 +1147,8 @@ impl ServerManager {
         server_data: &serde_json::Value,
         plaintext_symmetric_key: Vec<u8>,
         plaintext_signing_secret: Option<Vec<u8>>,
+        key_epoch: u32,
+        plaintext_previous_keys: Vec<(u32, Vec<u8>)>,
     ) -> Result<(), ServerError> {
         // Ensure servers directory exists
         let servers_dir = self.data_dir.join("servers");
@@ -901,6 +1180,16 @@ impl ServerManager {
             None
         };
         
+        let encrypted_previous_keys = plaintext_previous_keys
+            .iter()
+            .map(|(epoch, key)| {
+                Ok(StoredEpochKey {
+                    epoch: *epoch,
+                    encrypted_key: base64::encode(encrypt_with_key(&self.device_key, key)?),
+                })
+            })
+            .collect::<Result<Vec<_>, ServerError>>()?;
+
         // Parse server data from export (minimal - chats/members come from beacon)
         let server_id: String = server_data.get("id")
             .and_then(|v| v.as_str())
@@ -975,6 +1264,11 @@ impl ServerManager {
             signing_pubkey,
             encrypted_signing_secret,
             encrypted_symmetric_key,
+            key_epoch,
+            encrypted_previous_keys,
+            chat_signers: Vec::new(),
+            admin_pubkeys: Vec::new(),
+            pending_leaves: Vec::new(),
             invite_uri,
             connection_mode,
             signaling_url,
@@ -1106,6 +1400,20 @@ impl ServerManager {
         Ok(())
     }
 
+    pub fn set_pending_leaves(&self, server_id: &str, pending_leaves: Vec<SignedLeave>) -> Result<(), ServerError> {
+        let mut server = self.load_server(server_id)?;
+        server.pending_leaves = pending_leaves;
+        self.save_server(&server)?;
+        Ok(())
+    }
+
+    pub fn set_admin_pubkeys(&self, server_id: &str, admin_pubkeys: Vec<String>) -> Result<(), ServerError> {
+        let mut server = self.load_server(server_id)?;
+        server.admin_pubkeys = admin_pubkeys;
+        self.save_server(&server)?;
+        Ok(())
+    }
+
     pub fn find_server_id_by_signing_pubkey(&self, signing_pubkey: &str) -> Result<Option<String>, ServerError> {
         let server_ids = self.list_servers()?;
         for server_id in server_ids {
@@ -1123,26 +1431,20 @@ impl ServerManager {
     /// For new servers, returns Err(MissingSymmetricKey) - join via invite first.
     pub fn import_server_hint(&self, info: ServerInfo) -> Result<(), ServerError> {
         // Find existing server by signing_pubkey (not by id, since id can differ)
-        let (existing_server_id, preserve_encrypted_signing_secret, preserve_encrypted_symmetric_key) = 
-            match self.find_server_id_by_signing_pubkey(&info.signing_pubkey)? {
-                Some(existing_id) => {
-                    let server_path = self.get_server_path(&existing_id);
-                    let (signing_secret, symmetric_key) = if server_path.exists() {
-                        match fs::read_to_string(&server_path)
-                            .ok()
-                            .and_then(|s| serde_json::from_str::<ServerStorage>(&s).ok())
-                        {
-                            Some(existing) => (existing.encrypted_signing_secret, existing.encrypted_symmetric_key),
-                            None => (None, None),
-                        }
-                    } else {
-                        (None, None)
-                    };
-                    (existing_id, signing_secret, symmetric_key)
-                }
-                None => return Err(ServerError::MissingSymmetricKey),
-            };
-        
+        let existing_server_id = self.find_server_id_by_signing_pubkey(&info.signing_pubkey)?
+            .ok_or(ServerError::MissingSymmetricKey)?;
+        let server_path = self.get_server_path(&existing_server_id);
+        let existing = if server_path.exists() {
+            fs::read_to_string(&server_path)
+                .ok()
+                .and_then(|s| serde_json::from_str::<ServerStorage>(&s).ok())
+        } else {
+            None
+        };
+        // Keys (and their epochs), known chat signers, invite admins and pending leaves live outside
+        // the decrypted hint state; importing it never changes them.
+        let existing = existing.as_ref();
+
         let storage = ServerStorage {
             id: existing_server_id.clone(),
             name: info.name,
@@ -1150,8 +1452,13 @@ impl ServerManager {
             chats: info.chats,
             members: info.members,
             signing_pubkey: info.signing_pubkey,
-            encrypted_signing_secret: preserve_encrypted_signing_secret,
-            encrypted_symmetric_key: preserve_encrypted_symmetric_key,
+            encrypted_signing_secret: existing.and_then(|e| e.encrypted_signing_secret.clone()),
+            encrypted_symmetric_key: existing.and_then(|e| e.encrypted_symmetric_key.clone()),
+            key_epoch: existing.map_or(0, |e| e.key_epoch),
+            encrypted_previous_keys: existing.map(|e| e.encrypted_previous_keys.clone()).unwrap_or_default(),
+            chat_signers: existing.map(|e| e.chat_signers.clone()).unwrap_or_default(),
+            admin_pubkeys: existing.map(|e| e.admin_pubkeys.clone()).unwrap_or_default(),
+            pending_leaves: existing.map(|e| e.pending_leaves.clone()).unwrap_or_default(),
             invite_uri: info.invite_uri,
             connection_mode: info.connection_mode,
             signaling_url: info.signaling_url,
@@ -1167,7 +1474,7 @@ impl ServerManager {
     /// Import a server from an invite token that contains the server symmetric key.
     /// This lets a new member decrypt future Option-B hints.
     /// Returns the actual server ID used (may differ from info.id if server already existed).
-    pub fn import_server_invite(&self, info: ServerInfo, server_symmetric_key: Vec<u8>) -> Result<String, ServerError> {
+    pub fn import_server_invite(&self, info: ServerInfo, server_symmetric_key: Vec<u8>, key_epoch: u32) -> Result<String, ServerError> {
         // Check if server already exists by signing_pubkey
         let existing_server_id_opt = self.find_server_id_by_signing_pubkey(&info.signing_pubkey)?;
         
@@ -1185,23 +1492,19 @@ impl ServerManager {
         // Use existing server ID if found, otherwise use the ID from info
         let server_id = existing_server_id_opt.clone().unwrap_or(info.id.clone());
         
-        // If server exists, preserve its encrypted_signing_secret
-        let preserve_encrypted_signing_secret = if let Some(ref existing_id) = existing_server_id_opt {
+        // If server exists, preserve its encrypted_signing_secret, older-epoch keys, known chat signers,
+        // invite admins and pending leaves
+        let existing = existing_server_id_opt.as_ref().and_then(|existing_id| {
             let server_path = self.get_server_path(existing_id);
-            if server_path.exists() {
-                match fs::read_to_string(&server_path)
-                    .ok()
-                    .and_then(|s| serde_json::from_str::<ServerStorage>(&s).ok())
-                {
-                    Some(existing) => existing.encrypted_signing_secret,
-                    None => None,
-                }
-            } else {
-                None
-            }
-        } else {
-            None
-        };
+            fs::read_to_string(&server_path)
+                .ok()
+                .and_then(|s| serde_json::from_str::<ServerStorage>(&s).ok())
+        });
+        let preserve_encrypted_signing_secret = existing.as_ref().and_then(|e| e.encrypted_signing_secret.clone());
+        let preserve_chat_signers = existing.as_ref().map(|e| e.chat_signers.clone()).unwrap_or_default();
+        let preserve_admin_pubkeys = existing.as_ref().map(|e| e.admin_pubkeys.clone()).unwrap_or_default();
+        let preserve_pending_leaves = existing.as_ref().map(|e| e.pending_leaves.clone()).unwrap_or_default();
+        let preserve_previous_keys = existing.map(|e| e.encrypted_previous_keys).unwrap_or_default();
 
         let storage = ServerStorage {
             id: server_id.clone(),
@@ -1212,6 +1515,11 @@ impl ServerManager {
             signing_pubkey: info.signing_pubkey,
             encrypted_signing_secret: preserve_encrypted_signing_secret,
             encrypted_symmetric_key,
+            key_epoch,
+            encrypted_previous_keys: preserve_previous_keys,
+            chat_signers: preserve_chat_signers,
+            admin_pubkeys: preserve_admin_pubkeys,
+            pending_leaves: preserve_pending_leaves,
             invite_uri: info.invite_uri,
             connection_mode: info.connection_mode,
             signaling_url: info.signaling_url,
@@ -1225,3 +1533,87 @@ impl ServerManager {
         Ok(server_id)  // Return the actual server ID used
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_seals_the_new_key_to_members_and_keeps_old_epochs() {
+        let alice = SigningKey::from_bytes(&[1u8; 32]);
+        let bob = SigningKey::from_bytes(&[2u8; 32]);
+        let mut server = Server::new("s".to_string(), "alice".to_string(), "Alice".to_string(), None).unwrap();
+        server.add_member("bob".to_string(), "Bob".to_string());
+        server.add_member("carol".to_string(), "Carol".to_string());
+        for (member, key) in server.members.iter_mut().zip([&alice, &bob]) {
+            member.x25519_pubkey = Some(base64::encode(x25519_keypair_from_identity(key).1));
+        }
+
+        let before = server.encrypt_tagged(b"old").unwrap();
+        assert!(!before.contains('.'));
+        server.members.retain(|m| m.user_id != "carol");
+        server.rotate_symmetric_key().unwrap();
+        let after = server.encrypt_tagged(b"new").unwrap();
+        assert!(after.starts_with("1."));
+        assert_eq!(server.decrypt_tagged(&before).unwrap(), b"old");
+
+        // Bob unseals the epoch-1 key and can read both epochs once he adopts it.
+        let sealed = server.seal_key_for_members();
+        assert_eq!(sealed.len(), 2);
+        let (_, bob_sealed) = sealed.iter().find(|(id, _)| id == "bob").unwrap();
+        let bob_key = Server::decrypt_invite(bob_sealed, &x25519_keypair_from_identity(&bob).0).unwrap();
+        let mut bobs_view = server.clone();
+        bobs_view.key_epoch = 0;
+        bobs_view.previous_keys.clear();
+        bobs_view.set_symmetric_key(server.previous_keys[0].1.clone());
+        assert!(matches!(bobs_view.decrypt_tagged(&after), Err(ServerError::UnknownKeyEpoch(1))));
+        bobs_view.adopt_symmetric_key(1, bob_key);
+        assert_eq!(bobs_view.decrypt_tagged(&after).unwrap(), b"new");
+        assert_eq!(bobs_view.decrypt_tagged(&before).unwrap(), b"old");
+    }
+
+    #[test]
+    fn only_the_member_can_sign_their_leave() {
+        let bob = SigningKey::from_bytes(&[2u8; 32]);
+        let mallory = SigningKey::from_bytes(&[3u8; 32]);
+        let joined_at = Utc::now() - chrono::Duration::seconds(60);
+
+        let leave = SignedLeave::sign(&bob, "spk");
+        assert!(leave.verify("spk", joined_at).is_ok());
+        assert!(leave.verify("other-spk", joined_at).is_err());
+        // A replayed record from before Bob rejoined.
+        assert!(leave.verify("spk", Utc::now() + chrono::Duration::seconds(60)).is_err());
+
+        // Another member cannot leave on Bob's behalf.
+        let mut forged = SignedLeave::sign(&mallory, "spk");
+        forged.user_id = leave.user_id.clone();
+        assert!(forged.verify("spk", joined_at).is_err());
+        forged.public_key = leave.public_key.clone();
+        assert!(forged.verify("spk", joined_at).is_err());
+    }
+
+    #[test]
+    fn known_chat_signers_survive_a_restart() {
+        let device_key = [9u8; 32];
+        let mut server = Server::new("s".to_string(), "alice".to_string(), "Alice".to_string(), None).unwrap();
+        assert!(server.note_chat_signer("bob"));
+        assert!(!server.note_chat_signer("bob"));
+
+        let reloaded = Server::from_storage(server.to_storage(&device_key).unwrap(), &device_key).unwrap();
+        assert_eq!(reloaded.chat_signers, vec!["bob".to_string()]);
+    }
+
+    #[test]
+    fn leave_records_are_carried_until_the_key_rotates() {
+        let bob = SigningKey::from_bytes(&[2u8; 32]);
+        let mut server = Server::new("s".to_string(), "alice".to_string(), "Alice".to_string(), None).unwrap();
+        server.pending_leaves.push(SignedLeave::sign(&bob, &server.signing_pubkey));
+        assert_eq!(server.to_info().left_members.len(), 1);
+
+        let mut member_view = server.clone();
+        server.rotate_symmetric_key().unwrap();
+        assert!(server.to_info().left_members.is_empty());
+        member_view.adopt_symmetric_key(1, server.get_symmetric_key().unwrap());
+        assert!(member_view.to_info().left_members.is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+//! Server hints as the beacon stores them, and the bytes their signature covers.
+//!
+//! [`server_hint_message`] must match `server_hint_message` in beacon-server/src/signing.rs byte
+//! for byte, or the beacon refuses our hints; both crates pin it to the same test vector.
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedServerHint {
+    pub signing_pubkey: String,
+    pub encrypted_state: String,
+    pub signature: String,
+    pub last_updated: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer_pubkey: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delegate_pubkey: Option<String>,
+    /// Keys the server key authorizes to manage invites. Only the server key changes them; every
+    /// other hint must repeat the stored list.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub admin_pubkeys: Vec<String>,
+    /// Current server key sealed to each member after a rotation (only hints signed by the server
+    /// key set these, and that signature covers them; the beacon keeps them across delegate-signed hints).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_envelopes: Vec<SealedServerKey>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedServerKey {
+    pub user_id: String,
+    pub epoch: u32,
+    pub sealed_key: String,
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Canonical form of sealed keys: one "user_id:epoch:sealed_key" line each, in order.
+fn key_envelopes_canonical(envelopes: &[SealedServerKey]) -> String {
+    envelopes
+        .iter()
+        .map(|e| format!("{}:{}:{}", e.user_id, e.epoch, e.sealed_key))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Canonical bytes a hint signature covers:
+/// "cordia-server-hint\n" + signing_pubkey + "\n" + last_updated (unix millis) + "\n" + delegate_pubkey + "\n"
+/// + admin_pubkeys joined by "," + "\n" + sha256(encrypted_state).hex() + "\n" + sha256(key_envelopes).hex()
+///
+/// Delegate-signed hints cover no envelopes (an empty list), as the beacon replaces theirs.
+pub fn server_hint_message(hint: &EncryptedServerHint) -> Result<String, String> {
+    let last_updated = chrono::DateTime::parse_from_rfc3339(&hint.last_updated)
+        .map_err(|e| format!("Invalid hint last_updated: {}", e))?;
+    let by_delegate = hint.signer_pubkey.as_deref().is_some_and(|signer| signer != hint.signing_pubkey);
+    let envelopes = if by_delegate { &[] } else { hint.key_envelopes.as_slice() };
+    Ok(format!(
+        "cordia-server-hint\n{}\n{}\n{}\n{}\n{}\n{}",
+        hint.signing_pubkey,
+        last_updated.timestamp_millis(),
+        hint.delegate_pubkey.as_deref().unwrap_or(""),
+        hint.admin_pubkeys.join(","),
+        sha256_hex(hint.encrypted_state.as_bytes()),
+        sha256_hex(key_envelopes_canonical(envelopes).as_bytes()),
+    ))
+}
+
+/// Whether the hint carries a valid signature by the server key itself (not the delegate key,
+/// which every member can derive).
+pub fn signed_by_server_key(hint: &EncryptedServerHint) -> bool {
+    if hint.signer_pubkey.as_deref().is_some_and(|signer| signer != hint.signing_pubkey) {
+        return false;
+    }
+    let Ok(message) = server_hint_message(hint) else {
+        return false;
+    };
+    let Some(verifying_key) = base64::decode(&hint.signing_pubkey)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes.as_slice()).ok())
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+    else {
+        return false;
+    };
+    let Some(signature) = base64::decode(&hint.signature)
+        .ok()
+        .and_then(|bytes| <[u8; 64]>::try_from(bytes.as_slice()).ok())
+    else {
+        return false;
+    };
+    verifying_key.verify(message.as_bytes(), &Signature::from_bytes(&signature)).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    /// Same vector as beacon-server/src/signing.rs.
+    #[test]
+    fn server_hint_message_matches_the_beacon_format() {
+        let mut hint = EncryptedServerHint {
+            signing_pubkey: "spk".to_string(),
+            encrypted_state: "1.state".to_string(),
+            signature: String::new(),
+            last_updated: "2024-01-01T00:00:00.000Z".to_string(),
+            signer_pubkey: None,
+            delegate_pubkey: Some("delegate".to_string()),
+            admin_pubkeys: vec!["admin-a".to_string(), "admin-b".to_string()],
+            key_envelopes: vec![SealedServerKey { user_id: "bob".to_string(), epoch: 1, sealed_key: "sealed".to_string() }],
+        };
+        assert_eq!(
+            server_hint_message(&hint).unwrap(),
+            "cordia-server-hint\nspk\n1704067200000\ndelegate\nadmin-a,admin-b\n\
+             2938188fc771708152683740878ffe79790e726fd21e0c8fa04eeffdd1aedea1\n\
+             46884bafaac4b61842e64b1c88d4e616d896e102f2c75e8871b0905e8a81c466"
+        );
+        hint.signer_pubkey = Some("delegate".to_string());
+        assert!(server_hint_message(&hint)
+            .unwrap()
+            .ends_with("\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"));
+    }
+
+    #[test]
+    fn only_server_key_signatures_count_as_server_key_signed() {
+        let server_key = SigningKey::from_bytes(&[1u8; 32]);
+        let delegate = SigningKey::from_bytes(&[3u8; 32]);
+        let mut hint = EncryptedServerHint {
+            signing_pubkey: base64::encode(server_key.verifying_key().as_bytes()),
+            encrypted_state: "1.state".to_string(),
+            signature: String::new(),
+            last_updated: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            signer_pubkey: None,
+            delegate_pubkey: Some(base64::encode(delegate.verifying_key().as_bytes())),
+            admin_pubkeys: vec!["admin-a".to_string()],
+            key_envelopes: Vec::new(),
+        };
+        hint.signature = base64::encode(server_key.sign(server_hint_message(&hint).unwrap().as_bytes()).to_bytes());
+        assert!(signed_by_server_key(&hint));
+
+        let mut swapped = hint.clone();
+        swapped.admin_pubkeys = vec!["admin-b".to_string()];
+        assert!(!signed_by_server_key(&swapped));
+
+        let mut delegated = hint.clone();
+        delegated.signer_pubkey = delegated.delegate_pubkey.clone();
+        delegated.signature = base64::encode(delegate.sign(server_hint_message(&delegated).unwrap().as_bytes()).to_bytes());
+        assert!(!signed_by_server_key(&delegated));
+    }
+}
@@ -11,6 +11,8 @@ export interface MessageBubbleMessage {
   delivery_status?: 'pending' | 'delivered' | 'bundling'
   delivered_by?: string[]
   bundling_progress?: number
+  /** No identity-key signature: the sender shown is only what the beacon claimed. */
+  unverified?: boolean
 }
 
 export interface MessageBubbleProps {
@@ -58,6 +60,14 @@ export function MessageBubble({
           <span className="text-[10px] text-muted-foreground">{timeStr}</span>
         </div>
       ) : null}
+      {msg.unverified && (
+        <div
+          className="text-[10px] text-warning"
+          title="This message was not signed, so it may not be from the sender shown."
+        >
+          Unverified sender
+        </div>
+      )}
       <div className={cn(isFirstInGroup ? 'mt-0.5' : '')}>{children}</div>
       {showDeliveryStatus && (
         <div className="text-[10px] text-muted-foreground mt-0.5 flex items-baseline gap-0.5">
@@ -6,7 +6,8 @@ import { useVoicePresence } from '../contexts/VoicePresenceContext'
 import { useBeacon } from '../contexts/BeaconContext'
 import { useProfile } from '../contexts/ProfileContext'
 import { useRemoteProfiles } from '../contexts/RemoteProfilesContext'
-import { fetchAndImportServerHintOpaque, listServers, listFriends } from '../lib/tauri'
+import { fetchAndImportServerHintOpaque, listServers, listFriends, signBeaconAuthChallenge } from '../lib/tauri'
+import { beaconHello, CAP_FRIENDS_PRESENCE, CAP_SWARM, hasBeaconCapability } from '../lib/beacon-protocol'
 
 /**
  * Pull latest server metadata (members/chats) from the beacon after login.
@@ -35,6 +36,8 @@ export function ServerSyncBootstrap() {
   const subscribedSigningPubkeysRef = useRef<Set<string>>(new Set())
   const activeSigningPubkeyRef = useRef<string | null>(null)
   const pendingOutboundRef = useRef<string[]>([])
+  // Beacon session to resume after a drop (token rotates on every resume) and what it granted.
+  const resumeRef = useRef<{ token: string; capabilities: Set<string> | null } | null>(null)
   const profilePushRef = useRef({ profile, identity, accountInfoMap, currentAccountId })
   profilePushRef.current = { profile, identity, accountInfoMap, currentAccountId }
 
@@ -101,14 +104,19 @@ export function ServerSyncBootstrap() {
 
       const base = beaconUrl.replace(/\/$/, '')
       const wsUrl = base.endsWith('/ws') ? base : base + '/ws'
-      const ws = new WebSocket(wsUrl)
+      const resume = resumeRef.current
+      const ws = new WebSocket(resume ? `${wsUrl}?resume=${encodeURIComponent(resume.token)}` : wsUrl)
       wsRef.current = ws
       lastPongAtRef.current = Date.now()
       lastMessageAtRef.current = Date.now()
+      // Beacon only accepts presence/profile/chat traffic after AuthOk.
+      let authenticated = false
+      // Capabilities from the beacon's Welcome (null = older beacon without negotiation).
+      let grantedCapabilities: Set<string> | null = resume?.capabilities ?? null
 
       const sendOrQueue = (payload: unknown) => {
         const serialized = JSON.stringify(payload)
-        if (ws.readyState === WebSocket.OPEN) {
+        if (ws.readyState === WebSocket.OPEN && authenticated) {
           ws.send(serialized)
           return
         }
@@ -116,6 +124,8 @@ export function ServerSyncBootstrap() {
         if (pendingOutboundRef.current.length > 500) {
           pendingOutboundRef.current = pendingOutboundRef.current.slice(-500)
         }
+        // Open but not yet authenticated: the queue is flushed after AuthOk.
+        if (ws.readyState === WebSocket.OPEN) return
         if (!cancelled && beaconUrl) connectWs()
       }
 
@@ -126,7 +136,7 @@ export function ServerSyncBootstrap() {
         updated_at: string | null
       }) => {
         if (!identity?.user_id) return
-        if (ws.readyState !== WebSocket.OPEN) return
+        if (ws.readyState !== WebSocket.OPEN || !authenticated) return
         try {
           const servers = await listServers()
           const signingPubkeys = servers.map(s => s.signing_pubkey)
@@ -152,7 +162,7 @@ export function ServerSyncBootstrap() {
       }
 
       const sendProfileHello = async () => {
-        if (!ws || ws.readyState !== WebSocket.OPEN) return
+        if (!ws || ws.readyState !== WebSocket.OPEN || !authenticated) return
         try {
           const servers = await listServers()
           for (const s of servers) {
@@ -175,7 +185,7 @@ export function ServerSyncBootstrap() {
       const MAX_FRIEND_IDS = 100
 
       const sendProfileHelloForFriends = async () => {
-        if (!ws || ws.readyState !== WebSocket.OPEN) return
+        if (!ws || ws.readyState !== WebSocket.OPEN || !authenticated) return
         try {
           const friends = await listFriends()
           const userIds = Array.from(new Set(friends)).slice(0, MAX_FRIEND_IDS)
@@ -204,7 +214,7 @@ export function ServerSyncBootstrap() {
         },
         extraToUserIds?: string[]
       ) => {
-        if (!ws || ws.readyState !== WebSocket.OPEN) return
+        if (!ws || ws.readyState !== WebSocket.OPEN || !authenticated) return
         const { profile: p, identity: id, accountInfoMap: am, currentAccountId: cid } = profilePushRef.current
         if (!id?.user_id) return
         try {
@@ -238,7 +248,7 @@ export function ServerSyncBootstrap() {
 
       const sendPresenceHello = async (_fromLabel: string) => {
         if (!identity?.user_id) return
-        if (ws.readyState !== WebSocket.OPEN) return
+        if (ws.readyState !== WebSocket.OPEN || !authenticated) return
         try {
           const [servers, friends] = await Promise.all([listServers(), listFriends()])
           const signingPubkeys = servers.map(s => s.signing_pubkey)
@@ -259,7 +269,7 @@ export function ServerSyncBootstrap() {
       }
 
       const subscribeMissingServers = async () => {
-        if (ws.readyState !== WebSocket.OPEN) return
+        if (ws.readyState !== WebSocket.OPEN || !authenticated) return
         try {
           const servers = await listServers()
           const nextSet = new Set(subscribedSigningPubkeysRef.current)
@@ -286,8 +296,8 @@ export function ServerSyncBootstrap() {
         }
       }
 
-      ws.onopen = async () => {
-        reconnectAttemptRef.current = 0
+      // Runs after the beacon confirms our identity (AuthChallenge -> AuthResponse -> AuthOk).
+      const onAuthenticated = async () => {
         try {
           const servers = await listServers()
           const nextSet = new Set<string>()
@@ -305,23 +315,30 @@ export function ServerSyncBootstrap() {
           }
           subscribedSigningPubkeysRef.current = nextSet
           // Announce presence after subscriptions are set up
-          await sendPresenceHello('ws.onopen')
+          await sendPresenceHello('auth-ok')
           await sendProfileAnnounce()
           await sendProfileHello()
           await sendProfilePush()
 
-          // Flush any messages queued while reconnecting.
-          if (pendingOutboundRef.current.length > 0) {
-            const queued = pendingOutboundRef.current
-            pendingOutboundRef.current = []
-            for (const payload of queued) {
-              if (ws.readyState !== WebSocket.OPEN) break
-              ws.send(payload)
-            }
-          }
+          flushPendingOutbound()
         } catch (e) {
           console.warn('[ServerSyncBootstrap] Failed to subscribe servers over WS:', e)
         }
+      }
+
+      // Send messages queued while reconnecting.
+      const flushPendingOutbound = () => {
+        if (pendingOutboundRef.current.length === 0) return
+        const queued = pendingOutboundRef.current
+        pendingOutboundRef.current = []
+        for (const payload of queued) {
+          if (ws.readyState !== WebSocket.OPEN) break
+          ws.send(payload)
+        }
+      }
+
+      ws.onopen = async () => {
+        reconnectAttemptRef.current = 0
 
         // Heartbeat: keep idle WS alive and detect dead peers.
         if (heartbeatTimerRef.current != null) window.clearInterval(heartbeatTimerRef.current)
@@ -359,6 +376,41 @@ export function ServerSyncBootstrap() {
             lastPongAtRef.current = Date.now()
             return
           }
+          if (msg.type === 'SessionStarted') {
+            resumeRef.current = { token: msg.resume_token, capabilities: null }
+            grantedCapabilities = null
+            return
+          }
+          if (msg.type === 'Resumed') {
+            // Beacon kept our login, subscriptions and presence; nothing to re-register.
+            resumeRef.current = { token: msg.resume_token, capabilities: grantedCapabilities }
+            authenticated = true
+            flushPendingOutbound()
+            return
+          }
+          if (msg.type === 'Welcome') {
+            grantedCapabilities = new Set<string>(Array.isArray(msg.capabilities) ? msg.capabilities : [])
+            if (resumeRef.current) resumeRef.current.capabilities = grantedCapabilities
+            return
+          }
+          if (msg.type === 'AuthChallenge') {
+            // New session (first connect, or the previous one could not be resumed).
+            ws.send(beaconHello([CAP_SWARM, CAP_FRIENDS_PRESENCE]))
+            try {
+              const auth = await signBeaconAuthChallenge(msg.nonce)
+              if (ws.readyState === WebSocket.OPEN) {
+                ws.send(JSON.stringify({ type: 'AuthResponse', ...auth }))
+              }
+            } catch (e) {
+              console.warn('[ServerSyncBootstrap] Failed to answer auth challenge:', e)
+            }
+            return
+          }
+          if (msg.type === 'AuthOk') {
+            authenticated = true
+            await onAuthenticated()
+            return
+          }
           if (msg.type === 'ServerHintUpdated') {
             const signingPubkey: string = msg.signing_pubkey
 
@@ -440,6 +492,28 @@ export function ServerSyncBootstrap() {
             return
           }
 
+          if (
+            msg.type === 'EphemeralChatEdit' ||
+            msg.type === 'EphemeralChatDelete' ||
+            msg.type === 'EphemeralReactionAdd' ||
+            msg.type === 'EphemeralReactionRemove'
+          ) {
+            window.dispatchEvent(
+              new CustomEvent('cordia:ephemeral-envelope-incoming', {
+                detail: {
+                  type: String(msg.type),
+                  signing_pubkey: String(msg.signing_pubkey),
+                  chat_id: String(msg.chat_id),
+                  message_id: String(msg.message_id),
+                  from_user_id: String(msg.from_user_id),
+                  encrypted_payload: String(msg.encrypted_payload),
+                  sent_at: String(msg.sent_at ?? new Date().toISOString()),
+                },
+              })
+            )
+            return
+          }
+
           if (msg.type === 'EphemeralReceiptIncoming') {
             if (String(msg.receipt_type) !== 'delivered') {
               return
@@ -781,6 +855,37 @@ export function ServerSyncBootstrap() {
         })
       }
 
+      const onSendEphemeralEnvelope = (ev: Event) => {
+        const detail = (ev as CustomEvent<{
+          type?: string
+          signing_pubkey?: string
+          chat_id?: string
+          message_id?: string
+          encrypted_payload?: string
+        }>).detail
+        const type = detail?.type
+        const signing_pubkey = detail?.signing_pubkey?.trim()
+        const chat_id = detail?.chat_id?.trim()
+        const message_id = detail?.message_id?.trim()
+        const encrypted_payload = detail?.encrypted_payload?.trim()
+        if (!signing_pubkey || !chat_id || !message_id || !encrypted_payload) return
+        if (
+          type !== 'EphemeralChatEdit' &&
+          type !== 'EphemeralChatDelete' &&
+          type !== 'EphemeralReactionAdd' &&
+          type !== 'EphemeralReactionRemove'
+        ) {
+          return
+        }
+        sendOrQueue({
+          type,
+          signing_pubkey,
+          chat_id,
+          message_id,
+          encrypted_payload,
+        })
+      }
+
       const onSendFriendMutualCheck = (ev: Event) => {
         const detail = (ev as CustomEvent<{ to_user_id?: string }>).detail
         const to_user_id = detail?.to_user_id?.trim()
@@ -856,6 +961,7 @@ export function ServerSyncBootstrap() {
         const sha256 = detail?.sha256?.trim()
         const piece_count = Number(detail?.piece_count ?? 0)
         if (!signing_pubkey || !sha256 || !Number.isFinite(piece_count) || piece_count <= 0) return
+        if (!hasBeaconCapability(grantedCapabilities, CAP_SWARM)) return
         sendOrQueue({
           type: 'SwarmAnnounce',
           signing_pubkey,
@@ -884,6 +990,7 @@ export function ServerSyncBootstrap() {
         const signing_pubkey = detail?.signing_pubkey?.trim()
         const sha256 = detail?.sha256?.trim()
         if (!signing_pubkey || !sha256) return
+        if (!hasBeaconCapability(grantedCapabilities, CAP_SWARM)) return
         sendOrQueue({
           type: 'SwarmPeerListRequest',
           signing_pubkey,
@@ -903,6 +1010,7 @@ export function ServerSyncBootstrap() {
         const signing_pubkey = detail?.signing_pubkey?.trim()
         const sha256 = detail?.sha256?.trim()
         if (!signing_pubkey || !sha256) return
+        if (!hasBeaconCapability(grantedCapabilities, CAP_SWARM)) return
         sendOrQueue({
           type: 'SwarmHealthUpdate',
           signing_pubkey,
@@ -950,6 +1058,7 @@ export function ServerSyncBootstrap() {
       window.addEventListener('cordia:active-server-changed', onActiveServerChanged as any)
       window.addEventListener('cordia:send-ephemeral-chat', onSendEphemeralChat as EventListener)
       window.addEventListener('cordia:send-ephemeral-receipt', onSendEphemeralReceipt as EventListener)
+      window.addEventListener('cordia:send-ephemeral-envelope', onSendEphemeralEnvelope as EventListener)
       window.addEventListener('cordia:send-friend-mutual-check', onSendFriendMutualCheck as EventListener)
       window.addEventListener('cordia:send-friend-mutual-reply', onSendFriendMutualReply as EventListener)
       window.addEventListener('cordia:send-attachment-transfer-request', onSendAttachmentTransferRequest as EventListener)
@@ -969,6 +1078,7 @@ export function ServerSyncBootstrap() {
         window.removeEventListener('cordia:active-server-changed', onActiveServerChanged as any)
         window.removeEventListener('cordia:send-ephemeral-chat', onSendEphemeralChat as EventListener)
         window.removeEventListener('cordia:send-ephemeral-receipt', onSendEphemeralReceipt as EventListener)
+        window.removeEventListener('cordia:send-ephemeral-envelope', onSendEphemeralEnvelope as EventListener)
         window.removeEventListener('cordia:send-friend-mutual-check', onSendFriendMutualCheck as EventListener)
         window.removeEventListener('cordia:send-friend-mutual-reply', onSendFriendMutualReply as EventListener)
         window.removeEventListener('cordia:send-attachment-transfer-request', onSendAttachmentTransferRequest as EventListener)
@@ -988,6 +1098,7 @@ export function ServerSyncBootstrap() {
     return () => {
       cancelled = true
       pendingOutboundRef.current = []
+      resumeRef.current = null
       if (reconnectTimerRef.current != null) {
         window.clearTimeout(reconnectTimerRef.current)
         reconnectTimerRef.current = null
@@ -1001,7 +1112,8 @@ export function ServerSyncBootstrap() {
         watchdogTimerRef.current = null
       }
       if (wsRef.current) {
-        wsRef.current.close()
+        // Normal closure: the beacon drops the session now instead of holding it for resume.
+        wsRef.current.close(1000)
         wsRef.current = null
       }
     }
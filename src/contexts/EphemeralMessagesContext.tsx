@@ -11,8 +11,12 @@ import {
   type SharedAttachmentItem,
   unshareAttachment,
   decryptEphemeralChatMessageBySigningPubkey,
+  decryptVerifiedChatEnvelope,
   encryptEphemeralChatMessage,
   encryptEphemeralChatMessageBySigningPubkey,
+  encryptSignedChatEnvelope,
+  type ChatEnvelope,
+  type ChatEnvelopeKind,
 } from '../lib/tauri'
 import { isSwarmTransfersEnabled } from '../lib/featureFlags'
 import { SwarmCoordinator } from '../lib/swarm/swarmCoordinator'
@@ -79,6 +83,14 @@ export interface EphemeralChatMessage {
   bundling_progress?: number
   /** Cached encrypted payload for background retries. Sender-only. */
   encrypted_payload?: string
+  /** Payload carried no identity-key signature (older client): the sender is only the beacon's claim. */
+  unverified?: boolean
+  /** Set when the author edited the text (signed_at of the edit). */
+  edited_at?: string
+  /** emoji -> user_ids that reacted with it. */
+  reactions?: Record<string, string[]>
+  /** `${user_id}\n${emoji}` -> signed_at of the last applied reaction add/remove. */
+  reaction_signed_at?: Record<string, string>
 }
 
 interface SendEphemeralChatInput {
@@ -129,6 +141,16 @@ interface IncomingEphemeralChatDetail {
   sent_at: string
 }
 
+interface IncomingEphemeralEnvelopeDetail {
+  type: 'EphemeralChatEdit' | 'EphemeralChatDelete' | 'EphemeralReactionAdd' | 'EphemeralReactionRemove'
+  signing_pubkey: string
+  chat_id: string
+  message_id: string
+  from_user_id: string
+  encrypted_payload: string
+  sent_at: string
+}
+
 interface IncomingEphemeralReceiptDetail {
   signing_pubkey: string
   chat_id: string
@@ -228,6 +250,12 @@ interface EphemeralMessagesContextType {
   ) => void
   /** Scan loaded chat buckets for a message id (e.g. transfer history → original sent_at). */
   findMessageById: (messageId: string) => EphemeralChatMessage | undefined
+  /** Replace the text of one of our own messages (signed edit). */
+  editMessage: (msg: EphemeralChatMessage, text: string) => Promise<void>
+  /** Delete one of our own messages for everyone (signed delete). */
+  deleteMessage: (msg: EphemeralChatMessage) => Promise<void>
+  /** Add or remove our reaction to a message. */
+  setReaction: (msg: EphemeralChatMessage, emoji: string, add: boolean) => Promise<void>
 }
 
 export interface AttachmentTransferState {
@@ -288,6 +316,62 @@ function bucketKey(signingPubkey: string, chatId: string): string {
   return `${signingPubkey}::${chatId}`
 }
 
+const ENVELOPE_TYPE_BY_KIND: Record<ChatEnvelopeKind, IncomingEphemeralEnvelopeDetail['type']> = {
+  edit: 'EphemeralChatEdit',
+  delete: 'EphemeralChatDelete',
+  reaction_add: 'EphemeralReactionAdd',
+  reaction_remove: 'EphemeralReactionRemove',
+}
+
+/** True when `signedAt` is strictly later than the last applied envelope (or none was applied). */
+function isNewerEnvelope(signedAt: string, lastApplied: string | undefined): boolean {
+  if (!lastApplied) return true
+  const next = Date.parse(signedAt)
+  const last = Date.parse(lastApplied)
+  return !Number.isNaN(next) && (Number.isNaN(last) || next > last)
+}
+
+/**
+ * Apply a verified edit / delete / reaction to the message it references (no-op if not loaded).
+ * Edits and reactions older than the last applied one are ignored, so a replayed envelope cannot
+ * roll a message back.
+ */
+function applyChatEnvelope(
+  prev: MessageBuckets,
+  envelope: Pick<ChatEnvelope, 'kind' | 'signing_pubkey' | 'chat_id' | 'message_id' | 'body' | 'author_user_id' | 'signed_at'>
+): MessageBuckets {
+  const key = bucketKey(envelope.signing_pubkey, envelope.chat_id)
+  const list = prev[key]
+  if (!list || !list.some((m) => m.id === envelope.message_id)) return prev
+  if (envelope.kind === 'delete') {
+    return { ...prev, [key]: list.filter((m) => m.id !== envelope.message_id) }
+  }
+  return {
+    ...prev,
+    [key]: list.map((m) => {
+      if (m.id !== envelope.message_id) return m
+      if (envelope.kind === 'edit') {
+        // Attachment-only messages show the file name as text; there is nothing to edit.
+        if (m.kind === 'attachment' || !envelope.body.trim()) return m
+        if (!isNewerEnvelope(envelope.signed_at, m.edited_at)) return m
+        return { ...m, text: envelope.body.trim(), edited_at: envelope.signed_at }
+      }
+      const slot = `${envelope.author_user_id}\n${envelope.body}`
+      if (!isNewerEnvelope(envelope.signed_at, m.reaction_signed_at?.[slot])) return m
+      const reaction_signed_at = { ...(m.reaction_signed_at ?? {}), [slot]: envelope.signed_at }
+      const reactions = { ...(m.reactions ?? {}) }
+      const users = (reactions[envelope.body] ?? []).filter((u) => u !== envelope.author_user_id)
+      if (envelope.kind === 'reaction_add') users.push(envelope.author_user_id)
+      if (users.length > 0) {
+        reactions[envelope.body] = users
+      } else {
+        delete reactions[envelope.body]
+      }
+      return { ...m, reactions, reaction_signed_at }
+    }),
+  }
+}
+
 function appendMessage(
   prev: MessageBuckets,
   signingPubkey: string,
@@ -1665,11 +1749,17 @@ export function EphemeralMessagesProvider({ children }: { children: ReactNode })
       if (!detail?.signing_pubkey || !detail.chat_id || !detail.encrypted_payload || !detail.message_id) return
 
       try {
-        const plaintext = await decryptEphemeralChatMessageBySigningPubkey(
+        // Rejects payloads signed by someone other than the sender the beacon reported, and unsigned
+        // ones when that sender is known to sign; the rest are shown as unverified.
+        const { plaintext, verified_author_user_id } = await decryptEphemeralChatMessageBySigningPubkey(
           detail.signing_pubkey,
-          detail.encrypted_payload
+          detail.chat_id,
+          detail.message_id,
+          detail.encrypted_payload,
+          detail.from_user_id
         )
         if (cancelled) return
+        const unverified = verified_author_user_id ? undefined : true
         const parsed = JSON.parse(plaintext) as Partial<EphemeralPayload> & Record<string, any>
         const payloadSentAt = typeof parsed.sent_at === 'string' ? parsed.sent_at : ''
         const effectiveSentAt = Number.isFinite(Date.parse(payloadSentAt))
@@ -1702,6 +1792,7 @@ export function EphemeralMessagesProvider({ children }: { children: ReactNode })
             kind: 'mixed',
             attachments,
             sent_at: effectiveSentAt,
+            unverified,
           }
         } else if (parsed.kind === 'attachment' && parsed.attachment) {
           const text = (parsed.attachment.file_name ?? '').trim()
@@ -1715,6 +1806,7 @@ export function EphemeralMessagesProvider({ children }: { children: ReactNode })
             kind: 'attachment',
             attachment: parsed.attachment,
             sent_at: effectiveSentAt,
+            unverified,
           }
         } else {
           const text = (parsed.text ?? '').trim()
@@ -1727,6 +1819,7 @@ export function EphemeralMessagesProvider({ children }: { children: ReactNode })
             text,
             kind: 'text',
             sent_at: effectiveSentAt,
+            unverified,
           }
         }
         setMessagesByBucket((prev) => {
@@ -1787,7 +1880,7 @@ export function EphemeralMessagesProvider({ children }: { children: ReactNode })
               
               if (usedBytes + estimate > maxBudgetBytes) continue
               try {
-                encrypted_payload = await encryptEphemeralChatMessageBySigningPubkey(m.signing_pubkey, payload)
+                encrypted_payload = await encryptEphemeralChatMessageBySigningPubkey(m.signing_pubkey, m.chat_id, m.id, payload)
                 m.encrypted_payload = encrypted_payload
               } catch {
                 continue // Ignore encrypt errors; keep pending for next retry tick.
@@ -1804,7 +1897,7 @@ export function EphemeralMessagesProvider({ children }: { children: ReactNode })
                     ? JSON.stringify({ kind: 'attachment', attachment: m.attachment, sent_at: m.sent_at } satisfies EphemeralPayload)
                     : JSON.stringify({ kind: 'text', text: m.text, sent_at: m.sent_at } satisfies EphemeralPayload)
               try {
-                encrypted_payload = await encryptEphemeralChatMessageBySigningPubkey(m.signing_pubkey, payload)
+                encrypted_payload = await encryptEphemeralChatMessageBySigningPubkey(m.signing_pubkey, m.chat_id, m.id, payload)
                 m.encrypted_payload = encrypted_payload
               } catch {
                 continue
@@ -1844,6 +1937,39 @@ export function EphemeralMessagesProvider({ children }: { children: ReactNode })
     }
   }, [messagesByBucket, identity?.user_id, settingsBySigningPubkey])
 
+  // Edits and deletes apply only when signed by the original sender, reactions only when signed by
+  // the reacting user; the beacon's from_user_id alone is not trusted for this.
+  useEffect(() => {
+    const onEnvelope = async (e: Event) => {
+      const detail = (e as CustomEvent<IncomingEphemeralEnvelopeDetail>).detail
+      if (!detail?.signing_pubkey || !detail?.chat_id || !detail?.message_id || !detail?.encrypted_payload) return
+      const original = (messagesByBucketRef.current[bucketKey(detail.signing_pubkey, detail.chat_id)] ?? []).find(
+        (m) => m.id === detail.message_id
+      )
+      if (!original) return
+      const isReaction = detail.type === 'EphemeralReactionAdd' || detail.type === 'EphemeralReactionRemove'
+      const expectedAuthor = isReaction ? detail.from_user_id : original.from_user_id
+      try {
+        const envelope = await decryptVerifiedChatEnvelope(
+          detail.signing_pubkey,
+          detail.chat_id,
+          detail.message_id,
+          detail.encrypted_payload,
+          expectedAuthor
+        )
+        if (ENVELOPE_TYPE_BY_KIND[envelope.kind] !== detail.type) return
+        setMessagesByBucket((prev) => applyChatEnvelope(prev, envelope))
+      } catch {
+        // Not signed by the expected author, or not decryptable: ignore.
+      }
+    }
+
+    window.addEventListener('cordia:ephemeral-envelope-incoming', onEnvelope as EventListener)
+    return () => {
+      window.removeEventListener('cordia:ephemeral-envelope-incoming', onEnvelope as EventListener)
+    }
+  }, [])
+
   useEffect(() => {
     const onReceipt = (e: Event) => {
       const detail = (e as CustomEvent<IncomingEphemeralReceiptDetail>).detail
@@ -2246,8 +2372,8 @@ export function EphemeralMessagesProvider({ children }: { children: ReactNode })
 
     const sentAt = new Date().toISOString()
     const payload = JSON.stringify({ kind: 'text', text: trimmed, sent_at: sentAt } satisfies EphemeralPayload)
-    const encrypted_payload = await encryptEphemeralChatMessage(serverId, payload)
     const messageId = `${fromUserId}:${Date.now()}:${Math.random().toString(36).slice(2)}`
+    const encrypted_payload = await encryptEphemeralChatMessage(serverId, chatId, messageId, payload)
 
     window.dispatchEvent(
       new CustomEvent('cordia:send-ephemeral-chat', {
@@ -2280,6 +2406,50 @@ export function EphemeralMessagesProvider({ children }: { children: ReactNode })
     ensureBucketLoaded(signingPubkey, chatId)
   }
 
+  const sendChatEnvelope = async (msg: EphemeralChatMessage, kind: ChatEnvelopeKind, body: string) => {
+    if (!identity?.user_id) return
+    const encrypted_payload = await encryptSignedChatEnvelope(msg.signing_pubkey, msg.chat_id, msg.id, kind, body)
+    window.dispatchEvent(
+      new CustomEvent('cordia:send-ephemeral-envelope', {
+        detail: {
+          type: ENVELOPE_TYPE_BY_KIND[kind],
+          signing_pubkey: msg.signing_pubkey,
+          chat_id: msg.chat_id,
+          message_id: msg.id,
+          encrypted_payload,
+        },
+      })
+    )
+    // Sender is excluded from relay broadcast; apply locally.
+    const local = {
+      kind,
+      signing_pubkey: msg.signing_pubkey,
+      chat_id: msg.chat_id,
+      message_id: msg.id,
+      body,
+      author_user_id: identity.user_id,
+      signed_at: new Date().toISOString(),
+    }
+    setMessagesByBucket((prev) => applyChatEnvelope(prev, local))
+  }
+
+  const editMessage: EphemeralMessagesContextType['editMessage'] = async (msg, text) => {
+    const trimmed = text.trim()
+    if (!trimmed || msg.from_user_id !== identity?.user_id) return
+    await sendChatEnvelope(msg, 'edit', trimmed)
+  }
+
+  const deleteMessage: EphemeralMessagesContextType['deleteMessage'] = async (msg) => {
+    if (msg.from_user_id !== identity?.user_id) return
+    await sendChatEnvelope(msg, 'delete', '')
+  }
+
+  const setReaction: EphemeralMessagesContextType['setReaction'] = async (msg, emoji, add) => {
+    const trimmed = emoji.trim()
+    if (!trimmed) return
+    await sendChatEnvelope(msg, add ? 'reaction_add' : 'reaction_remove', trimmed)
+  }
+
   const sendAttachmentMessage: EphemeralMessagesContextType['sendAttachmentMessage'] = async ({
     serverId,
     signingPubkey,
@@ -2290,8 +2460,8 @@ export function EphemeralMessagesProvider({ children }: { children: ReactNode })
     if (!attachment?.attachment_id) return
     const sentAt = new Date().toISOString()
     const payload = JSON.stringify({ kind: 'attachment', attachment, sent_at: sentAt } satisfies EphemeralPayload)
-    const encrypted_payload = await encryptEphemeralChatMessage(serverId, payload)
     const messageId = `${fromUserId}:${Date.now()}:${Math.random().toString(36).slice(2)}`
+    const encrypted_payload = await encryptEphemeralChatMessage(serverId, chatId, messageId, payload)
     window.dispatchEvent(
       new CustomEvent('cordia:send-ephemeral-chat', {
         detail: {
@@ -2445,8 +2615,8 @@ export function EphemeralMessagesProvider({ children }: { children: ReactNode })
       text: text?.trim() || undefined,
       sent_at: sentAt,
     } satisfies EphemeralPayload)
-    const encrypted_payload = await encryptEphemeralChatMessage(serverId, payload)
     const messageId = replaceMessageId ?? `${fromUserId}:${Date.now()}:${Math.random().toString(36).slice(2)}`
+    const encrypted_payload = await encryptEphemeralChatMessage(serverId, chatId, messageId, payload)
     window.dispatchEvent(
       new CustomEvent('cordia:send-ephemeral-chat', {
         detail: {
@@ -2716,8 +2886,13 @@ export function EphemeralMessagesProvider({ children }: { children: ReactNode })
       attachment_id: attachmentId.trim(),
       sent_at: new Date().toISOString(),
     }
-    const encrypted_payload = await encryptEphemeralChatMessageBySigningPubkey(signingPubkey, JSON.stringify(payload))
     const message_id = `reshared:${attachmentId}:${Date.now()}:${Math.random().toString(36).slice(2)}`
+    const encrypted_payload = await encryptEphemeralChatMessageBySigningPubkey(
+      signingPubkey,
+      chatId.trim(),
+      message_id,
+      JSON.stringify(payload)
+    )
     window.dispatchEvent(
       new CustomEvent('cordia:send-ephemeral-chat', {
         detail: { signing_pubkey: signingPubkey.trim(), chat_id: chatId.trim(), message_id, encrypted_payload },
@@ -2767,6 +2942,9 @@ export function EphemeralMessagesProvider({ children }: { children: ReactNode })
       getCachedPathForSha,
       updateAttachmentAspect,
       findMessageById,
+      editMessage,
+      deleteMessage,
+      setReaction,
     }),
     [
       messagesByBucket,
@@ -26,7 +26,8 @@ import { useProfile } from './ProfileContext'
 import { useAccount } from './AccountContext'
 import { useRemoteProfiles } from './RemoteProfilesContext'
 import { RemoteAudioAnalyzer } from '../lib/remoteAudioAnalyzer'
-import { loadAudioSettings } from '../lib/tauri'
+import { loadAudioSettings, signBeaconAuthChallenge } from '../lib/tauri'
+import { beaconHello, type BeaconError } from '../lib/beacon-protocol'
 
 /**
  * WebRTC Context for peer-to-peer voice communication.
@@ -45,6 +46,8 @@ import { loadAudioSettings } from '../lib/tauri'
 const SIGNALING_KEEPALIVE_INTERVAL_MS = 25000
 // Let ICE pool warm before first offer (improves candidate selection; 100–200ms typical)
 const ICE_OFFER_WARMUP_MS = 100
+// Correlates the beacon's Error with our VoiceRegister (the beacon echoes request_id)
+const VOICE_REGISTER_REQUEST_ID = 'voice-register'
 
 export type PeerConnectionState = RTCPeerConnectionState
 
@@ -117,6 +120,8 @@ export function WebRTCProvider({ children }: { children: ReactNode }) {
   // Refs
   const inputLevelMeterRef = useRef<InputLevelMeter | null>(null)
   const wsRef = useRef<WebSocket | null>(null)
+  // Beacon resume token: a signaling reconnect reattaches to the same voice registration.
+  const resumeTokenRef = useRef<string | null>(null)
   const localStreamRef = useRef<MediaStream | null>(null)
   const currentRoomRef = useRef<string | null>(null)
   const currentHouseRef = useRef<string | null>(null)
@@ -1057,7 +1062,12 @@ export function WebRTCProvider({ children }: { children: ReactNode }) {
       }
 
       case 'Error': {
-        console.error('[Signal] Signaling error:', msg.message)
+        const err = msg as BeaconError
+        if (err.request_id === VOICE_REGISTER_REQUEST_ID) {
+          console.error(`[Signal] VoiceRegister rejected (${err.code ?? 'unknown'}):`, err.message)
+        } else {
+          console.error(`[Signal] Signaling error (${err.code ?? 'unknown'}):`, err.message)
+        }
         break
       }
 
@@ -1117,7 +1127,8 @@ export function WebRTCProvider({ children }: { children: ReactNode }) {
     console.log('[Signal] Connecting to signaling server...')
     const base = beaconUrl.replace(/\/$/, '')
     const wsUrl = base.endsWith('/ws') ? base : base + '/ws'
-    const ws = new WebSocket(wsUrl)
+    const resumeToken = resumeTokenRef.current
+    const ws = new WebSocket(resumeToken ? `${wsUrl}?resume=${encodeURIComponent(resumeToken)}` : wsUrl)
     wsRef.current = ws
 
     ws.onopen = () => {
@@ -1126,21 +1137,48 @@ export function WebRTCProvider({ children }: { children: ReactNode }) {
 
       // Start keepalive to prevent idle disconnect
       startKeepalive()
-
-      // Register for voice in the chat (beacon expects server_id and chat_id)
-      const registerMessage = {
-        type: 'VoiceRegister',
-        server_id: currentHouseRef.current,
-        chat_id: currentRoomRef.current,
-        peer_id: currentPeerIdRef.current,
-        user_id: currentUserIdRef.current,
-        signing_pubkey: currentSigningPubkeyRef.current
-      }
-      ws.send(JSON.stringify(registerMessage))
-      console.log(`[Signal] Sent VoiceRegister: peer=${currentPeerIdRef.current}`)
+      // VoiceRegister is sent once the beacon confirms our identity (AuthChallenge -> AuthOk).
     }
 
-    ws.onmessage = (event) => {
+    ws.onmessage = async (event) => {
+      let msg: { type?: string; nonce?: string; resume_token?: string } | null = null
+      try {
+        msg = JSON.parse(event.data)
+      } catch {
+        // fall through to the generic handler
+      }
+      if ((msg?.type === 'SessionStarted' || msg?.type === 'Resumed') && msg.resume_token) {
+        resumeTokenRef.current = msg.resume_token
+        // Resumed: the beacon kept our VoiceRegister, so other members saw no leave/join.
+        if (msg.type === 'Resumed') console.log('[Signal] Resumed signaling session')
+        return
+      }
+      if (msg?.type === 'AuthChallenge' && msg.nonce) {
+        // New session. Voice-only connection: no swarm or friend presence traffic.
+        ws.send(beaconHello([]))
+        try {
+          const auth = await signBeaconAuthChallenge(msg.nonce)
+          ws.send(JSON.stringify({ type: 'AuthResponse', ...auth }))
+        } catch (e) {
+          console.error('[Signal] Failed to answer auth challenge:', e)
+        }
+        return
+      }
+      if (msg?.type === 'AuthOk') {
+        // Register for voice in the chat (beacon expects server_id and chat_id)
+        const registerMessage = {
+          type: 'VoiceRegister',
+          request_id: VOICE_REGISTER_REQUEST_ID,
+          server_id: currentHouseRef.current,
+          chat_id: currentRoomRef.current,
+          peer_id: currentPeerIdRef.current,
+          user_id: currentUserIdRef.current,
+          signing_pubkey: currentSigningPubkeyRef.current
+        }
+        ws.send(JSON.stringify(registerMessage))
+        console.log(`[Signal] Sent VoiceRegister: peer=${currentPeerIdRef.current}`)
+        return
+      }
       handleSignalingMessage(event.data)
     }
 
@@ -1295,9 +1333,10 @@ export function WebRTCProvider({ children }: { children: ReactNode }) {
     // 4. Close WebSocket (SIGNALING teardown)
     if (wsRef.current) {
       wsRef.current.onclose = null  // Prevent reconnect handler from firing
-      wsRef.current.close()
+      wsRef.current.close(1000)  // Normal closure: beacon cleans up now instead of holding the session
       wsRef.current = null
     }
+    resumeTokenRef.current = null
     signalingConnectedRef.current = false
 
     // 5. Clear local stream reference
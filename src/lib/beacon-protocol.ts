@@ -0,0 +1,63 @@
+/**
+ * Beacon WebSocket handshake: the client sends Hello with its protocol version and the
+ * capabilities it understands; the beacon answers Welcome with what it granted.
+ * Beacons that predate negotiation reply with an Error, and everything keeps working as before.
+ * Must match beacon-server/src/state/protocol.rs.
+ */
+
+export const BEACON_PROTOCOL_VERSION = 2
+
+export const CAP_SWARM = 'swarm'
+export const CAP_FRIENDS_PRESENCE = 'friends_presence'
+/**
+ * Opt-in MessagePack frames (beacon-server/src/wire.rs): once requested in Hello, the beacon sends
+ * binary frames from Welcome on, with `encrypted_payload` / `encrypted_state` as raw bytes, and
+ * accepts binary frames in the same format. JSON text frames keep working either way.
+ */
+export const CAP_BINARY_FRAMES = 'binary_frames'
+/**
+ * Chat-scoped delivery: after ChatSubscribe { signing_pubkey, chat_ids }, ephemeral messages and
+ * receipts arrive only for followed chats; other chats of the server send ChatActivity (no payload).
+ */
+export const CAP_CHAT_SUBSCRIPTIONS = 'chat_subscriptions'
+/**
+ * Peer history sync: HistorySyncRequest { sync_id, signing_pubkey, chat_id, direction, cursor, limit }
+ * is relayed to a few online members; their HistorySyncResponse chunks (encrypted) come back,
+ * then HistorySyncDone. Ask for it only if this client also answers other members' requests.
+ */
+export const CAP_HISTORY_SYNC = 'history_sync'
+
+export function beaconHello(capabilities: string[]): string {
+  return JSON.stringify({ type: 'Hello', protocol_version: BEACON_PROTOCOL_VERSION, capabilities })
+}
+
+/**
+ * Capabilities granted by the beacon's Welcome, or null if it never sent one (older beacon:
+ * assume every protocol 1 feature is available).
+ */
+export function hasBeaconCapability(granted: Set<string> | null, capability: string): boolean {
+  return granted == null || granted.has(capability)
+}
+
+/** Stable `code` on the beacon's Error message. Must match ErrorCode in beacon-server/src/main.rs. */
+export type BeaconErrorCode =
+  | 'invalid_message'
+  | 'unsupported'
+  | 'rate_limited'
+  | 'unauthorized'
+  | 'not_registered'
+  | 'invalid_peer'
+  | 'payload_too_large'
+  | 'internal'
+
+/**
+ * Error from the beacon. Any client message may carry a `request_id`; the beacon echoes it here
+ * and on direct replies. AttachmentTransfer* messages are the exception: their `request_id` names
+ * the transfer and is never echoed. `code` is missing on beacons older than error codes.
+ */
+export interface BeaconError {
+  type: 'Error'
+  code?: BeaconErrorCode
+  message: string
+  request_id?: string
+}
@@ -1,5 +1,7 @@
 // Event synchronization manager for polling server events from beacon
 
+import { getServerMemberAuthHeaders, loadIdentity } from './tauri'
+
 export interface ServerEvent {
   event_id: string
   signing_pubkey: string
@@ -7,6 +9,14 @@ export interface ServerEvent {
   encrypted_payload: string  // Beacon cannot decrypt
   signature: string  // Signed by member's Ed25519 key
   timestamp: string
+  seq: number  // Beacon-assigned, strictly increasing per server
+}
+
+/** Body of the beacon's 410 when events after our cursor were garbage-collected. */
+interface CursorTooOld {
+  error: 'cursor_too_old'
+  oldest_seq: number
+  latest_seq: number
 }
 
 export interface EncryptedServerHint {
@@ -19,16 +29,19 @@ export interface EncryptedServerHint {
 export class EventSyncManager {
   private pollingInterval: number = 5000 // 5 seconds
   private activePolls = new Map<string, ReturnType<typeof setInterval>>()
-  private lastEventIds = new Map<string, string>()
+  private lastSeqs = new Map<string, number>()
   private eventHandlers = new Map<string, (events: ServerEvent[]) => void>()
+  private resyncHandlers = new Map<string, () => void>()
 
   /**
-   * Start polling events for a server
+   * Start polling events for a server. onResync runs when events were missed (the beacon already
+   * dropped them): the caller should reload the server from its hint.
    */
   async startPolling(
     signingPubkey: string,
     signalingServer: string,
-    onEvents?: (events: ServerEvent[]) => void
+    onEvents?: (events: ServerEvent[]) => void,
+    onResync?: () => void
   ) {
     if (this.activePolls.has(signingPubkey)) {
       console.log(`Already polling for server ${signingPubkey.slice(0, 8)}...`)
@@ -38,11 +51,21 @@ export class EventSyncManager {
     if (onEvents) {
       this.eventHandlers.set(signingPubkey, onEvents)
     }
+    if (onResync) {
+      this.resyncHandlers.set(signingPubkey, onResync)
+    }
 
     const poll = async () => {
       try {
-        const lastEventId = this.lastEventIds.get(signingPubkey)
-        const events = await this.fetchEvents(signalingServer, signingPubkey, lastEventId)
+        const lastSeq = this.lastSeqs.get(signingPubkey) ?? 0
+        const result = await this.fetchEvents(signalingServer, signingPubkey, lastSeq)
+        if (!Array.isArray(result)) {
+          console.warn(`Missed events for server ${signingPubkey.slice(0, 8)}...; resyncing from hint`)
+          this.lastSeqs.set(signingPubkey, result.latest_seq)
+          this.resyncHandlers.get(signingPubkey)?.()
+          return
+        }
+        const events = result
 
         if (events.length > 0) {
           console.log(`Received ${events.length} events for server ${signingPubkey.slice(0, 8)}...`)
@@ -53,15 +76,15 @@ export class EventSyncManager {
             handler(events)
           }
 
-          // Update last event ID
+          // Advance the cursor
           const lastEvent = events[events.length - 1]
-          this.lastEventIds.set(signingPubkey, lastEvent.event_id)
+          this.lastSeqs.set(signingPubkey, lastEvent.seq)
 
           // Acknowledge events (best-effort)
           await this.acknowledgeEvents(
             signalingServer,
             signingPubkey,
-            lastEvent.event_id
+            lastEvent
           ).catch(e => console.warn('Failed to ack events:', e))
         }
       } catch (error) {
@@ -88,6 +111,7 @@ export class EventSyncManager {
       clearInterval(intervalId)
       this.activePolls.delete(signingPubkey)
       this.eventHandlers.delete(signingPubkey)
+      this.resyncHandlers.delete(signingPubkey)
       console.log(`Stopped polling for server ${signingPubkey.slice(0, 8)}...`)
     }
   }
@@ -101,24 +125,27 @@ export class EventSyncManager {
     }
     this.activePolls.clear()
     this.eventHandlers.clear()
+    this.resyncHandlers.clear()
   }
 
   /**
-   * Fetch events from signaling server
+   * Fetch events after a seq from signaling server (one page), or the beacon's cursor-too-old answer
    */
   private async fetchEvents(
     signalingServer: string,
     signingPubkey: string,
-    sinceEventId?: string
-  ): Promise<ServerEvent[]> {
+    afterSeq: number
+  ): Promise<ServerEvent[] | CursorTooOld> {
     // Normalize beacon URL
     const baseUrl = this.normalizeServerUrl(signalingServer)
     const url = new URL(`${baseUrl}/api/servers/${encodeURIComponent(signingPubkey)}/events`)
-    if (sinceEventId) {
-      url.searchParams.set('since', sinceEventId)
-    }
+    url.searchParams.set('after_seq', String(afterSeq))
 
-    const response = await fetch(url.toString())
+    const headers = await getServerMemberAuthHeaders(signingPubkey, 'GET', url.pathname)
+    const response = await fetch(url.toString(), { headers })
+    if (response.status === 410) {
+      return response.json()
+    }
     if (!response.ok) {
       throw new Error(`Failed to fetch events: ${response.status} ${response.statusText}`)
     }
@@ -127,26 +154,29 @@ export class EventSyncManager {
   }
 
   /**
-   * Acknowledge events (best-effort)
+   * Acknowledge events (best-effort). Once every member acked an event the beacon drops it.
    */
   private async acknowledgeEvents(
     signalingServer: string,
     signingPubkey: string,
-    lastEventId: string
+    lastEvent: ServerEvent
   ): Promise<void> {
     const baseUrl = this.normalizeServerUrl(signalingServer)
-    const url = `${baseUrl}/api/servers/${encodeURIComponent(signingPubkey)}/events/ack`
-
-    // Get current user ID from somewhere (this would need to be passed in)
-    const userId = 'unknown' // TODO: Get from context
+    const path = `/api/servers/${encodeURIComponent(signingPubkey)}/events/ack`
+
+    // Ack as the signing identity; the beacon rejects acks for any other user_id.
+    const identity = await loadIdentity()
+    const body = JSON.stringify({
+      user_id: identity.user_id,
+      last_event_id: lastEvent.event_id,
+      last_seq: lastEvent.seq,
+    })
+    const headers = await getServerMemberAuthHeaders(signingPubkey, 'POST', path, body)
 
-    await fetch(url, {
+    await fetch(`${baseUrl}${path}`, {
       method: 'POST',
-      headers: { 'Content-Type': 'application/json' },
-      body: JSON.stringify({
-        user_id: userId,
-        last_event_id: lastEventId,
-      }),
+      headers: { 'Content-Type': 'application/json', ...headers },
+      body,
     })
   }
 
@@ -197,19 +227,21 @@ export class EventSyncManager {
    */
   async postEvent(
     signalingServer: string,
-    event: Omit<ServerEvent, 'event_id' | 'timestamp'>
+    event: Omit<ServerEvent, 'event_id' | 'timestamp' | 'seq'>
   ): Promise<void> {
     const baseUrl = this.normalizeServerUrl(signalingServer)
-    const url = `${baseUrl}/api/servers/${encodeURIComponent(event.signing_pubkey)}/events`
+    const path = `/api/servers/${encodeURIComponent(event.signing_pubkey)}/events`
+    const body = JSON.stringify({
+      ...event,
+      event_id: '',  // Server will generate
+      timestamp: new Date().toISOString(),
+    })
+    const headers = await getServerMemberAuthHeaders(event.signing_pubkey, 'POST', path, body)
 
-    const response = await fetch(url, {
+    const response = await fetch(`${baseUrl}${path}`, {
       method: 'POST',
-      headers: { 'Content-Type': 'application/json' },
-      body: JSON.stringify({
-        ...event,
-        event_id: '',  // Server will generate
-        timestamp: new Date().toISOString(),
-      }),
+      headers: { 'Content-Type': 'application/json', ...headers },
+      body,
     })
 
     if (!response.ok) {
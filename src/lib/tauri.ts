@@ -94,6 +94,10 @@ export interface EncryptedServerHint {
   encrypted_state: string
   signature: string
   last_updated: string
+  signer_pubkey?: string
+  delegate_pubkey?: string
+  /** Current server key sealed to each member after a rotation. */
+  key_envelopes?: { user_id: string; epoch: number; sealed_key: string }[]
 }
 
 export async function createServer(name: string, userId: string, displayName: string): Promise<Server> {
@@ -108,26 +112,111 @@ export async function loadServer(serverId: string): Promise<Server> {
   return await invoke('load_server', { serverId })
 }
 
-export async function encryptEphemeralChatMessage(serverId: string, plaintext: string): Promise<string> {
-  return await invoke('encrypt_ephemeral_chat_message', { serverId, plaintext })
+/** Sign a chat payload for `chatId` / `messageId` with the identity key and encrypt it with the server key. */
+export async function encryptEphemeralChatMessage(
+  serverId: string,
+  chatId: string,
+  messageId: string,
+  plaintext: string
+): Promise<string> {
+  return await invoke('encrypt_ephemeral_chat_message', { serverId, chatId, messageId, plaintext })
 }
 
 export async function encryptEphemeralChatMessageBySigningPubkey(
   signingPubkey: string,
+  chatId: string,
+  messageId: string,
   plaintext: string
 ): Promise<string> {
-  return await invoke('encrypt_ephemeral_chat_message_by_signing_pubkey', { signingPubkey, plaintext })
+  return await invoke('encrypt_ephemeral_chat_message_by_signing_pubkey', { signingPubkey, chatId, messageId, plaintext })
+}
+
+/** Decrypted chat payload; `verified_author_user_id` is null for unsigned payloads from older clients. */
+export interface OpenedChatPayload {
+  plaintext: string
+  verified_author_user_id: string | null
 }
 
-export async function decryptEphemeralChatMessage(serverId: string, encryptedPayloadB64: string): Promise<string> {
-  return await invoke('decrypt_ephemeral_chat_message', { serverId, encryptedPayloadB64 })
+/**
+ * Decrypt a chat payload. Signed payloads are rejected unless their identity-key signature holds,
+ * they were signed for the `chatId` / `messageId` they were relayed under and (when given) the
+ * signer is `expectedAuthorUserId`, the sender reported by the beacon. Unsigned payloads are
+ * rejected too once that sender is known to sign.
+ */
+export async function decryptEphemeralChatMessage(
+  serverId: string,
+  chatId: string,
+  messageId: string,
+  encryptedPayloadB64: string,
+  expectedAuthorUserId?: string
+): Promise<OpenedChatPayload> {
+  return await invoke('decrypt_ephemeral_chat_message', {
+    serverId,
+    chatId,
+    messageId,
+    encryptedPayloadB64,
+    expectedAuthorUserId: expectedAuthorUserId ?? null,
+  })
 }
 
 export async function decryptEphemeralChatMessageBySigningPubkey(
   signingPubkey: string,
-  encryptedPayloadB64: string
+  chatId: string,
+  messageId: string,
+  encryptedPayloadB64: string,
+  expectedAuthorUserId?: string
+): Promise<OpenedChatPayload> {
+  return await invoke('decrypt_ephemeral_chat_message_by_signing_pubkey', {
+    signingPubkey,
+    chatId,
+    messageId,
+    encryptedPayloadB64,
+    expectedAuthorUserId: expectedAuthorUserId ?? null,
+  })
+}
+
+export type ChatEnvelopeKind = 'edit' | 'delete' | 'reaction_add' | 'reaction_remove'
+
+/** Decrypted, signature-checked edit / delete / reaction (src-tauri/src/chat_envelope.rs). */
+export interface ChatEnvelope {
+  kind: ChatEnvelopeKind
+  signing_pubkey: string
+  chat_id: string
+  message_id: string
+  /** New text (edit) or emoji (reaction); empty for a delete. */
+  body: string
+  author_user_id: string
+  author_public_key: string
+  signed_at: string
+  signature: string
+}
+
+/** Sign with the identity key and encrypt with the server key. */
+export async function encryptSignedChatEnvelope(
+  signingPubkey: string,
+  chatId: string,
+  messageId: string,
+  kind: ChatEnvelopeKind,
+  body?: string
 ): Promise<string> {
-  return await invoke('decrypt_ephemeral_chat_message_by_signing_pubkey', { signingPubkey, encryptedPayloadB64 })
+  return await invoke('encrypt_signed_chat_envelope', { signingPubkey, chatId, messageId, kind, body: body ?? null })
+}
+
+/** Rejects unless the envelope is for this message and signed by expectedAuthorUserId. */
+export async function decryptVerifiedChatEnvelope(
+  signingPubkey: string,
+  chatId: string,
+  messageId: string,
+  encryptedPayloadB64: string,
+  expectedAuthorUserId: string
+): Promise<ChatEnvelope> {
+  return await invoke('decrypt_verified_chat_envelope', {
+    signingPubkey,
+    chatId,
+    messageId,
+    encryptedPayloadB64,
+    expectedAuthorUserId,
+  })
 }
 
 export interface AttachmentWaveformPeaks {
@@ -422,6 +511,11 @@ export async function publishServerHintMemberLeft(beaconUrl: string, serverId: s
   return await invoke('publish_server_hint_member_left', { beaconUrl, serverId, userId })
 }
 
+/** Owner only: remove a member and rotate the server key to a new epoch so they cannot read anything sent afterwards. */
+export async function removeServerMember(beaconUrl: string, serverId: string, userId: string): Promise<void> {
+  return await invoke('remove_server_member', { beaconUrl, serverId, userId })
+}
+
 export async function fetchAndImportServerHintOpaque(beaconUrl: string, signingPubkey: string): Promise<boolean> {
   return await invoke('fetch_and_import_server_hint_opaque', { beaconUrl, signingPubkey })
 }
@@ -447,6 +541,22 @@ export async function revokeActiveInvite(beaconUrl: string, serverId: string): P
   return await invoke('revoke_active_invite', { beaconUrl, serverId })
 }
 
+export interface InviteTokenRecord {
+  code: string
+  signing_pubkey: string
+  encrypted_payload: string
+  signature: string
+  created_at: string
+  expires_at: string
+  max_uses: number
+  remaining_uses: number
+}
+
+/** Owner only: outstanding invites for this server as recorded by the beacon. */
+export async function listServerInvites(beaconUrl: string, serverId: string): Promise<InviteTokenRecord[]> {
+  return await invoke('list_server_invites', { beaconUrl, serverId })
+}
+
 export async function checkBeacon(url?: string): Promise<boolean> {
   return await invoke('check_beacon', { url })
 }
@@ -572,6 +682,34 @@ export async function getFriendAuthHeaders(
   })
 }
 
+/**
+ * Headers for server event queue routes (/api/servers/:signing_pubkey/events*).
+ * Identity-signed like the friend API, plus a server membership signature.
+ */
+export async function getServerMemberAuthHeaders(
+  signingPubkey: string,
+  method: string,
+  path: string,
+  body?: string | null
+): Promise<Record<string, string>> {
+  return await invoke<Record<string, string>>('get_server_member_auth_headers', {
+    signingPubkey,
+    method,
+    path,
+    body: body ?? null,
+  })
+}
+
+/**
+ * Answer the beacon WebSocket login challenge (AuthChallenge) with the identity key.
+ * Send the result as { type: 'AuthResponse', ...result }.
+ */
+export async function signBeaconAuthChallenge(
+  nonce: string
+): Promise<{ user_id: string; public_key: string; signature: string }> {
+  return await invoke('sign_beacon_auth_challenge', { nonce })
+}
+
 /** Prefer when in Tauri app to avoid webview "Allow this site to read from your clipboard?" prompt. */
 export async function readClipboardText(): Promise<string> {
   return await invoke<string>('read_clipboard_text')
@@ -1256,6 +1256,8 @@ function ServerViewPage() {
                 <p className="text-xs text-muted-foreground font-light mb-1">Server invite</p>
                 {!server?.has_symmetric_key ? (
                   <p className="text-xs text-muted-foreground">This server cannot create invites (missing key).</p>
+                ) : !getActiveInviteUri() && !server?.has_signing_key ? (
+                  <p className="text-xs text-muted-foreground">Only the server owner can create invites.</p>
                 ) : !getActiveInviteUri() ? (
                   <Button
                     variant="outline"
@@ -1320,6 +1322,7 @@ function ServerViewPage() {
                             {copiedInvite ? <Check className="h-3.5 w-3.5 text-green-500" /> : <Copy className="h-3.5 w-3.5" />}
                           </Button>
                         </Tooltip>
+                        {server?.has_signing_key && (
                         <Tooltip content="Revoke" side="bottom">
                           <Button
                             variant="outline"
@@ -1334,6 +1337,7 @@ function ServerViewPage() {
                             <X className="h-3.5 w-3.5" />
                           </Button>
                         </Tooltip>
+                        )}
                       </div>
                     </button>
                   </>